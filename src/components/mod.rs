@@ -1,9 +1,15 @@
 use std::sync::Arc;
 
-use gpui::{App, Image, ImageCacheError, ImageFormat, RenderImage, SharedString, Window};
+use gpui::{
+    App, Asset, Image, ImageCacheError, ImageFormat, ImageSource, RenderImage, SharedString, Window,
+};
+use image::Frame;
+
+use crate::library::{LIBRARY, art::ArtSize};
 
 pub mod icon;
 pub mod player;
+pub mod rating;
 pub mod sidebar;
 pub mod track_list;
 
@@ -32,3 +38,50 @@ pub fn render_image(
         .get_render_image(w, a)
         .ok_or(ImageCacheError::Asset(SharedString::new("")))
 }
+
+/// Loads an album's cover from the library by album id and size through gpui's asset
+/// cache, so it is read and decoded once and shared by every element showing it.
+/// The art's version is part of the key, so a changed cover is loaded again.
+pub enum AlbumArt {}
+
+impl Asset for AlbumArt {
+    type Source = (String, ArtSize, u64);
+    type Output = Option<Arc<RenderImage>>;
+
+    fn load(
+        (album_id, size, _version): Self::Source,
+        _cx: &mut App,
+    ) -> impl Future<Output = Self::Output> + Send + 'static {
+        async move {
+            let art = LIBRARY
+                .get()?
+                .album_thumbnail(&album_id, size)
+                .await
+                .ok()
+                .flatten()?;
+            let mut buffer = image::load_from_memory(&art).ok()?.into_rgba8();
+            // gpui expects BGRA pixels
+            for pixel in buffer.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            Some(Arc::new(RenderImage::new(vec![Frame::new(buffer)])))
+        }
+    }
+}
+
+/// Image source for an album cover: the inline bytes if there are any,
+/// otherwise the cover at `size` loaded lazily from the library by album id
+pub fn album_art_source(
+    album_art: Option<Vec<u8>>,
+    album_id: Option<String>,
+    size: ArtSize,
+) -> ImageSource {
+    ImageSource::Custom(Arc::new(move |w, a| match (&album_art, &album_id) {
+        (Some(art), _) => Some(render_image(w, a, art.clone())),
+        (None, Some(id)) => {
+            let source = (id.clone(), size, LIBRARY.get()?.art_version(id));
+            Some(Ok(w.use_asset::<AlbumArt>(&source, a)??))
+        }
+        (None, None) => None,
+    }))
+}
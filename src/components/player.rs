@@ -1,10 +1,9 @@
-use std::sync::Arc;
 use std::time::Duration;
 
 use gpui::prelude::FluentBuilder;
 use gpui::{
-    AppContext, Context, Entity, ImageSource, IntoElement, ParentElement, Render, Styled, Timer,
-    div, img,
+    Action, App, AppContext, Context, Entity, ImageSource, IntoElement, KeyBinding, ParentElement,
+    Render, Styled, Timer, WeakEntity, actions, div, img,
 };
 use gpui_component::popover::Popover;
 use gpui_component::{
@@ -15,11 +14,38 @@ use gpui_component::{
 };
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::components::album_art_source;
 use crate::components::icon::Icon;
-use crate::components::render_image;
-use crate::library::Track;
+use crate::components::rating::{self, love_button, rating_buttons};
+use crate::library::{LIBRARY, LibraryEvent, Track, art::ArtSize};
 use crate::player::{PLAYER, PlayerCommand, PlayerEvent, Repeat};
 
+actions!(
+    player,
+    [
+        RateZero,
+        RateOne,
+        RateTwo,
+        RateThree,
+        RateFour,
+        RateFive,
+        ToggleLoved
+    ]
+);
+
+/// Bind the shortcuts for rating and loving the playing track
+pub fn init(cx: &mut App) {
+    cx.bind_keys([
+        KeyBinding::new("secondary-0", RateZero, None),
+        KeyBinding::new("secondary-1", RateOne, None),
+        KeyBinding::new("secondary-2", RateTwo, None),
+        KeyBinding::new("secondary-3", RateThree, None),
+        KeyBinding::new("secondary-4", RateFour, None),
+        KeyBinding::new("secondary-5", RateFive, None),
+        KeyBinding::new("secondary-l", ToggleLoved, None),
+    ]);
+}
+
 pub struct Player {
     playback_position: f32,
     playback_state: Entity<SliderState>,
@@ -67,6 +93,45 @@ impl Player {
         })
         .detach();
 
+        let player = cx.weak_entity();
+        let app: &mut App = cx;
+        on_rate::<RateZero>(app, player.clone(), 0);
+        on_rate::<RateOne>(app, player.clone(), 1);
+        on_rate::<RateTwo>(app, player.clone(), 2);
+        on_rate::<RateThree>(app, player.clone(), 3);
+        on_rate::<RateFour>(app, player.clone(), 4);
+        on_rate::<RateFive>(app, player.clone(), 5);
+        app.on_action(move |_: &ToggleLoved, cx| {
+            if let Some(track) = playing_track(&player, cx) {
+                rating::set_loved(track.id, !track.metadata.loved);
+            }
+        });
+
+        // keep the rating and loved flag of the playing track current
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                let LibraryEvent::TracksUpdated(tracks) = event else {
+                    continue;
+                };
+                let Some(this) = this.upgrade() else {
+                    return;
+                };
+                app.update_entity(&this, |player, cx| {
+                    let Some(current) = &player.current_track else {
+                        return;
+                    };
+                    if let Some(track) = tracks.into_iter().find(|t| t.id == current.id) {
+                        player.current_track = Some(track);
+                        cx.notify();
+                    }
+                })
+                .ok();
+            }
+        })
+        .detach();
+
         cx.spawn(async move |this, cx| {
             // wait for player to be initialized and subscribe
             let player = PLAYER.get().expect("Player not initialized");
@@ -148,15 +213,19 @@ impl Player {
         self.duration_secs = track.duration;
         self.playback_position_secs = 0.0;
         self.playback_position = 0.0;
-        self.album_art_source = track.album.album_art.clone().map(|album_art| {
-            ImageSource::Custom(Arc::new(move |w, a| {
-                Some(render_image(w, a, album_art.clone()))
-            }))
-        });
+        self.album_art_source = Some(album_art_source(
+            track.album.album_art.clone(),
+            Some(track.album.id.clone()),
+            ArtSize::Large,
+        ));
         self.current_track = Some(track);
         cx.notify();
     }
 
+    pub fn current_track(&self) -> Option<&Track> {
+        self.current_track.as_ref()
+    }
+
     fn format_time(seconds: f64) -> String {
         let mins = (seconds / 60.0).floor() as u32;
         let secs = (seconds % 60.0).floor() as u32;
@@ -164,6 +233,22 @@ impl Player {
     }
 }
 
+/// The track the player bar shows, if it is still open
+fn playing_track(player: &WeakEntity<Player>, cx: &App) -> Option<Track> {
+    player
+        .upgrade()
+        .and_then(|player| player.read(cx).current_track().cloned())
+}
+
+/// Handle `A` by giving the playing track `rating` stars
+fn on_rate<A: Action>(cx: &mut App, player: WeakEntity<Player>, rating: u8) {
+    cx.on_action(move |_: &A, cx| {
+        if let Some(track) = playing_track(&player, cx) {
+            rating::set_rating(track.id, rating);
+        }
+    });
+}
+
 impl Render for Player {
     fn render(
         &mut self,
@@ -240,7 +325,15 @@ impl Render for Player {
                                                 .text_ellipsis()
                                                 .font_semibold(),
                                         )
-                                        .child(div().child(artist).text_sm().text_ellipsis()),
+                                        .child(div().child(artist).text_sm().text_ellipsis())
+                                        .when_some(self.current_track.as_ref(), |el, track| {
+                                            el.child(
+                                                div()
+                                                    .h_flex()
+                                                    .child(rating_buttons("player_rating", track))
+                                                    .child(love_button("player_love", track)),
+                                            )
+                                        }),
                                 ),
                         )
                         .child(
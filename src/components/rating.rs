@@ -0,0 +1,58 @@
+use gpui::{Div, ElementId, ParentElement, SharedString, div};
+use gpui_component::{
+    StyledExt,
+    button::{Button, ButtonVariants},
+};
+use tokio::task;
+
+use crate::library::{LIBRARY, Track, rating::MAX_RATING};
+
+/// Set a track's rating in the background
+pub fn set_rating(track_id: String, rating: u8) {
+    task::spawn(async move {
+        let Some(library) = LIBRARY.get() else {
+            return;
+        };
+        if let Err(e) = library.set_rating(&track_id, rating).await {
+            eprintln!("Failed to set rating: {}", e);
+        }
+    });
+}
+
+/// Love or unlove a track in the background
+pub fn set_loved(track_id: String, loved: bool) {
+    task::spawn(async move {
+        let Some(library) = LIBRARY.get() else {
+            return;
+        };
+        if let Err(e) = library.set_loved(&track_id, loved).await {
+            eprintln!("Failed to set loved: {}", e);
+        }
+    });
+}
+
+/// One button per star, setting the track's rating to it. Clicking the current rating
+/// clears it.
+pub fn rating_buttons(id: impl Into<SharedString>, track: &Track) -> Div {
+    let id = id.into();
+    let rating = track.metadata.rating;
+    div().h_flex().children((1..=MAX_RATING).map(|star| {
+        let track_id = track.id.clone();
+        Button::new(ElementId::NamedInteger(id.clone(), star as u64))
+            .ghost()
+            .label(if star <= rating { "★" } else { "☆" })
+            .on_click(move |_event, _window, _cx| {
+                set_rating(track_id.clone(), if star == rating { 0 } else { star })
+            })
+    }))
+}
+
+/// Button toggling whether the track is loved
+pub fn love_button(id: impl Into<ElementId>, track: &Track) -> Button {
+    let track_id = track.id.clone();
+    let loved = track.metadata.loved;
+    Button::new(id)
+        .ghost()
+        .label(if loved { "♥" } else { "♡" })
+        .on_click(move |_event, _window, _cx| set_loved(track_id.clone(), !loved))
+}
@@ -1,6 +1,8 @@
+use std::{collections::HashSet, path::PathBuf};
+
 use gpui::{
-    AppContext, ClickEvent, Entity, IntoElement, PathPromptOptions, Render, SharedString, Styled,
-    Window, rgba,
+    AppContext, AsyncApp, ClickEvent, Entity, IntoElement, PathPromptOptions, Render, SharedString,
+    Styled, Window, rgba,
 };
 use gpui_component::{
     Side,
@@ -9,29 +11,221 @@ use gpui_component::{
 use tokio::task;
 use walkdir::WalkDir;
 
-use crate::{components::icon::Icon, library::LIBRARY, providers::local};
+use crate::{
+    components::{icon::Icon, track_list::PlaylistMenu},
+    library::{
+        LIBRARY, LibraryEvent, Playlist, PlaylistSummary, Track,
+        backup::{self, Backup, PathRemap},
+        smart::SmartRules,
+    },
+    preferences::PREFERENCES,
+    providers::{cue, formats, local},
+    views::import::FileImportReport,
+};
 
 pub struct Sidebar {
     pub navigation_state: Entity<NavigationState>,
     pub collapsed: Entity<bool>,
+    playlists: Vec<PlaylistSummary>,
 }
 
 pub enum NavigationState {
     Home,
+    Albums,
+    Artists,
     Search,
     Lyrics,
+    Health,
+    History,
+    Import,
+    Duplicates,
+    TagEditor,
+    /// The playlist with this id
+    Playlist(String),
 }
 
-const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "flac", "wav", "ogg", "m4a", "aac"];
+/// Number of tracks written to the library at once when importing files
+const IMPORT_BATCH_SIZE: usize = 500;
 
 impl Sidebar {
     pub fn new(cx: &mut gpui::Context<Self>) -> Self {
         let current_state = cx.new(|_| NavigationState::Home);
         let collapsed = cx.new(|_| false);
-        Self {
+
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                let Some(this) = this.upgrade() else {
+                    return;
+                };
+                app.update_entity(&this, |sidebar, cx| match event {
+                    LibraryEvent::PlaylistsChanged | LibraryEvent::Restored => {
+                        sidebar.reload_playlists(cx)
+                    }
+                    // the track counts of smart playlists change with the library
+                    LibraryEvent::TracksAdded(_)
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::PlaysRecorded(_) => {
+                        if sidebar.playlists.iter().any(|p| p.smart) {
+                            sidebar.reload_playlists(cx);
+                        }
+                    }
+                })
+                .ok();
+            }
+        })
+        .detach();
+
+        let mut sidebar = Self {
             navigation_state: current_state,
             collapsed,
-        }
+            playlists: Vec::new(),
+        };
+        sidebar.reload_playlists(cx);
+        sidebar
+    }
+
+    /// Reload the playlists listed in the sidebar and offered by "add to playlist"
+    fn reload_playlists(&mut self, cx: &mut gpui::Context<Self>) {
+        cx.spawn(async move |this, app| {
+            let playlists = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.all_playlist_summaries().await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let playlists = match playlists {
+                Ok(playlists) => playlists,
+                Err(e) => {
+                    eprintln!("Failed to load playlists: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |sidebar, cx| {
+                    cx.set_global(PlaylistMenu(playlists.clone()));
+                    sidebar.playlists = playlists;
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// Create an empty playlist and open it
+    pub fn new_playlist(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let playlist = Playlist::new(format!("Playlist {}", self.playlists.len() + 1), None);
+        self.create_playlist(playlist, cx);
+    }
+
+    /// Create a smart playlist with no rules yet, which matches every track, and open it
+    pub fn new_smart_playlist(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let playlist = Playlist {
+            rules: Some(SmartRules::default()),
+            ..Playlist::new(format!("Smart playlist {}", self.playlists.len() + 1), None)
+        };
+        self.create_playlist(playlist, cx);
+    }
+
+    fn create_playlist(&mut self, playlist: Playlist, cx: &mut gpui::Context<'_, Self>) {
+        let navigation_state = self.navigation_state.clone();
+        cx.spawn(async move |_, app| {
+            let created = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.create_playlist(&playlist).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            match created {
+                Ok(playlist) => {
+                    app.update_entity(&navigation_state, |state, cx| {
+                        *state = NavigationState::Playlist(playlist.id);
+                        cx.notify();
+                    })
+                    .ok();
+                }
+                Err(e) => eprintln!("Failed to create playlist: {}", e),
+            }
+        })
+        .detach();
+    }
+
+    /// Create a playlist from an M3U, PLS or XSPF file and open it
+    pub fn import_playlist(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let path_future = cx.prompt_for_paths(PathPromptOptions {
+            directories: false,
+            files: true,
+            multiple: false,
+            prompt: Some(SharedString::new("Select a playlist file")),
+        });
+        let navigation_state = self.navigation_state.clone();
+        cx.spawn(async move |_, app| {
+            let Some(path) = path_future
+                .await
+                .ok()
+                .and_then(|r| r.ok())
+                .flatten()
+                .and_then(|paths| paths.into_iter().next())
+            else {
+                return;
+            };
+            let import = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.import_playlist(&path).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            match import {
+                Ok(import) => {
+                    for location in &import.missing {
+                        eprintln!("Playlist entry not found: {}", location);
+                    }
+                    for (path, format) in &import.unsupported {
+                        eprintln!(
+                            "Playlist entry can't be played: {} ({})",
+                            path.display(),
+                            format
+                        );
+                    }
+                    app.update_entity(&navigation_state, |state, cx| {
+                        *state = NavigationState::Playlist(import.playlist.id);
+                        cx.notify();
+                    })
+                    .ok();
+                }
+                Err(e) => eprintln!("Failed to import playlist: {}", e),
+            }
+        })
+        .detach();
     }
 
     pub fn item_home(
@@ -45,6 +239,28 @@ impl Sidebar {
         });
     }
 
+    pub fn item_albums(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Albums;
+        });
+    }
+
+    pub fn item_artists(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Artists;
+        });
+    }
+
     pub fn item_search(
         &mut self,
         _event: &ClickEvent,
@@ -67,6 +283,61 @@ impl Sidebar {
         });
     }
 
+    pub fn item_health(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Health;
+        });
+    }
+
+    pub fn item_history(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::History;
+        });
+    }
+
+    pub fn item_import(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Import;
+        });
+    }
+
+    pub fn item_duplicates(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Duplicates;
+        });
+    }
+
+    pub fn item_tag_editor(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::TagEditor;
+        });
+    }
+
     pub fn load_media_file(
         &mut self,
         _event: &ClickEvent,
@@ -77,28 +348,19 @@ impl Sidebar {
             directories: false,
             files: true,
             multiple: true,
-            prompt: Some(SharedString::new("Select media file(s)")),
+            prompt: Some(SharedString::new("Select media file(s) or CUE sheets")),
         });
-        cx.spawn(async move |_, _| {
+        let navigation_state = self.navigation_state.clone();
+        cx.spawn(async move |_, app| {
             let paths = path_future.await.ok().and_then(|r| r.ok()).and_then(|p| p);
             if let Some(paths) = paths {
-                task::spawn(async move {
-                    let library = LIBRARY.get().expect("Library not initialized");
-                    for path in paths {
-                        match local::resolve_track(path.to_str().unwrap_or("")) {
-                            Ok(track) => {
-                                if let Err(e) = library.add_track(&track).await {
-                                    eprintln!("Failed to add track to library: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to resolve track: {}", e);
-                            }
-                        }
-                    }
-                })
-                .await
-                .ok();
+                let report = task::spawn(import_files(paths, true))
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e));
+                match report {
+                    Ok(report) => show_file_import(report, &navigation_state, app),
+                    Err(e) => eprintln!("Failed to add files: {}", e),
+                }
             }
         })
         .detach();
@@ -116,42 +378,188 @@ impl Sidebar {
             multiple: false,
             prompt: Some(SharedString::new("Select media directory")),
         });
-        cx.spawn(async move |_, _| {
+        let navigation_state = self.navigation_state.clone();
+        cx.spawn(async move |_, app| {
             let paths = path_future.await.ok().and_then(|r| r.ok()).and_then(|p| p);
             if let Some(paths) = paths
                 && let Some(dir) = paths.first()
             {
                 let dir = dir.clone();
-                task::spawn(async move {
-                    let library = LIBRARY.get().expect("Library not initialized");
-                    for entry in WalkDir::new(&dir)
-                        .follow_links(true)
-                        .into_iter()
-                        .filter_map(|e| e.ok())
+                let report = task::spawn(async move {
+                    // remember the directory so moved files can be searched for later
                     {
-                        if entry.file_type().is_file() {
-                            if let Some(ext) = entry.path().extension()
-                                && SUPPORTED_EXTENSIONS
-                                    .iter()
-                                    .any(|&e| e.eq_ignore_ascii_case(ext.to_str().unwrap_or("")))
-                            {
-                                match local::resolve_track(entry.path().to_str().unwrap_or("")) {
-                                    Ok(track) => {
-                                        if let Err(e) = library.add_track(&track).await {
-                                            eprintln!("Failed to add track to library: {}", e);
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!(
-                                            "Failed to resolve track {}: {}",
-                                            entry.path().display(),
-                                            e
-                                        );
-                                    }
-                                }
-                            }
+                        let mut preferences = PREFERENCES
+                            .get()
+                            .expect("Preferences not initialized")
+                            .write()
+                            .await;
+                        let root = dir.to_string_lossy().to_string();
+                        if !preferences.library_roots.contains(&root) {
+                            preferences.library_roots.push(root);
                         }
                     }
+                    let files = WalkDir::new(&dir)
+                        .follow_links(true)
+                        .into_iter()
+                        .filter_map(|e| e.ok())
+                        .filter(|e| e.file_type().is_file())
+                        .map(|e| e.into_path())
+                        .collect();
+                    import_files(files, false).await
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e));
+                match report {
+                    Ok(report) => show_file_import(report, &navigation_state, app),
+                    Err(e) => eprintln!("Failed to add directory: {}", e),
+                }
+            }
+        })
+        .detach();
+    }
+
+    /// Save the whole library and the preferences to a backup file
+    pub fn back_up_library(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let directory = dirs::document_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_default();
+        let path_future = cx.prompt_for_new_path(&directory, Some(&backup::backup_file_name()));
+        cx.spawn(async move |_, _| {
+            let Some(path) = path_future.await.ok().and_then(|r| r.ok()).flatten() else {
+                return;
+            };
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.export_backup().await?.write(&path).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Err(e) = result {
+                eprintln!("Failed to back up library: {}", e);
+            }
+        })
+        .detach();
+    }
+
+    /// Replace the library with a backup file. For every library folder of the backup
+    /// that doesn't exist on this machine, asks where it is now.
+    pub fn restore_library(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let path_future = cx.prompt_for_paths(PathPromptOptions {
+            directories: false,
+            files: true,
+            multiple: false,
+            prompt: Some(SharedString::new("Select a library backup")),
+        });
+        cx.spawn(async move |_, app| {
+            let Some(path) = path_future
+                .await
+                .ok()
+                .and_then(|r| r.ok())
+                .flatten()
+                .and_then(|paths| paths.into_iter().next())
+            else {
+                return;
+            };
+            let backup = task::spawn(async move { Backup::read(&path).await })
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+                .flatten();
+            let backup = match backup {
+                Ok(backup) => backup,
+                Err(e) => {
+                    eprintln!("Failed to read backup: {}", e);
+                    return;
+                }
+            };
+
+            let mut remaps = Vec::new();
+            for root in backup.missing_roots() {
+                let Ok(path_future) = app.update(|cx| {
+                    cx.prompt_for_paths(PathPromptOptions {
+                        directories: true,
+                        files: false,
+                        multiple: false,
+                        prompt: Some(SharedString::new(format!(
+                            "Select the folder that was {}",
+                            root
+                        ))),
+                    })
+                }) else {
+                    return;
+                };
+                // a folder that isn't chosen keeps its old paths
+                if let Some(dir) = path_future
+                    .await
+                    .ok()
+                    .and_then(|r| r.ok())
+                    .flatten()
+                    .and_then(|paths| paths.into_iter().next())
+                {
+                    remaps.push(PathRemap {
+                        from: root,
+                        to: dir.to_string_lossy().to_string(),
+                    });
+                }
+            }
+
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.restore_backup(backup, &remaps).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Err(e) = result {
+                eprintln!("Failed to restore library: {}", e);
+            }
+        })
+        .detach();
+    }
+
+    pub fn choose_youtube_export_folder(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) {
+        let path_future = cx.prompt_for_paths(PathPromptOptions {
+            directories: true,
+            files: false,
+            multiple: false,
+            prompt: Some(SharedString::new(
+                "Select a folder to save YouTube downloads to",
+            )),
+        });
+        cx.spawn(async move |_, _| {
+            let paths = path_future.await.ok().and_then(|r| r.ok()).and_then(|p| p);
+            if let Some(paths) = paths
+                && let Some(dir) = paths.first()
+            {
+                let dir = dir.to_string_lossy().to_string();
+                task::spawn(async move {
+                    let mut preferences = PREFERENCES
+                        .get()
+                        .expect("Preferences not initialized")
+                        .write()
+                        .await;
+                    preferences.youtube_export_folder = Some(dir);
                 })
                 .await
                 .ok();
@@ -161,12 +569,147 @@ impl Sidebar {
     }
 }
 
+/// Add the playable files among `paths` to the library, going by their contents rather
+/// than their extensions. CUE sheets among them are added as their tracks, in place of
+/// the files they split up. Files that aren't audio are skipped, and reported when
+/// `report_other` is set, e.g. because the user picked them.
+async fn import_files(paths: Vec<PathBuf>, report_other: bool) -> FileImportReport {
+    let mut report = FileImportReport::default();
+    let mut files = Vec::new();
+    let mut cue_sheets = Vec::new();
+    for path in paths {
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+        {
+            cue_sheets.push(path);
+            continue;
+        }
+        match formats::detect(&path) {
+            Some(format) if format.playable => files.push(path),
+            Some(format) => report.unsupported.push((path, format.name)),
+            None if report_other => report.failed.push((path, "Not an audio file".to_string())),
+            None => {}
+        }
+    }
+
+    // files split up by a CUE sheet are added as its tracks instead of whole
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut covered = HashSet::new();
+    for cue_path in cue_sheets {
+        match cue::resolve_tracks(&cue_path) {
+            Ok(tracks) => {
+                covered.extend(
+                    tracks
+                        .iter()
+                        .filter_map(|t| t.path.as_deref())
+                        .filter_map(|p| std::fs::canonicalize(p).ok()),
+                );
+                batch.extend(tracks);
+            }
+            Err(e) => report.failed.push((cue_path, e.to_string())),
+        }
+    }
+
+    for path in files {
+        if std::fs::canonicalize(&path).is_ok_and(|p| covered.contains(&p)) {
+            continue;
+        }
+        match local::resolve_track(path.to_str().unwrap_or("")) {
+            Ok(track) => batch.push(track),
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            add_batch(&mut batch, &mut report).await;
+        }
+    }
+    add_batch(&mut batch, &mut report).await;
+    report
+}
+
+/// Add a batch of tracks in one transaction. If it fails, the tracks are added one at a
+/// time instead, so one bad track doesn't lose the rest and ends up in the report.
+async fn add_batch(batch: &mut Vec<Track>, report: &mut FileImportReport) {
+    let library = LIBRARY.get().expect("Library not initialized");
+    match library.add_tracks(batch).await {
+        Ok(added) => report.added += added.len(),
+        Err(_) => {
+            for track in batch.iter() {
+                match library.add_tracks(std::slice::from_ref(track)).await {
+                    Ok(added) => report.added += added.len(),
+                    Err(e) => report.failed.push((
+                        PathBuf::from(track.path.clone().unwrap_or_default()),
+                        e.to_string(),
+                    )),
+                }
+            }
+        }
+    }
+    batch.clear();
+}
+
+/// Keep the report of an import for the import view, and open it if some files
+/// couldn't be added
+fn show_file_import(
+    report: FileImportReport,
+    navigation_state: &Entity<NavigationState>,
+    app: &mut AsyncApp,
+) {
+    app.update_entity(navigation_state, |state, cx| {
+        if !report.is_complete() {
+            *state = NavigationState::Import;
+            cx.notify();
+        }
+        cx.set_global(report);
+    })
+    .ok();
+}
+
 impl Render for Sidebar {
     fn render(
         &mut self,
         _window: &mut gpui::Window,
         cx: &mut gpui::Context<'_, Self>,
     ) -> impl IntoElement {
+        let mut playlists = SidebarMenu::new()
+            .child(
+                SidebarMenuItem::new("New playlist")
+                    .icon(Icon::FolderOpen)
+                    .on_click(cx.listener(Self::new_playlist)),
+            )
+            .child(
+                SidebarMenuItem::new("New smart playlist")
+                    .icon(Icon::FolderOpen)
+                    .on_click(cx.listener(Self::new_smart_playlist)),
+            )
+            .child(
+                SidebarMenuItem::new("Import playlist")
+                    .icon(Icon::FolderOpen)
+                    .on_click(cx.listener(Self::import_playlist)),
+            );
+        for playlist in &self.playlists {
+            let id = playlist.id.clone();
+            let active = matches!(
+                self.navigation_state.read(cx),
+                NavigationState::Playlist(open) if *open == playlist.id
+            );
+            playlists = playlists.child(
+                SidebarMenuItem::new(playlist.name.clone())
+                    .icon(if playlist.smart {
+                        Icon::Search
+                    } else {
+                        Icon::Play
+                    })
+                    .active(active)
+                    .on_click(cx.listener(move |sidebar, _, _, cx| {
+                        sidebar.navigation_state.update(cx, |state, cx| {
+                            *state = NavigationState::Playlist(id.clone());
+                            cx.notify();
+                        });
+                    })),
+            );
+        }
+
         GpuiSidebar::new(Side::Left)
             .bg(rgba(0x0000007a))
             .collapsible(true)
@@ -198,6 +741,21 @@ impl Render for Sidebar {
                         SidebarMenuItem::new("Load media directory")
                             .icon(Icon::FolderList)
                             .on_click(cx.listener(Self::load_media_directory)),
+                    )
+                    .child(
+                        SidebarMenuItem::new("YouTube download folder")
+                            .icon(Icon::FolderOpen)
+                            .on_click(cx.listener(Self::choose_youtube_export_folder)),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Back up library")
+                            .icon(Icon::FolderOpen)
+                            .on_click(cx.listener(Self::back_up_library)),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Restore library")
+                            .icon(Icon::FolderOpen)
+                            .on_click(cx.listener(Self::restore_library)),
                     ),
             )
             .child(
@@ -211,6 +769,24 @@ impl Render for Sidebar {
                                 NavigationState::Home
                             )),
                     )
+                    .child(
+                        SidebarMenuItem::new("Albums")
+                            .icon(Icon::FolderList)
+                            .on_click(cx.listener(Self::item_albums))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::Albums
+                            )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Artists")
+                            .icon(Icon::Navigation)
+                            .on_click(cx.listener(Self::item_artists))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::Artists
+                            )),
+                    )
                     .child(
                         SidebarMenuItem::new("Search")
                             .icon(Icon::Search)
@@ -228,7 +804,53 @@ impl Render for Sidebar {
                                 self.navigation_state.read(cx),
                                 NavigationState::Lyrics
                             )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("History")
+                            .icon(Icon::ArrowRepeatAll)
+                            .on_click(cx.listener(Self::item_history))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::History
+                            )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Library health")
+                            .icon(Icon::Settings)
+                            .on_click(cx.listener(Self::item_health))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::Health
+                            )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Import from another player")
+                            .icon(Icon::FolderOpen)
+                            .on_click(cx.listener(Self::item_import))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::Import
+                            )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Duplicates")
+                            .icon(Icon::FolderList)
+                            .on_click(cx.listener(Self::item_duplicates))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::Duplicates
+                            )),
+                    )
+                    .child(
+                        SidebarMenuItem::new("Edit tags")
+                            .icon(Icon::FolderList)
+                            .on_click(cx.listener(Self::item_tag_editor))
+                            .active(matches!(
+                                self.navigation_state.read(cx),
+                                NavigationState::TagEditor
+                            )),
                     ),
             )
+            .child(playlists)
     }
 }
@@ -1,23 +1,61 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
+use futures::future::BoxFuture;
 use gpui::{
-    AbsoluteLength, AppContext, Entity, ImageSource, IntoElement, ParentElement, Render,
-    SharedString, Styled, div, img, rgba,
+    AbsoluteLength, AppContext, Entity, Global, IntoElement, ParentElement, Render, SharedString,
+    Styled, div, img, prelude::FluentBuilder, rgba,
 };
 use gpui_component::{
     IndexPath, StyledExt,
-    button::Button,
+    button::{Button, ButtonVariants},
+    checkbox::Checkbox,
     list::{List, ListDelegate, ListItem, ListState},
+    popover::Popover,
 };
+use tokio::task;
 
 use crate::{
-    components::{icon::Icon, render_image},
-    library::Track,
+    components::{
+        album_art_source,
+        icon::Icon,
+        rating::{love_button, rating_buttons},
+    },
+    library::{
+        LIBRARY, PlaylistEntry, PlaylistSummary, Track,
+        art::ArtSize,
+        history::{Play, TrackPlays},
+        rating::stars,
+    },
     providers::youtube::YtTrack,
 };
 
 pub type OnPlayCallback<T> = Arc<dyn Fn(T) + Send + Sync>;
 
+/// Fetches the next page of items, returning them and whether there are more after them
+pub type LoadPageCallback<T> =
+    Arc<dyn Fn() -> BoxFuture<'static, anyhow::Result<(Vec<T>, bool)>> + Send + Sync>;
+
+/// A button shown on every row of a list, called with the row's index and item
+pub struct RowAction<T> {
+    label: &'static str,
+    callback: Arc<dyn Fn(usize, T) + Send + Sync>,
+}
+
+impl<T> RowAction<T> {
+    pub fn new(label: &'static str, callback: impl Fn(usize, T) + Send + Sync + 'static) -> Self {
+        Self {
+            label,
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+/// Playlists offered by "add to playlist" on every track list, kept up to date by the sidebar
+#[derive(Default)]
+pub struct PlaylistMenu(pub Vec<PlaylistSummary>);
+
+impl Global for PlaylistMenu {}
+
 /// This is necessary because we want to be able to use full `Track`s as well as
 /// other types that represent tracks (e.g. search results) in the TrackListDelegate.
 pub trait RenderedTrack: Clone + 'static {
@@ -25,12 +63,26 @@ pub trait RenderedTrack: Clone + 'static {
     fn id(&self) -> String;
     fn title(&self) -> String;
     fn album_art(&self) -> Option<Vec<u8>>;
+    /// Library album to load the cover from when there is no inline album art
+    fn album_id(&self) -> Option<String> {
+        None
+    }
+    /// The library track this item is, if it is in the library (e.g. to add it to a playlist)
+    fn library_track(&self) -> Option<Track> {
+        None
+    }
 }
 
 pub struct TrackListDelegate<T: RenderedTrack> {
     items: Vec<T>,
     selected_index: Option<IndexPath>,
     on_play: Option<OnPlayCallback<T>>,
+    load_page: Option<LoadPageCallback<T>>,
+    has_more: bool,
+    loading: bool,
+    row_actions: Vec<RowAction<T>>,
+    /// Ids of the checked items, `None` if rows have no checkbox
+    checked: Option<HashSet<String>>,
 }
 
 impl<T: RenderedTrack> TrackListDelegate<T> {
@@ -39,6 +91,11 @@ impl<T: RenderedTrack> TrackListDelegate<T> {
             items,
             selected_index: None,
             on_play: None,
+            load_page: None,
+            has_more: false,
+            loading: false,
+            row_actions: Vec::new(),
+            checked: None,
         }
     }
 
@@ -46,6 +103,61 @@ impl<T: RenderedTrack> TrackListDelegate<T> {
         self.on_play = Some(callback);
         self
     }
+
+    /// Load further pages with `callback` as the list is scrolled towards its end
+    pub fn with_load_page(mut self, callback: LoadPageCallback<T>, has_more: bool) -> Self {
+        self.load_page = Some(callback);
+        self.has_more = has_more;
+        self
+    }
+
+    /// Show `actions` as buttons on every row, before the play button
+    pub fn with_row_actions(mut self, actions: Vec<RowAction<T>>) -> Self {
+        self.row_actions = actions;
+        self
+    }
+
+    /// Show a checkbox on every row so several items can be picked, starting with the
+    /// items in `checked` checked
+    pub fn with_checkboxes(mut self, checked: HashSet<String>) -> Self {
+        self.checked = Some(checked);
+        self
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn selected_item(&self) -> Option<&T> {
+        self.selected_index.and_then(|ix| self.items.get(ix.row))
+    }
+
+    /// Ids of the checked items, including ones no longer in the list
+    pub fn checked_ids(&self) -> HashSet<String> {
+        self.checked.clone().unwrap_or_default()
+    }
+
+    /// The checked items, in list order
+    pub fn checked_items(&self) -> Vec<&T> {
+        let Some(checked) = &self.checked else {
+            return vec![];
+        };
+        self.items
+            .iter()
+            .filter(|item| checked.contains(&item.id()))
+            .collect()
+    }
+
+    /// Check or uncheck every item in the list
+    pub fn check_all(&mut self, check: bool) {
+        if let Some(checked) = &mut self.checked {
+            if check {
+                checked.extend(self.items.iter().map(|item| item.id()));
+            } else {
+                checked.clear();
+            }
+        }
+    }
 }
 
 impl<T: RenderedTrack> From<Vec<T>> for TrackListDelegate<T> {
@@ -65,15 +177,35 @@ impl<T: RenderedTrack> ListDelegate for TrackListDelegate<T> {
         &mut self,
         ix: gpui_component::IndexPath,
         _window: &mut gpui::Window,
-        _cx: &mut gpui::Context<ListState<Self>>,
+        cx: &mut gpui::Context<ListState<Self>>,
     ) -> Option<Self::Item> {
+        // smart playlists pick their own tracks
+        let playlists: Vec<PlaylistSummary> = cx
+            .try_global::<PlaylistMenu>()
+            .map(|menu| menu.0.iter().filter(|p| !p.smart).cloned().collect())
+            .unwrap_or_default();
         self.items.get(ix.row).map(|track| {
-            let aa = track.album_art();
+            let album_art = album_art_source(track.album_art(), track.album_id(), ArtSize::Small);
             let track_for_click = track.clone();
             let on_play = self.on_play.clone();
             let title = track.title();
             let artists = track.artists_string();
             let track_id = track.id();
+            let checkbox = self.checked.as_ref().map(|checked| {
+                let id = track_id.clone();
+                Checkbox::new(("track_check", ix.row))
+                    .checked(checked.contains(&track_id))
+                    .on_click(cx.listener(move |state, check: &bool, _window, cx| {
+                        if let Some(checked) = &mut state.delegate_mut().checked {
+                            if *check {
+                                checked.insert(id.clone());
+                            } else {
+                                checked.remove(&id);
+                            }
+                        }
+                        cx.notify();
+                    }))
+            });
             //println!("Rendering track at index {}: {:?}", ix.row, track);
             ListItem::new(ix)
                 .child(
@@ -85,18 +217,8 @@ impl<T: RenderedTrack> ListDelegate for TrackListDelegate<T> {
                             div()
                                 .h_flex()
                                 .gap_4()
-                                .child(
-                                    img(ImageSource::Custom(Arc::new(move |w, a| {
-                                        // album_art is base64
-                                        if let Some(album_art) = &aa {
-                                            Some(render_image(w, a, album_art.clone()))
-                                        } else {
-                                            None
-                                        }
-                                    })))
-                                    .rounded_md()
-                                    .h_16(),
-                                )
+                                .children(checkbox)
+                                .child(img(album_art).rounded_md().h_16())
                                 .child(
                                     div()
                                         .v_flex()
@@ -105,13 +227,36 @@ impl<T: RenderedTrack> ListDelegate for TrackListDelegate<T> {
                                 ),
                         )
                         .child(
-                            Button::new(SharedString::new(format!("play_{}", track_id)))
-                                .icon(Icon::Play)
-                                .on_click(move |_event, _window, _cx| {
-                                    if let Some(ref callback) = on_play {
-                                        callback(track_for_click.clone());
-                                    }
-                                }),
+                            div()
+                                .h_flex()
+                                .gap_1()
+                                .children(self.row_actions.iter().enumerate().map(|(i, action)| {
+                                    let callback = action.callback.clone();
+                                    let item = track.clone();
+                                    let row = ix.row;
+                                    Button::new(SharedString::new(format!("action_{}_{}", i, row)))
+                                        .ghost()
+                                        .label(action.label)
+                                        .on_click(move |_event, _window, _cx| {
+                                            callback(row, item.clone())
+                                        })
+                                }))
+                                .children(track.library_track().and_then(|library_track| {
+                                    rating_label(&library_track)
+                                        .map(|label| div().text_sm().child(label))
+                                }))
+                                .children(track.library_track().map(|library_track| {
+                                    track_menu(ix.row, library_track, &playlists)
+                                }))
+                                .child(
+                                    Button::new(SharedString::new(format!("play_{}", track_id)))
+                                        .icon(Icon::Play)
+                                        .on_click(move |_event, _window, _cx| {
+                                            if let Some(ref callback) = on_play {
+                                                callback(track_for_click.clone());
+                                            }
+                                        }),
+                                ),
                         )
                         .p_1(),
                 )
@@ -124,6 +269,43 @@ impl<T: RenderedTrack> ListDelegate for TrackListDelegate<T> {
         })
     }
 
+    fn has_more(&self, _cx: &gpui::App) -> bool {
+        self.has_more && self.load_page.is_some()
+    }
+
+    fn load_more(&mut self, _window: &mut gpui::Window, cx: &mut gpui::Context<ListState<Self>>) {
+        if self.loading {
+            return;
+        }
+        let Some(load_page) = self.load_page.clone() else {
+            return;
+        };
+        self.loading = true;
+        cx.spawn(async move |state, cx| {
+            let result = load_page().await;
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            cx.update_entity(&state, |state, cx| {
+                let delegate = state.delegate_mut();
+                delegate.loading = false;
+                match result {
+                    Ok((items, has_more)) => {
+                        delegate.items.extend(items);
+                        delegate.has_more = has_more;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to load more tracks: {}", e);
+                        delegate.has_more = false;
+                    }
+                }
+                cx.notify();
+            })
+            .ok();
+        })
+        .detach();
+    }
+
     fn set_selected_index(
         &mut self,
         ix: Option<gpui_component::IndexPath>,
@@ -135,6 +317,59 @@ impl<T: RenderedTrack> ListDelegate for TrackListDelegate<T> {
     }
 }
 
+/// Whether a track is loved and its stars, e.g. "♥ ★★★☆☆", `None` if it is neither
+fn rating_label(track: &Track) -> Option<String> {
+    let metadata = &track.metadata;
+    match (metadata.loved, metadata.rating) {
+        (false, 0) => None,
+        (true, 0) => Some("♥".to_string()),
+        (false, rating) => Some(stars(rating)),
+        (true, rating) => Some(format!("♥ {}", stars(rating))),
+    }
+}
+
+/// Button opening a menu to rate and love `track`, or add it to one of `playlists`
+fn track_menu(row: usize, track: Track, playlists: &[PlaylistSummary]) -> Popover {
+    Popover::new(("track_menu", row))
+        .trigger(Button::new(("track_menu_button", row)).ghost().label("⋯"))
+        .child(
+            div()
+                .v_flex()
+                .gap_1()
+                .child(div().text_sm().child("Rating"))
+                .child(
+                    div()
+                        .h_flex()
+                        .child(rating_buttons(format!("rate_{}", row), &track))
+                        .child(love_button(("love", row), &track)),
+                )
+                .when(!playlists.is_empty(), |menu| {
+                    menu.child(div().text_sm().child("Add to playlist"))
+                })
+                .children(playlists.iter().map(|playlist| {
+                    let playlist_id = playlist.id.clone();
+                    let track_id = track.id.clone();
+                    Button::new(SharedString::new(format!("add_to_{}", playlist.id)))
+                        .ghost()
+                        .label(playlist.name.clone())
+                        .on_click(move |_event, _window, _cx| {
+                            let playlist_id = playlist_id.clone();
+                            let track_id = track_id.clone();
+                            task::spawn(async move {
+                                let Some(library) = LIBRARY.get() else {
+                                    return;
+                                };
+                                if let Err(e) =
+                                    library.add_track_to_playlist(&playlist_id, &track_id).await
+                                {
+                                    eprintln!("Failed to add track to playlist: {}", e);
+                                }
+                            });
+                        })
+                })),
+        )
+}
+
 pub struct TrackList<T: RenderedTrack> {
     pub list_state: Entity<ListState<TrackListDelegate<T>>>,
 }
@@ -147,6 +382,9 @@ impl<T: RenderedTrack> TrackList<T> {
         let list_state = cx.new(|cx| ListState::new(delegate, window, cx));
         Self { list_state }
     }
+    pub fn delegate<'a>(&self, cx: &'a gpui::App) -> &'a TrackListDelegate<T> {
+        self.list_state.read(cx).delegate()
+    }
     pub fn update_delegate(
         &self,
         cx: &mut gpui::Context<'_, Self>,
@@ -188,6 +426,92 @@ impl RenderedTrack for Track {
     fn album_art(&self) -> Option<Vec<u8>> {
         self.album.album_art.clone()
     }
+
+    fn album_id(&self) -> Option<String> {
+        Some(self.album.id.clone())
+    }
+
+    fn library_track(&self) -> Option<Track> {
+        Some(self.clone())
+    }
+}
+
+impl RenderedTrack for PlaylistEntry {
+    fn artists_string(&self) -> String {
+        self.track.artists_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn title(&self) -> String {
+        self.track.title.clone()
+    }
+
+    fn album_art(&self) -> Option<Vec<u8>> {
+        self.track.album.album_art.clone()
+    }
+
+    fn album_id(&self) -> Option<String> {
+        Some(self.track.album.id.clone())
+    }
+
+    fn library_track(&self) -> Option<Track> {
+        Some(self.track.clone())
+    }
+}
+
+impl RenderedTrack for Play {
+    fn artists_string(&self) -> String {
+        self.track.artists_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn title(&self) -> String {
+        self.track.title.clone()
+    }
+
+    fn album_art(&self) -> Option<Vec<u8>> {
+        self.track.album.album_art.clone()
+    }
+
+    fn album_id(&self) -> Option<String> {
+        Some(self.track.album.id.clone())
+    }
+
+    fn library_track(&self) -> Option<Track> {
+        Some(self.track.clone())
+    }
+}
+
+impl RenderedTrack for TrackPlays {
+    fn artists_string(&self) -> String {
+        format!("{} · {} play(s)", self.track.artists_string(), self.plays)
+    }
+
+    fn id(&self) -> String {
+        self.track.id.clone()
+    }
+
+    fn title(&self) -> String {
+        self.track.title.clone()
+    }
+
+    fn album_art(&self) -> Option<Vec<u8>> {
+        self.track.album.album_art.clone()
+    }
+
+    fn album_id(&self) -> Option<String> {
+        Some(self.track.album.id.clone())
+    }
+
+    fn library_track(&self) -> Option<Track> {
+        Some(self.track.clone())
+    }
 }
 
 impl RenderedTrack for YtTrack {
@@ -0,0 +1,483 @@
+use std::f64::consts::PI;
+
+use anyhow::Result;
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+
+pub mod acoustid;
+
+/// Rate the audio is resampled to before fingerprinting
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+/// Frames overlap by two thirds
+const FRAME_STEP: usize = FRAME_SIZE / 3;
+/// Frequencies outside this range don't count towards the chroma
+const MIN_FREQUENCY: f64 = 28.0;
+const MAX_FREQUENCY: f64 = 3520.0;
+const BANDS: usize = 12;
+/// Smooths the chroma over 5 frames
+const CHROMA_FILTER: [f64; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+/// Chromaprint's id for the algorithm these fingerprints are compatible with
+const ALGORITHM: u8 = 1;
+
+/// Seconds of audio from the start of a file that are fingerprinted
+pub const MAX_SECONDS: usize = 120;
+/// Similarity above which two fingerprints are taken to be of the same recording.
+/// Unrelated audio is around 0.5, the same audio in other encodings well above 0.9.
+pub const MATCH_THRESHOLD: f64 = 0.8;
+/// How far apart, in fingerprint items (about 1/8 s each), the same audio can start
+const MAX_OFFSET: isize = 80;
+/// Fewest items that have to line up for two fingerprints to be compared
+const MIN_OVERLAP: usize = 40;
+
+/// Compares areas of the chroma image. `x` runs over time and `y` over the bands.
+#[derive(Clone, Copy)]
+struct Classifier {
+    kind: u8,
+    y: usize,
+    height: usize,
+    width: usize,
+    thresholds: [f64; 3],
+}
+
+const fn classifier(
+    kind: u8,
+    y: usize,
+    height: usize,
+    width: usize,
+    thresholds: [f64; 3],
+) -> Classifier {
+    Classifier {
+        kind,
+        y,
+        height,
+        width,
+        thresholds,
+    }
+}
+
+/// Chromaprint's default classifiers, each contributing two bits to an item, the first
+/// one the highest
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(0, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(4, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(1, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(3, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(3, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(4, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(1, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(5, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(3, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(3, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(1, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(3, 4, 2, 14, [-0.164292, -0.0321188, 0.0846339]),
+];
+
+/// Chromaprint-style acoustic fingerprint: one 32-bit item per 1/8 s or so, describing
+/// how the energy of each pitch class changes. It survives re-encoding, resampling and
+/// changes in volume, unlike a hash of the decoded samples.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fingerprint(pub Vec<u32>);
+
+impl Fingerprint {
+    /// Fingerprint interleaved samples, only the first `MAX_SECONDS` are used
+    pub fn from_samples(samples: &[f32], channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let limit = MAX_SECONDS * sample_rate as usize * channels;
+        let mono: Vec<f64> = samples[..samples.len().min(limit)]
+            .chunks(channels)
+            .map(|frame| frame.iter().map(|&s| s as f64).sum::<f64>() / frame.len() as f64)
+            // the thresholds below are tuned for 16-bit samples
+            .map(|sample| sample * i16::MAX as f64)
+            .collect();
+        let audio = resample(&mono, sample_rate);
+        let image = chroma_image(&audio);
+        Self(classify(&image))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// How alike two fingerprints are, from 0.5 for unrelated audio to 1.0 for the same
+    /// audio. The fingerprints are shifted against each other to line up the best.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let (a, b) = (&self.0, &other.0);
+        let mut best = 0.0;
+        for offset in -MAX_OFFSET..=MAX_OFFSET {
+            let (a_start, b_start) = if offset < 0 {
+                (0, offset.unsigned_abs())
+            } else {
+                (offset as usize, 0)
+            };
+            if a_start >= a.len() || b_start >= b.len() {
+                continue;
+            }
+            let overlap = (a.len() - a_start).min(b.len() - b_start);
+            if overlap < MIN_OVERLAP {
+                continue;
+            }
+            let errors: u32 = a[a_start..a_start + overlap]
+                .iter()
+                .zip(&b[b_start..b_start + overlap])
+                .map(|(x, y)| (x ^ y).count_ones())
+                .sum();
+            let score = 1.0 - errors as f64 / (32 * overlap) as f64;
+            if score > best {
+                best = score;
+            }
+        }
+        best
+    }
+
+    /// Whether two fingerprints are likely of the same recording
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        self.similarity(other) >= MATCH_THRESHOLD
+    }
+
+    /// Encode the items for storing in the library
+    pub fn encode(&self) -> String {
+        let bytes: Vec<u8> = self.0.iter().flat_map(|item| item.to_le_bytes()).collect();
+        BASE64_STANDARD.encode(bytes)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let bytes = BASE64_STANDARD.decode(encoded)?;
+        if bytes.len() % 4 != 0 {
+            return Err(anyhow::anyhow!("Fingerprint has a partial item"));
+        }
+        Ok(Self(
+            bytes
+                .chunks(4)
+                .map(|item| u32::from_le_bytes([item[0], item[1], item[2], item[3]]))
+                .collect(),
+        ))
+    }
+
+    /// Chromaprint's compressed form, as lookup services such as AcoustID expect it
+    pub fn compressed(&self) -> String {
+        // each item is stored as the positions of the bits that differ from the previous
+        // item: 3 bits per position, with positions of 7 and above continued in 5 bits
+        let mut normal = Vec::new();
+        let mut exceptional = Vec::new();
+        let mut previous = 0u32;
+        for &item in &self.0 {
+            let mut changed = item ^ previous;
+            let mut last_bit = 0;
+            let mut bit = 1;
+            while changed != 0 {
+                if changed & 1 == 1 {
+                    let delta = bit - last_bit;
+                    if delta >= 7 {
+                        normal.push(7);
+                        exceptional.push(delta - 7);
+                    } else {
+                        normal.push(delta);
+                    }
+                    last_bit = bit;
+                }
+                changed >>= 1;
+                bit += 1;
+            }
+            normal.push(0);
+            previous = item;
+        }
+        let length = self.0.len() as u32;
+        let mut bytes = vec![
+            ALGORITHM,
+            (length >> 16) as u8,
+            (length >> 8) as u8,
+            length as u8,
+        ];
+        bytes.extend(pack_bits(&normal, 3));
+        bytes.extend(pack_bits(&exceptional, 5));
+        BASE64_URL_SAFE_NO_PAD.encode(bytes)
+    }
+}
+
+/// What a fingerprinted recording is, according to a lookup service or the library
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identification {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    /// How sure the match is, from 0 to 1
+    pub score: f64,
+    /// Where the match came from, e.g. "AcoustID" or "Library"
+    pub source: String,
+}
+
+/// Service that identifies recordings by their fingerprint, such as AcoustID
+pub trait FingerprintLookup {
+    /// Recordings the audio could be, best match first. `duration` is the length of the
+    /// whole track in seconds.
+    fn lookup(
+        &self,
+        fingerprint: &Fingerprint,
+        duration: f64,
+    ) -> impl Future<Output = Result<Vec<Identification>>> + Send;
+}
+
+/// Finds nothing when there is no service, e.g. without an API key
+impl<L: FingerprintLookup + Sync> FingerprintLookup for Option<L> {
+    async fn lookup(
+        &self,
+        fingerprint: &Fingerprint,
+        duration: f64,
+    ) -> Result<Vec<Identification>> {
+        match self {
+            Some(lookup) => lookup.lookup(fingerprint, duration).await,
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Pack values of `bits` bits each, lowest bits first
+fn pack_bits(values: &[u32], bits: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((values.len() * bits as usize).div_ceil(8));
+    let mut buffer = 0u32;
+    let mut buffered = 0;
+    for &value in values {
+        buffer |= (value & ((1 << bits) - 1)) << buffered;
+        buffered += bits;
+        while buffered >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            buffered -= 8;
+        }
+    }
+    if buffered > 0 {
+        bytes.push(buffer as u8);
+    }
+    bytes
+}
+
+/// Resample mono audio to `SAMPLE_RATE`, averaging the samples each output sample
+/// covers so higher frequencies don't fold back into the range that is fingerprinted
+fn resample(samples: &[f64], sample_rate: u32) -> Vec<f64> {
+    if sample_rate == SAMPLE_RATE || sample_rate == 0 {
+        return samples.to_vec();
+    }
+    let step = sample_rate as f64 / SAMPLE_RATE as f64;
+    let length = (samples.len() as f64 / step) as usize;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            if step <= 1.0 {
+                let index = position as usize;
+                let fraction = position - index as f64;
+                let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+                samples[index] * (1.0 - fraction) + next * fraction
+            } else {
+                let start = position as usize;
+                let end = (((i + 1) as f64 * step) as usize).clamp(start + 1, samples.len());
+                samples[start..end].iter().sum::<f64>() / (end - start) as f64
+            }
+        })
+        .collect()
+}
+
+/// In-place radix-2 FFT, `real.len()` has to be a power of two
+fn fft(real: &mut [f64], imaginary: &mut [f64]) {
+    let n = real.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + length / 2);
+                let t_real = real[b] * cos - imaginary[b] * sin;
+                let t_imaginary = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - t_real;
+                imaginary[b] = imaginary[a] - t_imaginary;
+                real[a] += t_real;
+                imaginary[a] += t_imaginary;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// Energy of each pitch class per frame, smoothed over time and normalized
+fn chroma_image(audio: &[f64]) -> Vec<[f64; BANDS]> {
+    let window: Vec<f64> = (0..FRAME_SIZE)
+        .map(|i| 0.54 - 0.46 * (2.0 * PI * i as f64 / (FRAME_SIZE - 1) as f64).cos())
+        .collect();
+    let index =
+        |frequency: f64| (FRAME_SIZE as f64 * frequency / SAMPLE_RATE as f64).round() as usize;
+    let (min_index, max_index) = (
+        index(MIN_FREQUENCY).max(1),
+        index(MAX_FREQUENCY).min(FRAME_SIZE / 2),
+    );
+    // pitch class of each frequency bin, counted from A0
+    let bands: Vec<usize> = (min_index..max_index)
+        .map(|i| {
+            let frequency = i as f64 * SAMPLE_RATE as f64 / FRAME_SIZE as f64;
+            let octave = (frequency / (440.0 / 16.0)).log2();
+            ((BANDS as f64 * (octave - octave.floor())) as usize).min(BANDS - 1)
+        })
+        .collect();
+
+    let mut chroma = Vec::new();
+    let mut start = 0;
+    while start + FRAME_SIZE <= audio.len() {
+        let mut real: Vec<f64> = audio[start..start + FRAME_SIZE]
+            .iter()
+            .zip(&window)
+            .map(|(sample, weight)| sample * weight)
+            .collect();
+        let mut imaginary = vec![0.0; FRAME_SIZE];
+        fft(&mut real, &mut imaginary);
+        let mut row = [0.0; BANDS];
+        for (offset, &band) in bands.iter().enumerate() {
+            let i = min_index + offset;
+            row[band] += real[i] * real[i] + imaginary[i] * imaginary[i];
+        }
+        chroma.push(row);
+        start += FRAME_STEP;
+    }
+
+    chroma
+        .windows(CHROMA_FILTER.len())
+        .map(|frames| {
+            let mut row = [0.0; BANDS];
+            for (frame, weight) in frames.iter().zip(CHROMA_FILTER) {
+                for band in 0..BANDS {
+                    row[band] += frame[band] * weight;
+                }
+            }
+            let norm = row.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm < 0.01 {
+                [0.0; BANDS]
+            } else {
+                row.map(|v| v / norm)
+            }
+        })
+        .collect()
+}
+
+/// Turn the chroma image into fingerprint items, one per position the widest
+/// classifier fits at
+fn classify(image: &[[f64; BANDS]]) -> Vec<u32> {
+    // integral[x][y] is the sum of the image above and left of (x, y)
+    let mut integral = vec![[0.0; BANDS + 1]; image.len() + 1];
+    for (x, row) in image.iter().enumerate() {
+        for y in 0..BANDS {
+            integral[x + 1][y + 1] =
+                row[y] + integral[x][y + 1] + integral[x + 1][y] - integral[x][y];
+        }
+    }
+    let area = |x1: usize, y1: usize, x2: usize, y2: usize| {
+        integral[x2][y2] - integral[x1][y2] - integral[x2][y1] + integral[x1][y1]
+    };
+    let compare = |a: f64, b: f64| (1.0 + a).ln() - (1.0 + b).ln();
+
+    let widest = CLASSIFIERS.iter().map(|c| c.width).max().unwrap_or(1);
+    if image.len() < widest {
+        return Vec::new();
+    }
+    (0..=image.len() - widest)
+        .map(|x| {
+            let mut item = 0u32;
+            for c in &CLASSIFIERS {
+                let (y, w, h) = (c.y, c.width, c.height);
+                let value = match c.kind {
+                    0 => compare(area(x, y, x + w, y + h), 0.0),
+                    1 => {
+                        let h2 = h / 2;
+                        compare(area(x, y + h2, x + w, y + h), area(x, y, x + w, y + h2))
+                    }
+                    2 => {
+                        let w2 = w / 2;
+                        compare(area(x + w2, y, x + w, y + h), area(x, y, x + w2, y + h))
+                    }
+                    3 => {
+                        let (w2, h2) = (w / 2, h / 2);
+                        compare(
+                            area(x, y + h2, x + w2, y + h) + area(x + w2, y, x + w, y + h2),
+                            area(x, y, x + w2, y + h2) + area(x + w2, y + h2, x + w, y + h),
+                        )
+                    }
+                    4 => {
+                        let h3 = h / 3;
+                        compare(
+                            area(x, y + h3, x + w, y + 2 * h3),
+                            area(x, y, x + w, y + h3) + area(x, y + 2 * h3, x + w, y + h),
+                        )
+                    }
+                    _ => {
+                        let w3 = w / 3;
+                        compare(
+                            area(x + w3, y, x + 2 * w3, y + h),
+                            area(x, y, x + w3, y + h) + area(x + 2 * w3, y, x + w, y + h),
+                        )
+                    }
+                };
+                let [low, middle, high] = c.thresholds;
+                let quantized = match value {
+                    v if v < low => 0,
+                    v if v < middle => 1,
+                    v if v < high => 2,
+                    _ => 3,
+                };
+                // Gray code, so neighbouring levels differ in one bit
+                item = (item << 2) | [0, 1, 3, 2][quantized];
+            }
+            item
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_audio::tune;
+
+    #[test]
+    fn fingerprints_survive_resampling_and_volume() {
+        let stereo: Vec<f32> = tune(1, 30, 44100).iter().flat_map(|&s| [s, s]).collect();
+        let original = Fingerprint::from_samples(&stereo, 2, 44100);
+        // the same tune in quieter mono at half the sample rate
+        let quieter: Vec<f32> = tune(1, 30, 22050).iter().map(|s| s * 0.5).collect();
+        let copy = Fingerprint::from_samples(&quieter, 1, 22050);
+        let other = Fingerprint::from_samples(&tune(2, 30, 44100), 1, 44100);
+        assert!(!original.is_empty());
+        assert!(original.matches(&copy));
+        assert!(!original.matches(&other));
+        assert!(original.similarity(&copy) > original.similarity(&other));
+    }
+
+    #[test]
+    fn fingerprints_encode_and_decode() {
+        let fingerprint = Fingerprint(vec![0, 1, 0x8000_0001, u32::MAX]);
+        let decoded = Fingerprint::decode(&fingerprint.encode()).expect("Failed to decode");
+        assert_eq!(decoded, fingerprint);
+        assert!(Fingerprint::decode(&BASE64_STANDARD.encode([1, 2, 3])).is_err());
+
+        let compressed = BASE64_URL_SAFE_NO_PAD
+            .decode(fingerprint.compressed())
+            .expect("Not base64");
+        // the algorithm, then the number of items in 24 bits
+        assert_eq!(compressed[..4], [ALGORITHM, 0, 0, 4]);
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use reqwest::Url;
+
+use crate::{
+    fingerprint::{Fingerprint, FingerprintLookup, Identification},
+    lyrics::get_client,
+};
+
+/// Looks up fingerprints in the AcoustID database, which links them to MusicBrainz
+/// recordings
+pub struct AcoustId {
+    /// Application key from https://acoustid.org/new-application
+    pub api_key: String,
+}
+
+impl FingerprintLookup for AcoustId {
+    async fn lookup(
+        &self,
+        fingerprint: &Fingerprint,
+        duration: f64,
+    ) -> Result<Vec<Identification>> {
+        let mut url =
+            Url::parse("https://api.acoustid.org/v2/lookup").expect("This URL should parse");
+        url.query_pairs_mut()
+            .append_pair("client", &self.api_key)
+            .append_pair("meta", "recordings releasegroups")
+            .append_pair("duration", &(duration.round() as u64).to_string())
+            .append_pair("fingerprint", &fingerprint.compressed());
+        let client = get_client()?;
+        let request = client.get(url).send().await?;
+        if !request.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to look up fingerprint on AcoustID: HTTP {}",
+                request.status()
+            ));
+        }
+        let json = serde_json::from_str::<serde_json::Value>(&request.text().await?)?;
+        if json.get("status").and_then(|s| s.as_str()) != Some("ok") {
+            let message = json
+                .get("error")
+                .and_then(|e| e.get("message"))
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error");
+            return Err(anyhow::anyhow!("AcoustID lookup failed: {}", message));
+        }
+        let results = json
+            .get("results")
+            .and_then(|r| r.as_array())
+            .ok_or(anyhow::anyhow!("Invalid response format from AcoustID"))?;
+
+        let mut identifications = Vec::new();
+        for result in results {
+            let score = result.get("score").and_then(|s| s.as_f64()).unwrap_or(0.0);
+            let recordings = result.get("recordings").and_then(|r| r.as_array());
+            for recording in recordings.into_iter().flatten() {
+                // recordings without metadata are only known by their id
+                let Some(title) = recording.get("title").and_then(|t| t.as_str()) else {
+                    continue;
+                };
+                let artists = recording
+                    .get("artists")
+                    .and_then(|a| a.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|artist| artist.get("name").and_then(|n| n.as_str()))
+                    .map(String::from)
+                    .collect();
+                let album = recording
+                    .get("releasegroups")
+                    .and_then(|r| r.as_array())
+                    .and_then(|groups| groups.first())
+                    .and_then(|group| group.get("title"))
+                    .and_then(|t| t.as_str())
+                    .map(String::from);
+                identifications.push(Identification {
+                    title: title.to_string(),
+                    artists,
+                    album,
+                    score,
+                    source: "AcoustID".to_string(),
+                });
+            }
+        }
+        identifications.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(identifications)
+    }
+}
@@ -1,26 +1,81 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
 
 use anyhow::Result;
+use chrono::Utc;
 use once_cell::sync::OnceCell;
 use rodio::{Decoder, Source};
 use serde::{Deserialize, Serialize};
 use tokio::{
     fs,
     sync::{
-        broadcast::{channel, Sender as BroadcastSender},
+        broadcast::{
+            Receiver as BroadcastReceiver, Sender as BroadcastSender, channel, error::RecvError,
+        },
         mpsc, oneshot,
     },
+    task,
 };
-use turso::{Builder, Connection, Value};
+use turso::{Builder, Connection, Value, params::Params};
 use ulid::Ulid;
 
-use crate::providers::youtube;
+use crate::{
+    library::{
+        art::{self, ArtSize, ArtVersions},
+        search::IndexState,
+        smart::SmartRules,
+        sort::sort_key,
+    },
+    providers::{cue::CueSource, youtube},
+};
+
+pub mod art;
+pub mod backup;
+pub mod duplicates;
+pub mod edit;
+pub mod health;
+pub mod history;
+pub mod identify;
+pub mod import;
+pub mod playlist_file;
+pub mod rating;
+pub mod search;
+pub mod smart;
+pub mod sort;
 
 pub static LIBRARY: OnceCell<Library> = OnceCell::new();
 
 #[derive(Debug, Clone)]
 pub enum LibraryEvent {
     TracksAdded(Vec<Track>),
+    TracksUpdated(Vec<Track>),
+    /// A playlist was created, renamed, deleted or had its tracks changed
+    PlaylistsChanged,
+    /// The track with this id was played or skipped, changing its listening statistics
+    PlaysRecorded(String),
+    /// The whole library was replaced by a restored backup
+    Restored,
+}
+
+/// Library events as a subscriber receives them
+pub struct LibraryEvents(BroadcastReceiver<LibraryEvent>);
+
+impl LibraryEvents {
+    /// The next event, `None` once the library is gone. A subscriber that fell behind
+    /// skips the events it missed instead of stopping, e.g. after a large import.
+    pub async fn recv(&mut self) -> Option<LibraryEvent> {
+        loop {
+            match self.0.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
 }
 
 enum DbCommand {
@@ -41,6 +96,22 @@ enum DbCommand {
         params: Vec<Value>,
         respond_to: oneshot::Sender<Result<Vec<Vec<Value>>, String>>,
     },
+    /// Runs all statements between `BEGIN` and `COMMIT`, rolling back if any of them fails.
+    /// Nothing else can run on the connection in between.
+    Transaction {
+        statements: Vec<(String, Vec<Value>)>,
+        respond_to: oneshot::Sender<Result<(), String>>,
+    },
+}
+
+fn optional_text(value: &Option<String>) -> Value {
+    value.clone().map(Value::Text).unwrap_or(Value::Null)
+}
+
+fn optional_integer(value: Option<impl Into<i64>>) -> Value {
+    value
+        .map(|v| Value::Integer(v.into()))
+        .unwrap_or(Value::Null)
 }
 
 async fn row_to_values(row: &turso::Row) -> Result<Vec<Value>> {
@@ -100,23 +171,253 @@ CREATE TABLE IF NOT EXISTS track_artists (
     FOREIGN KEY (artist_id) REFERENCES artists(id) ON DELETE CASCADE
 );
 
-CREATE TABLE IF NOT EXISTS playlist_tracks (
-    playlist_id TEXT NOT NULL,
-    track_id TEXT NOT NULL,
-    position INTEGER,
-    added_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-    PRIMARY KEY (playlist_id, track_id),
-    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
-    FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
-);
-
 CREATE INDEX IF NOT EXISTS idx_tracks_album ON tracks(album_id);
 CREATE INDEX IF NOT EXISTS idx_tracks_source ON tracks(source);
-CREATE INDEX IF NOT EXISTS idx_playlist_tracks_position ON playlist_tracks(playlist_id, position);
 CREATE INDEX IF NOT EXISTS idx_album_artists_artist ON album_artists(artist_id);
 CREATE INDEX IF NOT EXISTS idx_track_artists_artist ON track_artists(artist_id);
+
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL
+);
 "#;
 
+/// Schema changes applied on top of `CREATE_DB`, in order. The number of applied
+/// migrations is stored in `schema_version`, so only ever append to this list.
+/// Statements are split on `;`, so they can't contain one in a string.
+const MIGRATIONS: &[&str] = &[
+    // file size and content hash, used to find local files that have been moved
+    r#"
+    ALTER TABLE tracks ADD COLUMN file_size INTEGER;
+    ALTER TABLE tracks ADD COLUMN content_hash TEXT;
+    CREATE INDEX IF NOT EXISTS idx_tracks_content_hash ON tracks(content_hash);
+    "#,
+    // date added and play count, used as sort keys for paginated queries
+    r#"
+    ALTER TABLE tracks ADD COLUMN added_at INTEGER;
+    ALTER TABLE tracks ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX IF NOT EXISTS idx_tracks_title ON tracks(title, id);
+    CREATE INDEX IF NOT EXISTS idx_tracks_duration ON tracks(duration, id);
+    CREATE INDEX IF NOT EXISTS idx_tracks_added_at ON tracks(added_at, id);
+    CREATE INDEX IF NOT EXISTS idx_tracks_play_count ON tracks(play_count, id);
+    "#,
+    // extended tag metadata, audio properties and genres
+    r#"
+    ALTER TABLE tracks ADD COLUMN disc_number INTEGER;
+    ALTER TABLE tracks ADD COLUMN disc_total INTEGER;
+    ALTER TABLE tracks ADD COLUMN track_total INTEGER;
+    ALTER TABLE tracks ADD COLUMN composer TEXT;
+    ALTER TABLE tracks ADD COLUMN isrc TEXT;
+    ALTER TABLE tracks ADD COLUMN comment TEXT;
+    ALTER TABLE tracks ADD COLUMN bitrate INTEGER;
+    ALTER TABLE tracks ADD COLUMN sample_rate INTEGER;
+    ALTER TABLE tracks ADD COLUMN channels INTEGER;
+    ALTER TABLE tracks ADD COLUMN codec TEXT;
+    ALTER TABLE albums ADD COLUMN release_date TEXT;
+    ALTER TABLE albums ADD COLUMN label TEXT;
+    CREATE TABLE IF NOT EXISTS track_genres (
+        track_id TEXT NOT NULL,
+        genre TEXT NOT NULL,
+        PRIMARY KEY (track_id, genre),
+        FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS idx_track_genres_genre ON track_genres(genre);
+    "#,
+    // sort names from tags and the keys derived from them, filled in by `backfill_sort_keys`
+    r#"
+    ALTER TABLE artists ADD COLUMN sort_name TEXT;
+    ALTER TABLE artists ADD COLUMN sort_key TEXT;
+    ALTER TABLE albums ADD COLUMN sort_title TEXT;
+    ALTER TABLE albums ADD COLUMN sort_key TEXT;
+    ALTER TABLE tracks ADD COLUMN sort_title TEXT;
+    ALTER TABLE tracks ADD COLUMN sort_key TEXT;
+    CREATE INDEX IF NOT EXISTS idx_artists_sort_key ON artists(sort_key);
+    CREATE INDEX IF NOT EXISTS idx_albums_sort_key ON albums(sort_key);
+    CREATE INDEX IF NOT EXISTS idx_tracks_sort_key ON tracks(sort_key, id);
+    "#,
+    // playlist entries get their own id so a track can be in a playlist more than once.
+    // Existing entries are copied over from `playlist_tracks`, which is then dropped.
+    // New databases never had the old table, so it is created empty for them first.
+    r#"
+    CREATE TABLE IF NOT EXISTS playlist_tracks (
+        playlist_id TEXT NOT NULL,
+        track_id TEXT NOT NULL,
+        position INTEGER,
+        added_at DATETIME DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE TABLE IF NOT EXISTS playlist_entries (
+        id TEXT PRIMARY KEY,
+        playlist_id TEXT NOT NULL,
+        track_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        added_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+        FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS idx_playlist_entries_position ON playlist_entries(playlist_id, position);
+    CREATE INDEX IF NOT EXISTS idx_playlist_entries_track ON playlist_entries(track_id);
+    INSERT INTO playlist_entries (id, playlist_id, track_id, position, added_at)
+        SELECT playlist_id || ':' || track_id, playlist_id, track_id, COALESCE(position, 0), added_at
+        FROM playlist_tracks;
+    DROP INDEX IF EXISTS idx_playlist_tracks_position;
+    DROP TABLE playlist_tracks;
+    "#,
+    // rules of smart playlists as JSON, NULL for playlists with stored entries
+    r#"
+    ALTER TABLE playlists ADD COLUMN rules TEXT;
+    "#,
+    // listening history, one row per play, and the statistics kept on each track
+    r#"
+    CREATE TABLE IF NOT EXISTS plays (
+        id TEXT PRIMARY KEY,
+        track_id TEXT NOT NULL,
+        played_at INTEGER NOT NULL,
+        FOREIGN KEY (track_id) REFERENCES tracks(id) ON DELETE CASCADE
+    );
+    CREATE INDEX IF NOT EXISTS idx_plays_played_at ON plays(played_at);
+    CREATE INDEX IF NOT EXISTS idx_plays_track ON plays(track_id, played_at);
+    ALTER TABLE tracks ADD COLUMN last_played INTEGER;
+    ALTER TABLE tracks ADD COLUMN skip_count INTEGER NOT NULL DEFAULT 0;
+    "#,
+    // star ratings (0 is unrated) and favourites
+    r#"
+    ALTER TABLE tracks ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE tracks ADD COLUMN loved INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX IF NOT EXISTS idx_tracks_rating ON tracks(rating, id);
+    "#,
+    // hash of the decoded audio, filled in when looking for duplicates
+    r#"
+    ALTER TABLE tracks ADD COLUMN audio_hash TEXT;
+    CREATE INDEX IF NOT EXISTS idx_tracks_audio_hash ON tracks(audio_hash);
+    "#,
+    // acoustic fingerprint, filled in when looking for duplicates or identifying a track
+    r#"
+    ALTER TABLE tracks ADD COLUMN fingerprint TEXT;
+    "#,
+    // part of the file a track from a CUE sheet covers
+    r#"
+    ALTER TABLE tracks ADD COLUMN cue_start REAL;
+    ALTER TABLE tracks ADD COLUMN cue_end REAL;
+    "#,
+    // lookups by path and case-insensitive title, which match playlist entries and
+    // tracks from other players to the library
+    r#"
+    CREATE INDEX IF NOT EXISTS idx_tracks_path ON tracks(path);
+    CREATE INDEX IF NOT EXISTS idx_tracks_title_nocase ON tracks(title COLLATE NOCASE);
+    "#,
+];
+
+/// Columns selected for every track, in the order `Library::row_to_track` expects
+const TRACK_COLUMNS: &str = "t.id, t.title, t.album_id, t.duration, t.path, t.source, t.source_id, t.track_number, t.file_size, t.content_hash, al.title, al.release_year, \
+     t.disc_number, t.disc_total, t.track_total, t.composer, t.isrc, t.comment, \
+     t.bitrate, t.sample_rate, t.channels, t.codec, al.release_date, al.label, t.sort_title, al.sort_title, \
+     t.rating, t.loved, t.cue_start, t.cue_end";
+
+/// Tracks are always read together with their album, so queries can filter and sort on either
+const TRACK_FROM: &str = "FROM tracks t INNER JOIN albums al ON al.id = t.album_id";
+
+/// Number of columns in `TRACK_COLUMNS`
+const TRACK_COLUMN_COUNT: usize = 30;
+
+/// Maximum number of values bound in a single `IN (...)` list
+const MAX_IN_PARAMS: usize = 500;
+
+/// Number of albums whose cover art is kept in memory
+const ALBUM_ART_CACHE_SIZE: usize = 512;
+
+/// Keys paginated track queries can be sorted by
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TrackSort {
+    #[default]
+    Title,
+    Artist,
+    Album,
+    Year,
+    DateAdded,
+    PlayCount,
+    Rating,
+    Duration,
+}
+
+impl TrackSort {
+    pub const ALL: [TrackSort; 8] = [
+        TrackSort::Title,
+        TrackSort::Artist,
+        TrackSort::Album,
+        TrackSort::Year,
+        TrackSort::DateAdded,
+        TrackSort::PlayCount,
+        TrackSort::Rating,
+        TrackSort::Duration,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TrackSort::Title => "Title",
+            TrackSort::Artist => "Artist",
+            TrackSort::Album => "Album",
+            TrackSort::Year => "Year",
+            TrackSort::DateAdded => "Date added",
+            TrackSort::PlayCount => "Play count",
+            TrackSort::Rating => "Rating",
+            TrackSort::Duration => "Duration",
+        }
+    }
+
+    /// SQL expression for the sort key. Never NULL, so it can be compared against a cursor.
+    fn expression(&self) -> &'static str {
+        match self {
+            TrackSort::Title => "t.sort_key",
+            TrackSort::Artist => {
+                "COALESCE((SELECT MIN(sa.sort_key) FROM track_artists st
+                 INNER JOIN artists sa ON sa.id = st.artist_id
+                 WHERE st.track_id = t.id), '')"
+            }
+            TrackSort::Album => "al.sort_key",
+            TrackSort::Year => "COALESCE(al.release_year, 0)",
+            TrackSort::DateAdded => "COALESCE(t.added_at, 0)",
+            TrackSort::PlayCount => "t.play_count",
+            TrackSort::Rating => "t.rating",
+            TrackSort::Duration => "t.duration",
+        }
+    }
+}
+
+/// Restricts which tracks paginated queries return
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrackFilter {
+    /// Only tracks rated at least this many stars
+    pub min_rating: u8,
+    pub loved_only: bool,
+}
+
+impl TrackFilter {
+    /// SQL conditions on `t` (tracks) for the filter, `None` if it lets every track through
+    fn conditions(&self) -> Option<String> {
+        let mut conditions = Vec::new();
+        if self.min_rating > 0 {
+            conditions.push(format!("t.rating >= {}", self.min_rating));
+        }
+        if self.loved_only {
+            conditions.push("t.loved = 1".to_string());
+        }
+        (!conditions.is_empty()).then(|| conditions.join(" AND "))
+    }
+}
+
+/// Position after the last track of a page, pass it back to get the next page
+#[derive(Clone, Debug)]
+pub struct TrackCursor {
+    sort_value: Value,
+    id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackPage {
+    pub tracks: Vec<Track>,
+    /// `None` when this is the last page
+    pub next: Option<TrackCursor>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum TrackSource {
     Local,
@@ -151,9 +452,63 @@ pub struct Track {
     pub source: TrackSource,
     pub source_id: Option<String>,
     pub track_number: Option<i32>,
+    pub file_size: Option<u64>,
+    pub content_hash: Option<String>,
+    pub metadata: TrackMetadata,
+    pub audio: AudioProperties,
+    /// Part of the file the track covers, when it comes from a CUE sheet
+    pub cue: Option<CueRange>,
+}
+
+/// Tag metadata beyond what is needed to list and play a track
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrackMetadata {
+    pub genres: Vec<String>,
+    pub disc_number: Option<i32>,
+    pub disc_total: Option<i32>,
+    pub track_total: Option<i32>,
+    pub composer: Option<String>,
+    pub isrc: Option<String>,
+    pub comment: Option<String>,
+    /// Title to sort by instead of `title`, from the file's tags
+    pub sort_title: Option<String>,
+    /// Stars from 0 (unrated) to `rating::MAX_RATING`
+    pub rating: u8,
+    pub loved: bool,
+}
+
+/// Technical properties of a track's audio stream
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct AudioProperties {
+    /// Audio bitrate in kbps
+    pub bitrate: Option<u32>,
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub codec: Option<String>,
+}
+
+/// Part of a file, for albums ripped to a single file with a CUE sheet
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CueRange {
+    /// Seconds into the file the track starts at
+    pub start: f64,
+    /// Seconds into the file the track ends at
+    pub end: f64,
 }
 
 impl Track {
+    /// Whether this track starts in the same file where `previous` ends, as consecutive
+    /// tracks of a CUE sheet do
+    pub fn follows(&self, previous: &Track) -> bool {
+        match (self.cue, previous.cue) {
+            (Some(range), Some(previous_range)) => {
+                self.path == previous.path && (range.start - previous_range.end).abs() < 0.001
+            }
+            _ => false,
+        }
+    }
+
     pub fn artists_string(&self) -> String {
         self.artists
             .iter()
@@ -167,6 +522,8 @@ impl Track {
 pub struct Artist {
     pub id: String,
     pub name: String,
+    /// Name to sort by instead of `name`, from the file's tags
+    pub sort_name: Option<String>,
 }
 
 impl Artist {
@@ -174,10 +531,20 @@ impl Artist {
         Self {
             id: Ulid::new().to_string(),
             name,
+            sort_name: None,
         }
     }
 }
 
+/// An artist together with how much of the library they appear on
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtistSummary {
+    pub artist: Artist,
+    pub track_count: usize,
+    /// Albums credited to the artist plus albums with at least one track by them
+    pub album_count: usize,
+}
+
 impl ToString for Artist {
     fn to_string(&self) -> String {
         self.name.clone()
@@ -190,7 +557,14 @@ pub struct Album {
     pub title: String,
     pub artists: Vec<Artist>,
     pub release_year: Option<i32>,
-    pub album_art: Option<Vec<u8>>, // binary image data
+    /// Full release date as tagged, e.g. "1997-05-21" (may be just a year)
+    pub release_date: Option<String>,
+    pub label: Option<String>,
+    /// Title to sort by instead of `title`, from the file's tags
+    pub sort_title: Option<String>,
+    /// Binary image data. Only carried by albums that are not in the library yet,
+    /// use `Album::art` to get the cover of any album.
+    pub album_art: Option<Vec<u8>>,
 }
 
 impl Album {
@@ -205,11 +579,38 @@ impl Album {
             title,
             artists,
             release_year,
+            release_date: None,
+            label: None,
+            sort_title: None,
             album_art,
         }
     }
 }
 
+impl Album {
+    /// The album's cover art, either carried inline or loaded lazily from the library
+    pub async fn art(&self) -> Option<Arc<Vec<u8>>> {
+        if let Some(art) = &self.album_art {
+            return Some(Arc::new(art.clone()));
+        }
+        LIBRARY.get()?.album_art(&self.id).await.ok().flatten()
+    }
+
+    /// The album's cover art scaled down to `size`
+    pub async fn thumbnail(&self, size: ArtSize) -> Option<Arc<Vec<u8>>> {
+        if let Some(art) = self.album_art.clone() {
+            let thumbnail = task::spawn_blocking(move || art::thumbnail(&art, size.pixels()));
+            return thumbnail.await.ok().map(Arc::new);
+        }
+        LIBRARY
+            .get()?
+            .album_thumbnail(&self.id, size)
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
 impl ToString for Album {
     fn to_string(&self) -> String {
         self.title.clone()
@@ -221,7 +622,26 @@ pub struct Playlist {
     pub id: String,
     pub name: String,
     pub description: Option<String>,
-    pub tracks: Vec<Track>,
+    pub entries: Vec<PlaylistEntry>,
+    /// Set for smart playlists, whose entries are the tracks matching the rules
+    pub rules: Option<SmartRules>,
+}
+
+/// A track at one position in a playlist. The same track can be in a playlist several
+/// times, the entry id tells them apart.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PlaylistEntry {
+    pub id: String,
+    pub track: Track,
+}
+
+/// A playlist without its tracks, for listing playlists
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaylistSummary {
+    pub id: String,
+    pub name: String,
+    pub track_count: usize,
+    pub smart: bool,
 }
 
 impl Playlist {
@@ -230,15 +650,32 @@ impl Playlist {
             id: Ulid::new().to_string(),
             name,
             description,
-            tracks: Vec::new(),
+            entries: Vec::new(),
+            rules: None,
         }
     }
+
+    /// The playlist's tracks in order
+    pub fn tracks(&self) -> Vec<Track> {
+        self.entries
+            .iter()
+            .map(|entry| entry.track.clone())
+            .collect()
+    }
 }
 
 #[derive(Debug)]
 pub struct Library {
     db_sender: mpsc::Sender<DbCommand>,
     event_sender: BroadcastSender<LibraryEvent>,
+    search_index: RwLock<IndexState>,
+    /// Held while the search index is built
+    search_build: tokio::sync::Mutex<()>,
+    album_art_cache: Mutex<HashMap<String, Option<Arc<Vec<u8>>>>>,
+    /// How often album art changed while running, see `art_version`
+    art_versions: Mutex<ArtVersions>,
+    /// Directory the scaled album art is cached in, one subdirectory per size
+    thumbnail_dir: PathBuf,
 }
 
 async fn db_worker(mut rx: mpsc::Receiver<DbCommand>, connection: Connection) {
@@ -255,28 +692,12 @@ async fn db_worker(mut rx: mpsc::Receiver<DbCommand>, connection: Connection) {
                 let result = connection.cacheflush().map_err(|e| e.to_string());
                 let _ = respond_to.send(result);
             }
-            // these are a bit ugly and could use some macros 
-            // but turso's execute/query methods don't take slices directly
             DbCommand::Execute {
                 sql,
                 params,
                 respond_to,
             } => {
-                let result = match params.len() {
-                    0 => connection.execute(&sql, ()).await,
-                    1 => connection.execute(&sql, [params[0].clone()]).await,
-                    2 => connection.execute(&sql, [params[0].clone(), params[1].clone()]).await,
-                    3 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone()]).await,
-                    4 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone()]).await,
-                    5 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone()]).await,
-                    6 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone()]).await,
-                    7 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone(), params[6].clone()]).await,
-                    8 => connection.execute(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone(), params[6].clone(), params[7].clone()]).await,
-                    _ => {
-                        let _ = respond_to.send(Err("Too many parameters".to_string()));
-                        continue;
-                    }
-                };
+                let result = connection.execute(&sql, Params::Positional(params)).await;
                 let _ = respond_to.send(result.map(|_| ()).map_err(|e| e.to_string()));
             }
             DbCommand::Query {
@@ -284,22 +705,7 @@ async fn db_worker(mut rx: mpsc::Receiver<DbCommand>, connection: Connection) {
                 params,
                 respond_to,
             } => {
-                let result = match params.len() {
-                    0 => connection.query(&sql, ()).await,
-                    1 => connection.query(&sql, [params[0].clone()]).await,
-                    2 => connection.query(&sql, [params[0].clone(), params[1].clone()]).await,
-                    3 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone()]).await,
-                    4 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone()]).await,
-                    5 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone()]).await,
-                    6 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone()]).await,
-                    7 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone(), params[6].clone()]).await,
-                    8 => connection.query(&sql, [params[0].clone(), params[1].clone(), params[2].clone(), params[3].clone(), params[4].clone(), params[5].clone(), params[6].clone(), params[7].clone()]).await,
-                    _ => {
-                        let _ = respond_to.send(Err("Too many parameters".to_string()));
-                        continue;
-                    }
-                };
-                
+                let result = connection.query(&sql, Params::Positional(params)).await;
                 match result {
                     Ok(mut rows) => {
                         let mut all_rows = Vec::new();
@@ -321,8 +727,37 @@ async fn db_worker(mut rx: mpsc::Receiver<DbCommand>, connection: Connection) {
                     }
                 }
             }
+            DbCommand::Transaction {
+                statements,
+                respond_to,
+            } => {
+                let _ = respond_to.send(run_transaction(&connection, statements).await);
+            }
+        }
+    }
+}
+
+async fn run_transaction(
+    connection: &Connection,
+    statements: Vec<(String, Vec<Value>)>,
+) -> Result<(), String> {
+    connection
+        .execute("BEGIN", ())
+        .await
+        .map_err(|e| e.to_string())?;
+    for (sql, params) in statements {
+        if let Err(e) = connection.execute(&sql, Params::Positional(params)).await {
+            if let Err(rollback) = connection.execute("ROLLBACK", ()).await {
+                eprintln!("Failed to roll back transaction: {}", rollback);
+            }
+            return Err(e.to_string());
         }
     }
+    connection
+        .execute("COMMIT", ())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
 }
 
 impl Library {
@@ -337,6 +772,11 @@ impl Library {
                 .ok_or(anyhow::anyhow!("Could not find parent directory"))?,
         )
         .await?;
+        Self::open(&db_path).await
+    }
+
+    /// Open (or create) a library database at the given path
+    pub async fn open(db_path: &Path) -> anyhow::Result<Self> {
         let connection =
             Builder::new_local(db_path.to_str().ok_or(anyhow::anyhow!("Invalid path"))?)
                 .build()
@@ -344,26 +784,90 @@ impl Library {
                 .connect()?;
         let (tx, rx) = mpsc::channel::<DbCommand>(100);
         tokio::spawn(db_worker(rx, connection));
-        let (respond_tx, respond_rx) = oneshot::channel();
-        tx.send(DbCommand::ExecuteBatch {
-            sql: CREATE_DB.to_string(),
-            respond_to: respond_tx,
-        })
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to send command: {}", e))?;
-        respond_rx
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))?
-            .map_err(|e| anyhow::anyhow!("Failed to create database: {}", e))?;
         let (event_sender, _) = channel::<LibraryEvent>(25);
-        Ok(Self {
+        let library = Self {
             db_sender: tx,
             event_sender,
-        })
+            search_index: RwLock::new(IndexState::Unbuilt),
+            search_build: tokio::sync::Mutex::new(()),
+            album_art_cache: Mutex::new(HashMap::new()),
+            art_versions: Mutex::new(ArtVersions::default()),
+            thumbnail_dir: db_path.with_file_name("thumbnails"),
+        };
+        library
+            .execute_batch(CREATE_DB)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create database: {}", e))?;
+        library.migrate().await?;
+        library.backfill_sort_keys().await?;
+        Ok(library)
+    }
+
+    /// Apply any migrations that have not been applied to this database yet
+    async fn migrate(&self) -> anyhow::Result<()> {
+        let rows = self
+            .query(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+                vec![],
+            )
+            .await?;
+        let current = rows
+            .first()
+            .and_then(|row| Self::get_optional_i64(&row[0]))
+            .unwrap_or(0) as usize;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            // a migration and its version are applied together, so one that fails halfway
+            // is rolled back and tried again on the next start
+            let mut statements: Vec<(String, Vec<Value>)> = migration
+                .split(';')
+                .map(str::trim)
+                .filter(|statement| !statement.is_empty())
+                .map(|statement| (statement.to_string(), vec![]))
+                .collect();
+            statements.push((
+                "INSERT INTO schema_version (version) VALUES (?)".to_string(),
+                vec![Value::Integer(i as i64 + 1)],
+            ));
+            self.transaction(statements)
+                .await
+                .map_err(|e| anyhow::anyhow!("Migration {} failed: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the sort keys of rows added before they existed
+    async fn backfill_sort_keys(&self) -> anyhow::Result<()> {
+        let mut statements = Vec::new();
+        for (table, name, sort_name) in [
+            ("artists", "name", "sort_name"),
+            ("albums", "title", "sort_title"),
+            ("tracks", "title", "sort_title"),
+        ] {
+            let rows = self
+                .query(
+                    &format!("SELECT id, {name}, {sort_name} FROM {table} WHERE sort_key IS NULL"),
+                    vec![],
+                )
+                .await?;
+            for row in rows {
+                let key = sort_key(
+                    &Self::get_string(&row[1])?,
+                    Self::get_optional_string(&row[2]).as_deref(),
+                );
+                statements.push((
+                    format!("UPDATE {table} SET sort_key = ? WHERE id = ?"),
+                    vec![Value::Text(key), row[0].clone()],
+                ));
+            }
+        }
+        if !statements.is_empty() {
+            self.transaction(statements).await?;
+        }
+        Ok(())
     }
 
-    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<LibraryEvent> {
-        self.event_sender.subscribe()
+    pub fn subscribe(&self) -> LibraryEvents {
+        LibraryEvents(self.event_sender.subscribe())
     }
 
     pub async fn write(&self) -> anyhow::Result<()> {
@@ -381,6 +885,22 @@ impl Library {
         Ok(())
     }
 
+    async fn execute_batch(&self, sql: &str) -> anyhow::Result<()> {
+        let (respond_tx, respond_rx) = oneshot::channel();
+        self.db_sender
+            .send(DbCommand::ExecuteBatch {
+                sql: sql.to_string(),
+                respond_to: respond_tx,
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send command: {}", e))?;
+        respond_rx
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))?
+            .map_err(|e| anyhow::anyhow!("Execute batch failed: {}", e))?;
+        Ok(())
+    }
+
     async fn execute(&self, sql: &str, params: Vec<Value>) -> anyhow::Result<()> {
         let (respond_tx, respond_rx) = oneshot::channel();
         self.db_sender
@@ -415,6 +935,48 @@ impl Library {
             .map_err(|e| anyhow::anyhow!("Query failed: {}", e))
     }
 
+    /// Run `statements` atomically, either all of them are applied or none are
+    async fn transaction(&self, statements: Vec<(String, Vec<Value>)>) -> anyhow::Result<()> {
+        let (respond_tx, respond_rx) = oneshot::channel();
+        self.db_sender
+            .send(DbCommand::Transaction {
+                statements,
+                respond_to: respond_tx,
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send command: {}", e))?;
+        respond_rx
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to receive response: {}", e))?
+            .map_err(|e| anyhow::anyhow!("Transaction failed: {}", e))?;
+        Ok(())
+    }
+
+    /// Look up the ids of rows in `table` whose `column` is one of `names`, keyed by name
+    async fn ids_by_name(
+        &self,
+        table: &str,
+        column: &str,
+        names: Vec<String>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut ids = HashMap::new();
+        for chunk in names.chunks(MAX_IN_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let rows = self
+                .query(
+                    &format!("SELECT id, {column} FROM {table} WHERE {column} IN ({placeholders})"),
+                    chunk.iter().cloned().map(Value::Text).collect(),
+                )
+                .await?;
+            for row in rows {
+                // keep the first match, like find_artist_by_name and find_album_by_title
+                ids.entry(Self::get_string(&row[1])?)
+                    .or_insert(Self::get_string(&row[0])?);
+            }
+        }
+        Ok(ids)
+    }
+
     fn get_string(value: &Value) -> Result<String> {
         match value {
             Value::Text(s) => Ok(s.clone()),
@@ -466,67 +1028,104 @@ impl Library {
             return Ok(existing);
         }
 
-        self.execute(
-            "INSERT INTO artists (id, name) VALUES (?, ?)",
+        let (sql, params) = Self::insert_artist(artist);
+        self.execute(&sql, params).await?;
+
+        Ok(artist.clone())
+    }
+
+    fn insert_artist(artist: &Artist) -> (String, Vec<Value>) {
+        (
+            "INSERT INTO artists (id, name, sort_name, sort_key) VALUES (?, ?, ?, ?)".to_string(),
             vec![
                 Value::Text(artist.id.clone()),
                 Value::Text(artist.name.clone()),
+                optional_text(&artist.sort_name),
+                Value::Text(sort_key(&artist.name, artist.sort_name.as_deref())),
             ],
         )
-        .await?;
-
-        Ok(artist.clone())
     }
 
     /// Find an artist by ID
     pub async fn find_artist_by_id(&self, id: &str) -> anyhow::Result<Option<Artist>> {
         let rows = self
             .query(
-                "SELECT id, name FROM artists WHERE id = ?",
+                "SELECT id, name, sort_name FROM artists WHERE id = ?",
                 vec![Value::Text(id.to_string())],
             )
             .await?;
 
-        if let Some(row) = rows.first() {
-            let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            Ok(Some(Artist { id, name }))
-        } else {
-            Ok(None)
-        }
+        rows.first().map(|row| Self::row_to_artist(row)).transpose()
     }
 
     /// Find an artist by name
     pub async fn find_artist_by_name(&self, name: &str) -> anyhow::Result<Option<Artist>> {
         let rows = self
             .query(
-                "SELECT id, name FROM artists WHERE name = ?",
+                "SELECT id, name, sort_name FROM artists WHERE name = ?",
                 vec![Value::Text(name.to_string())],
             )
             .await?;
 
-        if let Some(row) = rows.first() {
-            let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            Ok(Some(Artist { id, name }))
-        } else {
-            Ok(None)
-        }
+        rows.first().map(|row| Self::row_to_artist(row)).transpose()
     }
 
     /// Get all artists
     pub async fn all_artists(&self) -> anyhow::Result<Vec<Artist>> {
         let rows = self
-            .query("SELECT id, name FROM artists ORDER BY name", vec![])
+            .query(
+                "SELECT id, name, sort_name FROM artists ORDER BY sort_key",
+                vec![],
+            )
             .await?;
 
-        let mut artists = Vec::new();
-        for row in rows {
-            let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            artists.push(Artist { id, name });
-        }
-        Ok(artists)
+        rows.iter().map(|row| Self::row_to_artist(row)).collect()
+    }
+
+    /// Get all artists with their track and album counts
+    pub async fn all_artist_summaries(&self) -> anyhow::Result<Vec<ArtistSummary>> {
+        let artists = self.all_artists().await?;
+        let count_map = |rows: Vec<Vec<Value>>| -> anyhow::Result<HashMap<String, usize>> {
+            rows.iter()
+                .map(|row| Ok((Self::get_string(&row[0])?, Self::get_i64(&row[1])? as usize)))
+                .collect()
+        };
+        let track_counts = count_map(
+            self.query(
+                "SELECT artist_id, COUNT(*) FROM track_artists GROUP BY artist_id",
+                vec![],
+            )
+            .await?,
+        )?;
+        let album_counts = count_map(
+            self.query(
+                "SELECT artist_id, COUNT(*) FROM (
+                     SELECT artist_id, album_id FROM album_artists
+                     UNION
+                     SELECT ta.artist_id, t.album_id FROM track_artists ta
+                     INNER JOIN tracks t ON t.id = ta.track_id
+                 ) GROUP BY artist_id",
+                vec![],
+            )
+            .await?,
+        )?;
+        Ok(artists
+            .into_iter()
+            .map(|artist| ArtistSummary {
+                track_count: track_counts.get(&artist.id).copied().unwrap_or(0),
+                album_count: album_counts.get(&artist.id).copied().unwrap_or(0),
+                artist,
+            })
+            .collect())
+    }
+
+    /// Convert an (id, name, sort_name) row to an Artist
+    fn row_to_artist(row: &[Value]) -> anyhow::Result<Artist> {
+        Ok(Artist {
+            id: Self::get_string(&row[0])?,
+            name: Self::get_string(&row[1])?,
+            sort_name: Self::get_optional_string(&row[2]),
+        })
     }
 
     /// Add an album to the library
@@ -535,8 +1134,26 @@ impl Library {
             return Ok(existing);
         }
 
-        self.execute(
-            "INSERT INTO albums (id, title, release_year, album_art) VALUES (?, ?, ?, ?)",
+        let (sql, params) = Self::insert_album(album);
+        self.execute(&sql, params).await?;
+
+        for artist in &album.artists {
+            let artist = self.add_artist(artist).await?;
+            self.execute(
+                "INSERT OR IGNORE INTO album_artists (album_id, artist_id) VALUES (?, ?)",
+                vec![Value::Text(album.id.clone()), Value::Text(artist.id)],
+            )
+            .await?;
+        }
+
+        Ok(album.clone())
+    }
+
+    fn insert_album(album: &Album) -> (String, Vec<Value>) {
+        (
+            "INSERT INTO albums (id, title, release_year, release_date, label, album_art, sort_title, sort_key)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+                .to_string(),
             vec![
                 Value::Text(album.id.clone()),
                 Value::Text(album.title.clone()),
@@ -544,216 +1161,431 @@ impl Library {
                     .release_year
                     .map(|y| Value::Integer(y as i64))
                     .unwrap_or(Value::Null),
+                optional_text(&album.release_date),
+                optional_text(&album.label),
                 album
                     .album_art
                     .clone()
                     .map(Value::Blob)
                     .unwrap_or(Value::Null),
+                optional_text(&album.sort_title),
+                Value::Text(sort_key(&album.title, album.sort_title.as_deref())),
             ],
         )
-        .await?;
-
-        for artist in &album.artists {
-            let artist = self.add_artist(artist).await?;
-            self.execute(
-                "INSERT OR IGNORE INTO album_artists (album_id, artist_id) VALUES (?, ?)",
-                vec![Value::Text(album.id.clone()), Value::Text(artist.id)],
-            )
-            .await?;
-        }
-
-        Ok(album.clone())
     }
 
     /// Find an album by ID
     pub async fn find_album_by_id(&self, id: &str) -> anyhow::Result<Option<Album>> {
-        let rows = self
-            .query(
-                "SELECT id, title, release_year, album_art FROM albums WHERE id = ?",
-                vec![Value::Text(id.to_string())],
-            )
-            .await?;
-
-        if let Some(row) = rows.first() {
-            let id = Self::get_string(&row[0])?;
-            let title = Self::get_string(&row[1])?;
-            let release_year = Self::get_optional_i64(&row[2]).map(|y| y as i32);
-            let album_art = Self::get_optional_blob(&row[3]);
-
-            let artists = self.get_album_artists(&id).await?;
-
-            Ok(Some(Album {
-                id,
-                title,
-                artists,
-                release_year,
-                album_art,
-            }))
-        } else {
-            Ok(None)
-        }
+        Ok(self
+            .load_albums("WHERE al.id = ?", vec![Value::Text(id.to_string())])
+            .await?
+            .into_iter()
+            .next())
     }
 
     /// Find an album by title
     pub async fn find_album_by_title(&self, title: &str) -> anyhow::Result<Option<Album>> {
+        Ok(self
+            .load_albums("WHERE al.title = ?", vec![Value::Text(title.to_string())])
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Load albums with their artists in two queries, however many rows match.
+    /// `clause` follows `FROM albums al` (joins, WHERE and ORDER BY).
+    /// Cover art is not loaded, see `Library::album_art`.
+    async fn load_albums(&self, clause: &str, params: Vec<Value>) -> anyhow::Result<Vec<Album>> {
         let rows = self
             .query(
-                "SELECT id, title, release_year, album_art FROM albums WHERE title = ?",
-                vec![Value::Text(title.to_string())],
+                &format!(
+                    "SELECT al.id, al.title, al.release_year, al.release_date, al.label, al.sort_title FROM albums al {clause}"
+                ),
+                params.clone(),
             )
             .await?;
-
-        if let Some(row) = rows.first() {
-            let id = Self::get_string(&row[0])?;
-            let title = Self::get_string(&row[1])?;
-            let release_year = Self::get_optional_i64(&row[2]).map(|y| y as i32);
-            let album_art = Self::get_optional_blob(&row[3]);
-
-            let artists = self.get_album_artists(&id).await?;
-
-            Ok(Some(Album {
-                id,
-                title,
-                artists,
-                release_year,
-                album_art,
-            }))
-        } else {
-            Ok(None)
+        if rows.is_empty() {
+            return Ok(Vec::new());
         }
-    }
-
-    /// Get artists for an album
-    async fn get_album_artists(&self, album_id: &str) -> anyhow::Result<Vec<Artist>> {
-        let rows = self
-            .query(
-                "SELECT a.id, a.name FROM artists a 
-                 INNER JOIN album_artists aa ON a.id = aa.artist_id 
-                 WHERE aa.album_id = ?",
-                vec![Value::Text(album_id.to_string())],
+        let album_artists = self
+            .query_artist_map(
+                &format!(
+                    "SELECT link.album_id, ar.id, ar.name, ar.sort_name FROM album_artists link
+                     INNER JOIN artists ar ON ar.id = link.artist_id
+                     WHERE link.album_id IN (SELECT al.id FROM albums al {clause})"
+                ),
+                params,
             )
             .await?;
 
-        let mut artists = Vec::new();
+        let mut albums = Vec::new();
         for row in rows {
             let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            artists.push(Artist { id, name });
+            albums.push(Album {
+                title: Self::get_string(&row[1])?,
+                artists: album_artists.get(&id).cloned().unwrap_or_default(),
+                release_year: Self::get_optional_i64(&row[2]).map(|y| y as i32),
+                release_date: Self::get_optional_string(&row[3]),
+                label: Self::get_optional_string(&row[4]),
+                sort_title: Self::get_optional_string(&row[5]),
+                album_art: None,
+                id,
+            });
+        }
+        Ok(albums)
+    }
+
+    /// Run a query returning (owner id, artist id, artist name, artist sort name) rows
+    /// and group the artists by owner
+    async fn query_artist_map(
+        &self,
+        sql: &str,
+        params: Vec<Value>,
+    ) -> anyhow::Result<HashMap<String, Vec<Artist>>> {
+        let rows = self.query(sql, params).await?;
+        let mut artists: HashMap<String, Vec<Artist>> = HashMap::new();
+        for row in rows {
+            let owner = Self::get_string(&row[0])?;
+            artists
+                .entry(owner)
+                .or_default()
+                .push(Self::row_to_artist(&row[1..])?);
         }
         Ok(artists)
     }
 
-    /// Get all albums
-    pub async fn all_albums(&self) -> anyhow::Result<Vec<Album>> {
+    /// Get an album's cover art. Art is not loaded with tracks or albums, so it is
+    /// fetched here on demand and shared by everything that displays it.
+    pub async fn album_art(&self, album_id: &str) -> anyhow::Result<Option<Arc<Vec<u8>>>> {
+        let cached = self
+            .album_art_cache
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art cache lock poisoned"))?
+            .get(album_id)
+            .cloned();
+        if let Some(art) = cached {
+            return Ok(art);
+        }
+
+        let art = self.read_album_art(album_id).await?.map(Arc::new);
+
+        let mut cache = self
+            .album_art_cache
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art cache lock poisoned"))?;
+        if cache.len() >= ALBUM_ART_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(album_id.to_string(), art.clone());
+        Ok(art)
+    }
+
+    /// Get an album's cover art scaled down to `size`. Each size is generated from the
+    /// full size art the first time it is requested and cached on disk.
+    pub async fn album_thumbnail(
+        &self,
+        album_id: &str,
+        size: ArtSize,
+    ) -> anyhow::Result<Option<Arc<Vec<u8>>>> {
+        let path = self.thumbnail_path(album_id, size);
+        let cached = path.clone();
+        if let Some(thumbnail) = task::spawn_blocking(move || std::fs::read(cached).ok()).await? {
+            return Ok(Some(Arc::new(thumbnail)));
+        }
+
+        // skip the art cache, the full size art isn't needed again once it's scaled
+        let Some(art) = self.read_album_art(album_id).await? else {
+            return Ok(None);
+        };
+        let thumbnail = task::spawn_blocking(move || {
+            let thumbnail = art::thumbnail(&art, size.pixels());
+            // a failed write only means the thumbnail is generated again next time
+            if let Some(dir) = path.parent()
+                && std::fs::create_dir_all(dir).is_ok()
+            {
+                let _ = std::fs::write(&path, &thumbnail);
+            }
+            thumbnail
+        })
+        .await?;
+        Ok(Some(Arc::new(thumbnail)))
+    }
+
+    async fn read_album_art(&self, album_id: &str) -> anyhow::Result<Option<Vec<u8>>> {
         let rows = self
             .query(
-                "SELECT id, title, release_year, album_art FROM albums ORDER BY title",
-                vec![],
+                "SELECT album_art FROM albums WHERE id = ?",
+                vec![Value::Text(album_id.to_string())],
             )
             .await?;
+        Ok(rows
+            .first()
+            .and_then(|row| Self::get_optional_blob(&row[0])))
+    }
 
-        let mut albums = Vec::new();
-        for row in rows {
-            let id = Self::get_string(&row[0])?;
-            let title = Self::get_string(&row[1])?;
-            let release_year = Self::get_optional_i64(&row[2]).map(|y| y as i32);
-            let album_art = Self::get_optional_blob(&row[3]);
+    fn thumbnail_path(&self, album_id: &str, size: ArtSize) -> PathBuf {
+        self.thumbnail_dir
+            .join(size.pixels().to_string())
+            .join(album_id)
+    }
+
+    /// Changes whenever the album's art does, so covers cached for display can be keyed
+    /// by it and are loaded again once they are out of date
+    pub fn art_version(&self, album_id: &str) -> u64 {
+        self.art_versions
+            .lock()
+            .map(|versions| versions.get(album_id))
+            .unwrap_or_default()
+    }
 
-            let artists = self.get_album_artists(&id).await?;
+    /// Forget the cached art and thumbnails of the given albums after their art changed
+    fn invalidate_art(&self, album_ids: &[String]) -> anyhow::Result<()> {
+        let mut cache = self
+            .album_art_cache
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art cache lock poisoned"))?;
+        let mut versions = self
+            .art_versions
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art versions lock poisoned"))?;
+        for album_id in album_ids {
+            cache.remove(album_id);
+            versions.bump(album_id);
+            for size in ArtSize::ALL {
+                let _ = std::fs::remove_file(self.thumbnail_path(album_id, size));
+            }
+        }
+        Ok(())
+    }
 
-            albums.push(Album {
-                id,
-                title,
-                artists,
-                release_year,
-                album_art,
-            });
+    /// Forget all cached art and thumbnails, after every album may have changed
+    async fn invalidate_all_art(&self) -> anyhow::Result<()> {
+        self.album_art_cache
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art cache lock poisoned"))?
+            .clear();
+        self.art_versions
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Album art versions lock poisoned"))?
+            .bump_all();
+        match fs::remove_dir_all(&self.thumbnail_dir).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
         }
-        Ok(albums)
+    }
+
+    /// Get all albums
+    pub async fn all_albums(&self) -> anyhow::Result<Vec<Album>> {
+        self.load_albums("ORDER BY al.sort_key", vec![]).await
     }
 
     /// Add a track to the library
     pub async fn add_track(&self, track: &Track) -> anyhow::Result<Track> {
-        let track = self.add_track_internal(track).await?;
-        let _ = self.event_sender.send(LibraryEvent::TracksAdded(vec![track.clone()]));
-        Ok(track)
+        self.add_tracks(std::slice::from_ref(track))
+            .await?
+            .pop()
+            .ok_or(anyhow::anyhow!("Track was not added"))
     }
 
-    async fn add_track_internal(&self, track: &Track) -> anyhow::Result<Track> {
-        let album = self.add_album(&track.album).await?;
-
-        let mut artists_with_ids = Vec::new();
-        for artist in &track.artists {
-            let artist = self.add_artist(artist).await?;
-            artists_with_ids.push(artist);
+    /// Add multiple tracks to the library. They are written in a single transaction,
+    /// so either all of them are added or none are.
+    pub async fn add_tracks(&self, tracks: &[Track]) -> anyhow::Result<Vec<Track>> {
+        if tracks.is_empty() {
+            return Ok(Vec::new());
         }
 
-        self.execute(
-            "INSERT INTO tracks (id, title, album_id, duration, path, source, source_id, track_number) 
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-            vec![
-                Value::Text(track.id.clone()),
-                Value::Text(track.title.clone()),
-                Value::Text(album.id.clone()),
-                Value::Real(track.duration),
-                track.path.clone().map(Value::Text).unwrap_or(Value::Null),
-                Value::Text(track.source.as_str().to_string()),
-                track
-                    .source_id
-                    .clone()
-                    .map(Value::Text)
-                    .unwrap_or(Value::Null),
-                track
-                    .track_number
-                    .map(|n| Value::Integer(n as i64))
-                    .unwrap_or(Value::Null),
-            ],
-        )
-        .await?;
+        // reuse the artists and albums that are already in the library
+        let mut artist_names: Vec<String> = tracks
+            .iter()
+            .flat_map(|t| t.artists.iter().chain(&t.album.artists))
+            .map(|a| a.name.clone())
+            .collect();
+        artist_names.sort();
+        artist_names.dedup();
+        let mut album_titles: Vec<String> = tracks.iter().map(|t| t.album.title.clone()).collect();
+        album_titles.sort();
+        album_titles.dedup();
+        let mut artist_ids = self.ids_by_name("artists", "name", artist_names).await?;
+        let mut album_ids = self.ids_by_name("albums", "title", album_titles).await?;
+
+        let added_at = Utc::now().timestamp_millis();
+        let mut statements = Vec::new();
+        let mut results = Vec::with_capacity(tracks.len());
+        // albums whose art is set, or queued to be set, by this batch
+        let mut albums_with_art = HashSet::new();
+        for track in tracks {
+            let album_id = match album_ids.get(&track.album.title) {
+                Some(id) => id.clone(),
+                None => {
+                    let album = &track.album;
+                    album_ids.insert(album.title.clone(), album.id.clone());
+                    if album.album_art.is_some() {
+                        albums_with_art.insert(album.id.clone());
+                    }
+                    statements.push(Self::insert_album(album));
+                    for artist in &album.artists {
+                        let artist = Self::resolve_artist(artist, &mut artist_ids, &mut statements);
+                        statements.push((
+                            "INSERT OR IGNORE INTO album_artists (album_id, artist_id) VALUES (?, ?)"
+                                .to_string(),
+                            vec![Value::Text(album.id.clone()), Value::Text(artist.id)],
+                        ));
+                    }
+                    album.id.clone()
+                }
+            };
+
+            // every track carries the art it was tagged with, but only the first one found
+            // for an album is stored, and only if the album doesn't have any yet
+            if let Some(art) = &track.album.album_art
+                && albums_with_art.insert(album_id.clone())
+            {
+                statements.push((
+                    "UPDATE albums SET album_art = ? WHERE id = ? AND album_art IS NULL"
+                        .to_string(),
+                    vec![Value::Blob(art.clone()), Value::Text(album_id.clone())],
+                ));
+            }
 
-        for artist in &artists_with_ids {
-            self.execute(
-                "INSERT OR IGNORE INTO track_artists (track_id, artist_id) VALUES (?, ?)",
+            let artists: Vec<Artist> = track
+                .artists
+                .iter()
+                .map(|artist| Self::resolve_artist(artist, &mut artist_ids, &mut statements))
+                .collect();
+
+            statements.push((
+                "INSERT INTO tracks (id, title, album_id, duration, path, source, source_id, track_number, file_size, content_hash, added_at,
+                 disc_number, disc_total, track_total, composer, isrc, comment, bitrate, sample_rate, channels, codec, sort_title, sort_key,
+                 rating, loved, cue_start, cue_end)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                    .to_string(),
                 vec![
                     Value::Text(track.id.clone()),
-                    Value::Text(artist.id.clone()),
+                    Value::Text(track.title.clone()),
+                    Value::Text(album_id.clone()),
+                    Value::Real(track.duration),
+                    track.path.clone().map(Value::Text).unwrap_or(Value::Null),
+                    Value::Text(track.source.as_str().to_string()),
+                    track
+                        .source_id
+                        .clone()
+                        .map(Value::Text)
+                        .unwrap_or(Value::Null),
+                    track
+                        .track_number
+                        .map(|n| Value::Integer(n as i64))
+                        .unwrap_or(Value::Null),
+                    track
+                        .file_size
+                        .map(|s| Value::Integer(s as i64))
+                        .unwrap_or(Value::Null),
+                    track
+                        .content_hash
+                        .clone()
+                        .map(Value::Text)
+                        .unwrap_or(Value::Null),
+                    Value::Integer(added_at),
+                    optional_integer(track.metadata.disc_number),
+                    optional_integer(track.metadata.disc_total),
+                    optional_integer(track.metadata.track_total),
+                    optional_text(&track.metadata.composer),
+                    optional_text(&track.metadata.isrc),
+                    optional_text(&track.metadata.comment),
+                    optional_integer(track.audio.bitrate),
+                    optional_integer(track.audio.sample_rate),
+                    optional_integer(track.audio.channels),
+                    optional_text(&track.audio.codec),
+                    optional_text(&track.metadata.sort_title),
+                    Value::Text(sort_key(
+                        &track.title,
+                        track.metadata.sort_title.as_deref(),
+                    )),
+                    Value::Integer(track.metadata.rating as i64),
+                    Value::Integer(track.metadata.loved as i64),
+                    track
+                        .cue
+                        .map(|cue| Value::Real(cue.start))
+                        .unwrap_or(Value::Null),
+                    track
+                        .cue
+                        .map(|cue| Value::Real(cue.end))
+                        .unwrap_or(Value::Null),
                 ],
-            )
-            .await?;
+            ));
+            for genre in &track.metadata.genres {
+                statements.push((
+                    "INSERT OR IGNORE INTO track_genres (track_id, genre) VALUES (?, ?)"
+                        .to_string(),
+                    vec![Value::Text(track.id.clone()), Value::Text(genre.clone())],
+                ));
+            }
+            for artist in &artists {
+                statements.push((
+                    "INSERT OR IGNORE INTO track_artists (track_id, artist_id) VALUES (?, ?)"
+                        .to_string(),
+                    vec![
+                        Value::Text(track.id.clone()),
+                        Value::Text(artist.id.clone()),
+                    ],
+                ));
+            }
+
+            let mut result = track.clone();
+            result.album.id = album_id;
+            // the art is loaded from the library by album id from now on
+            result.album.album_art = None;
+            result.artists = artists;
+            results.push(result);
         }
 
-        let mut result = track.clone();
-        result.artists = artists_with_ids;
-        Ok(result)
+        self.transaction(statements).await?;
+        let album_ids: Vec<String> = albums_with_art.into_iter().collect();
+        self.invalidate_art(&album_ids)?;
+
+        self.index_tracks(&results);
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::TracksAdded(results.clone()));
+        Ok(results)
     }
 
-    /// Add multiple tracks to the library
-    pub async fn add_tracks(&self, tracks: &[Track]) -> anyhow::Result<Vec<Track>> {
-        let mut results = Vec::new();
-        for track in tracks {
-            results.push(self.add_track_internal(track).await?);
-        }
-        if !results.is_empty() {
-            let _ = self.event_sender.send(LibraryEvent::TracksAdded(results.clone()));
+    /// Returns the library's artist with the same name, queueing an insert if there is none yet
+    fn resolve_artist(
+        artist: &Artist,
+        artist_ids: &mut HashMap<String, String>,
+        statements: &mut Vec<(String, Vec<Value>)>,
+    ) -> Artist {
+        if let Some(id) = artist_ids.get(&artist.name) {
+            // a sort name from these tags fills in one the library doesn't have yet
+            if let Some(sort_name) = &artist.sort_name {
+                statements.push((
+                    "UPDATE artists SET sort_name = ?, sort_key = ? WHERE id = ? AND sort_name IS NULL"
+                        .to_string(),
+                    vec![
+                        Value::Text(sort_name.clone()),
+                        Value::Text(sort_key(&artist.name, Some(sort_name))),
+                        Value::Text(id.clone()),
+                    ],
+                ));
+            }
+            return Artist {
+                id: id.clone(),
+                name: artist.name.clone(),
+                sort_name: artist.sort_name.clone(),
+            };
         }
-        Ok(results)
+        artist_ids.insert(artist.name.clone(), artist.id.clone());
+        statements.push(Self::insert_artist(artist));
+        artist.clone()
     }
 
     /// Find a track by ID
     pub async fn find_track_by_id(&self, id: &str) -> anyhow::Result<Option<Track>> {
-        let rows = self
-            .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks WHERE id = ?",
-                vec![Value::Text(id.to_string())],
-            )
-            .await?;
-
-        if let Some(row) = rows.first() {
-            self.row_to_track(&row).await.map(Some)
-        } else {
-            Ok(None)
-        }
+        Ok(self
+            .load_tracks("WHERE t.id = ?", vec![Value::Text(id.to_string())])
+            .await?
+            .into_iter()
+            .next())
     }
 
     /// Find a track by source ID
@@ -762,100 +1594,187 @@ impl Library {
         source: TrackSource,
         id: &str,
     ) -> anyhow::Result<Option<Track>> {
-        let rows = self
-            .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks WHERE source = ? AND source_id = ?",
+        Ok(self
+            .load_tracks(
+                "WHERE t.source = ? AND t.source_id = ?",
                 vec![
                     Value::Text(source.as_str().to_string()),
                     Value::Text(id.to_string()),
                 ],
             )
-            .await?;
-
-        if let Some(row) = rows.first() {
-            self.row_to_track(&row).await.map(Some)
-        } else {
-            Ok(None)
-        }
+            .await?
+            .into_iter()
+            .next())
     }
 
     /// Find tracks by source
     pub async fn find_tracks_by_source(&self, source: TrackSource) -> anyhow::Result<Vec<Track>> {
-        let rows = self
-            .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks WHERE source = ?",
-                vec![Value::Text(source.as_str().to_string())],
-            )
-            .await?;
-
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
-        }
-        Ok(tracks)
+        self.load_tracks(
+            "WHERE t.source = ?",
+            vec![Value::Text(source.as_str().to_string())],
+        )
+        .await
     }
 
     /// Get all tracks in the library
     pub async fn all_tracks(&self) -> anyhow::Result<Vec<Track>> {
-        let rows = self
-            .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks ORDER BY title",
-                vec![],
-            )
-            .await?;
-
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
-        }
-        Ok(tracks)
+        self.load_tracks("ORDER BY t.sort_key", vec![]).await
     }
 
     /// Get all tracks not in any playlist
     pub async fn all_unorganized_tracks(&self) -> anyhow::Result<Vec<Track>> {
+        self.load_tracks(
+            "LEFT JOIN playlist_entries pe ON t.id = pe.track_id
+             WHERE pe.track_id IS NULL
+             ORDER BY t.sort_key",
+            vec![],
+        )
+        .await
+    }
+
+    /// Load tracks with their album and artists in three queries, however many rows
+    /// match. `clause` follows `TRACK_FROM` (joins, WHERE, ORDER BY and LIMIT).
+    async fn load_tracks(&self, clause: &str, params: Vec<Value>) -> anyhow::Result<Vec<Track>> {
+        Ok(self
+            .load_tracks_with("", clause, params)
+            .await?
+            .into_iter()
+            .map(|(track, _)| track)
+            .collect())
+    }
+
+    /// Like `load_tracks`, but also selects the `extra` columns (a comma separated list)
+    /// and returns their values alongside each track
+    async fn load_tracks_with(
+        &self,
+        extra: &str,
+        clause: &str,
+        params: Vec<Value>,
+    ) -> anyhow::Result<Vec<(Track, Vec<Value>)>> {
+        let extra = if extra.is_empty() {
+            String::new()
+        } else {
+            format!(", {extra}")
+        };
         let rows = self
             .query(
-                "SELECT t.id, t.title, t.album_id, t.duration, t.path, t.source, t.source_id, t.track_number 
-                 FROM tracks t
-                 LEFT JOIN playlist_tracks pt ON t.id = pt.track_id
-                 WHERE pt.track_id IS NULL
-                 ORDER BY t.title",
-                vec![],
+                &format!("SELECT {TRACK_COLUMNS}{extra} {TRACK_FROM} {clause}"),
+                params.clone(),
             )
             .await?;
-
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
+        if rows.is_empty() {
+            return Ok(Vec::new());
         }
-        Ok(tracks)
+        let track_artists = self
+            .query_artist_map(
+                &format!(
+                    "SELECT link.track_id, ar.id, ar.name, ar.sort_name FROM track_artists link
+                     INNER JOIN artists ar ON ar.id = link.artist_id
+                     WHERE link.track_id IN (SELECT t.id {TRACK_FROM} {clause})"
+                ),
+                params.clone(),
+            )
+            .await?;
+        let album_artists = self
+            .query_artist_map(
+                &format!(
+                    "SELECT link.album_id, ar.id, ar.name, ar.sort_name FROM album_artists link
+                     INNER JOIN artists ar ON ar.id = link.artist_id
+                     WHERE link.album_id IN (SELECT t.album_id {TRACK_FROM} {clause})"
+                ),
+                params.clone(),
+            )
+            .await?;
+        let mut genres: HashMap<String, Vec<String>> = HashMap::new();
+        for row in self
+            .query(
+                &format!(
+                    "SELECT g.track_id, g.genre FROM track_genres g
+                     WHERE g.track_id IN (SELECT t.id {TRACK_FROM} {clause})
+                     ORDER BY g.rowid"
+                ),
+                params,
+            )
+            .await?
+        {
+            genres
+                .entry(Self::get_string(&row[0])?)
+                .or_default()
+                .push(Self::get_string(&row[1])?);
+        }
+
+        rows.iter()
+            .map(|row| {
+                let track = Self::row_to_track(row, &track_artists, &album_artists, &genres)?;
+                Ok((track, row[TRACK_COLUMN_COUNT..].to_vec()))
+            })
+            .collect()
     }
 
-    /// Get artists for a track
-    async fn get_track_artists(&self, track_id: &str) -> anyhow::Result<Vec<Artist>> {
-        let rows = self
-            .query(
-                "SELECT a.id, a.name FROM artists a 
-                 INNER JOIN track_artists ta ON a.id = ta.artist_id 
-                 WHERE ta.track_id = ?",
-                vec![Value::Text(track_id.to_string())],
+    /// Get one page of the library's tracks matching `filter`, in the given order. Pages
+    /// are keyed on the sort value and id of the last track rather than an offset, so
+    /// fetching a page costs the same however deep into the library it is.
+    pub async fn tracks_page(
+        &self,
+        sort: TrackSort,
+        descending: bool,
+        filter: TrackFilter,
+        after: Option<&TrackCursor>,
+        limit: usize,
+    ) -> anyhow::Result<TrackPage> {
+        let key = sort.expression();
+        let (direction, comparison) = if descending {
+            ("DESC", "<")
+        } else {
+            ("ASC", ">")
+        };
+        let mut params = Vec::new();
+        let mut conditions: Vec<String> = filter.conditions().into_iter().collect();
+        if let Some(cursor) = after {
+            params.push(cursor.sort_value.clone());
+            params.push(cursor.sort_value.clone());
+            params.push(Value::Text(cursor.id.clone()));
+            conditions.push(format!(
+                "({key} {comparison} ? OR ({key} = ? AND t.id {comparison} ?))"
+            ));
+        }
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        // fetch one extra row to find out whether there is another page
+        params.push(Value::Integer(limit as i64 + 1));
+        let mut rows = self
+            .load_tracks_with(
+                key,
+                &format!("{filter} ORDER BY {key} {direction}, t.id {direction} LIMIT ?"),
+                params,
             )
             .await?;
 
-        let mut artists = Vec::new();
-        for row in rows {
-            let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            artists.push(Artist { id, name });
-        }
-        Ok(artists)
+        let next = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|(track, extra)| TrackCursor {
+                sort_value: extra[0].clone(),
+                id: track.id.clone(),
+            })
+        } else {
+            None
+        };
+        Ok(TrackPage {
+            tracks: rows.into_iter().map(|(track, _)| track).collect(),
+            next,
+        })
     }
 
-    /// Convert a database row to a Track
-    async fn row_to_track(&self, row: &Vec<Value>) -> anyhow::Result<Track> {
+    /// Convert a database row selected with `TRACK_COLUMNS` to a Track
+    fn row_to_track(
+        row: &[Value],
+        track_artists: &HashMap<String, Vec<Artist>>,
+        album_artists: &HashMap<String, Vec<Artist>>,
+        genres: &HashMap<String, Vec<String>>,
+    ) -> anyhow::Result<Track> {
         let id = Self::get_string(&row[0])?;
         let title = Self::get_string(&row[1])?;
         let album_id = Self::get_string(&row[2])?;
@@ -864,26 +1783,64 @@ impl Library {
         let source_str = Self::get_string(&row[5])?;
         let source_id = Self::get_optional_string(&row[6]);
         let track_number = Self::get_optional_i64(&row[7]).map(|n| n as i32);
+        let file_size = Self::get_optional_i64(&row[8]).map(|s| s as u64);
+        let content_hash = Self::get_optional_string(&row[9]);
 
         let source = TrackSource::from_str(&source_str)
             .ok_or_else(|| anyhow::anyhow!("Invalid track source: {}", source_str))?;
 
-        let artists = self.get_track_artists(&id).await?;
-        let album = self
-            .find_album_by_id(&album_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("Album not found for track: {}", id))?;
+        let album = Album {
+            title: Self::get_string(&row[10])?,
+            artists: album_artists.get(&album_id).cloned().unwrap_or_default(),
+            release_year: Self::get_optional_i64(&row[11]).map(|y| y as i32),
+            release_date: Self::get_optional_string(&row[22]),
+            label: Self::get_optional_string(&row[23]),
+            sort_title: Self::get_optional_string(&row[25]),
+            album_art: None,
+            id: album_id,
+        };
+
+        let metadata = TrackMetadata {
+            genres: genres.get(&id).cloned().unwrap_or_default(),
+            disc_number: Self::get_optional_i64(&row[12]).map(|n| n as i32),
+            disc_total: Self::get_optional_i64(&row[13]).map(|n| n as i32),
+            track_total: Self::get_optional_i64(&row[14]).map(|n| n as i32),
+            composer: Self::get_optional_string(&row[15]),
+            isrc: Self::get_optional_string(&row[16]),
+            comment: Self::get_optional_string(&row[17]),
+            sort_title: Self::get_optional_string(&row[24]),
+            rating: Self::get_i64(&row[26])? as u8,
+            loved: Self::get_i64(&row[27])? != 0,
+        };
+        let audio = AudioProperties {
+            bitrate: Self::get_optional_i64(&row[18]).map(|b| b as u32),
+            sample_rate: Self::get_optional_i64(&row[19]).map(|r| r as u32),
+            channels: Self::get_optional_i64(&row[20]).map(|c| c as u8),
+            codec: Self::get_optional_string(&row[21]),
+        };
+        let cue = match (&row[28], &row[29]) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (start, end) => Some(CueRange {
+                start: Self::get_f64(start)?,
+                end: Self::get_f64(end)?,
+            }),
+        };
 
         Ok(Track {
+            artists: track_artists.get(&id).cloned().unwrap_or_default(),
             id,
             title,
-            artists,
             album,
             duration,
             path,
             source,
             source_id,
             track_number,
+            file_size,
+            content_hash,
+            metadata,
+            audio,
+            cue,
         })
     }
 
@@ -894,13 +1851,14 @@ impl Library {
             vec![Value::Text(id.to_string())],
         )
         .await?;
+        self.unindex_track(id);
         Ok(())
     }
 
     /// Create a new playlist
     pub async fn create_playlist(&self, playlist: &Playlist) -> anyhow::Result<Playlist> {
         self.execute(
-            "INSERT INTO playlists (id, name, description) VALUES (?, ?, ?)",
+            "INSERT INTO playlists (id, name, description, rules) VALUES (?, ?, ?, ?)",
             vec![
                 Value::Text(playlist.id.clone()),
                 Value::Text(playlist.name.clone()),
@@ -909,10 +1867,12 @@ impl Library {
                     .clone()
                     .map(Value::Text)
                     .unwrap_or(Value::Null),
+                Self::rules_value(playlist.rules.as_ref())?,
             ],
         )
         .await?;
 
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
         Ok(playlist.clone())
     }
 
@@ -920,7 +1880,7 @@ impl Library {
     pub async fn find_playlist_by_id(&self, id: &str) -> anyhow::Result<Option<Playlist>> {
         let rows = self
             .query(
-                "SELECT id, name, description FROM playlists WHERE id = ?",
+                "SELECT id, name, description, rules FROM playlists WHERE id = ?",
                 vec![Value::Text(id.to_string())],
             )
             .await?;
@@ -929,14 +1889,16 @@ impl Library {
             let id = Self::get_string(&row[0])?;
             let name = Self::get_string(&row[1])?;
             let description = Self::get_optional_string(&row[2]);
+            let rules = Self::get_rules(&row[3])?;
 
-            let tracks = self.get_playlist_tracks(&id).await?;
+            let entries = self.get_playlist_entries(&id, rules.as_ref()).await?;
 
             Ok(Some(Playlist {
                 id,
                 name,
                 description,
-                tracks,
+                entries,
+                rules,
             }))
         } else {
             Ok(None)
@@ -947,7 +1909,7 @@ impl Library {
     pub async fn all_playlists(&self) -> anyhow::Result<Vec<Playlist>> {
         let rows = self
             .query(
-                "SELECT id, name, description FROM playlists ORDER BY name",
+                "SELECT id, name, description, rules FROM playlists ORDER BY name",
                 vec![],
             )
             .await?;
@@ -957,48 +1919,135 @@ impl Library {
             let id = Self::get_string(&row[0])?;
             let name = Self::get_string(&row[1])?;
             let description = Self::get_optional_string(&row[2]);
+            let rules = Self::get_rules(&row[3])?;
 
-            let tracks = self.get_playlist_tracks(&id).await?;
+            let entries = self.get_playlist_entries(&id, rules.as_ref()).await?;
 
             playlists.push(Playlist {
                 id,
                 name,
                 description,
-                tracks,
+                entries,
+                rules,
             });
         }
         Ok(playlists)
     }
 
-    /// Get tracks in a playlist
-    async fn get_playlist_tracks(&self, playlist_id: &str) -> anyhow::Result<Vec<Track>> {
+    /// Get all playlists with their number of tracks, without loading the tracks
+    pub async fn all_playlist_summaries(&self) -> anyhow::Result<Vec<PlaylistSummary>> {
         let rows = self
             .query(
-                "SELECT t.id, t.title, t.album_id, t.duration, t.path, t.source, t.source_id, t.track_number 
-                 FROM tracks t
-                 INNER JOIN playlist_tracks pt ON t.id = pt.track_id
-                 WHERE pt.playlist_id = ?
-                 ORDER BY pt.position",
-                vec![Value::Text(playlist_id.to_string())],
+                "SELECT p.id, p.name, (SELECT COUNT(*) FROM playlist_entries pe WHERE pe.playlist_id = p.id), p.rules
+                 FROM playlists p ORDER BY p.name",
+                vec![],
             )
             .await?;
-
-        let mut tracks = Vec::new();
+        let mut summaries = Vec::with_capacity(rows.len());
         for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
+            let rules = Self::get_rules(&row[3])?;
+            let track_count = match &rules {
+                Some(rules) => self.smart_playlist_count(rules).await?,
+                None => Self::get_i64(&row[2])? as usize,
+            };
+            summaries.push(PlaylistSummary {
+                id: Self::get_string(&row[0])?,
+                name: Self::get_string(&row[1])?,
+                track_count,
+                smart: rules.is_some(),
+            });
         }
-        Ok(tracks)
+        Ok(summaries)
+    }
+
+    /// Read the rules column of a playlist
+    fn get_rules(value: &Value) -> anyhow::Result<Option<SmartRules>> {
+        Self::get_optional_string(value)
+            .map(|json| {
+                serde_json::from_str(&json)
+                    .map_err(|e| anyhow::anyhow!("Invalid smart playlist rules: {}", e))
+            })
+            .transpose()
+    }
+
+    fn rules_value(rules: Option<&SmartRules>) -> anyhow::Result<Value> {
+        Ok(match rules {
+            Some(rules) => Value::Text(serde_json::to_string(rules)?),
+            None => Value::Null,
+        })
+    }
+
+    /// Get the entries of a playlist in order. The entries of smart playlists are the
+    /// tracks matching their `rules`, each with the track id as the entry id.
+    async fn get_playlist_entries(
+        &self,
+        playlist_id: &str,
+        rules: Option<&SmartRules>,
+    ) -> anyhow::Result<Vec<PlaylistEntry>> {
+        if let Some(rules) = rules {
+            return Ok(self
+                .smart_playlist_tracks(rules)
+                .await?
+                .into_iter()
+                .map(|track| PlaylistEntry {
+                    id: track.id.clone(),
+                    track,
+                })
+                .collect());
+        }
+        self.load_tracks_with(
+            "pe.id",
+            "INNER JOIN playlist_entries pe ON t.id = pe.track_id
+             WHERE pe.playlist_id = ?
+             ORDER BY pe.position, pe.id",
+            vec![Value::Text(playlist_id.to_string())],
+        )
+        .await?
+        .into_iter()
+        .map(|(track, extra)| {
+            Ok(PlaylistEntry {
+                id: Self::get_string(&extra[0])?,
+                track,
+            })
+        })
+        .collect()
+    }
+
+    /// Ids of a playlist's entries in order
+    async fn playlist_entry_ids(&self, playlist_id: &str) -> anyhow::Result<Vec<String>> {
+        self.query(
+            "SELECT id FROM playlist_entries WHERE playlist_id = ? ORDER BY position, id",
+            vec![Value::Text(playlist_id.to_string())],
+        )
+        .await?
+        .iter()
+        .map(|row| Self::get_string(&row[0]))
+        .collect()
+    }
+
+    /// Statements numbering the entries in `order` from 1, so positions stay contiguous
+    fn playlist_position_statements(order: &[String]) -> Vec<(String, Vec<Value>)> {
+        order
+            .iter()
+            .enumerate()
+            .map(|(i, entry_id)| {
+                (
+                    "UPDATE playlist_entries SET position = ? WHERE id = ?".to_string(),
+                    vec![Value::Integer(i as i64 + 1), Value::Text(entry_id.clone())],
+                )
+            })
+            .collect()
     }
 
-    /// Add a track to a playlist
+    /// Add a track to the end of a playlist, returning the id of the new entry
     pub async fn add_track_to_playlist(
         &self,
         playlist_id: &str,
         track_id: &str,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<String> {
         let rows = self
             .query(
-                "SELECT COALESCE(MAX(position), 0) + 1 FROM playlist_tracks WHERE playlist_id = ?",
+                "SELECT COALESCE(MAX(position), 0) + 1 FROM playlist_entries WHERE playlist_id = ?",
                 vec![Value::Text(playlist_id.to_string())],
             )
             .await?;
@@ -1009,9 +2058,11 @@ impl Library {
             1
         };
 
+        let entry_id = Ulid::new().to_string();
         self.execute(
-            "INSERT OR IGNORE INTO playlist_tracks (playlist_id, track_id, position) VALUES (?, ?, ?)",
+            "INSERT INTO playlist_entries (id, playlist_id, track_id, position) VALUES (?, ?, ?, ?)",
             vec![
+                Value::Text(entry_id.clone()),
                 Value::Text(playlist_id.to_string()),
                 Value::Text(track_id.to_string()),
                 Value::Integer(position),
@@ -1019,24 +2070,145 @@ impl Library {
         )
         .await?;
 
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        Ok(entry_id)
+    }
+
+    /// Insert tracks into a playlist before the entry at `position` (0 is the start, past
+    /// the end appends), returning the ids of the new entries
+    pub async fn insert_into_playlist(
+        &self,
+        playlist_id: &str,
+        position: usize,
+        track_ids: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut order = self.playlist_entry_ids(playlist_id).await?;
+        let position = position.min(order.len());
+        let entry_ids: Vec<String> = track_ids.iter().map(|_| Ulid::new().to_string()).collect();
+
+        // new entries are numbered along with the rest below
+        let mut statements: Vec<(String, Vec<Value>)> = entry_ids
+            .iter()
+            .zip(track_ids)
+            .map(|(entry_id, track_id)| {
+                (
+                    "INSERT INTO playlist_entries (id, playlist_id, track_id, position) VALUES (?, ?, ?, 0)"
+                        .to_string(),
+                    vec![
+                        Value::Text(entry_id.clone()),
+                        Value::Text(playlist_id.to_string()),
+                        Value::Text(track_id.clone()),
+                    ],
+                )
+            })
+            .collect();
+        order.splice(position..position, entry_ids.iter().cloned());
+        statements.extend(Self::playlist_position_statements(&order));
+        self.transaction(statements).await?;
+
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        Ok(entry_ids)
+    }
+
+    /// Move an entry of a playlist to `position`, counted from 0 after it is taken out
+    pub async fn move_playlist_entry(
+        &self,
+        playlist_id: &str,
+        entry_id: &str,
+        position: usize,
+    ) -> anyhow::Result<()> {
+        let mut order = self.playlist_entry_ids(playlist_id).await?;
+        let from = order
+            .iter()
+            .position(|id| id == entry_id)
+            .ok_or(anyhow::anyhow!(
+                "Entry {} is not in playlist {}",
+                entry_id,
+                playlist_id
+            ))?;
+        let entry = order.remove(from);
+        order.insert(position.min(order.len()), entry);
+        self.transaction(Self::playlist_position_statements(&order))
+            .await?;
+
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        Ok(())
+    }
+
+    /// Put a playlist's entries in the order of `entry_ids`. Entries not listed keep their
+    /// order after the listed ones.
+    pub async fn reorder_playlist(
+        &self,
+        playlist_id: &str,
+        entry_ids: &[String],
+    ) -> anyhow::Result<()> {
+        let current = self.playlist_entry_ids(playlist_id).await?;
+        let listed: HashSet<&String> = entry_ids.iter().collect();
+        let order: Vec<String> = entry_ids
+            .iter()
+            .filter(|id| current.contains(id))
+            .chain(current.iter().filter(|id| !listed.contains(id)))
+            .cloned()
+            .collect();
+        self.transaction(Self::playlist_position_statements(&order))
+            .await?;
+
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        Ok(())
+    }
+
+    /// Remove one entry from a playlist
+    pub async fn remove_playlist_entry(
+        &self,
+        playlist_id: &str,
+        entry_id: &str,
+    ) -> anyhow::Result<()> {
+        let mut order = self.playlist_entry_ids(playlist_id).await?;
+        order.retain(|id| id != entry_id);
+        let mut statements = vec![(
+            "DELETE FROM playlist_entries WHERE playlist_id = ? AND id = ?".to_string(),
+            vec![
+                Value::Text(playlist_id.to_string()),
+                Value::Text(entry_id.to_string()),
+            ],
+        )];
+        statements.extend(Self::playlist_position_statements(&order));
+        self.transaction(statements).await?;
+
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
         Ok(())
     }
 
-    /// Remove a track from a playlist
+    /// Remove every entry of a track from a playlist
     pub async fn remove_track_from_playlist(
         &self,
         playlist_id: &str,
         track_id: &str,
     ) -> anyhow::Result<()> {
-        self.execute(
-            "DELETE FROM playlist_tracks WHERE playlist_id = ? AND track_id = ?",
+        let order = self
+            .query(
+                "SELECT id FROM playlist_entries WHERE playlist_id = ? AND track_id != ?
+                 ORDER BY position, id",
+                vec![
+                    Value::Text(playlist_id.to_string()),
+                    Value::Text(track_id.to_string()),
+                ],
+            )
+            .await?
+            .iter()
+            .map(|row| Self::get_string(&row[0]))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut statements = vec![(
+            "DELETE FROM playlist_entries WHERE playlist_id = ? AND track_id = ?".to_string(),
             vec![
                 Value::Text(playlist_id.to_string()),
                 Value::Text(track_id.to_string()),
             ],
-        )
-        .await?;
+        )];
+        statements.extend(Self::playlist_position_statements(&order));
+        self.transaction(statements).await?;
 
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
         Ok(())
     }
 
@@ -1047,13 +2219,14 @@ impl Library {
             vec![Value::Text(id.to_string())],
         )
         .await?;
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
         Ok(())
     }
 
     /// Update a playlist
     pub async fn update_playlist(&self, playlist: &Playlist) -> anyhow::Result<()> {
         self.execute(
-            "UPDATE playlists SET name = ?, description = ? WHERE id = ?",
+            "UPDATE playlists SET name = ?, description = ?, rules = ? WHERE id = ?",
             vec![
                 Value::Text(playlist.name.clone()),
                 playlist
@@ -1061,131 +2234,119 @@ impl Library {
                     .clone()
                     .map(Value::Text)
                     .unwrap_or(Value::Null),
+                Self::rules_value(playlist.rules.as_ref())?,
                 Value::Text(playlist.id.clone()),
             ],
         )
         .await?;
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
         Ok(())
     }
 
     /// Search tracks by title
     pub async fn search_tracks(&self, query: &str) -> anyhow::Result<Vec<Track>> {
+        let pattern = format!("%{}%", query);
+        self.load_tracks(
+            "WHERE t.title LIKE ? ORDER BY t.sort_key",
+            vec![Value::Text(pattern)],
+        )
+        .await
+    }
+
+    /// Search artists by name
+    pub async fn search_artists(&self, query: &str) -> anyhow::Result<Vec<Artist>> {
         let pattern = format!("%{}%", query);
         let rows = self
             .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks WHERE title LIKE ? ORDER BY title",
+                "SELECT id, name, sort_name FROM artists WHERE name LIKE ? ORDER BY sort_key",
                 vec![Value::Text(pattern)],
             )
             .await?;
 
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
-        }
-        Ok(tracks)
-    }
-
-    /// Search artists by name
-    pub async fn search_artists(&self, query: &str) -> anyhow::Result<Vec<Artist>> {
-        let pattern = format!("%{}%", query);
-        let rows = self
-            .query(
-                "SELECT id, name FROM artists WHERE name LIKE ? ORDER BY name",
-                vec![Value::Text(pattern)],
-            )
-            .await?;
-
-        let mut artists = Vec::new();
-        for row in rows {
-            let id = Self::get_string(&row[0])?;
-            let name = Self::get_string(&row[1])?;
-            artists.push(Artist { id, name });
-        }
-        Ok(artists)
+        rows.iter().map(|row| Self::row_to_artist(row)).collect()
     }
 
     /// Search albums by title
     pub async fn search_albums(&self, query: &str) -> anyhow::Result<Vec<Album>> {
         let pattern = format!("%{}%", query);
-        let rows = self
-            .query(
-                "SELECT id, title, release_year, album_art FROM albums WHERE title LIKE ? ORDER BY title",
-                vec![Value::Text(pattern)],
-            )
-            .await?;
-
-        let mut albums = Vec::new();
-        for row in rows {
-            let id = Self::get_string(&row[0])?;
-            let title = Self::get_string(&row[1])?;
-            let release_year = Self::get_optional_i64(&row[2]).map(|y| y as i32);
-            let album_art = Self::get_optional_blob(&row[3]);
-
-            let artists = self.get_album_artists(&id).await?;
-
-            albums.push(Album {
-                id,
-                title,
-                artists,
-                release_year,
-                album_art,
-            });
-        }
-        Ok(albums)
+        self.load_albums(
+            "WHERE al.title LIKE ? ORDER BY al.sort_key",
+            vec![Value::Text(pattern)],
+        )
+        .await
     }
 
     /// Get tracks by album
     pub async fn get_tracks_by_album(&self, album_id: &str) -> anyhow::Result<Vec<Track>> {
-        let rows = self
-            .query(
-                "SELECT id, title, album_id, duration, path, source, source_id, track_number 
-                 FROM tracks WHERE album_id = ? ORDER BY track_number, title",
-                vec![Value::Text(album_id.to_string())],
-            )
-            .await?;
+        self.load_tracks(
+            "WHERE t.album_id = ?
+             ORDER BY t.disc_number IS NULL, t.disc_number, t.track_number IS NULL, t.track_number, t.sort_key",
+            vec![Value::Text(album_id.to_string())],
+        )
+        .await
+    }
 
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
-        }
-        Ok(tracks)
+    /// Get the albums an artist is credited on or has tracks on, oldest first
+    pub async fn get_albums_by_artist(&self, artist_id: &str) -> anyhow::Result<Vec<Album>> {
+        self.load_albums(
+            "WHERE al.id IN (
+                 SELECT album_id FROM album_artists WHERE artist_id = ?
+                 UNION
+                 SELECT t.album_id FROM tracks t
+                 INNER JOIN track_artists ta ON ta.track_id = t.id
+                 WHERE ta.artist_id = ?
+             )
+             ORDER BY al.release_year IS NULL, al.release_year, al.sort_key",
+            vec![
+                Value::Text(artist_id.to_string()),
+                Value::Text(artist_id.to_string()),
+            ],
+        )
+        .await
+    }
+
+    /// Get an artist's most played tracks, at most `limit` of them
+    pub async fn get_top_tracks_by_artist(
+        &self,
+        artist_id: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Track>> {
+        self.load_tracks(
+            "INNER JOIN track_artists ta ON t.id = ta.track_id
+             WHERE ta.artist_id = ?
+             ORDER BY t.play_count DESC, t.sort_key
+             LIMIT ?",
+            vec![
+                Value::Text(artist_id.to_string()),
+                Value::Integer(limit as i64),
+            ],
+        )
+        .await
     }
 
     /// Get tracks by artist
     pub async fn get_tracks_by_artist(&self, artist_id: &str) -> anyhow::Result<Vec<Track>> {
-        let rows = self
-            .query(
-                "SELECT t.id, t.title, t.album_id, t.duration, t.path, t.source, t.source_id, t.track_number 
-                 FROM tracks t
-                 INNER JOIN track_artists ta ON t.id = ta.track_id
-                 WHERE ta.artist_id = ?
-                 ORDER BY t.title",
-                vec![Value::Text(artist_id.to_string())],
-            )
-            .await?;
-
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(self.row_to_track(&row).await?);
-        }
-        Ok(tracks)
+        self.load_tracks(
+            "INNER JOIN track_artists ta ON t.id = ta.track_id
+             WHERE ta.artist_id = ?
+             ORDER BY t.sort_key",
+            vec![Value::Text(artist_id.to_string())],
+        )
+        .await
     }
 }
 
 impl Track {
-    pub async fn load(&self) -> anyhow::Result<impl Source + use<>> {
-        match self.source {
+    pub async fn load(&self) -> anyhow::Result<Box<dyn Source + Send>> {
+        let path = match self.source {
             TrackSource::Local => {
                 let Some(path) = &self.path else {
                     return Err(anyhow::anyhow!("Local track missing path"));
                 };
                 if !PathBuf::from(&path).exists() {
-                    Err(anyhow::anyhow!("Local file does not exist: {}", path))
-                } else {
-                    let file = File::open(&path).unwrap();
-                    Ok(Decoder::try_from(file).unwrap())
+                    return Err(anyhow::anyhow!("Local file does not exist: {}", path));
                 }
+                PathBuf::from(path)
             }
             TrackSource::YouTube => {
                 let Some(source_id) = &self.source_id else {
@@ -1196,10 +2357,18 @@ impl Track {
                     // doesn't exist, so download it
                     youtube::download_track_and_save(&self, &path).await?;
                 }
-                let file = File::open(path).unwrap();
-                Ok(Decoder::try_from(file).unwrap())
+                PathBuf::from(path)
             }
-        }
+        };
+        // a file that can't be played only fails this track, not the player
+        let file = File::open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        let decoder = Decoder::try_from(file)
+            .map_err(|e| anyhow::anyhow!("Failed to decode {}: {}", path.display(), e))?;
+        Ok(match self.cue {
+            Some(range) => Box::new(CueSource::new(decoder, range)?),
+            None => Box::new(decoder),
+        })
     }
 }
 
@@ -1217,3 +2386,1215 @@ pub async fn get_library() -> anyhow::Result<&'static Library> {
             .ok_or(anyhow::anyhow!("Library connection not set"))?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        fingerprint::{Fingerprint, FingerprintLookup, Identification},
+        providers::{cue, local},
+        test_audio::{cue_album, temp_dir, tune, write_wav},
+    };
+
+    pub(super) async fn temp_library() -> Library {
+        let path = std::env::temp_dir().join(format!("vibrance-test-{}.db", Ulid::new()));
+        Library::open(&path)
+            .await
+            .expect("Failed to open test library")
+    }
+
+    fn generated_track(i: usize) -> Track {
+        let artist = Artist::new(format!("Artist {}", i / 100));
+        Track {
+            id: Ulid::new().to_string(),
+            title: format!("Track {}", i),
+            artists: vec![artist.clone()],
+            album: Album::new(
+                format!("Album {}", i / 12),
+                vec![artist],
+                Some(1970 + (i % 50) as i32),
+                Some(vec![0xAB; 16 * 1024]),
+            ),
+            duration: 180.0 + (i % 120) as f64,
+            path: Some(format!("/music/{}.flac", i)),
+            source: TrackSource::Local,
+            source_id: None,
+            track_number: Some((i % 12) as i32 + 1),
+            file_size: None,
+            content_hash: None,
+            metadata: TrackMetadata::default(),
+            audio: AudioProperties::default(),
+            cue: None,
+        }
+    }
+
+    /// The read path `all_tracks` used to have: two more queries per row,
+    /// one of them copying the album art into every track
+    async fn all_tracks_per_row(library: &Library) -> Result<Vec<Track>> {
+        let rows = library
+            .query(
+                "SELECT id, title, album_id, duration FROM tracks ORDER BY title",
+                vec![],
+            )
+            .await?;
+        let mut tracks = Vec::new();
+        for row in rows {
+            let id = Library::get_string(&row[0])?;
+            let album_id = Library::get_string(&row[2])?;
+            let artists = library
+                .query_artist_map(
+                    "SELECT link.track_id, ar.id, ar.name, ar.sort_name FROM track_artists link
+                     INNER JOIN artists ar ON ar.id = link.artist_id
+                     WHERE link.track_id = ?",
+                    vec![Value::Text(id.clone())],
+                )
+                .await?
+                .remove(&id)
+                .unwrap_or_default();
+            let album_rows = library
+                .query(
+                    "SELECT id, title, release_year, album_art FROM albums WHERE id = ?",
+                    vec![Value::Text(album_id.clone())],
+                )
+                .await?;
+            let album_row = album_rows.first().expect("Album not found");
+            tracks.push(Track {
+                id,
+                title: Library::get_string(&row[1])?,
+                artists,
+                album: Album {
+                    id: album_id,
+                    title: Library::get_string(&album_row[1])?,
+                    artists: vec![],
+                    release_year: Library::get_optional_i64(&album_row[2]).map(|y| y as i32),
+                    release_date: None,
+                    label: None,
+                    sort_title: None,
+                    album_art: Library::get_optional_blob(&album_row[3]),
+                },
+                duration: Library::get_f64(&row[3])?,
+                path: None,
+                source: TrackSource::Local,
+                source_id: None,
+                track_number: None,
+                file_size: None,
+                content_hash: None,
+                metadata: TrackMetadata::default(),
+                audio: AudioProperties::default(),
+                cue: None,
+            });
+        }
+        Ok(tracks)
+    }
+
+    /// A playlist holding `count` new tracks, in order
+    async fn playlist_with_tracks(library: &Library, count: usize) -> (Playlist, Vec<Track>) {
+        let tracks = library
+            .add_tracks(&(0..count).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        let playlist = library
+            .create_playlist(&Playlist::new("Test".to_string(), None))
+            .await
+            .expect("Failed to create playlist");
+        for track in &tracks {
+            library
+                .add_track_to_playlist(&playlist.id, &track.id)
+                .await
+                .expect("Failed to add track to playlist");
+        }
+        (playlist, tracks)
+    }
+
+    /// Track titles of a playlist's entries in order
+    async fn playlist_titles(library: &Library, playlist_id: &str) -> Vec<String> {
+        library
+            .find_playlist_by_id(playlist_id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found")
+            .entries
+            .into_iter()
+            .map(|entry| entry.track.title)
+            .collect()
+    }
+
+    /// Stored positions of a playlist's entries in order
+    async fn playlist_positions(library: &Library, playlist_id: &str) -> Vec<i64> {
+        library
+            .query(
+                "SELECT position FROM playlist_entries WHERE playlist_id = ? ORDER BY position",
+                vec![Value::Text(playlist_id.to_string())],
+            )
+            .await
+            .expect("Failed to query positions")
+            .iter()
+            .map(|row| Library::get_i64(&row[0]).expect("Position is not an integer"))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn playlist_allows_duplicates() {
+        let library = temp_library().await;
+        let (playlist, tracks) = playlist_with_tracks(&library, 2).await;
+        let entry_id = library
+            .add_track_to_playlist(&playlist.id, &tracks[0].id)
+            .await
+            .expect("Failed to add duplicate");
+
+        let playlist = library
+            .find_playlist_by_id(&playlist.id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found");
+        assert_eq!(playlist.entries.len(), 3);
+        assert_eq!(playlist.entries[0].track.id, tracks[0].id);
+        assert_eq!(playlist.entries[2].track.id, tracks[0].id);
+        assert_eq!(playlist.entries[2].id, entry_id);
+        assert_ne!(playlist.entries[0].id, playlist.entries[2].id);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn playlist_insert_at_position() {
+        let library = temp_library().await;
+        let (playlist, tracks) = playlist_with_tracks(&library, 3).await;
+        library
+            .insert_into_playlist(
+                &playlist.id,
+                1,
+                &[tracks[2].id.clone(), tracks[2].id.clone()],
+            )
+            .await
+            .expect("Failed to insert");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id).await,
+            ["Track 0", "Track 2", "Track 2", "Track 1", "Track 2"]
+        );
+        assert_eq!(
+            playlist_positions(&library, &playlist.id).await,
+            [1, 2, 3, 4, 5]
+        );
+
+        // past the end appends
+        library
+            .insert_into_playlist(&playlist.id, 100, &[tracks[1].id.clone()])
+            .await
+            .expect("Failed to insert");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id)
+                .await
+                .last()
+                .map(String::as_str),
+            Some("Track 1")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn playlist_move_entry() {
+        let library = temp_library().await;
+        let (playlist, _) = playlist_with_tracks(&library, 4).await;
+        let entries = library
+            .find_playlist_by_id(&playlist.id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found")
+            .entries;
+
+        library
+            .move_playlist_entry(&playlist.id, &entries[0].id, 2)
+            .await
+            .expect("Failed to move entry");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id).await,
+            ["Track 1", "Track 2", "Track 0", "Track 3"]
+        );
+        library
+            .move_playlist_entry(&playlist.id, &entries[3].id, 0)
+            .await
+            .expect("Failed to move entry");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id).await,
+            ["Track 3", "Track 1", "Track 2", "Track 0"]
+        );
+        assert_eq!(
+            playlist_positions(&library, &playlist.id).await,
+            [1, 2, 3, 4]
+        );
+        assert!(
+            library
+                .move_playlist_entry(&playlist.id, "missing", 0)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn playlist_reorder() {
+        let library = temp_library().await;
+        let (playlist, _) = playlist_with_tracks(&library, 3).await;
+        let entries = library
+            .find_playlist_by_id(&playlist.id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found")
+            .entries;
+
+        // unlisted entries keep their order after the listed ones
+        library
+            .reorder_playlist(&playlist.id, &[entries[2].id.clone()])
+            .await
+            .expect("Failed to reorder");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id).await,
+            ["Track 2", "Track 0", "Track 1"]
+        );
+        assert_eq!(playlist_positions(&library, &playlist.id).await, [1, 2, 3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn playlist_remove_renumbers() {
+        let library = temp_library().await;
+        let (playlist, tracks) = playlist_with_tracks(&library, 3).await;
+        library
+            .add_track_to_playlist(&playlist.id, &tracks[0].id)
+            .await
+            .expect("Failed to add duplicate");
+        let entries = library
+            .find_playlist_by_id(&playlist.id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found")
+            .entries;
+
+        library
+            .remove_playlist_entry(&playlist.id, &entries[1].id)
+            .await
+            .expect("Failed to remove entry");
+        assert_eq!(
+            playlist_titles(&library, &playlist.id).await,
+            ["Track 0", "Track 2", "Track 0"]
+        );
+        assert_eq!(playlist_positions(&library, &playlist.id).await, [1, 2, 3]);
+
+        // removing a track removes every entry of it
+        library
+            .remove_track_from_playlist(&playlist.id, &tracks[0].id)
+            .await
+            .expect("Failed to remove track");
+        assert_eq!(playlist_titles(&library, &playlist.id).await, ["Track 2"]);
+        assert_eq!(playlist_positions(&library, &playlist.id).await, [1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_counts_plays_and_skips() {
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&(0..2).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        for id in [&tracks[0].id, &tracks[1].id, &tracks[0].id] {
+            library
+                .record_play(id)
+                .await
+                .expect("Failed to record play");
+        }
+        library
+            .record_skip(&tracks[1].id)
+            .await
+            .expect("Failed to record skip");
+        // plays of tracks outside the library aren't kept
+        library
+            .record_play("missing")
+            .await
+            .expect("Failed to record play");
+
+        let stats = library
+            .track_stats(&tracks[0].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 2);
+        assert_eq!(stats.skip_count, 0);
+        assert!(stats.last_played.is_some());
+        let stats = library
+            .track_stats(&tracks[1].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!((stats.play_count, stats.skip_count), (1, 1));
+
+        let recent = library
+            .recently_played(10)
+            .await
+            .expect("Failed to load history");
+        assert_eq!(recent.len(), 3);
+        assert_eq!(
+            recent.iter().filter(|p| p.track.id == tracks[0].id).count(),
+            2
+        );
+
+        let most_played = library
+            .most_played_since(0, 10)
+            .await
+            .expect("Failed to load most played");
+        assert_eq!(most_played[0].track.id, tracks[0].id);
+        assert_eq!(
+            most_played.iter().map(|t| t.plays).collect::<Vec<_>>(),
+            [2, 1]
+        );
+
+        let artists = library
+            .artist_play_totals(0, 10)
+            .await
+            .expect("Failed to load artist totals");
+        assert_eq!(artists.len(), 1);
+        assert_eq!(artists[0].plays, 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ratings_sort_and_filter() {
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&(0..3).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        for (track, rating) in tracks.iter().zip([3, 9, 0]) {
+            library
+                .set_rating(&track.id, rating)
+                .await
+                .expect("Failed to set rating");
+        }
+        library
+            .set_loved(&tracks[2].id, true)
+            .await
+            .expect("Failed to set loved");
+
+        let page = library
+            .tracks_page(TrackSort::Rating, true, TrackFilter::default(), None, 10)
+            .await
+            .expect("Failed to load page");
+        // ratings above the maximum are clamped
+        assert_eq!(
+            page.tracks
+                .iter()
+                .map(|t| t.metadata.rating)
+                .collect::<Vec<_>>(),
+            [rating::MAX_RATING, 3, 0]
+        );
+
+        let filter = TrackFilter {
+            min_rating: 3,
+            loved_only: false,
+        };
+        let page = library
+            .tracks_page(TrackSort::Title, false, filter, None, 10)
+            .await
+            .expect("Failed to load page");
+        assert_eq!(page.tracks.len(), 2);
+
+        let filter = TrackFilter {
+            min_rating: 0,
+            loved_only: true,
+        };
+        let page = library
+            .tracks_page(TrackSort::Title, false, filter, None, 10)
+            .await
+            .expect("Failed to load page");
+        assert_eq!(page.tracks.len(), 1);
+        assert!(page.tracks[0].metadata.loved);
+        assert_eq!(page.tracks[0].id, tracks[2].id);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backup_restores_library() {
+        let library = temp_library().await;
+        let (playlist, tracks) = playlist_with_tracks(&library, 2).await;
+        library
+            .record_play(&tracks[1].id)
+            .await
+            .expect("Failed to record play");
+        library
+            .set_rating(&tracks[0].id, 4)
+            .await
+            .expect("Failed to set rating");
+        let backup = library.export_backup().await.expect("Failed to export");
+        // the backup survives being written out as JSON
+        let backup: backup::Backup =
+            serde_json::from_slice(&serde_json::to_vec(&backup).expect("Failed to serialize"))
+                .expect("Failed to deserialize");
+
+        let restored = temp_library().await;
+        let replaced = restored
+            .add_tracks(&[generated_track(99)])
+            .await
+            .expect("Failed to add tracks")
+            .remove(0)
+            .album
+            .id;
+        restored
+            .album_thumbnail(&replaced, ArtSize::Small)
+            .await
+            .expect("Failed to load thumbnail");
+        let thumbnail = restored.thumbnail_path(&replaced, ArtSize::Small);
+        assert!(thumbnail.exists());
+        let version = restored.art_version(&replaced);
+        let remaps = [backup::PathRemap {
+            from: "/music".to_string(),
+            to: "/mnt/library/".to_string(),
+        }];
+        restored
+            .restore_backup(backup, &remaps)
+            .await
+            .expect("Failed to restore");
+        // covers of the old library are reloaded
+        assert!(!thumbnail.exists());
+        assert!(restored.art_version(&replaced) > version);
+
+        // the library is replaced, not merged
+        let all = restored.all_tracks().await.expect("Failed to load tracks");
+        assert_eq!(all.len(), 2);
+        let track = restored
+            .find_track_by_id(&tracks[0].id)
+            .await
+            .expect("Failed to load track")
+            .expect("Track not restored");
+        assert_eq!(track.metadata.rating, 4);
+        assert_eq!(
+            track.path,
+            Some(format!("/mnt/library{}0.flac", std::path::MAIN_SEPARATOR))
+        );
+        assert_eq!(
+            restored
+                .album_art(&track.album.id)
+                .await
+                .expect("Failed to load art")
+                .map(|art| art.len()),
+            Some(16 * 1024)
+        );
+        assert_eq!(
+            playlist_titles(&restored, &playlist.id).await,
+            ["Track 0", "Track 1"]
+        );
+        let stats = restored
+            .track_stats(&tracks[1].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 1);
+        assert_eq!(
+            restored
+                .recently_played(10)
+                .await
+                .expect("Failed to load history")
+                .len(),
+            1
+        );
+
+        // paths only match whole folders
+        assert_eq!(backup::remap_path("/musical/a.mp3", &remaps), None);
+    }
+
+    /// A directory of its own in the temp directory, holding the given files
+    async fn temp_files(files: &[(&str, &str)]) -> PathBuf {
+        let dir = temp_dir();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents)
+                .await
+                .expect("Failed to write file");
+        }
+        dir
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn import_rhythmbox_library() {
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&(0..2).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        let dir = temp_files(&[
+            (
+                "rhythmdb.xml",
+                r#"<?xml version="1.0" standalone="yes"?>
+                <rhythmdb version="2.0">
+                  <entry type="song">
+                    <title>Track 0</title><artist>Artist 0</artist>
+                    <location>file:///music/0.flac</location>
+                    <rating>4</rating><play-count>7</play-count>
+                    <last-played>1600000000</last-played>
+                  </entry>
+                  <entry type="song">
+                    <title>Track 1</title><artist>Artist 0</artist>
+                    <location>file:///elsewhere/1.flac</location>
+                    <play-count>2</play-count>
+                  </entry>
+                  <entry type="song">
+                    <title>Gone</title><artist>Nobody</artist>
+                    <location>file:///elsewhere/gone.flac</location>
+                  </entry>
+                  <entry type="iradio">
+                    <title>Radio</title><location>http://radio.example</location>
+                  </entry>
+                </rhythmdb>"#,
+            ),
+            (
+                "playlists.xml",
+                r#"<rhythmdb-playlists>
+                  <playlist name="Favourites" type="static">
+                    <location>file:///music/0.flac</location>
+                    <location>file:///elsewhere/gone.flac</location>
+                    <location>file:///elsewhere/1.flac</location>
+                  </playlist>
+                  <playlist name="Recently added" type="automatic"/>
+                </rhythmdb-playlists>"#,
+            ),
+        ])
+        .await;
+        let path = dir.join("rhythmdb.xml");
+        assert_eq!(
+            import::ImportSource::detect(&path).expect("Failed to detect"),
+            Some(import::ImportSource::Rhythmbox)
+        );
+
+        let report = library
+            .import_player_library(import::ImportSource::Rhythmbox, &path, true)
+            .await
+            .expect("Failed to preview import");
+        assert_eq!((report.matched_by_path, report.matched_by_tags), (1, 1));
+        assert_eq!(report.updated, 2);
+        assert_eq!(report.playlists, [("Favourites".to_string(), 2)]);
+        assert_eq!(report.unmatched, ["/elsewhere/gone.flac"]);
+        // a dry run changes nothing
+        let stats = library
+            .track_stats(&tracks[0].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 0);
+        assert!(
+            library
+                .all_playlist_summaries()
+                .await
+                .expect("Failed to load playlists")
+                .is_empty()
+        );
+
+        library
+            .import_player_library(import::ImportSource::Rhythmbox, &path, false)
+            .await
+            .expect("Failed to import");
+        let track = library
+            .find_track_by_id(&tracks[0].id)
+            .await
+            .expect("Failed to load track")
+            .expect("Track not found");
+        assert_eq!(track.metadata.rating, 4);
+        let stats = library
+            .track_stats(&tracks[0].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 7);
+        assert_eq!(stats.last_played, Some(1_600_000_000_000));
+        let playlists = library
+            .all_playlist_summaries()
+            .await
+            .expect("Failed to load playlists");
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(
+            playlist_titles(&library, &playlists[0].id).await,
+            ["Track 0", "Track 1"]
+        );
+
+        // importing again keeps the counts and doesn't duplicate the playlist
+        let report = library
+            .import_player_library(import::ImportSource::Rhythmbox, &path, false)
+            .await
+            .expect("Failed to import again");
+        assert_eq!(report.skipped_playlists, ["Favourites"]);
+        let stats = library
+            .track_stats(&tracks[1].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn import_itunes_library() {
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&(0..2).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        let dir = temp_files(&[(
+            "Library.xml",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE plist PUBLIC "-//Apple Computer//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+            <plist version="1.0">
+            <dict>
+              <key>Major Version</key><integer>1</integer>
+              <key>Tracks</key>
+              <dict>
+                <key>101</key>
+                <dict>
+                  <key>Track ID</key><integer>101</integer>
+                  <key>Name</key><string>Track 1</string>
+                  <key>Artist</key><string>Artist 0</string>
+                  <key>Location</key><string>file://localhost/music/1.flac</string>
+                  <key>Rating</key><integer>100</integer>
+                  <key>Loved</key><true/>
+                  <key>Play Count</key><integer>3</integer>
+                  <key>Skip Count</key><integer>1</integer>
+                  <key>Play Date UTC</key><date>2020-09-13T12:26:40Z</date>
+                </dict>
+                <key>102</key>
+                <dict>
+                  <key>Track ID</key><integer>102</integer>
+                  <key>Name</key><string>Track 0</string>
+                  <key>Rating</key><integer>60</integer>
+                  <key>Rating Computed</key><true/>
+                </dict>
+              </dict>
+              <key>Playlists</key>
+              <array>
+                <dict>
+                  <key>Name</key><string>Library</string>
+                  <key>Master</key><true/>
+                  <key>Playlist Items</key>
+                  <array>
+                    <dict><key>Track ID</key><integer>101</integer></dict>
+                    <dict><key>Track ID</key><integer>102</integer></dict>
+                  </array>
+                </dict>
+                <dict>
+                  <key>Name</key><string>Road trip</string>
+                  <key>Playlist Items</key>
+                  <array>
+                    <dict><key>Track ID</key><integer>101</integer></dict>
+                    <dict><key>Track ID</key><integer>102</integer></dict>
+                    <dict><key>Track ID</key><integer>101</integer></dict>
+                  </array>
+                </dict>
+              </array>
+            </dict>
+            </plist>"#,
+        )])
+        .await;
+        let path = dir.join("Library.xml");
+        assert_eq!(
+            import::ImportSource::detect(&path).expect("Failed to detect"),
+            Some(import::ImportSource::ITunes)
+        );
+
+        let report = library
+            .import_player_library(import::ImportSource::ITunes, &path, false)
+            .await
+            .expect("Failed to import");
+        assert_eq!((report.matched_by_path, report.matched_by_tags), (1, 1));
+        // the computed rating of the second track is its album's, not its own
+        assert_eq!(report.updated, 1);
+        let track = library
+            .find_track_by_id(&tracks[1].id)
+            .await
+            .expect("Failed to load track")
+            .expect("Track not found");
+        assert_eq!(track.metadata.rating, 5);
+        assert!(track.metadata.loved);
+        let stats = library
+            .track_stats(&tracks[1].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!((stats.play_count, stats.skip_count), (3, 1));
+        assert_eq!(stats.last_played, Some(1_600_000_000_000));
+
+        let playlists = library
+            .all_playlist_summaries()
+            .await
+            .expect("Failed to load playlists");
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].name, "Road trip");
+        assert_eq!(
+            playlist_titles(&library, &playlists[0].id).await,
+            ["Track 1", "Track 0", "Track 1"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn import_beets_library() {
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&(0..3).map(generated_track).collect::<Vec<_>>())
+            .await
+            .expect("Failed to add tracks");
+        library
+            .set_rating(&tracks[1].id, 2)
+            .await
+            .expect("Failed to set rating");
+
+        let path = temp_dir().join("library.db");
+        let beets = Builder::new_local(&path.to_string_lossy())
+            .build()
+            .await
+            .expect("Failed to create database")
+            .connect()
+            .expect("Failed to connect");
+        beets
+            .execute_batch(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY, path BLOB, title TEXT, artist TEXT);
+                 CREATE TABLE item_attributes (entity_id INTEGER, key TEXT, value TEXT);
+                 INSERT INTO items VALUES (1, CAST('/music/0.flac' AS BLOB), 'Track 0', 'Artist 0');
+                 INSERT INTO items VALUES (2, CAST('/music/1.flac' AS BLOB), 'Track 1', 'Artist 0');
+                 INSERT INTO items VALUES (3, CAST('/music/2.flac' AS BLOB), 'Track 2', 'Artist 0');
+                 INSERT INTO item_attributes VALUES (1, 'rating', '0.8');
+                 INSERT INTO item_attributes VALUES (1, 'play_count', '5');
+                 INSERT INTO item_attributes VALUES (2, 'rating', '0.5');
+                 INSERT INTO item_attributes VALUES (2, 'play_count', '2');
+                 INSERT INTO item_attributes VALUES (3, 'rating', '1');",
+            )
+            .await
+            .expect("Failed to fill database");
+        // the import reads a copy of the file, so everything has to be in it
+        beets
+            .query("PRAGMA wal_checkpoint(TRUNCATE)", ())
+            .await
+            .expect("Failed to checkpoint");
+        drop(beets);
+        assert_eq!(
+            import::ImportSource::detect(&path).expect("Failed to detect"),
+            Some(import::ImportSource::Beets)
+        );
+
+        let report = library
+            .import_player_library(import::ImportSource::Beets, &path, false)
+            .await
+            .expect("Failed to import");
+        assert_eq!(report.matched_by_path, 3);
+        // ratings are from 0 to 1, and the 0.5 mpdstats starts every track at keeps the
+        // rating set in the library
+        let mut ratings = Vec::new();
+        for track in &tracks {
+            let track = library
+                .find_track_by_id(&track.id)
+                .await
+                .expect("Failed to load track")
+                .expect("Track not found");
+            ratings.push(track.metadata.rating);
+        }
+        assert_eq!(ratings, [4, 2, 5]);
+        let stats = library
+            .track_stats(&tracks[1].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn duplicates_found_and_merged() {
+        let library = temp_library().await;
+        let mut copies: Vec<Track> = (0..3).map(generated_track).collect();
+        copies[0].content_hash = Some("same".to_string());
+        copies[1].content_hash = Some("same".to_string());
+        // a download of the third track, named the way videos usually are
+        let mut video = generated_track(2);
+        video.title = "Artist 0 - Track 2 (Official Video)".to_string();
+        video.artists = vec![Artist::new("Artist 0 - Topic".to_string())];
+        video.duration += 2.0;
+        video.path = None;
+        video.source = TrackSource::YouTube;
+        video.source_id = Some("dQw4w9WgXcQ".to_string());
+        copies.push(video);
+        let tracks = library
+            .add_tracks(&copies)
+            .await
+            .expect("Failed to add tracks");
+
+        let groups = library
+            .find_duplicates()
+            .await
+            .expect("Failed to find duplicates");
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].kind, duplicates::DuplicateKind::SameFile);
+        assert_eq!(groups[1].kind, duplicates::DuplicateKind::SimilarTags);
+        let mut similar: Vec<&str> = groups[1].tracks.iter().map(|t| t.id.as_str()).collect();
+        similar.sort();
+        let mut expected = vec![tracks[2].id.as_str(), tracks[3].id.as_str()];
+        expected.sort();
+        assert_eq!(similar, expected);
+
+        let playlist = library
+            .create_playlist(&Playlist::new("Test".to_string(), None))
+            .await
+            .expect("Failed to create playlist");
+        library
+            .add_track_to_playlist(&playlist.id, &tracks[3].id)
+            .await
+            .expect("Failed to add track to playlist");
+        for id in [&tracks[2].id, &tracks[3].id, &tracks[3].id] {
+            library
+                .record_play(id)
+                .await
+                .expect("Failed to record play");
+        }
+        library
+            .set_rating(&tracks[3].id, 4)
+            .await
+            .expect("Failed to set rating");
+
+        let kept = library
+            .merge_duplicates(&tracks[2].id, &[tracks[2].id.clone(), tracks[3].id.clone()])
+            .await
+            .expect("Failed to merge");
+        assert_eq!(kept.metadata.rating, 4);
+        assert!(
+            library
+                .find_track_by_id(&tracks[3].id)
+                .await
+                .expect("Failed to load track")
+                .is_none()
+        );
+        let stats = library
+            .track_stats(&tracks[2].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 3);
+        assert_eq!(playlist_titles(&library, &playlist.id).await, ["Track 2"]);
+        let groups = library
+            .find_duplicates()
+            .await
+            .expect("Failed to find duplicates");
+        assert_eq!(groups.len(), 1);
+    }
+
+    /// Lookup service that knows a fixed set of fingerprints
+    struct StandInLookup(Vec<(Fingerprint, Identification)>);
+
+    impl FingerprintLookup for StandInLookup {
+        async fn lookup(
+            &self,
+            fingerprint: &Fingerprint,
+            _duration: f64,
+        ) -> Result<Vec<Identification>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(known, _)| known.matches(fingerprint))
+                .map(|(_, identification)| identification.clone())
+                .collect())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fingerprints_match_across_encodings() {
+        let library = temp_library().await;
+        let dir = temp_files(&[]).await;
+        // the same tune in stereo and in quieter mono at half the sample rate
+        write_wav(&dir.join("tune.wav"), &tune(1, 30, 44100), 2, 44100);
+        let quieter: Vec<f32> = tune(1, 30, 22050).iter().map(|s| s * 0.5).collect();
+        write_wav(&dir.join("tune-low.wav"), &quieter, 1, 22050);
+        write_wav(&dir.join("other.wav"), &tune(2, 30, 44100), 1, 44100);
+        write_wav(&dir.join("untagged.wav"), &tune(3, 30, 22050), 1, 22050);
+        let mut files: Vec<Track> = ["tune", "tune-low", "other", "untagged"]
+            .iter()
+            .map(|name| {
+                local::resolve_track(&dir.join(format!("{}.wav", name)).to_string_lossy())
+                    .expect("Failed to read file")
+            })
+            .collect();
+        files[0].artists = vec![Artist::new("Artist 0".to_string())];
+        let tracks = library
+            .add_tracks(&files)
+            .await
+            .expect("Failed to add tracks");
+
+        let groups = library
+            .find_duplicates()
+            .await
+            .expect("Failed to find duplicates");
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].kind, duplicates::DuplicateKind::SimilarAudio);
+        let mut ids: Vec<&str> = groups[0].tracks.iter().map(|t| t.id.as_str()).collect();
+        ids.sort();
+        let mut expected = vec![tracks[0].id.as_str(), tracks[1].id.as_str()];
+        expected.sort();
+        assert_eq!(ids, expected);
+        let stored = library
+            .query(
+                "SELECT COUNT(*) FROM tracks WHERE fingerprint IS NOT NULL",
+                vec![],
+            )
+            .await
+            .expect("Failed to count fingerprints");
+        assert_eq!(Library::get_i64(&stored[0][0]).expect("Not a number"), 4);
+
+        let known = Identification {
+            title: "Found".to_string(),
+            artists: vec!["Stand-in".to_string()],
+            album: None,
+            score: 0.9,
+            source: "Stand-in".to_string(),
+        };
+        let lookup = StandInLookup(vec![(
+            Fingerprint::from_samples(&tune(3, 30, 11025), 1, 11025),
+            known.clone(),
+        )]);
+        // the quieter copy is recognised as the tagged one in the library
+        let found = library
+            .identify_track(&tracks[1], &lookup)
+            .await
+            .expect("Failed to identify");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].source, "Library");
+        assert_eq!(found[0].artists, ["Artist 0"]);
+        let found = library
+            .identify_track(&tracks[3], &lookup)
+            .await
+            .expect("Failed to identify");
+        assert_eq!(found, [known]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cue_sheet_tracks() {
+        let library = temp_library().await;
+        let dir = cue_album();
+        let files = cue::resolve_tracks(&dir.join("album.cue")).expect("Failed to read sheet");
+        let tracks = library
+            .add_tracks(&files)
+            .await
+            .expect("Failed to add tracks");
+        let stored = library
+            .find_track_by_id(&tracks[1].id)
+            .await
+            .expect("Failed to load track")
+            .expect("Track not found");
+        assert_eq!(stored.cue, files[1].cue);
+        assert!(stored.follows(&tracks[0]));
+        assert!(!tracks[0].follows(&stored));
+        // the tracks share a file but aren't the same song
+        assert!(
+            library
+                .find_duplicates()
+                .await
+                .expect("Failed to find duplicates")
+                .is_empty()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark, run with `cargo test --release bench_all_tracks -- --ignored --nocapture`"]
+    async fn bench_all_tracks() {
+        const TRACKS: usize = 50_000;
+        let library = temp_library().await;
+        let tracks: Vec<Track> = (0..TRACKS).map(generated_track).collect();
+        for chunk in tracks.chunks(1000) {
+            library
+                .add_tracks(chunk)
+                .await
+                .expect("Failed to add tracks");
+        }
+
+        let start = Instant::now();
+        let old = all_tracks_per_row(&library)
+            .await
+            .expect("Old read path failed");
+        let old_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        let new = library.all_tracks().await.expect("New read path failed");
+        let new_elapsed = start.elapsed();
+
+        assert_eq!(old.len(), TRACKS);
+        assert_eq!(new.len(), TRACKS);
+        println!(
+            "all_tracks on {} tracks: per-row {:?}, batched {:?}",
+            TRACKS, old_elapsed, new_elapsed
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn edits_split_shared_albums() {
+        let library = temp_library().await;
+        let dir = temp_files(&[]).await;
+        let album = edit::TagEdit {
+            album: Some("Shared".to_string()),
+            ..Default::default()
+        };
+        let files: Vec<Track> = (0..3)
+            .map(|i| {
+                let path = dir.join(format!("{}.wav", i));
+                write_wav(&path, &tune(i, 1, 8000), 1, 8000);
+                local::write_tags(&path, &album).expect("Failed to write tags");
+                local::resolve_track(&path.to_string_lossy()).expect("Failed to read file")
+            })
+            .collect();
+        let tracks = library
+            .add_tracks(&files)
+            .await
+            .expect("Failed to add tracks");
+        let shared = tracks[0].album.id.clone();
+        assert!(tracks.iter().all(|t| t.album.id == shared));
+
+        // only the edited tracks move to a copy of the album with the new album artist
+        let edit = edit::TagEdit {
+            album_artist: Some("Someone".to_string()),
+            year: Some(Some(2001)),
+            ..Default::default()
+        };
+        let report = library
+            .edit_tracks(&tracks[..2], &edit)
+            .await
+            .expect("Failed to edit tracks");
+        assert_eq!(report.updated.len(), 2);
+        assert!(report.skipped.is_empty());
+        let split = report.updated[0].album.id.clone();
+        assert_ne!(split, shared);
+        assert!(report.updated.iter().all(|t| t.album.id == split));
+        let split_album = library
+            .find_album_by_id(&split)
+            .await
+            .expect("Failed to find album")
+            .expect("Album not found");
+        assert_eq!(split_album.title, "Shared");
+        assert_eq!(split_album.release_year, Some(2001));
+        let names: Vec<&str> = split_album
+            .artists
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(names, ["Someone"]);
+        let untouched = library
+            .find_track_by_id(&tracks[2].id)
+            .await
+            .expect("Failed to find track")
+            .expect("Track not found");
+        assert_eq!(untouched.album.id, shared);
+        assert_eq!(untouched.album.release_year, None);
+
+        // the tags are written to the edited files only
+        for (i, expected) in [(0, Some(2001)), (2, None)] {
+            let file = local::resolve_track(&dir.join(format!("{}.wav", i)).to_string_lossy())
+                .expect("Failed to read file");
+            assert_eq!(file.album.release_year, expected);
+            assert_eq!(file.album.title, "Shared");
+        }
+
+        // editing every track of an album keeps it, and moving its last track away deletes it
+        let report = library
+            .edit_tracks(&report.updated, &edit)
+            .await
+            .expect("Failed to edit tracks");
+        assert!(report.updated.iter().all(|t| t.album.id == split));
+        let moved = edit::TagEdit {
+            album: Some("Elsewhere".to_string()),
+            ..Default::default()
+        };
+        let report = library
+            .edit_tracks(std::slice::from_ref(&untouched), &moved)
+            .await
+            .expect("Failed to edit tracks");
+        assert_eq!(report.updated[0].album.title, "Elsewhere");
+        assert!(
+            library
+                .find_album_by_id(&shared)
+                .await
+                .expect("Failed to find album")
+                .is_none()
+        );
+        let file = local::resolve_track(&dir.join("2.wav").to_string_lossy())
+            .expect("Failed to read file");
+        assert_eq!(file.album.title, "Elsewhere");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn smart_rules_run_against_the_library() {
+        let library = temp_library().await;
+        let mut tracks: Vec<Track> = (0..4).map(generated_track).collect();
+        tracks[0].metadata.composer = Some("Bach".to_string());
+        let tracks = library
+            .add_tracks(&tracks)
+            .await
+            .expect("Failed to add tracks");
+        library
+            .execute(
+                "UPDATE tracks SET play_count = 3, last_played = ? WHERE id = ?",
+                vec![
+                    Value::Integer(Utc::now().timestamp_millis()),
+                    Value::Text(tracks[1].id.clone()),
+                ],
+            )
+            .await
+            .expect("Failed to record plays");
+        let titles = |rules: smart::SmartRules| {
+            let library = &library;
+            async move {
+                library
+                    .smart_playlist_tracks(&rules)
+                    .await
+                    .expect("Failed to run rules")
+                    .into_iter()
+                    .map(|t| t.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let rule = |field, operator, value: &str| smart::Rule {
+            field,
+            operator,
+            value: value.to_string(),
+        };
+        let only = |only_rule| smart::SmartRules {
+            rules: vec![only_rule],
+            ..Default::default()
+        };
+
+        // tracks without a composer or plays match the negated rules
+        assert_eq!(
+            titles(only(rule(
+                smart::RuleField::Composer,
+                smart::RuleOperator::IsNot,
+                "bach"
+            )))
+            .await,
+            ["Track 1", "Track 2", "Track 3"]
+        );
+        assert_eq!(
+            titles(only(rule(
+                smart::RuleField::LastPlayed,
+                smart::RuleOperator::NotInTheLast,
+                "30"
+            )))
+            .await,
+            ["Track 0", "Track 2", "Track 3"]
+        );
+        let rules = smart::SmartRules {
+            match_all: false,
+            rules: vec![
+                rule(smart::RuleField::Composer, smart::RuleOperator::Is, "Bach"),
+                rule(
+                    smart::RuleField::PlayCount,
+                    smart::RuleOperator::GreaterThan,
+                    "2",
+                ),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(titles(rules.clone()).await, ["Track 0", "Track 1"]);
+        assert_eq!(
+            library
+                .smart_playlist_count(&rules)
+                .await
+                .expect("Failed to count"),
+            2
+        );
+        let limited = smart::SmartRules {
+            limit: Some(1),
+            descending: true,
+            ..rules
+        };
+        assert_eq!(titles(limited).await, ["Track 1"]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, io::Cursor};
+
+use image::{ImageFormat, ImageReader};
+
+/// Sizes album art is scaled to for display. Each is generated once per album and
+/// cached on disk, see `Library::album_thumbnail`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArtSize {
+    /// Covers in track lists
+    Small,
+    /// Tiles in the album grid and album pages
+    Medium,
+    /// The player bar and the system media controls
+    Large,
+}
+
+impl ArtSize {
+    pub const ALL: [ArtSize; 3] = [ArtSize::Small, ArtSize::Medium, ArtSize::Large];
+
+    /// Largest width or height of the art at this size
+    pub fn pixels(&self) -> u32 {
+        match self {
+            ArtSize::Small => 64,
+            ArtSize::Medium => 200,
+            ArtSize::Large => 512,
+        }
+    }
+}
+
+/// Counts changes to album art while running, so covers cached for display can be told
+/// apart from the current ones. Versions only ever increase.
+#[derive(Debug, Default)]
+pub struct ArtVersions {
+    latest: u64,
+    /// Version of every album, from the last time all art changed at once
+    all: u64,
+    albums: HashMap<String, u64>,
+}
+
+impl ArtVersions {
+    pub fn get(&self, album_id: &str) -> u64 {
+        self.albums.get(album_id).copied().unwrap_or(self.all)
+    }
+
+    /// The art of `album_id` changed
+    pub fn bump(&mut self, album_id: &str) {
+        self.latest += 1;
+        self.albums.insert(album_id.to_string(), self.latest);
+    }
+
+    /// The art of every album changed, e.g. because the library was replaced
+    pub fn bump_all(&mut self) {
+        self.latest += 1;
+        self.all = self.latest;
+        self.albums.clear();
+    }
+}
+
+/// Downscale an encoded image so it fits in `size` x `size`, keeping its aspect ratio.
+///
+/// Returns the image unchanged if it already fits or can't be decoded, so the result can
+/// always be displayed in place of the original.
+pub fn thumbnail(data: &[u8], size: u32) -> Vec<u8> {
+    downscale(data, size).unwrap_or_else(|| data.to_vec())
+}
+
+fn downscale(data: &[u8], size: u32) -> Option<Vec<u8>> {
+    // only the header is read here, most covers in a library are already small enough
+    let (width, height) = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    if width <= size && height <= size {
+        return None;
+    }
+    let image = image::load_from_memory(data).ok()?;
+    let mut encoded = Cursor::new(Vec::new());
+    // covers don't need transparency, and JPEG keeps the thumbnails small
+    image
+        .thumbnail(size, size)
+        .to_rgb8()
+        .write_to(&mut encoded, ImageFormat::Jpeg)
+        .ok()?;
+    Some(encoded.into_inner())
+}
@@ -0,0 +1,378 @@
+use std::{
+    path::{MAIN_SEPARATOR, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chrono::{Local, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, time};
+use turso::Value;
+
+use crate::{
+    library::{LIBRARY, Library, LibraryEvent, MIGRATIONS, search::IndexState},
+    preferences::{PREFERENCES, Preferences},
+};
+
+/// Version of the backup file format, increased when it changes incompatibly
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Tables copied into a backup, parents before the tables referencing them
+const BACKUP_TABLES: &[&str] = &[
+    "artists",
+    "albums",
+    "tracks",
+    "album_artists",
+    "track_artists",
+    "track_genres",
+    "playlists",
+    "playlist_entries",
+    "plays",
+];
+
+/// How often the automatic backup schedule is checked
+const BACKUP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Prefix of the file names of automatic backups
+const AUTOMATIC_BACKUP_PREFIX: &str = "vibrance-backup-";
+
+/// Everything in the library and the preferences, as written to a backup file
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Backup {
+    pub version: u32,
+    /// Number of migrations applied to the database the backup was made from
+    pub schema_version: usize,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    pub tables: Vec<BackupTable>,
+    pub preferences: Option<Preferences>,
+}
+
+/// The rows of one database table. Values are JSON numbers, strings and nulls, blobs
+/// are objects with their bytes in a `base64` field.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BackupTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// Replace the start of local paths when restoring on a machine where the music is
+/// somewhere else, e.g. `D:\Music` with `/home/me/Music`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathRemap {
+    pub from: String,
+    pub to: String,
+}
+
+impl Backup {
+    pub async fn read(path: &Path) -> Result<Self> {
+        let backup: Self = serde_json::from_slice(&fs::read(path).await?)?;
+        if backup.version > BACKUP_VERSION {
+            return Err(anyhow::anyhow!(
+                "Backup was made by a newer version of Vibrance (format {})",
+                backup.version
+            ));
+        }
+        Ok(backup)
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // write next to the destination first so a failed write doesn't replace a backup
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(self)?).await?;
+        fs::rename(&partial, path).await?;
+        Ok(())
+    }
+
+    /// Library roots from the backed up preferences that don't exist on this machine,
+    /// the paths that likely need remapping
+    pub fn missing_roots(&self) -> Vec<String> {
+        self.preferences
+            .iter()
+            .flat_map(|preferences| preferences.library_roots.iter())
+            .filter(|root| !Path::new(root).exists())
+            .cloned()
+            .collect()
+    }
+}
+
+impl Library {
+    /// Copy the whole library and the current preferences into a backup. Keys for online
+    /// services are left out, backups are plain files that are easily shared.
+    pub async fn export_backup(&self) -> Result<Backup> {
+        let mut tables = Vec::new();
+        for &name in BACKUP_TABLES {
+            let columns = self.table_columns(name).await?;
+            let rows = self
+                .query(
+                    &format!("SELECT {} FROM {name}", columns.join(", ")),
+                    vec![],
+                )
+                .await?;
+            tables.push(BackupTable {
+                name: name.to_string(),
+                columns,
+                rows: rows
+                    .iter()
+                    .map(|row| row.iter().map(value_to_json).collect())
+                    .collect(),
+            });
+        }
+        let preferences = match PREFERENCES.get() {
+            Some(preferences) => Some(Preferences {
+                acoustid_api_key: None,
+                ..preferences.read().await.clone()
+            }),
+            None => None,
+        };
+        Ok(Backup {
+            version: BACKUP_VERSION,
+            schema_version: MIGRATIONS.len(),
+            created_at: Utc::now().timestamp_millis(),
+            tables,
+            preferences,
+        })
+    }
+
+    /// Replace the library with the contents of a backup, rewriting local paths with
+    /// `remaps`. The backed up preferences replace the current ones too, except for the
+    /// automatic backup schedule and the keys of this machine.
+    pub async fn restore_backup(&self, backup: Backup, remaps: &[PathRemap]) -> Result<()> {
+        if backup.schema_version > MIGRATIONS.len() {
+            return Err(anyhow::anyhow!(
+                "Backup was made by a newer version of Vibrance (schema {})",
+                backup.schema_version
+            ));
+        }
+        let mut statements: Vec<(String, Vec<Value>)> = BACKUP_TABLES
+            .iter()
+            .rev()
+            .map(|table| (format!("DELETE FROM {table}"), vec![]))
+            .collect();
+        for table in &backup.tables {
+            // only tables and columns this version knows about end up in the SQL
+            let Some(name) = BACKUP_TABLES.iter().find(|&&t| t == table.name) else {
+                eprintln!("Skipping unknown table {} in backup", table.name);
+                continue;
+            };
+            let known = self.table_columns(name).await?;
+            let columns: Vec<(usize, &String)> = table
+                .columns
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| known.contains(*column))
+                .collect();
+            if columns.is_empty() {
+                continue;
+            }
+            let sql = format!(
+                "INSERT INTO {name} ({}) VALUES ({})",
+                columns
+                    .iter()
+                    .map(|(_, column)| column.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                vec!["?"; columns.len()].join(", ")
+            );
+            for row in &table.rows {
+                let mut params = Vec::with_capacity(columns.len());
+                for &(i, column) in &columns {
+                    let value = json_to_value(row.get(i).unwrap_or(&serde_json::Value::Null))?;
+                    params.push(match value {
+                        Value::Text(path) if *name == "tracks" && column == "path" => {
+                            Value::Text(remap_path(&path, remaps).unwrap_or(path))
+                        }
+                        value => value,
+                    });
+                }
+                statements.push((sql.clone(), params));
+            }
+        }
+        self.transaction(statements).await?;
+
+        // everything cached about the old library is stale now
+        self.invalidate_all_art().await?;
+        if let Ok(mut index) = self.search_index.write() {
+            *index = IndexState::Unbuilt;
+        }
+        self.backfill_sort_keys().await?;
+
+        if let Some(mut restored) = backup.preferences
+            && let Some(preferences) = PREFERENCES.get()
+        {
+            let mut preferences = preferences.write().await;
+            for root in &mut restored.library_roots {
+                if let Some(remapped) = remap_path(root, remaps) {
+                    *root = remapped;
+                }
+            }
+            restored.youtube_export_folder = restored
+                .youtube_export_folder
+                .map(|folder| remap_path(&folder, remaps).unwrap_or(folder));
+            restored.backup_interval_hours = preferences.backup_interval_hours;
+            restored.backups_kept = preferences.backups_kept;
+            restored.acoustid_api_key = preferences.acoustid_api_key.clone();
+            *preferences = restored;
+        }
+
+        let _ = self.event_sender.send(LibraryEvent::Restored);
+        Ok(())
+    }
+
+    /// Names of the columns of `table`, in order
+    async fn table_columns(&self, table: &str) -> Result<Vec<String>> {
+        let rows = self
+            .query(&format!("PRAGMA table_info({table})"), vec![])
+            .await?;
+        rows.iter().map(|row| Self::get_string(&row[1])).collect()
+    }
+}
+
+/// `path` with the first matching prefix of `remaps` replaced, `None` if none match.
+/// Prefixes only match whole path components, and the separators of the rest of the
+/// path are changed to this platform's.
+pub fn remap_path(path: &str, remaps: &[PathRemap]) -> Option<String> {
+    remaps.iter().find_map(|remap| {
+        let from = remap.from.trim_end_matches(['/', '\\']);
+        let rest = path.strip_prefix(from)?;
+        if !(rest.is_empty() || rest.starts_with(['/', '\\'])) {
+            return None;
+        }
+        let to = remap.to.trim_end_matches(['/', '\\']);
+        Some(format!(
+            "{to}{}",
+            rest.replace(['/', '\\'], &MAIN_SEPARATOR.to_string())
+        ))
+    })
+}
+
+fn value_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => (*i).into(),
+        Value::Real(f) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Text(s) => serde_json::Value::String(s.clone()),
+        Value::Blob(b) => serde_json::json!({ "base64": BASE64_STANDARD.encode(b) }),
+    }
+}
+
+fn json_to_value(value: &serde_json::Value) -> Result<Value> {
+    Ok(match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(
+                n.as_f64()
+                    .ok_or(anyhow::anyhow!("Invalid number {} in backup", n))?,
+            ),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        serde_json::Value::Object(object) => {
+            let data = object
+                .get("base64")
+                .and_then(|data| data.as_str())
+                .ok_or(anyhow::anyhow!("Invalid value {} in backup", value))?;
+            Value::Blob(BASE64_STANDARD.decode(data)?)
+        }
+        serde_json::Value::Array(_) => {
+            return Err(anyhow::anyhow!("Invalid value {} in backup", value));
+        }
+    })
+}
+
+/// Directory automatic backups are kept in
+pub fn backup_dir() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .ok_or(anyhow::anyhow!("Could not find data directory"))?
+        .join("Vibrance")
+        .join("backups"))
+}
+
+/// File name for a backup made now, e.g. "vibrance-backup-20250301-142500.json"
+pub fn backup_file_name() -> String {
+    format!(
+        "{AUTOMATIC_BACKUP_PREFIX}{}.json",
+        Local::now().format("%Y%m%d-%H%M%S")
+    )
+}
+
+/// Back the library up to `backup_dir` whenever the last backup there is older than
+/// `Preferences::backup_interval_hours`, keeping the newest `Preferences::backups_kept`
+pub async fn run_automatic_backups() {
+    loop {
+        if let Err(e) = automatic_backup().await {
+            eprintln!("Automatic backup failed: {}", e);
+        }
+        time::sleep(BACKUP_CHECK_INTERVAL).await;
+    }
+}
+
+async fn automatic_backup() -> Result<()> {
+    let (interval_hours, kept) = {
+        let preferences = PREFERENCES
+            .get()
+            .ok_or(anyhow::anyhow!("Preferences not initialized"))?
+            .read()
+            .await;
+        (preferences.backup_interval_hours, preferences.backups_kept)
+    };
+    if interval_hours == 0 {
+        return Ok(());
+    }
+    let dir = backup_dir()?;
+    let mut backups = automatic_backups(&dir).await?;
+    let interval = Duration::from_secs(interval_hours as u64 * 60 * 60);
+    let due = backups.last().is_none_or(|(modified, _)| {
+        SystemTime::now()
+            .duration_since(*modified)
+            .is_ok_and(|age| age >= interval)
+    });
+    if !due {
+        return Ok(());
+    }
+
+    let library = LIBRARY
+        .get()
+        .ok_or(anyhow::anyhow!("Library not initialized"))?;
+    let path = dir.join(backup_file_name());
+    library.export_backup().await?.write(&path).await?;
+    backups.push((SystemTime::now(), path));
+
+    let excess = backups.len().saturating_sub(kept.max(1));
+    for (_, old) in backups.drain(..excess) {
+        if let Err(e) = fs::remove_file(&old).await {
+            eprintln!("Failed to delete old backup {}: {}", old.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Automatic backups in `dir` with their modification times, oldest first
+async fn automatic_backups(dir: &Path) -> Result<Vec<(SystemTime, PathBuf)>> {
+    let mut backups = Vec::new();
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return Ok(backups);
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let automatic = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with(AUTOMATIC_BACKUP_PREFIX) && name.ends_with(".json")
+            });
+        if automatic {
+            backups.push((entry.metadata().await?.modified()?, path));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
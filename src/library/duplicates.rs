@@ -0,0 +1,485 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use tokio::task;
+use turso::Value;
+
+use crate::{
+    library::{Library, LibraryEvent, Track, TrackSource, search::normalize},
+    providers::local,
+};
+
+/// How far apart, in seconds, the lengths of two copies of a song can be
+const DURATION_TOLERANCE: f64 = 4.0;
+
+/// Words marking a bracketed part of a title as describing an upload rather than the
+/// recording, e.g. "(Official Music Video)"
+const UPLOAD_WORDS: &[&str] = &[
+    "official",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "visualiser",
+    "hd",
+    "hq",
+    "4k",
+];
+
+/// Why tracks are thought to be the same, from most to least certain
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DuplicateKind {
+    /// The files are identical
+    SameFile,
+    /// The files decode to the same audio, only their tags differ
+    SameAudio,
+    /// The files sound the same but are encoded differently, going by their fingerprints
+    SimilarAudio,
+    /// Same artist and title and about as long, e.g. a local file and a YouTube download
+    /// of the song
+    SimilarTags,
+}
+
+impl DuplicateKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::SameFile => "Identical files",
+            Self::SameAudio => "Same audio, different tags",
+            Self::SimilarAudio => "Same recording, different encoding",
+            Self::SimilarTags => "Same artist and title",
+        }
+    }
+}
+
+/// Tracks that are likely copies of each other
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub tracks: Vec<Track>,
+}
+
+impl Library {
+    /// Find groups of tracks that are likely the same song. A track is only in the most
+    /// certain group it could be in.
+    pub async fn find_duplicates(&self) -> Result<Vec<DuplicateGroup>> {
+        let tracks = self.all_tracks().await?;
+        let mut groups = Vec::new();
+        let mut grouped = HashSet::new();
+
+        // the tracks of a CUE sheet share their file, so only the same part of it counts
+        let mut by_content: HashMap<(&str, Option<u64>), Vec<&Track>> = HashMap::new();
+        for track in &tracks {
+            if let Some(hash) = &track.content_hash {
+                let start = track.cue.map(|range| range.start.to_bits());
+                by_content.entry((hash, start)).or_default().push(track);
+            }
+        }
+        push_groups(
+            &mut groups,
+            &mut grouped,
+            DuplicateKind::SameFile,
+            confirm_same_files(by_content.into_values().collect()).await?,
+        );
+
+        let audio_hashes = self.audio_hashes(&tracks, &grouped).await?;
+        let mut by_audio: HashMap<&str, Vec<&Track>> = HashMap::new();
+        for track in &tracks {
+            if let Some(hash) = audio_hashes.get(&track.id) {
+                by_audio.entry(hash).or_default().push(track);
+            }
+        }
+        push_groups(
+            &mut groups,
+            &mut grouped,
+            DuplicateKind::SameAudio,
+            by_audio.into_values(),
+        );
+
+        // fingerprinting takes a while, so only local tracks about as long as another
+        // one are fingerprinted
+        let mut candidates: Vec<&Track> = tracks
+            .iter()
+            .filter(|t| t.source == TrackSource::Local && t.path.is_some())
+            .filter(|t| !grouped.contains(&t.id))
+            .collect();
+        candidates.sort_by(|a, b| a.duration.total_cmp(&b.duration));
+        let candidates: Vec<&Track> = (0..candidates.len())
+            .filter(|&i| {
+                let close = |a: &Track, b: &Track| b.duration - a.duration <= DURATION_TOLERANCE;
+                (i > 0 && close(candidates[i - 1], candidates[i]))
+                    || (i + 1 < candidates.len() && close(candidates[i], candidates[i + 1]))
+            })
+            .map(|i| candidates[i])
+            .collect();
+        let fingerprints = self.fingerprints(&candidates).await?;
+        let mut similar: Vec<Vec<&Track>> = Vec::new();
+        for track in candidates {
+            let Some(fingerprint) = fingerprints.get(&track.id) else {
+                continue;
+            };
+            // groups are started in order of length, so the last ones are the closest
+            let group = similar
+                .iter_mut()
+                .rev()
+                .take_while(|group| track.duration - group[0].duration <= DURATION_TOLERANCE)
+                .find(|group| fingerprints[&group[0].id].matches(fingerprint));
+            match group {
+                Some(group) => group.push(track),
+                None => similar.push(vec![track]),
+            }
+        }
+        push_groups(
+            &mut groups,
+            &mut grouped,
+            DuplicateKind::SimilarAudio,
+            similar,
+        );
+
+        let mut by_title: HashMap<String, Vec<&Track>> = HashMap::new();
+        for track in tracks.iter().filter(|t| !grouped.contains(&t.id)) {
+            by_title
+                .entry(clean_title(song_title(track)))
+                .or_default()
+                .push(track);
+        }
+        for candidates in by_title.into_values() {
+            let mut similar: Vec<Vec<&Track>> = Vec::new();
+            for track in candidates {
+                match similar.iter_mut().find(|group| is_similar(group[0], track)) {
+                    Some(group) => group.push(track),
+                    None => similar.push(vec![track]),
+                }
+            }
+            push_groups(
+                &mut groups,
+                &mut grouped,
+                DuplicateKind::SimilarTags,
+                similar,
+            );
+        }
+
+        groups.sort_by_cached_key(|group| (group.kind, normalize(&group.tracks[0].title)));
+        Ok(groups)
+    }
+
+    /// Audio hashes of the local tracks that are as long as another local track not
+    /// grouped yet, keyed by track id. Decoding a file takes a while, so hashes are
+    /// stored and only computed for tracks that could have a match.
+    async fn audio_hashes(
+        &self,
+        tracks: &[Track],
+        grouped: &HashSet<String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut stored = HashMap::new();
+        for row in self
+            .query(
+                "SELECT id, audio_hash FROM tracks WHERE audio_hash IS NOT NULL",
+                vec![],
+            )
+            .await?
+        {
+            stored.insert(Self::get_string(&row[0])?, Self::get_string(&row[1])?);
+        }
+
+        let candidates: Vec<&Track> = tracks
+            .iter()
+            .filter(|t| t.source == TrackSource::Local && t.path.is_some())
+            .filter(|t| !grouped.contains(&t.id))
+            .collect();
+        let mut lengths: HashMap<i64, usize> = HashMap::new();
+        for track in &candidates {
+            *lengths.entry(track.duration.round() as i64).or_default() += 1;
+        }
+
+        let mut hashes = HashMap::new();
+        let mut statements = Vec::new();
+        for track in candidates
+            .into_iter()
+            .filter(|t| lengths[&(t.duration.round() as i64)] > 1)
+        {
+            let hash = match stored.remove(&track.id) {
+                Some(hash) => hash,
+                None => {
+                    let path = PathBuf::from(track.path.clone().unwrap_or_default());
+                    let cue = track.cue;
+                    match task::spawn_blocking(move || local::audio_hash(&path, cue)).await? {
+                        Ok(hash) => {
+                            statements.push((
+                                "UPDATE tracks SET audio_hash = ? WHERE id = ?".to_string(),
+                                vec![Value::Text(hash.clone()), Value::Text(track.id.clone())],
+                            ));
+                            hash
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to decode {}: {}", track.title, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            hashes.insert(track.id.clone(), hash);
+        }
+        if !statements.is_empty() {
+            self.transaction(statements).await?;
+        }
+        Ok(hashes)
+    }
+
+    /// Keep the track `keep` and remove `duplicates` from the library. Their plays and
+    /// playlist entries move over to the kept track, their play and skip counts are
+    /// added to its own, and it takes their rating if it has none.
+    pub async fn merge_duplicates(&self, keep: &str, duplicates: &[String]) -> Result<Track> {
+        self.find_track_by_id(keep)
+            .await?
+            .ok_or(anyhow::anyhow!("Track not found"))?;
+        let duplicates: Vec<&String> = duplicates.iter().filter(|id| *id != keep).collect();
+        if !duplicates.is_empty() {
+            let placeholders = vec!["?"; duplicates.len()].join(", ");
+            let ids = || {
+                duplicates
+                    .iter()
+                    .map(|id| Value::Text(id.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            let keep_id = Value::Text(keep.to_string());
+            let statistics = [
+                ids(),
+                ids(),
+                vec![keep_id.clone()],
+                ids(),
+                ids(),
+                ids(),
+                vec![keep_id.clone()],
+            ]
+            .concat();
+            let mut statements = vec![(
+                format!(
+                    "UPDATE tracks SET
+                     play_count = play_count
+                         + (SELECT COALESCE(SUM(play_count), 0) FROM tracks WHERE id IN ({placeholders})),
+                     skip_count = skip_count
+                         + (SELECT COALESCE(SUM(skip_count), 0) FROM tracks WHERE id IN ({placeholders})),
+                     last_played = (SELECT MAX(last_played) FROM tracks
+                                    WHERE id = ? OR id IN ({placeholders})),
+                     rating = CASE WHEN rating > 0 THEN rating
+                              ELSE (SELECT MAX(rating) FROM tracks WHERE id IN ({placeholders})) END,
+                     loved = MAX(loved, (SELECT MAX(loved) FROM tracks WHERE id IN ({placeholders})))
+                     WHERE id = ?"
+                ),
+                statistics,
+            )];
+            for table in ["plays", "playlist_entries"] {
+                statements.push((
+                    format!("UPDATE {table} SET track_id = ? WHERE track_id IN ({placeholders})"),
+                    [vec![keep_id.clone()], ids()].concat(),
+                ));
+            }
+            for table in ["track_artists", "track_genres"] {
+                statements.push((
+                    format!("DELETE FROM {table} WHERE track_id IN ({placeholders})"),
+                    ids(),
+                ));
+            }
+            statements.push((
+                format!("DELETE FROM tracks WHERE id IN ({placeholders})"),
+                ids(),
+            ));
+            self.transaction(statements).await?;
+            for id in &duplicates {
+                self.unindex_track(id);
+            }
+        }
+
+        let track = self
+            .find_track_by_id(keep)
+            .await?
+            .ok_or(anyhow::anyhow!("Track not found"))?;
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::TracksUpdated(vec![track.clone()]));
+        let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        Ok(track)
+    }
+}
+
+/// Add a group for every set of `candidates` with more than one track not grouped yet
+/// Split tracks whose content hashes match by hashes of their whole files. The content
+/// hash only samples large files, so files that differ elsewhere can share it. Tracks
+/// whose file can't be read are left out, to be compared by their audio instead.
+async fn confirm_same_files(candidates: Vec<Vec<&Track>>) -> Result<Vec<Vec<&Track>>> {
+    let mut confirmed = Vec::new();
+    for tracks in candidates.into_iter().filter(|tracks| tracks.len() > 1) {
+        let paths: Vec<PathBuf> = tracks
+            .iter()
+            .map(|t| PathBuf::from(t.path.clone().unwrap_or_default()))
+            .collect();
+        let hashes = task::spawn_blocking(move || {
+            paths
+                .iter()
+                .map(|path| local::full_content_hash(path).ok())
+                .collect::<Vec<_>>()
+        })
+        .await?;
+        let mut by_hash: HashMap<String, Vec<&Track>> = HashMap::new();
+        for (track, hash) in tracks.into_iter().zip(hashes) {
+            if let Some(hash) = hash {
+                by_hash.entry(hash).or_default().push(track);
+            }
+        }
+        confirmed.extend(by_hash.into_values());
+    }
+    Ok(confirmed)
+}
+
+fn push_groups<'a>(
+    groups: &mut Vec<DuplicateGroup>,
+    grouped: &mut HashSet<String>,
+    kind: DuplicateKind,
+    candidates: impl IntoIterator<Item = Vec<&'a Track>>,
+) {
+    for tracks in candidates {
+        let tracks: Vec<Track> = tracks
+            .into_iter()
+            .filter(|t| !grouped.contains(&t.id))
+            .cloned()
+            .collect();
+        if tracks.len() > 1 {
+            grouped.extend(tracks.iter().map(|t| t.id.clone()));
+            groups.push(DuplicateGroup { kind, tracks });
+        }
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// An artist name as it is compared, without the suffixes of YouTube channels
+fn clean_artist(name: &str) -> String {
+    let name = words(name).join(" ");
+    let name = name.strip_suffix(" topic").unwrap_or(&name);
+    name.strip_suffix("vevo").unwrap_or(name).trim().to_string()
+}
+
+/// The track's title without an "Artist - " prefix naming one of its artists, as is
+/// common in the titles of videos
+fn song_title(track: &Track) -> &str {
+    match track.title.split_once(" - ") {
+        Some((artist, title))
+            if track
+                .artists
+                .iter()
+                .any(|a| clean_artist(&a.name) == clean_artist(artist)) =>
+        {
+            title
+        }
+        _ => &track.title,
+    }
+}
+
+/// A title as it is compared: bracketed parts about the upload are left out, and case
+/// and punctuation are ignored, so "Song (Official Video)" and "song" are the same
+fn clean_title(title: &str) -> String {
+    let mut kept = String::new();
+    let mut rest = title;
+    while let Some(open) = rest.find(['(', '[']) {
+        let close = if rest[open..].starts_with('(') {
+            ')'
+        } else {
+            ']'
+        };
+        let Some(length) = rest[open..].find(close) else {
+            break;
+        };
+        kept.push_str(&rest[..open]);
+        let inner = &rest[open + 1..open + length];
+        // other bracketed parts, e.g. "(Live)", tell recordings apart
+        if !words(inner)
+            .iter()
+            .any(|word| UPLOAD_WORDS.contains(&word.as_str()))
+        {
+            kept.push(' ');
+            kept.push_str(inner);
+        }
+        rest = &rest[open + length + 1..];
+    }
+    kept.push_str(rest);
+    words(&kept).join(" ")
+}
+
+/// Whether two tracks with the same title are by the same artist and about as long.
+/// Unknown lengths and tracks without artists match anything.
+fn is_similar(a: &Track, b: &Track) -> bool {
+    let lengths_match = a.duration <= 0.0
+        || b.duration <= 0.0
+        || (a.duration - b.duration).abs() <= DURATION_TOLERANCE;
+    let artists = |track: &Track| -> HashSet<String> {
+        track
+            .artists
+            .iter()
+            .map(|artist| clean_artist(&artist.name))
+            .filter(|name| !name.is_empty())
+            .collect()
+    };
+    let (artists_a, artists_b) = (artists(a), artists(b));
+    lengths_match
+        && (artists_a.is_empty() || artists_b.is_empty() || !artists_a.is_disjoint(&artists_b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        library::tests::temp_library,
+        test_audio::{temp_dir, tune, write_wav},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn same_file_confirmed_by_whole_content() {
+        let dir = temp_dir();
+        // large enough that the content hash only samples it
+        write_wav(&dir.join("a.wav"), &tune(1, 10, 44100), 1, 44100);
+        std::fs::copy(dir.join("a.wav"), dir.join("copy.wav")).expect("Failed to copy file");
+        // differs only between the parts the content hash reads
+        let mut changed = std::fs::read(dir.join("a.wav")).expect("Failed to read file");
+        changed[280_000] ^= 0xff;
+        std::fs::write(dir.join("changed.wav"), changed).expect("Failed to write file");
+        let files: Vec<Track> = ["a.wav", "copy.wav", "changed.wav"]
+            .iter()
+            .map(|name| {
+                local::resolve_track(&dir.join(name).to_string_lossy())
+                    .expect("Failed to read file")
+            })
+            .collect();
+        assert_eq!(files[0].content_hash, files[2].content_hash);
+
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&files)
+            .await
+            .expect("Failed to add tracks");
+        let groups = library
+            .find_duplicates()
+            .await
+            .expect("Failed to find duplicates");
+        let same_file: Vec<Vec<&str>> = groups
+            .iter()
+            .filter(|group| group.kind == DuplicateKind::SameFile)
+            .map(|group| group.tracks.iter().map(|t| t.id.as_str()).collect())
+            .collect();
+        assert_eq!(same_file.len(), 1);
+        let mut ids = same_file[0].clone();
+        ids.sort();
+        let mut expected = vec![tracks[0].id.as_str(), tracks[1].id.as_str()];
+        expected.sort();
+        assert_eq!(ids, expected);
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use tokio::task;
+use turso::Value;
+use ulid::Ulid;
+
+use crate::{
+    library::{
+        Artist, Library, LibraryEvent, MAX_IN_PARAMS, Track, TrackSource, optional_integer,
+        optional_text, sort::sort_key,
+    },
+    providers::local,
+};
+
+/// Changes to make to one or more tracks. Fields left as `None` are not touched,
+/// so the same edit can be applied to a whole selection (e.g. only the album artist).
+/// Fields wrapping another `Option` can be cleared by setting them to `Some(None)`.
+#[derive(Clone, Debug, Default)]
+pub struct TagEdit {
+    pub title: Option<String>,
+    pub artists: Option<Vec<String>>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<Option<i32>>,
+    pub track_number: Option<Option<i32>>,
+    pub disc_number: Option<Option<i32>>,
+    pub genres: Option<Vec<String>>,
+    pub composer: Option<Option<String>>,
+    /// New front cover, as encoded image data
+    pub cover: Option<Vec<u8>>,
+}
+
+impl TagEdit {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artists.is_none()
+            && self.album.is_none()
+            && self.album_artist.is_none()
+            && self.year.is_none()
+            && self.track_number.is_none()
+            && self.disc_number.is_none()
+            && self.genres.is_none()
+            && self.composer.is_none()
+            && self.cover.is_none()
+    }
+
+    /// Apply the track level fields to `track`. Artists and album are resolved against
+    /// the library separately.
+    fn apply(&self, track: &mut Track) {
+        if let Some(title) = &self.title {
+            track.title = title.clone();
+        }
+        if let Some(track_number) = self.track_number {
+            track.track_number = track_number;
+        }
+        if let Some(disc_number) = self.disc_number {
+            track.metadata.disc_number = disc_number;
+        }
+        if let Some(genres) = &self.genres {
+            track.metadata.genres = genres.clone();
+        }
+        if let Some(composer) = &self.composer {
+            track.metadata.composer = composer.clone();
+        }
+    }
+}
+
+/// Result of applying a `TagEdit`
+#[derive(Debug, Clone, Default)]
+pub struct EditReport {
+    /// Tracks as they are in the library after the edit
+    pub updated: Vec<Track>,
+    /// Tracks that were left unchanged, with the reason
+    pub skipped: Vec<(Track, String)>,
+}
+
+/// Path of the file the edit can be written to, or why the track can't be edited
+fn editable_path(track: &Track) -> std::result::Result<PathBuf, String> {
+    if track.source == TrackSource::YouTube {
+        return Err("YouTube tracks are cached downloads and can't be edited".to_string());
+    }
+    if track.cue.is_some() {
+        return Err("Tracks from a CUE sheet share one file and can't be edited".to_string());
+    }
+    let Some(path) = &track.path else {
+        return Err("Track has no file".to_string());
+    };
+    let path = PathBuf::from(path);
+    let metadata = std::fs::metadata(&path).map_err(|_| "File not found".to_string())?;
+    if metadata.permissions().readonly() {
+        return Err("File is read-only".to_string());
+    }
+    Ok(path)
+}
+
+impl Library {
+    /// Apply `edit` to the given tracks, writing it to their files first and then to the
+    /// library. Tracks that can't be written (YouTube tracks, missing or read-only files)
+    /// are skipped and reported rather than failing the whole batch.
+    ///
+    /// The album artist, year and cover are stored per album. Edited tracks that share
+    /// an album with tracks left out of the edit are moved to a copy of it first, so the
+    /// library keeps matching the files' tags.
+    pub async fn edit_tracks(&self, tracks: &[Track], edit: &TagEdit) -> Result<EditReport> {
+        let mut report = EditReport::default();
+        if edit.is_empty() {
+            return Ok(report);
+        }
+
+        let mut writable = Vec::new();
+        for track in tracks {
+            match editable_path(track) {
+                Ok(path) => writable.push((track.clone(), path)),
+                Err(reason) => report.skipped.push((track.clone(), reason)),
+            }
+        }
+
+        // write the files first, a track only changes in the library if its file did
+        let file_edit = edit.clone();
+        let written = task::spawn_blocking(move || {
+            writable
+                .into_iter()
+                .map(|(track, path)| {
+                    let result = local::write_tags(&path, &file_edit).and_then(|_| {
+                        let file_size = std::fs::metadata(&path)?.len();
+                        Ok((file_size, local::content_hash(&path)?))
+                    });
+                    (track, result)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await?;
+        let mut edited = Vec::new();
+        for (track, result) in written {
+            match result {
+                Ok((file_size, content_hash)) => {
+                    let mut track = track;
+                    track.file_size = Some(file_size);
+                    track.content_hash = Some(content_hash);
+                    edited.push(track);
+                }
+                Err(e) => report.skipped.push((track, e.to_string())),
+            }
+        }
+        if edited.is_empty() {
+            return Ok(report);
+        }
+
+        let statements = self.edit_statements(&mut edited, edit).await?;
+        self.transaction(statements).await?;
+        if edit.cover.is_some() {
+            let album_ids: Vec<String> = edited.iter().map(|t| t.album.id.clone()).collect();
+            self.invalidate_art(&album_ids)?;
+        }
+
+        let ids: Vec<String> = edited.iter().map(|t| t.id.clone()).collect();
+        for chunk in ids.chunks(MAX_IN_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            report.updated.extend(
+                self.load_tracks(
+                    &format!("WHERE t.id IN ({placeholders})"),
+                    chunk.iter().cloned().map(Value::Text).collect(),
+                )
+                .await?,
+            );
+        }
+        self.index_tracks(&report.updated);
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::TracksUpdated(report.updated.clone()));
+        Ok(report)
+    }
+
+    /// Statements that store the edited tracks. Moves each track to its new album
+    /// (creating it if needed) and updates `album_id` on the tracks accordingly.
+    /// Albums the edit leaves without tracks are deleted.
+    async fn edit_statements(
+        &self,
+        tracks: &mut [Track],
+        edit: &TagEdit,
+    ) -> Result<Vec<(String, Vec<Value>)>> {
+        let mut artist_names: Vec<String> = edit.artists.clone().unwrap_or_default();
+        artist_names.extend(edit.album_artist.clone());
+        let mut artist_ids = self.ids_by_name("artists", "name", artist_names).await?;
+        let album_ids = match &edit.album {
+            Some(title) => {
+                self.ids_by_name("albums", "title", vec![title.clone()])
+                    .await?
+            }
+            None => HashMap::new(),
+        };
+        let mut previous_albums: Vec<String> = tracks.iter().map(|t| t.album.id.clone()).collect();
+        previous_albums.sort();
+        previous_albums.dedup();
+
+        let mut statements = Vec::new();
+        // album created by this edit when no album has the new title yet
+        let mut new_album: Option<String> = None;
+        for track in tracks.iter_mut() {
+            edit.apply(track);
+
+            if let Some(title) = &edit.album
+                && *title != track.album.title
+            {
+                let album_id = match album_ids.get(title) {
+                    Some(id) => id.clone(),
+                    None => match new_album.clone() {
+                        Some(id) => id,
+                        None => {
+                            // a new album keeps everything but the title from the old one
+                            let id = Ulid::new().to_string();
+                            statements.extend(Self::copy_album(&track.album.id, &id, Some(title)));
+                            new_album = Some(id.clone());
+                            id
+                        }
+                    },
+                };
+                track.album.id = album_id;
+                track.album.title = title.clone();
+            }
+        }
+        if edit.album_artist.is_some() || edit.year.is_some() || edit.cover.is_some() {
+            self.split_albums(tracks, &mut statements).await?;
+        }
+
+        for track in tracks.iter() {
+            statements.push((
+                "UPDATE tracks SET title = ?, sort_key = ?, album_id = ?, track_number = ?, disc_number = ?,
+                 composer = ?, file_size = ?, content_hash = ? WHERE id = ?"
+                    .to_string(),
+                vec![
+                    Value::Text(track.title.clone()),
+                    Value::Text(sort_key(
+                        &track.title,
+                        track.metadata.sort_title.as_deref(),
+                    )),
+                    Value::Text(track.album.id.clone()),
+                    optional_integer(track.track_number),
+                    optional_integer(track.metadata.disc_number),
+                    optional_text(&track.metadata.composer),
+                    optional_integer(track.file_size.map(|s| s as i64)),
+                    optional_text(&track.content_hash),
+                    Value::Text(track.id.clone()),
+                ],
+            ));
+
+            if let Some(names) = &edit.artists {
+                statements.push((
+                    "DELETE FROM track_artists WHERE track_id = ?".to_string(),
+                    vec![Value::Text(track.id.clone())],
+                ));
+                for name in names {
+                    let artist = Self::resolve_artist(
+                        &Artist::new(name.clone()),
+                        &mut artist_ids,
+                        &mut statements,
+                    );
+                    statements.push((
+                        "INSERT OR IGNORE INTO track_artists (track_id, artist_id) VALUES (?, ?)"
+                            .to_string(),
+                        vec![Value::Text(track.id.clone()), Value::Text(artist.id)],
+                    ));
+                }
+            }
+
+            if edit.genres.is_some() {
+                statements.push((
+                    "DELETE FROM track_genres WHERE track_id = ?".to_string(),
+                    vec![Value::Text(track.id.clone())],
+                ));
+                for genre in &track.metadata.genres {
+                    statements.push((
+                        "INSERT OR IGNORE INTO track_genres (track_id, genre) VALUES (?, ?)"
+                            .to_string(),
+                        vec![Value::Text(track.id.clone()), Value::Text(genre.clone())],
+                    ));
+                }
+            }
+        }
+
+        let mut album_ids: Vec<String> = tracks.iter().map(|t| t.album.id.clone()).collect();
+        album_ids.sort();
+        album_ids.dedup();
+        for album_id in album_ids {
+            if let Some(name) = &edit.album_artist {
+                let artist = Self::resolve_artist(
+                    &Artist::new(name.clone()),
+                    &mut artist_ids,
+                    &mut statements,
+                );
+                statements.push((
+                    "DELETE FROM album_artists WHERE album_id = ?".to_string(),
+                    vec![Value::Text(album_id.clone())],
+                ));
+                statements.push((
+                    "INSERT INTO album_artists (album_id, artist_id) VALUES (?, ?)".to_string(),
+                    vec![Value::Text(album_id.clone()), Value::Text(artist.id)],
+                ));
+            }
+            if let Some(year) = edit.year {
+                statements.push((
+                    "UPDATE albums SET release_year = ?, release_date = ? WHERE id = ?".to_string(),
+                    vec![
+                        optional_integer(year),
+                        optional_text(&year.map(|y| y.to_string())),
+                        Value::Text(album_id.clone()),
+                    ],
+                ));
+            }
+            if let Some(cover) = &edit.cover {
+                statements.push((
+                    "UPDATE albums SET album_art = ? WHERE id = ?".to_string(),
+                    vec![Value::Blob(cover.clone()), Value::Text(album_id.clone())],
+                ));
+            }
+        }
+
+        // albums whose tracks have all moved to another album
+        for album_id in previous_albums {
+            statements.push((
+                "DELETE FROM album_artists WHERE album_id = ?
+                 AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = ?)"
+                    .to_string(),
+                vec![Value::Text(album_id.clone()), Value::Text(album_id.clone())],
+            ));
+            statements.push((
+                "DELETE FROM albums WHERE id = ?
+                 AND NOT EXISTS (SELECT 1 FROM tracks WHERE album_id = ?)"
+                    .to_string(),
+                vec![Value::Text(album_id.clone()), Value::Text(album_id)],
+            ));
+        }
+        Ok(statements)
+    }
+
+    /// Give edited tracks that share their album with tracks outside the edit an album
+    /// of their own, so album level changes (album artist, year, cover) only apply to
+    /// tracks whose files were written.
+    async fn split_albums(
+        &self,
+        tracks: &mut [Track],
+        statements: &mut Vec<(String, Vec<Value>)>,
+    ) -> Result<()> {
+        let edited: HashSet<String> = tracks.iter().map(|t| t.id.clone()).collect();
+        let mut album_ids: Vec<String> = tracks.iter().map(|t| t.album.id.clone()).collect();
+        album_ids.sort();
+        album_ids.dedup();
+        let mut shared = HashSet::new();
+        for chunk in album_ids.chunks(MAX_IN_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let rows = self
+                .query(
+                    &format!("SELECT album_id, id FROM tracks WHERE album_id IN ({placeholders})"),
+                    chunk.iter().cloned().map(Value::Text).collect(),
+                )
+                .await?;
+            for row in rows {
+                if !edited.contains(&Self::get_string(&row[1])?) {
+                    shared.insert(Self::get_string(&row[0])?);
+                }
+            }
+        }
+
+        let mut copies: HashMap<String, String> = HashMap::new();
+        for track in tracks.iter_mut() {
+            if !shared.contains(&track.album.id) {
+                continue;
+            }
+            let copy = copies.entry(track.album.id.clone()).or_insert_with(|| {
+                let id = Ulid::new().to_string();
+                statements.extend(Self::copy_album(&track.album.id, &id, None));
+                id
+            });
+            track.album.id = copy.clone();
+        }
+        Ok(())
+    }
+
+    /// Statements creating album `id` as a copy of album `from`, including its art and
+    /// album artists. With a `title` the copy gets that title and no sort title.
+    fn copy_album(from: &str, id: &str, title: Option<&str>) -> Vec<(String, Vec<Value>)> {
+        let insert = match title {
+            Some(title) => (
+                "INSERT INTO albums (id, title, release_year, release_date, label, album_art, sort_title, sort_key)
+                 SELECT ?, ?, release_year, release_date, label, album_art, NULL, ? FROM albums WHERE id = ?"
+                    .to_string(),
+                vec![
+                    Value::Text(id.to_string()),
+                    Value::Text(title.to_string()),
+                    Value::Text(sort_key(title, None)),
+                    Value::Text(from.to_string()),
+                ],
+            ),
+            None => (
+                "INSERT INTO albums (id, title, release_year, release_date, label, album_art, sort_title, sort_key)
+                 SELECT ?, title, release_year, release_date, label, album_art, sort_title, sort_key FROM albums WHERE id = ?"
+                    .to_string(),
+                vec![Value::Text(id.to_string()), Value::Text(from.to_string())],
+            ),
+        };
+        vec![
+            insert,
+            (
+                "INSERT OR IGNORE INTO album_artists (album_id, artist_id)
+                 SELECT ?, artist_id FROM album_artists WHERE album_id = ?"
+                    .to_string(),
+                vec![Value::Text(id.to_string()), Value::Text(from.to_string())],
+            ),
+        ]
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tokio::{fs, task};
+use turso::Value;
+use walkdir::WalkDir;
+
+use crate::{
+    library::{Library, LibraryEvent, Track, TrackSource},
+    providers::local,
+};
+
+/// Result of trying to relocate missing tracks
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    /// Tracks that were found at a new location and relinked
+    pub relocated: Vec<Track>,
+    /// Tracks that could not be found anywhere
+    pub missing: Vec<Track>,
+}
+
+impl Library {
+    /// Find local tracks whose file no longer exists
+    pub async fn find_missing_tracks(&self) -> Result<Vec<Track>> {
+        let mut missing = Vec::new();
+        for track in self.find_tracks_by_source(TrackSource::Local).await? {
+            let exists = match &track.path {
+                Some(path) => fs::try_exists(path).await.unwrap_or(false),
+                None => false,
+            };
+            if !exists {
+                missing.push(track);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Search the given roots for files matching missing tracks and relink the ones
+    /// that can be identified, first by size and content hash, then by file name and tags
+    pub async fn relocate_missing_tracks(&self, roots: &[PathBuf]) -> Result<HealthReport> {
+        let missing = self.find_missing_tracks().await?;
+        if missing.is_empty() {
+            return Ok(HealthReport::default());
+        }
+        let roots = roots.to_vec();
+        let matches = task::spawn_blocking(move || find_relocations(missing, &roots)).await?;
+
+        let mut report = HealthReport::default();
+        for (mut track, found) in matches {
+            match found {
+                Some(path) => {
+                    track.path = Some(path);
+                    report.relocated.push(track);
+                }
+                None => report.missing.push(track),
+            }
+        }
+        self.update_track_paths(&report.relocated).await?;
+        if !report.relocated.is_empty() {
+            let _ = self
+                .event_sender
+                .send(LibraryEvent::TracksUpdated(report.relocated.clone()));
+        }
+        Ok(report)
+    }
+
+    /// Point every local track under `from` at the same relative path under `to`,
+    /// e.g. after a music folder was moved. Ids, playlists and play counts are kept.
+    /// Tracks whose file isn't at the new path are left as they are and reported as
+    /// missing, so a mistyped folder doesn't unlink the whole library.
+    pub async fn relink_prefix(&self, from: &Path, to: &Path) -> Result<HealthReport> {
+        let mut report = HealthReport::default();
+        for mut track in self.find_tracks_by_source(TrackSource::Local).await? {
+            let Some(path) = &track.path else {
+                continue;
+            };
+            let Ok(relative) = Path::new(path).strip_prefix(from) else {
+                continue;
+            };
+            let new_path = to.join(relative);
+            if !fs::try_exists(&new_path).await.unwrap_or(false) {
+                report.missing.push(track);
+                continue;
+            }
+            track.path = Some(new_path.to_string_lossy().to_string());
+            report.relocated.push(track);
+        }
+        self.update_track_paths(&report.relocated).await?;
+        if !report.relocated.is_empty() {
+            let _ = self
+                .event_sender
+                .send(LibraryEvent::TracksUpdated(report.relocated.clone()));
+        }
+        Ok(report)
+    }
+
+    /// Point tracks at their new `path`, all of them or none
+    async fn update_track_paths(&self, tracks: &[Track]) -> Result<()> {
+        if tracks.is_empty() {
+            return Ok(());
+        }
+        let statements = tracks
+            .iter()
+            .map(|track| {
+                (
+                    "UPDATE tracks SET path = ? WHERE id = ?".to_string(),
+                    vec![
+                        Value::Text(track.path.clone().unwrap_or_default()),
+                        Value::Text(track.id.clone()),
+                    ],
+                )
+            })
+            .collect();
+        self.transaction(statements).await
+    }
+}
+
+fn find_relocations(missing: Vec<Track>, roots: &[PathBuf]) -> Vec<(Track, Option<String>)> {
+    // index everything under the roots once, by size and by file name
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut by_name: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+    for root in roots {
+        for entry in WalkDir::new(root)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                by_size
+                    .entry(metadata.len())
+                    .or_default()
+                    .push(entry.path().to_path_buf());
+            }
+            by_name
+                .entry(entry.file_name().to_os_string())
+                .or_default()
+                .push(entry.path().to_path_buf());
+        }
+    }
+
+    let mut hashes: HashMap<PathBuf, Option<String>> = HashMap::new();
+    missing
+        .into_iter()
+        .map(|track| {
+            let found = find_relocation(&track, &by_size, &by_name, &mut hashes)
+                .map(|p| p.to_string_lossy().to_string());
+            (track, found)
+        })
+        .collect()
+}
+
+fn find_relocation(
+    track: &Track,
+    by_size: &HashMap<u64, Vec<PathBuf>>,
+    by_name: &HashMap<OsString, Vec<PathBuf>>,
+    hashes: &mut HashMap<PathBuf, Option<String>>,
+) -> Option<PathBuf> {
+    // identical content is the strongest signal
+    if let (Some(size), Some(hash)) = (track.file_size, &track.content_hash) {
+        for candidate in by_size.get(&size).into_iter().flatten() {
+            let candidate_hash = hashes
+                .entry(candidate.clone())
+                .or_insert_with(|| local::content_hash(candidate).ok());
+            if candidate_hash.as_ref() == Some(hash) {
+                return Some(candidate.clone());
+            }
+        }
+    }
+    // otherwise the file may have been retagged, so fall back to the name and tags
+    let name = Path::new(track.path.as_ref()?).file_name()?;
+    for candidate in by_name.get(name).into_iter().flatten() {
+        let Some(candidate_path) = candidate.to_str() else {
+            continue;
+        };
+        let Ok(resolved) = local::resolve_track(candidate_path) else {
+            continue;
+        };
+        if tags_match(track, &resolved) {
+            return Some(candidate.clone());
+        }
+    }
+    None
+}
+
+fn tags_match(a: &Track, b: &Track) -> bool {
+    a.title == b.title
+        && a.album.title == b.album.title
+        && a.artists_string() == b.artists_string()
+        && (a.duration - b.duration).abs() < 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        library::{Playlist, tests::temp_library},
+        test_audio::{temp_dir, tune, write_wav},
+    };
+
+    /// A library holding a local track for each name, read from a WAV file of its own
+    /// tune in `dir`
+    async fn library_with_files(dir: &Path, names: &[&str]) -> (Library, Vec<Track>) {
+        let files: Vec<Track> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let path = dir.join(name);
+                write_wav(&path, &tune(i as u64, 5, 8000), 1, 8000);
+                local::resolve_track(&path.to_string_lossy()).expect("Failed to read file")
+            })
+            .collect();
+        let library = temp_library().await;
+        let tracks = library
+            .add_tracks(&files)
+            .await
+            .expect("Failed to add tracks");
+        (library, tracks)
+    }
+
+    async fn stored_path(library: &Library, id: &str) -> Option<String> {
+        library
+            .find_track_by_id(id)
+            .await
+            .expect("Failed to load track")
+            .expect("Track not found")
+            .path
+    }
+
+    /// Ids of the tracks, sorted as the library returns them in no particular order
+    fn ids(tracks: &[Track]) -> Vec<&str> {
+        let mut ids: Vec<&str> = tracks.iter().map(|t| t.id.as_str()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_tracks_found() {
+        let dir = temp_dir();
+        let (library, tracks) = library_with_files(&dir, &["a.wav", "b.wav"]).await;
+        std::fs::remove_file(dir.join("b.wav")).expect("Failed to remove file");
+
+        let missing = library
+            .find_missing_tracks()
+            .await
+            .expect("Failed to find missing tracks");
+        assert_eq!(ids(&missing), [tracks[1].id.as_str()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_tracks_relocated_by_hash_then_tags() {
+        let (old, new) = (temp_dir(), temp_dir());
+        let (library, tracks) = library_with_files(&old, &["a.wav", "b.wav", "c.wav"]).await;
+        // `a` is renamed, so only its content identifies it
+        std::fs::rename(old.join("a.wav"), new.join("renamed.wav")).expect("Failed to move");
+        // `b` keeps its name but its content changed, e.g. it was re-encoded
+        std::fs::create_dir(new.join("sub")).expect("Failed to create directory");
+        write_wav(&new.join("sub").join("b.wav"), &tune(9, 5, 8000), 1, 8000);
+        std::fs::remove_file(old.join("b.wav")).expect("Failed to remove file");
+        std::fs::remove_file(old.join("c.wav")).expect("Failed to remove file");
+
+        let report = library
+            .relocate_missing_tracks(std::slice::from_ref(&new))
+            .await
+            .expect("Failed to relocate");
+        assert_eq!(ids(&report.relocated), ids(&tracks[..2]));
+        assert_eq!(ids(&report.missing), ids(&tracks[2..]));
+        let expected = [new.join("renamed.wav"), new.join("sub").join("b.wav")];
+        for (track, path) in tracks.iter().zip(expected) {
+            assert_eq!(
+                stored_path(&library, &track.id).await,
+                Some(path.to_string_lossy().to_string())
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn relink_keeps_ids_playlists_and_plays() {
+        let (old, new) = (temp_dir(), temp_dir());
+        let (library, tracks) = library_with_files(&old, &["a.wav", "b.wav"]).await;
+        let playlist = library
+            .create_playlist(&Playlist::new("Moved".to_string(), None))
+            .await
+            .expect("Failed to create playlist");
+        library
+            .add_track_to_playlist(&playlist.id, &tracks[0].id)
+            .await
+            .expect("Failed to add track to playlist");
+        library
+            .record_play(&tracks[0].id)
+            .await
+            .expect("Failed to record play");
+        // only `a` made it to the new folder
+        std::fs::rename(old.join("a.wav"), new.join("a.wav")).expect("Failed to move");
+
+        let report = library
+            .relink_prefix(&old, &new)
+            .await
+            .expect("Failed to relink");
+        assert_eq!(ids(&report.relocated), [tracks[0].id.as_str()]);
+        assert_eq!(ids(&report.missing), [tracks[1].id.as_str()]);
+        assert_eq!(
+            stored_path(&library, &tracks[0].id).await,
+            Some(new.join("a.wav").to_string_lossy().to_string())
+        );
+        assert_eq!(stored_path(&library, &tracks[1].id).await, tracks[1].path);
+
+        let playlist = library
+            .find_playlist_by_id(&playlist.id)
+            .await
+            .expect("Failed to load playlist")
+            .expect("Playlist not found");
+        assert_eq!(playlist.entries.len(), 1);
+        assert_eq!(playlist.entries[0].track.id, tracks[0].id);
+        let stats = library
+            .track_stats(&tracks[0].id)
+            .await
+            .expect("Failed to load stats")
+            .expect("Track not found");
+        assert_eq!(stats.play_count, 1);
+    }
+}
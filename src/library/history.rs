@@ -0,0 +1,173 @@
+use anyhow::Result;
+use chrono::Utc;
+use turso::Value;
+use ulid::Ulid;
+
+use crate::library::{Artist, Library, LibraryEvent, Track};
+
+/// One time a track was played, from the listening history
+#[derive(Clone, Debug, PartialEq)]
+pub struct Play {
+    pub id: String,
+    pub track: Track,
+    /// Unix time in milliseconds
+    pub played_at: i64,
+}
+
+/// A track with the number of times it was played in some period
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackPlays {
+    pub track: Track,
+    pub plays: usize,
+}
+
+/// An artist with the number of times their tracks were played in some period
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtistPlays {
+    pub artist: Artist,
+    pub plays: usize,
+    /// Total length in seconds of the tracks played
+    pub duration: f64,
+}
+
+/// Listening statistics of a single track
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackStats {
+    pub play_count: usize,
+    /// Unix time in milliseconds of the last play
+    pub last_played: Option<i64>,
+    pub skip_count: usize,
+}
+
+impl Library {
+    /// Record that a track was played, counting it towards its play count. Tracks that
+    /// aren't in the library are ignored.
+    pub async fn record_play(&self, track_id: &str) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        self.transaction(vec![
+            (
+                "INSERT INTO plays (id, track_id, played_at)
+                 SELECT ?, id, ? FROM tracks WHERE id = ?"
+                    .to_string(),
+                vec![
+                    Value::Text(Ulid::new().to_string()),
+                    Value::Integer(now),
+                    Value::Text(track_id.to_string()),
+                ],
+            ),
+            (
+                "UPDATE tracks SET play_count = play_count + 1, last_played = ? WHERE id = ?"
+                    .to_string(),
+                vec![Value::Integer(now), Value::Text(track_id.to_string())],
+            ),
+        ])
+        .await?;
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::PlaysRecorded(track_id.to_string()));
+        Ok(())
+    }
+
+    /// Record that a track was left before it counted as played
+    pub async fn record_skip(&self, track_id: &str) -> Result<()> {
+        self.execute(
+            "UPDATE tracks SET skip_count = skip_count + 1 WHERE id = ?",
+            vec![Value::Text(track_id.to_string())],
+        )
+        .await?;
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::PlaysRecorded(track_id.to_string()));
+        Ok(())
+    }
+
+    /// Play count, last played time and skip count of a track
+    pub async fn track_stats(&self, track_id: &str) -> Result<Option<TrackStats>> {
+        let rows = self
+            .query(
+                "SELECT play_count, last_played, skip_count FROM tracks WHERE id = ?",
+                vec![Value::Text(track_id.to_string())],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        Ok(Some(TrackStats {
+            play_count: Self::get_i64(&row[0])? as usize,
+            last_played: Self::get_optional_i64(&row[1]),
+            skip_count: Self::get_i64(&row[2])? as usize,
+        }))
+    }
+
+    /// The last `limit` plays, most recent first. A track played several times is listed
+    /// once per play.
+    pub async fn recently_played(&self, limit: usize) -> Result<Vec<Play>> {
+        self.load_tracks_with(
+            "p.id, p.played_at",
+            "INNER JOIN plays p ON p.track_id = t.id
+             ORDER BY p.played_at DESC, p.id DESC
+             LIMIT ?",
+            vec![Value::Integer(limit as i64)],
+        )
+        .await?
+        .into_iter()
+        .map(|(track, extra)| {
+            Ok(Play {
+                id: Self::get_string(&extra[0])?,
+                played_at: Self::get_i64(&extra[1])?,
+                track,
+            })
+        })
+        .collect()
+    }
+
+    /// The `limit` tracks played most often since `since` (Unix time in milliseconds)
+    pub async fn most_played_since(&self, since: i64, limit: usize) -> Result<Vec<TrackPlays>> {
+        self.load_tracks_with(
+            "pc.plays",
+            "INNER JOIN (
+                 SELECT track_id, COUNT(*) AS plays FROM plays
+                 WHERE played_at >= ? GROUP BY track_id
+             ) pc ON pc.track_id = t.id
+             ORDER BY pc.plays DESC, t.sort_key, t.id
+             LIMIT ?",
+            vec![Value::Integer(since), Value::Integer(limit as i64)],
+        )
+        .await?
+        .into_iter()
+        .map(|(track, extra)| {
+            Ok(TrackPlays {
+                plays: Self::get_i64(&extra[0])? as usize,
+                track,
+            })
+        })
+        .collect()
+    }
+
+    /// The `limit` artists whose tracks were played most often since `since` (Unix time
+    /// in milliseconds), with their totals
+    pub async fn artist_play_totals(&self, since: i64, limit: usize) -> Result<Vec<ArtistPlays>> {
+        let rows = self
+            .query(
+                "SELECT ar.id, ar.name, ar.sort_name, COUNT(*), SUM(t.duration) FROM plays p
+                 INNER JOIN tracks t ON t.id = p.track_id
+                 INNER JOIN track_artists ta ON ta.track_id = p.track_id
+                 INNER JOIN artists ar ON ar.id = ta.artist_id
+                 WHERE p.played_at >= ?
+                 GROUP BY ar.id, ar.name, ar.sort_name, ar.sort_key
+                 ORDER BY COUNT(*) DESC, ar.sort_key
+                 LIMIT ?",
+                vec![Value::Integer(since), Value::Integer(limit as i64)],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(ArtistPlays {
+                    artist: Self::row_to_artist(row)?,
+                    plays: Self::get_i64(&row[3])? as usize,
+                    duration: Self::get_f64(&row[4]).unwrap_or(0.0),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use tokio::task;
+use turso::Value;
+
+use crate::{
+    fingerprint::{Fingerprint, FingerprintLookup, Identification, MATCH_THRESHOLD},
+    library::{Library, MAX_IN_PARAMS, Track, TrackSource},
+    providers::local,
+};
+
+/// How much longer or shorter, in seconds, a track in the library can be and still be
+/// offered as what another track is
+const LENGTH_TOLERANCE: f64 = 10.0;
+
+impl Library {
+    /// Fingerprints of the given tracks keyed by id, computing and storing the missing
+    /// ones. Tracks without a file, or whose file can't be decoded, are left out.
+    pub async fn fingerprints(&self, tracks: &[&Track]) -> Result<HashMap<String, Fingerprint>> {
+        let ids: Vec<String> = tracks.iter().map(|t| t.id.clone()).collect();
+        let mut stored = self.stored_fingerprints(&ids).await?;
+
+        let mut fingerprints = HashMap::new();
+        let mut statements = Vec::new();
+        for track in tracks {
+            if let Some(encoded) = stored.remove(&track.id) {
+                match Fingerprint::decode(&encoded) {
+                    Ok(fingerprint) => {
+                        fingerprints.insert(track.id.clone(), fingerprint);
+                        continue;
+                    }
+                    Err(e) => eprintln!("Invalid fingerprint stored for {}: {}", track.title, e),
+                }
+            }
+            let Some(path) = track.path.clone() else {
+                continue;
+            };
+            let cue = track.cue;
+            match task::spawn_blocking(move || local::fingerprint(&PathBuf::from(path), cue))
+                .await?
+            {
+                Ok(fingerprint) => {
+                    statements.push((
+                        "UPDATE tracks SET fingerprint = ? WHERE id = ?".to_string(),
+                        vec![
+                            Value::Text(fingerprint.encode()),
+                            Value::Text(track.id.clone()),
+                        ],
+                    ));
+                    fingerprints.insert(track.id.clone(), fingerprint);
+                }
+                Err(e) => eprintln!("Failed to fingerprint {}: {}", track.title, e),
+            }
+        }
+        if !statements.is_empty() {
+            self.transaction(statements).await?;
+        }
+        Ok(fingerprints)
+    }
+
+    /// Encoded fingerprints stored for the given tracks, keyed by id
+    async fn stored_fingerprints(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        let mut stored = HashMap::new();
+        for chunk in ids.chunks(MAX_IN_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            for row in self
+                .query(
+                    &format!(
+                        "SELECT id, fingerprint FROM tracks
+                         WHERE fingerprint IS NOT NULL AND id IN ({placeholders})"
+                    ),
+                    chunk.iter().cloned().map(Value::Text).collect(),
+                )
+                .await?
+            {
+                stored.insert(Self::get_string(&row[0])?, Self::get_string(&row[1])?);
+            }
+        }
+        Ok(stored)
+    }
+
+    /// What an untagged or mistagged local track could be, best match first: tagged
+    /// tracks in the library that sound the same, then what `lookup` finds.
+    ///
+    /// Only tracks that already have a fingerprint are compared (they are stored when
+    /// looking for duplicates or identifying), decoding every other file of about the
+    /// same length would take too long.
+    pub async fn identify_track(
+        &self,
+        track: &Track,
+        lookup: &impl FingerprintLookup,
+    ) -> Result<Vec<Identification>> {
+        let fingerprint = self
+            .fingerprints(&[track])
+            .await?
+            .remove(&track.id)
+            .ok_or(anyhow::anyhow!("Failed to fingerprint the track's file"))?;
+        if fingerprint.is_empty() {
+            return Err(anyhow::anyhow!("The track is too short to identify"));
+        }
+
+        let candidates: Vec<Track> = self
+            .load_tracks(
+                "WHERE t.id != ? AND t.source = ? AND t.fingerprint IS NOT NULL
+                 AND t.duration BETWEEN ? AND ?",
+                vec![
+                    Value::Text(track.id.clone()),
+                    Value::Text(TrackSource::Local.as_str().to_string()),
+                    Value::Real(track.duration - LENGTH_TOLERANCE),
+                    Value::Real(track.duration + LENGTH_TOLERANCE),
+                ],
+            )
+            .await?
+            .into_iter()
+            .filter(|t| !t.artists.is_empty())
+            .collect();
+        let ids: Vec<String> = candidates.iter().map(|t| t.id.clone()).collect();
+        let stored = self.stored_fingerprints(&ids).await?;
+        let mut identifications: Vec<Identification> = candidates
+            .iter()
+            .filter_map(|candidate| {
+                let candidate_fingerprint = Fingerprint::decode(stored.get(&candidate.id)?).ok()?;
+                let score = fingerprint.similarity(&candidate_fingerprint);
+                (score >= MATCH_THRESHOLD).then(|| Identification {
+                    title: candidate.title.clone(),
+                    artists: candidate.artists.iter().map(|a| a.name.clone()).collect(),
+                    album: Some(candidate.album.title.clone()),
+                    score,
+                    source: "Library".to_string(),
+                })
+            })
+            .collect();
+        identifications.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        match lookup.lookup(&fingerprint, track.duration).await {
+            Ok(found) => identifications.extend(found),
+            // matches in the library are still worth showing when the service is down
+            Err(e) if !identifications.is_empty() => {
+                eprintln!("Failed to look up {}: {}", track.title, e)
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(identifications)
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use chrono::DateTime;
+use roxmltree::Node;
+use tokio::{fs, task};
+use turso::{Builder, Connection, Value};
+use ulid::Ulid;
+use url::Url;
+
+use crate::{
+    library::{Library, LibraryEvent, Playlist, Track, rating::MAX_RATING, row_to_values},
+    providers::{formats, local},
+};
+
+/// Music players whose libraries can be imported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportSource {
+    /// `rhythmdb.xml`, with the playlists from the `playlists.xml` next to it
+    Rhythmbox,
+    /// The `Library.xml` property list exported by iTunes and Music
+    ITunes,
+    /// The SQLite `library.db` of beets
+    Beets,
+}
+
+impl ImportSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rhythmbox => "Rhythmbox",
+            Self::ITunes => "iTunes",
+            Self::Beets => "beets",
+        }
+    }
+
+    /// Guess which player a library file is from by its first few bytes
+    pub fn detect(path: &Path) -> Result<Option<Self>> {
+        let mut start = Vec::new();
+        std::fs::File::open(path)?
+            .take(4096)
+            .read_to_end(&mut start)?;
+        if start.starts_with(b"SQLite format 3\0") {
+            return Ok(Some(Self::Beets));
+        }
+        let start = String::from_utf8_lossy(&start);
+        Ok(if start.contains("<rhythmdb") {
+            Some(Self::Rhythmbox)
+        } else if start.contains("<plist") {
+            Some(Self::ITunes)
+        } else {
+            None
+        })
+    }
+}
+
+/// A track as another player knows it
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ForeignTrack {
+    location: Option<PathBuf>,
+    title: Option<String>,
+    artist: Option<String>,
+    /// Stars from 0 (unrated) to `MAX_RATING`
+    rating: u8,
+    loved: bool,
+    play_count: usize,
+    skip_count: usize,
+    /// Unix time in milliseconds
+    last_played: Option<i64>,
+}
+
+impl ForeignTrack {
+    /// Whether the other player knows anything about the track that the library keeps
+    fn has_statistics(&self) -> bool {
+        self.rating > 0
+            || self.loved
+            || self.play_count > 0
+            || self.skip_count > 0
+            || self.last_played.is_some()
+    }
+
+    /// How the track is listed in the report when it can't be found
+    fn describe(&self) -> String {
+        match (&self.location, &self.artist, &self.title) {
+            (Some(location), _, _) => location.display().to_string(),
+            (None, Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, None, Some(title)) => title.clone(),
+            _ => "Unknown track".to_string(),
+        }
+    }
+}
+
+/// A playlist from another player, its tracks as indices into `ForeignLibrary::tracks`
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ForeignPlaylist {
+    name: String,
+    tracks: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ForeignLibrary {
+    tracks: Vec<ForeignTrack>,
+    playlists: Vec<ForeignPlaylist>,
+}
+
+/// What importing another player's library changed, or would change on a dry run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Tracks found in the library by the path of their file
+    pub matched_by_path: usize,
+    /// Tracks found in the library by artist and title after their path didn't match
+    pub matched_by_tags: usize,
+    /// Files that weren't in the library yet and were added to it
+    pub added: usize,
+    /// Tracks given a rating, the loved flag or play statistics
+    pub updated: usize,
+    /// Names of the playlists created, with their number of tracks
+    pub playlists: Vec<(String, usize)>,
+    /// Playlists left out because one with the same name exists or none of their
+    /// tracks were found
+    pub skipped_playlists: Vec<String>,
+    /// Files that weren't in the library and are in formats that can't be played, with
+    /// the format's name
+    pub unsupported: Vec<(PathBuf, &'static str)>,
+    /// Tracks that matched nothing, by path or artist and title
+    pub unmatched: Vec<String>,
+}
+
+impl Library {
+    /// Bring ratings, play counts and playlists over from another player's library file.
+    /// Tracks are matched by path, then by artist and title, and files that aren't in
+    /// the library yet are added. Play and skip counts keep the higher of the two, so
+    /// importing the same file twice changes nothing. With `dry_run` the library is
+    /// left as it is and the report says what would change.
+    pub async fn import_player_library(
+        &self,
+        source: ImportSource,
+        path: &Path,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let foreign = match source {
+            ImportSource::Rhythmbox => {
+                let playlists = fs::read_to_string(path.with_file_name("playlists.xml"))
+                    .await
+                    .ok();
+                parse_rhythmbox(&fs::read_to_string(path).await?, playlists.as_deref())?
+            }
+            ImportSource::ITunes => parse_itunes(&fs::read_to_string(path).await?)?,
+            ImportSource::Beets => read_beets(path).await?,
+        };
+
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+        // id of the library track each foreign track maps to
+        let mut ids: Vec<Option<String>> = Vec::with_capacity(foreign.tracks.len());
+        // files added by this import, so tracks listed twice reuse the new track
+        let mut added: HashMap<PathBuf, Track> = HashMap::new();
+        let mut updates = Vec::new();
+        for foreign_track in &foreign.tracks {
+            let mut track = None;
+            if let Some(location) = &foreign_track.location {
+                track = self.find_track_by_path(location).await?;
+                report.matched_by_path += track.is_some() as usize;
+            }
+            if track.is_none()
+                && let Some(title) = &foreign_track.title
+            {
+                track = self
+                    .find_track_by_tags(foreign_track.artist.as_deref(), title)
+                    .await?;
+                report.matched_by_tags += track.is_some() as usize;
+            }
+            if track.is_none()
+                && let Some(location) = &foreign_track.location
+            {
+                track = match added.get(location) {
+                    Some(track) => Some(track.clone()),
+                    None => match resolve_new_file(location).await? {
+                        NewFile::Track(track) => Some(track),
+                        NewFile::Unsupported(format) => {
+                            report.unsupported.push((location.clone(), format));
+                            ids.push(None);
+                            continue;
+                        }
+                        NewFile::Unreadable => None,
+                    },
+                };
+                if let Some(track) = &track {
+                    added.insert(location.clone(), track.clone());
+                }
+            }
+
+            let Some(track) = track else {
+                report.unmatched.push(foreign_track.describe());
+                ids.push(None);
+                continue;
+            };
+            ids.push(Some(track.id.clone()));
+            if foreign_track.has_statistics() {
+                updates.push((track, foreign_track));
+            }
+        }
+        report.added = added.len();
+        report.updated = updates.len();
+
+        let existing: Vec<String> = self
+            .all_playlist_summaries()
+            .await?
+            .into_iter()
+            .map(|playlist| playlist.name)
+            .collect();
+        let mut playlists = Vec::new();
+        for playlist in &foreign.playlists {
+            let track_ids: Vec<String> = playlist
+                .tracks
+                .iter()
+                .filter_map(|&i| ids.get(i).cloned().flatten())
+                .collect();
+            if track_ids.is_empty() || existing.contains(&playlist.name) {
+                report.skipped_playlists.push(playlist.name.clone());
+                continue;
+            }
+            report
+                .playlists
+                .push((playlist.name.clone(), track_ids.len()));
+            playlists.push((playlist.name.clone(), track_ids));
+        }
+
+        if dry_run {
+            return Ok(report);
+        }
+
+        // the added tracks may have been given the ids of artists and albums already in
+        // the library
+        let added: HashMap<String, Track> = self
+            .add_tracks(&added.into_values().collect::<Vec<_>>())
+            .await?
+            .into_iter()
+            .map(|track| (track.id.clone(), track))
+            .collect();
+
+        let mut statements = Vec::new();
+        let mut updated = Vec::new();
+        for (track, foreign_track) in updates {
+            let mut track = added.get(&track.id).cloned().unwrap_or(track);
+            if foreign_track.rating > 0 {
+                track.metadata.rating = foreign_track.rating;
+            }
+            track.metadata.loved |= foreign_track.loved;
+            statements.push((
+                "UPDATE tracks SET rating = ?, loved = ?,
+                 play_count = MAX(play_count, ?), skip_count = MAX(skip_count, ?),
+                 last_played = COALESCE(MAX(last_played, ?), last_played, ?)
+                 WHERE id = ?"
+                    .to_string(),
+                vec![
+                    Value::Integer(track.metadata.rating as i64),
+                    Value::Integer(track.metadata.loved as i64),
+                    Value::Integer(foreign_track.play_count as i64),
+                    Value::Integer(foreign_track.skip_count as i64),
+                    foreign_track
+                        .last_played
+                        .map(Value::Integer)
+                        .unwrap_or(Value::Null),
+                    foreign_track
+                        .last_played
+                        .map(Value::Integer)
+                        .unwrap_or(Value::Null),
+                    Value::Text(track.id.clone()),
+                ],
+            ));
+            updated.push(track);
+        }
+        if !statements.is_empty() {
+            self.transaction(statements).await?;
+            let _ = self.event_sender.send(LibraryEvent::TracksUpdated(updated));
+        }
+
+        // all playlists in one go, an event for each would flood the subscribers
+        let mut statements = Vec::new();
+        for (name, track_ids) in playlists {
+            let playlist = Playlist::new(name, None);
+            statements.push((
+                "INSERT INTO playlists (id, name) VALUES (?, ?)".to_string(),
+                vec![Value::Text(playlist.id.clone()), Value::Text(playlist.name)],
+            ));
+            for (i, track_id) in track_ids.into_iter().enumerate() {
+                statements.push((
+                    "INSERT INTO playlist_entries (id, playlist_id, track_id, position)
+                     VALUES (?, ?, ?, ?)"
+                        .to_string(),
+                    vec![
+                        Value::Text(Ulid::new().to_string()),
+                        Value::Text(playlist.id.clone()),
+                        Value::Text(track_id),
+                        Value::Integer(i as i64 + 1),
+                    ],
+                ));
+            }
+        }
+        if !statements.is_empty() {
+            self.transaction(statements).await?;
+            let _ = self.event_sender.send(LibraryEvent::PlaylistsChanged);
+        }
+        Ok(report)
+    }
+}
+
+/// What a file that isn't in the library yet turned out to be
+pub(super) enum NewFile {
+    Track(Track),
+    /// Audio in a format that can't be played, with the format's name
+    Unsupported(&'static str),
+    /// Missing, not audio or unreadable
+    Unreadable,
+}
+
+/// Read the tags of a file that isn't in the library yet. Whether it can be played goes
+/// by its contents, as for files added from the sidebar.
+pub(super) async fn resolve_new_file(location: &Path) -> Result<NewFile> {
+    let location = location.to_path_buf();
+    Ok(task::spawn_blocking(move || {
+        if !location.is_file() {
+            return NewFile::Unreadable;
+        }
+        match formats::detect(&location) {
+            Some(format) if format.playable => {}
+            Some(format) => return NewFile::Unsupported(format.name),
+            None => return NewFile::Unreadable,
+        }
+        match local::resolve_track(&location.to_string_lossy()) {
+            Ok(track) => NewFile::Track(track),
+            Err(_) => NewFile::Unreadable,
+        }
+    })
+    .await?)
+}
+
+/// Stars for a rating of `value` on a scale from 0 to `scale`
+fn stars(value: f64, scale: f64) -> u8 {
+    (value / scale * MAX_RATING as f64)
+        .round()
+        .clamp(0.0, MAX_RATING as f64) as u8
+}
+
+/// The local file a `file://` URL points at
+fn file_url_path(location: &str) -> Option<PathBuf> {
+    Url::parse(location)
+        .ok()
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+fn parse_rhythmbox(text: &str, playlists: Option<&str>) -> Result<ForeignLibrary> {
+    let document = roxmltree::Document::parse(text)?;
+    let mut library = ForeignLibrary::default();
+    let mut by_location = HashMap::new();
+    for entry in document
+        .root_element()
+        .children()
+        .filter(|node| node.has_tag_name("entry") && node.attribute("type") == Some("song"))
+    {
+        let field = |name| child_text(entry, name);
+        let number = |name| field(name).and_then(|text| text.parse::<f64>().ok());
+        if let Some(location) = field("location") {
+            by_location.insert(location, library.tracks.len());
+        }
+        library.tracks.push(ForeignTrack {
+            location: field("location").as_deref().and_then(file_url_path),
+            title: field("title"),
+            artist: field("artist"),
+            rating: number("rating").map_or(0, |rating| stars(rating, 5.0)),
+            loved: false,
+            play_count: number("play-count").unwrap_or(0.0) as usize,
+            skip_count: 0,
+            // seconds, 0 for never
+            last_played: number("last-played")
+                .filter(|&seconds| seconds > 0.0)
+                .map(|seconds| seconds as i64 * 1000),
+        });
+    }
+
+    // the playlists are kept in their own file, automatic ones and the play queue are
+    // left out
+    if let Some(playlists) = playlists {
+        let document = roxmltree::Document::parse(playlists)?;
+        for playlist in document.root_element().children().filter(|node| {
+            node.has_tag_name("playlist") && node.attribute("type") == Some("static")
+        }) {
+            library.playlists.push(ForeignPlaylist {
+                name: playlist
+                    .attribute("name")
+                    .unwrap_or("Rhythmbox")
+                    .to_string(),
+                tracks: playlist
+                    .children()
+                    .filter(|node| node.has_tag_name("location"))
+                    .filter_map(|node| node.text())
+                    .filter_map(|location| by_location.get(location.trim()).copied())
+                    .collect(),
+            });
+        }
+    }
+    Ok(library)
+}
+
+/// The key and value elements of a property list `<dict>`
+fn plist_dict<'a, 'input>(dict: Node<'a, 'input>) -> HashMap<&'a str, Node<'a, 'input>> {
+    let mut entries = HashMap::new();
+    let mut key = None;
+    for child in dict.children().filter(|node| node.is_element()) {
+        if child.has_tag_name("key") {
+            key = child.text();
+        } else if let Some(key) = key.take() {
+            entries.insert(key, child);
+        }
+    }
+    entries
+}
+
+fn parse_itunes(text: &str) -> Result<ForeignLibrary> {
+    let document = roxmltree::Document::parse(text)?;
+    let root = document
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name("dict"))
+        .ok_or(anyhow::anyhow!("Not an iTunes library"))?;
+    let root = plist_dict(root);
+    let mut library = ForeignLibrary::default();
+
+    let mut by_id = HashMap::new();
+    for track in root
+        .get("Tracks")
+        .into_iter()
+        .flat_map(|tracks| plist_dict(*tracks).into_values())
+    {
+        let fields = plist_dict(track);
+        let text = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|node| node.text())
+                .map(str::to_string)
+        };
+        let integer = |key: &str| text(key).and_then(|text| text.trim().parse::<i64>().ok());
+        let flag = |key: &str| {
+            fields
+                .get(key)
+                .is_some_and(|node| node.has_tag_name("true"))
+        };
+        if let Some(id) = integer("Track ID") {
+            by_id.insert(id, library.tracks.len());
+        }
+        library.tracks.push(ForeignTrack {
+            location: text("Location").as_deref().and_then(file_url_path),
+            title: text("Name"),
+            artist: text("Artist"),
+            // ratings shown for tracks of a rated album are "computed", not the track's
+            rating: if flag("Rating Computed") {
+                0
+            } else {
+                integer("Rating").map_or(0, |rating| stars(rating as f64, 100.0))
+            },
+            loved: flag("Loved") || flag("Favorited"),
+            play_count: integer("Play Count").unwrap_or(0).max(0) as usize,
+            skip_count: integer("Skip Count").unwrap_or(0).max(0) as usize,
+            last_played: text("Play Date UTC")
+                .and_then(|date| DateTime::parse_from_rfc3339(date.trim()).ok())
+                .map(|date| date.timestamp_millis()),
+        });
+    }
+
+    for playlist in root
+        .get("Playlists")
+        .into_iter()
+        .flat_map(|playlists| playlists.children())
+        .filter(|node| node.has_tag_name("dict"))
+    {
+        let fields = plist_dict(playlist);
+        let flag = |key: &str| {
+            fields
+                .get(key)
+                .is_some_and(|node| node.has_tag_name("true"))
+        };
+        // the whole library, built in lists such as Music or Podcasts, folders and smart
+        // playlists, none of which are playlists the user made
+        if flag("Master")
+            || flag("Folder")
+            || fields.contains_key("Distinguished Kind")
+            || fields.contains_key("Smart Info")
+        {
+            continue;
+        }
+        let Some(name) = fields.get("Name").and_then(|node| node.text()) else {
+            continue;
+        };
+        library.playlists.push(ForeignPlaylist {
+            name: name.to_string(),
+            tracks: fields
+                .get("Playlist Items")
+                .into_iter()
+                .flat_map(|items| items.children())
+                .filter(|node| node.has_tag_name("dict"))
+                .filter_map(|item| plist_dict(item).get("Track ID")?.text()?.parse().ok())
+                .filter_map(|id: i64| by_id.get(&id).copied())
+                .collect(),
+        });
+    }
+    Ok(library)
+}
+
+/// Rating mpdstats gives tracks before they are played or skipped
+const MPDSTATS_NEUTRAL_RATING: f64 = 0.5;
+
+/// Read a beets library. beets has no playlists, and ratings and play counts are
+/// flexible attributes set by plugins such as mpdstats.
+async fn read_beets(path: &Path) -> Result<ForeignLibrary> {
+    // work on a copy so the other player's database is never written to
+    let copy = std::env::temp_dir().join(format!("vibrance-beets-{}.db", Ulid::new()));
+    fs::copy(path, &copy).await?;
+    let library = read_beets_copy(&copy).await;
+    for leftover in [copy.clone(), copy.with_extension("db-wal")] {
+        let _ = fs::remove_file(leftover).await;
+    }
+    library
+}
+
+async fn read_beets_copy(path: &Path) -> Result<ForeignLibrary> {
+    let connection = Builder::new_local(path.to_str().ok_or(anyhow::anyhow!("Invalid path"))?)
+        .build()
+        .await?
+        .connect()?;
+    let mut library = ForeignLibrary::default();
+    let mut by_id = HashMap::new();
+    for row in query_all(&connection, "SELECT id, path, title, artist FROM items").await? {
+        let text = |value: &Value| match value {
+            Value::Text(text) => Some(text.clone()),
+            // paths are stored as bytes
+            Value::Blob(bytes) => Some(String::from_utf8_lossy(bytes).to_string()),
+            _ => None,
+        };
+        if let Value::Integer(id) = row[0] {
+            by_id.insert(id, library.tracks.len());
+        }
+        library.tracks.push(ForeignTrack {
+            location: text(&row[1]).map(PathBuf::from),
+            title: text(&row[2]).filter(|title| !title.is_empty()),
+            artist: text(&row[3]).filter(|artist| !artist.is_empty()),
+            ..Default::default()
+        });
+    }
+
+    let attributes = query_all(
+        &connection,
+        "SELECT entity_id, key, value FROM item_attributes
+         WHERE key IN ('rating', 'loved', 'play_count', 'skip_count', 'last_played')",
+    )
+    .await?;
+    for row in attributes {
+        let (Value::Integer(id), Value::Text(key)) = (&row[0], &row[1]) else {
+            continue;
+        };
+        let Some(track) = by_id.get(id).map(|&i| &mut library.tracks[i]) else {
+            continue;
+        };
+        let value = match &row[2] {
+            Value::Text(text) => text.trim().parse::<f64>().unwrap_or(0.0),
+            Value::Integer(i) => *i as f64,
+            Value::Real(f) => *f,
+            _ => continue,
+        };
+        match key.as_str() {
+            // mpdstats rates from 0 to 1 and gives every track it sees 0.5 to start with,
+            // which says nothing about the track
+            "rating" if value != MPDSTATS_NEUTRAL_RATING => track.rating = stars(value, 1.0),
+            "loved" => track.loved = value > 0.0,
+            "play_count" => track.play_count = value.max(0.0) as usize,
+            "skip_count" => track.skip_count = value.max(0.0) as usize,
+            // seconds
+            "last_played" if value > 0.0 => track.last_played = Some(value as i64 * 1000),
+            _ => {}
+        }
+    }
+    Ok(library)
+}
+
+async fn query_all(connection: &Connection, sql: &str) -> Result<Vec<Vec<Value>>> {
+    let mut rows = connection.query(sql, ()).await?;
+    let mut all = Vec::new();
+    while let Some(row) = rows.next().await? {
+        all.push(row_to_values(&row).await?);
+    }
+    Ok(all)
+}
@@ -0,0 +1,688 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Result;
+use tokio::fs;
+use turso::Value;
+use url::Url;
+
+use crate::library::{
+    Library, Playlist, Track, TrackSource,
+    import::{NewFile, resolve_new_file},
+    search::normalize,
+};
+
+/// Playlist file formats that can be imported and exported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// M3U and M3U8, with `#EXTINF` lines
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The format of a file, by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the contents, for files without a known extension
+    fn sniff(text: &str) -> Self {
+        let text = text.trim_start();
+        if text.starts_with('<') {
+            Self::Xspf
+        } else if text
+            .get(..10)
+            .is_some_and(|start| start.eq_ignore_ascii_case("[playlist]"))
+        {
+            Self::Pls
+        } else {
+            Self::M3u
+        }
+    }
+}
+
+/// An entry read from a playlist file
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FileEntry {
+    /// Path or URL as written in the file, may be empty in XSPF files
+    location: String,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+/// The contents of a playlist file
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PlaylistFile {
+    name: Option<String>,
+    entries: Vec<FileEntry>,
+}
+
+/// Result of importing a playlist file
+#[derive(Clone, Debug)]
+pub struct PlaylistImport {
+    pub playlist: Playlist,
+    /// Number of local files that were not in the library and were added to it
+    pub imported: usize,
+    /// Entries that matched no track in the library and no readable file
+    pub missing: Vec<String>,
+    /// Local files that weren't in the library and are in formats that can't be played,
+    /// with the format's name
+    pub unsupported: Vec<(PathBuf, &'static str)>,
+}
+
+impl Library {
+    /// Create a playlist from an M3U, PLS or XSPF file. Entries are matched to tracks in
+    /// the library by path, then by artist and title, and local files that are not in the
+    /// library yet are added to it if they can be played.
+    pub async fn import_playlist(&self, path: &Path) -> Result<PlaylistImport> {
+        let text = decode(&fs::read(path).await?);
+        let format =
+            PlaylistFormat::from_path(path).unwrap_or_else(|| PlaylistFormat::sniff(&text));
+        let file = match format {
+            PlaylistFormat::M3u => parse_m3u(&text),
+            PlaylistFormat::Pls => parse_pls(&text),
+            PlaylistFormat::Xspf => parse_xspf(&text)?,
+        };
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        let mut track_ids = Vec::new();
+        let mut new_tracks = Vec::new();
+        // files added by this import, so entries repeating them reuse the new track
+        let mut imported: HashMap<PathBuf, String> = HashMap::new();
+        let mut missing = Vec::new();
+        let mut unsupported = Vec::new();
+        for entry in &file.entries {
+            let local = local_path(&entry.location, base, format == PlaylistFormat::Xspf);
+            if let Some(local) = &local
+                && let Some(track) = self.find_track_by_path(local).await?
+            {
+                track_ids.push(track.id);
+                continue;
+            }
+            if let Some(title) = &entry.title
+                && let Some(track) = self
+                    .find_track_by_tags(entry.artist.as_deref(), title)
+                    .await?
+            {
+                track_ids.push(track.id);
+                continue;
+            }
+            if let Some(local) = local {
+                if let Some(id) = imported.get(&local) {
+                    track_ids.push(id.clone());
+                    continue;
+                }
+                match resolve_new_file(&local).await? {
+                    NewFile::Track(track) => {
+                        imported.insert(local, track.id.clone());
+                        track_ids.push(track.id.clone());
+                        new_tracks.push(track);
+                        continue;
+                    }
+                    NewFile::Unsupported(format) => {
+                        unsupported.push((local, format));
+                        continue;
+                    }
+                    NewFile::Unreadable => {}
+                }
+            }
+            missing.push(if entry.location.is_empty() {
+                entry.title.clone().unwrap_or_default()
+            } else {
+                entry.location.clone()
+            });
+        }
+        self.add_tracks(&new_tracks).await?;
+
+        let name = file
+            .name
+            .filter(|name| !name.is_empty())
+            .or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+            })
+            .unwrap_or_else(|| "Imported playlist".to_string());
+        let playlist = self.create_playlist(&Playlist::new(name, None)).await?;
+        self.insert_into_playlist(&playlist.id, 0, &track_ids)
+            .await?;
+        let playlist = self
+            .find_playlist_by_id(&playlist.id)
+            .await?
+            .ok_or(anyhow::anyhow!("Imported playlist not found"))?;
+        Ok(PlaylistImport {
+            playlist,
+            imported: new_tracks.len(),
+            missing,
+            unsupported,
+        })
+    }
+
+    /// Find a track by the path of its file
+    pub(super) async fn find_track_by_path(&self, path: &Path) -> Result<Option<Track>> {
+        Ok(self
+            .load_tracks(
+                "WHERE t.path = ? LIMIT 1",
+                vec![Value::Text(path.to_string_lossy().to_string())],
+            )
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Find a track by its title and, if given, one of its artists
+    pub(super) async fn find_track_by_tags(
+        &self,
+        artist: Option<&str>,
+        title: &str,
+    ) -> Result<Option<Track>> {
+        let candidates = self
+            .load_tracks(
+                "WHERE t.title = ? COLLATE NOCASE ORDER BY t.sort_key",
+                vec![Value::Text(title.to_string())],
+            )
+            .await?;
+        let Some(artist) = artist.map(normalize) else {
+            return Ok(candidates.into_iter().next());
+        };
+        Ok(candidates.into_iter().find(|track| {
+            normalize(&track.artists_string()) == artist
+                || track.artists.iter().any(|a| normalize(&a.name) == artist)
+        }))
+    }
+}
+
+impl Playlist {
+    /// Write the playlist to `path` in the format of its extension. Paths are written
+    /// relative to the playlist file when `relative` is set and the track is on the same
+    /// drive, and absolute otherwise. Returns the number of entries left out because
+    /// they are not local files.
+    pub async fn export(&self, path: &Path, relative: bool) -> Result<usize> {
+        let format = PlaylistFormat::from_path(path).ok_or(anyhow::anyhow!(
+            "Unsupported playlist format: {}",
+            path.display()
+        ))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        let mut skipped = 0;
+        let mut tracks = Vec::new();
+        for entry in &self.entries {
+            match &entry.track.path {
+                Some(track_path) if entry.track.source == TrackSource::Local => {
+                    let track_path = Path::new(track_path);
+                    let base = relative.then_some(base);
+                    let location = match format {
+                        PlaylistFormat::Xspf => location_uri(track_path, base),
+                        _ => location_path(track_path, base),
+                    };
+                    tracks.push((&entry.track, location));
+                }
+                _ => skipped += 1,
+            }
+        }
+
+        let text = match format {
+            PlaylistFormat::M3u => write_m3u(&self.name, &tracks),
+            PlaylistFormat::Pls => write_pls(&tracks),
+            PlaylistFormat::Xspf => write_xspf(&self.name, &tracks),
+        };
+        fs::write(path, text).await?;
+        Ok(skipped)
+    }
+}
+
+/// Playlist files are meant to be UTF-8, but older M3U and PLS files are often Latin-1
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Split a display title such as "Artist - Title" into its artist and title
+fn split_display(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+    match display.split_once(" - ") {
+        Some((artist, title)) => (
+            Some(artist.trim().to_string()),
+            Some(title.trim().to_string()),
+        ),
+        None if display.is_empty() => (None, None),
+        None => (None, Some(display.to_string())),
+    }
+}
+
+fn parse_m3u(text: &str) -> PlaylistFile {
+    let mut file = PlaylistFile::default();
+    // artist and title from the `#EXTINF` line before the next location
+    let mut info = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<duration> [attributes],<display title>, attribute values may be
+            // quoted and contain commas
+            let mut quoted = false;
+            let comma = extinf.char_indices().find_map(|(i, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                (c == ',' && !quoted).then_some(i)
+            });
+            info = comma.map(|i| split_display(&extinf[i + 1..]));
+        } else if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            file.name = Some(name.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let (artist, title) = info.take().unwrap_or_default();
+            file.entries.push(FileEntry {
+                location: line.to_string(),
+                title,
+                artist,
+            });
+        }
+    }
+    file
+}
+
+fn parse_pls(text: &str) -> PlaylistFile {
+    // keys are numbered from 1 (File1, Title1, Length1...) and may come in any order
+    let mut entries: BTreeMap<u32, FileEntry> = BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let Some(digits) = key.find(|c: char| c.is_ascii_digit()) else {
+            continue;
+        };
+        let (field, index) = key.split_at(digits);
+        let Ok(index) = index.parse() else {
+            continue;
+        };
+        let entry = entries.entry(index).or_default();
+        match field {
+            "file" => entry.location = value.trim().to_string(),
+            "title" => (entry.artist, entry.title) = split_display(value),
+            _ => {}
+        }
+    }
+    PlaylistFile {
+        name: None,
+        entries: entries
+            .into_values()
+            .filter(|entry| !entry.location.is_empty())
+            .collect(),
+    }
+}
+
+fn parse_xspf(text: &str) -> Result<PlaylistFile> {
+    let document = roxmltree::Document::parse(text)?;
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+    let root = document.root_element();
+    let entries = root
+        .children()
+        .filter(|child| child.has_tag_name("trackList"))
+        .flat_map(|list| list.children())
+        .filter(|child| child.has_tag_name("track"))
+        .map(|track| FileEntry {
+            location: child_text(track, "location").unwrap_or_default(),
+            title: child_text(track, "title"),
+            artist: child_text(track, "creator"),
+        })
+        .filter(|entry| !entry.location.is_empty() || entry.title.is_some())
+        .collect();
+    Ok(PlaylistFile {
+        name: child_text(root, "title"),
+        entries,
+    })
+}
+
+/// The local file a playlist location points at. Relative locations are resolved from
+/// `base`, and in XSPF files (`uri`) locations are URIs rather than paths.
+fn local_path(location: &str, base: &Path, uri: bool) -> Option<PathBuf> {
+    if location.is_empty() {
+        return None;
+    }
+    if uri {
+        let url = Url::from_directory_path(base).ok()?.join(location).ok()?;
+        return (url.scheme() == "file")
+            .then(|| url.to_file_path().ok())
+            .flatten();
+    }
+    // single letter schemes are Windows drive letters
+    if let Ok(url) = Url::parse(location)
+        && url.scheme().len() > 1
+    {
+        return (url.scheme() == "file")
+            .then(|| url.to_file_path().ok())
+            .flatten();
+    }
+    // files written on Windows use backslashes, which are only separators there
+    let location = if cfg!(windows) {
+        location.to_string()
+    } else {
+        location.replace('\\', "/")
+    };
+    Some(normalize_path(&base.join(location)))
+}
+
+/// Resolve `.` and `..` components without touching the file system, so paths match
+/// the ones stored in the library
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` relative to the directory `base`, or `None` if they don't share a root
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    if path.first() != base.first() {
+        return None;
+    }
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component);
+    }
+    Some(relative)
+}
+
+/// Location of a track in an M3U or PLS file, relative to `base` if given
+fn location_path(path: &Path, base: Option<&Path>) -> String {
+    base.and_then(|base| relative_path(path, base))
+        .unwrap_or_else(|| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Location of a track in an XSPF file, a URI relative to `base` if given
+fn location_uri(path: &Path, base: Option<&Path>) -> String {
+    let Ok(url) = Url::from_file_path(path) else {
+        return path.to_string_lossy().to_string();
+    };
+    base.and_then(|base| Url::from_directory_path(base).ok())
+        .and_then(|base| base.make_relative(&url))
+        .unwrap_or_else(|| url.to_string())
+}
+
+fn display_title(track: &Track) -> String {
+    let artists = track.artists_string();
+    if artists.is_empty() {
+        track.title.clone()
+    } else {
+        format!("{} - {}", artists, track.title)
+    }
+}
+
+fn write_m3u(name: &str, tracks: &[(&Track, String)]) -> String {
+    let mut text = format!("#EXTM3U\n#PLAYLIST:{}\n", name);
+    for (track, location) in tracks {
+        text.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            track.duration.round() as i64,
+            display_title(track),
+            location
+        ));
+    }
+    text
+}
+
+fn write_pls(tracks: &[(&Track, String)]) -> String {
+    let mut text = "[playlist]\n".to_string();
+    for (i, (track, location)) in tracks.iter().enumerate() {
+        let n = i + 1;
+        text.push_str(&format!(
+            "File{n}={}\nTitle{n}={}\nLength{n}={}\n",
+            location,
+            display_title(track),
+            track.duration.round() as i64
+        ));
+    }
+    text.push_str(&format!("NumberOfEntries={}\nVersion=2\n", tracks.len()));
+    text
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn write_xspf(name: &str, tracks: &[(&Track, String)]) -> String {
+    let mut text = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+         <title>{}</title>\n  <trackList>\n",
+        escape_xml(name)
+    );
+    for (track, location) in tracks {
+        text.push_str(&format!(
+            "    <track>\n      <location>{}</location>\n      <title>{}</title>\n      \
+             <creator>{}</creator>\n      <album>{}</album>\n      \
+             <duration>{}</duration>\n    </track>\n",
+            escape_xml(location),
+            escape_xml(&track.title),
+            escape_xml(&track.artists_string()),
+            escape_xml(&track.album.title),
+            (track.duration * 1000.0).round() as i64
+        ));
+    }
+    text.push_str("  </trackList>\n</playlist>\n");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        library::tests::temp_library,
+        test_audio::{temp_dir, tune, write_wav},
+    };
+
+    fn entry(location: &str, artist: Option<&str>, title: Option<&str>) -> FileEntry {
+        FileEntry {
+            location: location.to_string(),
+            title: title.map(str::to_string),
+            artist: artist.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn m3u_entries_and_titles() {
+        let file = parse_m3u(
+            "#EXTM3U\n\
+             #PLAYLIST:Road trip\n\
+             #EXTINF:215 tvg-name=\"a, b\",Artist - Song, Part 2\n\
+             music/song.mp3\n\
+             \n\
+             #EXTINF:-1,Untitled\n\
+             # a comment\n\
+             http://example.com/stream\n\
+             bare.flac\n",
+        );
+        assert_eq!(file.name.as_deref(), Some("Road trip"));
+        assert_eq!(
+            file.entries,
+            [
+                entry("music/song.mp3", Some("Artist"), Some("Song, Part 2")),
+                entry("http://example.com/stream", None, Some("Untitled")),
+                entry("bare.flac", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_entries_in_any_order() {
+        let file = parse_pls(
+            "[playlist]\n\
+             Title2=Second\n\
+             File2=two.mp3\n\
+             file1 = one.mp3\n\
+             Title1=Artist - First\n\
+             Length1=100\n\
+             Title3=No file\n\
+             NumberOfEntries=3\n",
+        );
+        assert_eq!(
+            file.entries,
+            [
+                entry("one.mp3", Some("Artist"), Some("First")),
+                entry("two.mp3", None, Some("Second")),
+            ]
+        );
+        assert_eq!(
+            PlaylistFormat::sniff("  [Playlist]\nFile1=a"),
+            PlaylistFormat::Pls
+        );
+        assert_eq!(PlaylistFormat::sniff("<?xml?>"), PlaylistFormat::Xspf);
+        assert_eq!(PlaylistFormat::sniff("a.mp3"), PlaylistFormat::M3u);
+    }
+
+    #[test]
+    fn xspf_entries() {
+        let file = parse_xspf(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <playlist version="1" xmlns="http://xspf.org/ns/0/">
+              <title>Mix</title>
+              <trackList>
+                <track>
+                  <location>music/My%20Song.flac</location>
+                  <title>My Song</title>
+                  <creator>Artist &amp; Friends</creator>
+                </track>
+                <track><title>Only a title</title></track>
+                <track><album>Nothing to match</album></track>
+              </trackList>
+            </playlist>"#,
+        )
+        .expect("Failed to parse");
+        assert_eq!(file.name.as_deref(), Some("Mix"));
+        assert_eq!(
+            file.entries,
+            [
+                entry(
+                    "music/My%20Song.flac",
+                    Some("Artist & Friends"),
+                    Some("My Song")
+                ),
+                entry("", None, Some("Only a title")),
+            ]
+        );
+        assert!(parse_xspf("<playlist>").is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn locations_resolve_to_local_paths() {
+        let base = Path::new("/music/playlists");
+        assert_eq!(
+            local_path("../album/01 song.mp3", base, false),
+            Some(PathBuf::from("/music/album/01 song.mp3"))
+        );
+        assert_eq!(
+            local_path("./a.mp3", base, false),
+            Some(PathBuf::from("/music/playlists/a.mp3"))
+        );
+        assert_eq!(
+            local_path("/other/a.mp3", base, false),
+            Some(PathBuf::from("/other/a.mp3"))
+        );
+        assert_eq!(
+            local_path("file:///other/a%20b.mp3", base, false),
+            Some(PathBuf::from("/other/a b.mp3"))
+        );
+        assert_eq!(local_path("http://example.com/a.mp3", base, false), None);
+        assert_eq!(local_path("", base, false), None);
+        assert_eq!(
+            local_path("../album/My%20Song.flac", base, true),
+            Some(PathBuf::from("/music/album/My Song.flac"))
+        );
+        assert_eq!(local_path("https://example.com/a.mp3", base, true), None);
+    }
+
+    // backslashes are separators on Windows anyway
+    #[cfg(not(windows))]
+    #[test]
+    fn windows_separators_are_converted() {
+        let base = Path::new("/music/playlists");
+        assert_eq!(
+            local_path("..\\album\\a.mp3", base, false),
+            Some(PathBuf::from("/music/album/a.mp3"))
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn exported_locations() {
+        let path = Path::new("/music/album/a b.mp3");
+        assert_eq!(
+            location_path(path, Some(Path::new("/music/playlists"))),
+            "../album/a b.mp3"
+        );
+        assert_eq!(location_path(path, None), "/music/album/a b.mp3");
+        assert_eq!(
+            location_uri(path, Some(Path::new("/music/playlists"))),
+            "../album/a%20b.mp3"
+        );
+        assert_eq!(location_uri(path, None), "file:///music/album/a%20b.mp3");
+    }
+
+    #[test]
+    fn latin1_files_are_decoded() {
+        assert_eq!(decode(b"\xEF\xBB\xBFcaf\xC3\xA9"), "café");
+        assert_eq!(decode(b"caf\xE9"), "café");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unplayable_files_are_not_imported() {
+        let dir = temp_dir();
+        write_wav(&dir.join("song.wav"), &tune(1, 1, 8000), 1, 8000);
+        // the first page of an Ogg stream holding Opus, which can't be played
+        let mut ogg = b"OggS".to_vec();
+        ogg.resize(26, 0);
+        ogg.extend([1, 19]);
+        ogg.extend(b"OpusHead");
+        ogg.resize(64, 0);
+        std::fs::write(dir.join("song.ogg"), ogg).expect("Failed to write file");
+        std::fs::write(dir.join("list.m3u"), "song.wav\nsong.ogg\ngone.wav\n")
+            .expect("Failed to write file");
+
+        let library = temp_library().await;
+        let import = library
+            .import_playlist(&dir.join("list.m3u"))
+            .await
+            .expect("Failed to import playlist");
+        assert_eq!(import.imported, 1);
+        assert_eq!(import.playlist.entries.len(), 1);
+        assert_eq!(import.unsupported, [(dir.join("song.ogg"), "Opus")]);
+        assert_eq!(import.missing, ["gone.wav"]);
+    }
+}
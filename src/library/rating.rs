@@ -0,0 +1,85 @@
+use anyhow::Result;
+use tokio::task;
+use turso::Value;
+
+use crate::{
+    library::{Library, LibraryEvent, Track, TrackSource, optional_integer, optional_text},
+    providers::local,
+};
+
+/// Highest star rating a track can have
+pub const MAX_RATING: u8 = 5;
+
+impl Library {
+    /// Give a track 0 (unrated) to `MAX_RATING` stars. Also written to the file's tags
+    /// when `Preferences::rating_tags` is on, but a file that can't be written doesn't
+    /// keep the library from being updated.
+    pub async fn set_rating(&self, track_id: &str, rating: u8) -> Result<Track> {
+        let rating = rating.min(MAX_RATING);
+        let mut track = self
+            .find_track_by_id(track_id)
+            .await?
+            .ok_or(anyhow::anyhow!("Track not found"))?;
+        if local::rating_tags_enabled()
+            && track.source == TrackSource::Local
+            // the tracks of a CUE sheet share one file, which has room for one rating
+            && track.cue.is_none()
+            && let Some(path) = track.path.clone()
+        {
+            let written = task::spawn_blocking(move || {
+                let path = std::path::Path::new(&path);
+                local::write_rating(path, rating)?;
+                let file_size = std::fs::metadata(path)?.len();
+                Ok::<_, anyhow::Error>((file_size, local::content_hash(path)?))
+            })
+            .await?;
+            match written {
+                // keep the file's size and hash current, they are used to find it if it moves
+                Ok((file_size, content_hash)) => {
+                    track.file_size = Some(file_size);
+                    track.content_hash = Some(content_hash);
+                }
+                Err(e) => eprintln!("Failed to write rating to {}: {}", track.title, e),
+            }
+        }
+        self.execute(
+            "UPDATE tracks SET rating = ?, file_size = ?, content_hash = ? WHERE id = ?",
+            vec![
+                Value::Integer(rating as i64),
+                optional_integer(track.file_size.map(|s| s as i64)),
+                optional_text(&track.content_hash),
+                Value::Text(track.id.clone()),
+            ],
+        )
+        .await?;
+        track.metadata.rating = rating;
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::TracksUpdated(vec![track.clone()]));
+        Ok(track)
+    }
+
+    /// Mark a track as loved, or not
+    pub async fn set_loved(&self, track_id: &str, loved: bool) -> Result<Track> {
+        let mut track = self
+            .find_track_by_id(track_id)
+            .await?
+            .ok_or(anyhow::anyhow!("Track not found"))?;
+        self.execute(
+            "UPDATE tracks SET loved = ? WHERE id = ?",
+            vec![Value::Integer(loved as i64), Value::Text(track.id.clone())],
+        )
+        .await?;
+        track.metadata.loved = loved;
+        let _ = self
+            .event_sender
+            .send(LibraryEvent::TracksUpdated(vec![track.clone()]));
+        Ok(track)
+    }
+}
+
+/// A rating as stars, e.g. "★★★☆☆"
+pub fn stars(rating: u8) -> String {
+    let rating = rating.min(MAX_RATING) as usize;
+    "★".repeat(rating) + &"☆".repeat(MAX_RATING as usize - rating)
+}
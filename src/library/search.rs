@@ -0,0 +1,490 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use turso::Value;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::library::{Album, Artist, Library, MAX_IN_PARAMS, Track};
+
+/// Maximum number of tracks returned by a library search
+const MAX_SEARCH_RESULTS: usize = 500;
+
+/// Fields a query term can be restricted to, e.g. `artist:radiohead`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchField {
+    Title,
+    Artist,
+    Album,
+    Year,
+    Genre,
+}
+
+impl SearchField {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "title" => Some(SearchField::Title),
+            "artist" => Some(SearchField::Artist),
+            "album" => Some(SearchField::Album),
+            "year" => Some(SearchField::Year),
+            "genre" => Some(SearchField::Genre),
+            _ => None,
+        }
+    }
+
+    /// How much a match in this field counts towards a result's rank
+    fn weight(self) -> f32 {
+        match self {
+            SearchField::Title => 3.0,
+            SearchField::Artist => 2.0,
+            SearchField::Album => 1.5,
+            SearchField::Year => 1.0,
+            SearchField::Genre => 1.0,
+        }
+    }
+}
+
+/// Fold text for matching: lowercase with diacritics removed ("Beyoncé" -> "beyonce")
+pub fn normalize(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct QueryTerm {
+    field: Option<SearchField>,
+    token: String,
+}
+
+/// Split a query into terms. `field:value` restricts a term to one field and
+/// double quotes keep words together, e.g. `artist:"the beatles" help`.
+fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    let mut terms = Vec::new();
+    for word in &words {
+        let (field, value) = match word.split_once(':') {
+            Some((name, value)) => match SearchField::from_name(name) {
+                Some(field) => (Some(field), value),
+                None => (None, word.as_str()),
+            },
+            None => (None, word.as_str()),
+        };
+        for token in tokenize(value) {
+            terms.push(QueryTerm { field, token });
+        }
+    }
+    terms
+}
+
+fn matches_any(terms: &[QueryTerm], field: SearchField, text: &str) -> bool {
+    let tokens = tokenize(text);
+    terms.iter().any(|term| {
+        term.field.is_none_or(|f| f == field) && tokens.iter().any(|t| t.starts_with(&term.token))
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    doc: u32,
+    field: SearchField,
+}
+
+/// In-memory inverted index over the library's tracks
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    tokens: BTreeMap<String, Vec<Posting>>,
+    /// track id for each document, `None` once the track has been removed
+    docs: Vec<Option<String>>,
+    doc_by_track: HashMap<String, u32>,
+}
+
+impl SearchIndex {
+    pub fn new(tracks: &[Track]) -> Self {
+        let mut index = Self::default();
+        for track in tracks {
+            index.insert(track);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, track: &Track) {
+        self.remove(&track.id);
+        let doc = self.docs.len() as u32;
+        self.docs.push(Some(track.id.clone()));
+        self.doc_by_track.insert(track.id.clone(), doc);
+
+        self.add_field(doc, SearchField::Title, &track.title);
+        for artist in track.artists.iter().chain(&track.album.artists) {
+            self.add_field(doc, SearchField::Artist, &artist.name);
+        }
+        self.add_field(doc, SearchField::Album, &track.album.title);
+        if let Some(year) = track.album.release_year {
+            self.add_field(doc, SearchField::Year, &year.to_string());
+        }
+        for genre in &track.metadata.genres {
+            self.add_field(doc, SearchField::Genre, genre);
+        }
+    }
+
+    pub fn remove(&mut self, track_id: &str) {
+        // postings of removed documents are skipped until they outnumber the live ones,
+        // then cleaned up all at once
+        if let Some(doc) = self.doc_by_track.remove(track_id) {
+            self.docs[doc as usize] = None;
+            if self.docs.len() - self.doc_by_track.len() > self.doc_by_track.len() {
+                self.compact();
+            }
+        }
+    }
+
+    /// Drop removed documents and their postings, renumbering the rest
+    fn compact(&mut self) {
+        let mut renumbered = vec![None; self.docs.len()];
+        let mut docs = Vec::with_capacity(self.doc_by_track.len());
+        for (doc, id) in std::mem::take(&mut self.docs).into_iter().enumerate() {
+            if let Some(id) = id {
+                let new_doc = docs.len() as u32;
+                renumbered[doc] = Some(new_doc);
+                self.doc_by_track.insert(id.clone(), new_doc);
+                docs.push(Some(id));
+            }
+        }
+        self.docs = docs;
+        self.tokens.retain(|_, postings| {
+            postings.retain_mut(|posting| match renumbered[posting.doc as usize] {
+                Some(doc) => {
+                    posting.doc = doc;
+                    true
+                }
+                None => false,
+            });
+            !postings.is_empty()
+        });
+    }
+
+    fn add_field(&mut self, doc: u32, field: SearchField, text: &str) {
+        for token in tokenize(text) {
+            self.tokens
+                .entry(token)
+                .or_default()
+                .push(Posting { doc, field });
+        }
+    }
+
+    /// Ids of tracks matching every term of the query, best match first
+    pub fn search(&self, query: &str) -> Vec<(String, f32)> {
+        let terms = parse_query(query);
+        let mut scores: Option<HashMap<u32, f32>> = None;
+        for term in &terms {
+            let mut term_scores: HashMap<u32, f32> = HashMap::new();
+            for (token, postings) in self
+                .tokens
+                .range(term.token.clone()..)
+                .take_while(|(t, _)| t.starts_with(&term.token))
+            {
+                // whole word matches rank above prefix matches
+                let closeness = if *token == term.token { 1.0 } else { 0.5 };
+                for posting in postings {
+                    if term.field.is_some_and(|f| f != posting.field) {
+                        continue;
+                    }
+                    let score = posting.field.weight() * closeness;
+                    let best = term_scores.entry(posting.doc).or_insert(0.0);
+                    if score > *best {
+                        *best = score;
+                    }
+                }
+            }
+            let combined = match scores {
+                None => term_scores,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(doc, s)| term_scores.get(&doc).map(|t| (doc, s + t)))
+                    .collect(),
+            };
+            let done = combined.is_empty();
+            scores = Some(combined);
+            if done {
+                break;
+            }
+        }
+
+        let mut results: Vec<(String, f32)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(doc, score)| self.docs[doc as usize].clone().map(|id| (id, score)))
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        results
+    }
+}
+
+/// Change to the library's tracks that the search index has to follow
+#[derive(Debug)]
+pub(super) enum IndexChange {
+    Insert(Track),
+    Remove(String),
+}
+
+impl IndexChange {
+    fn apply(self, index: &mut SearchIndex) {
+        match self {
+            IndexChange::Insert(track) => index.insert(&track),
+            IndexChange::Remove(track_id) => index.remove(&track_id),
+        }
+    }
+}
+
+/// The search index is built from the database the first time the library is searched
+#[derive(Debug, Default)]
+pub(super) enum IndexState {
+    #[default]
+    Unbuilt,
+    /// Changes made while the index is being built, applied to it before it is used
+    Building(Vec<IndexChange>),
+    Built(SearchIndex),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub tracks: Vec<Track>,
+    pub albums: Vec<Album>,
+    pub artists: Vec<Artist>,
+}
+
+impl Library {
+    /// Search the whole library. Terms are matched as word prefixes ignoring case and
+    /// diacritics, and can be restricted with `title:`, `artist:`, `album:`, `year:` or `genre:`.
+    pub async fn search(&self, query: &str) -> Result<SearchResults> {
+        self.ensure_search_index().await?;
+        let ranked = {
+            let index = self
+                .search_index
+                .read()
+                .map_err(|_| anyhow::anyhow!("Search index lock poisoned"))?;
+            match &*index {
+                IndexState::Built(index) => index.search(query),
+                _ => Vec::new(),
+            }
+        };
+
+        let ids: Vec<String> = ranked
+            .into_iter()
+            .take(MAX_SEARCH_RESULTS)
+            .map(|(id, _)| id)
+            .collect();
+        let mut found: HashMap<String, Track> = HashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_IN_PARAMS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let tracks = self
+                .load_tracks(
+                    &format!("WHERE t.id IN ({placeholders})"),
+                    chunk.iter().cloned().map(Value::Text).collect(),
+                )
+                .await?;
+            found.extend(tracks.into_iter().map(|t| (t.id.clone(), t)));
+        }
+
+        let terms = parse_query(query);
+        let mut results = SearchResults::default();
+        // in the order the index ranked them
+        for id in &ids {
+            let Some(track) = found.remove(id) else {
+                continue;
+            };
+            if !results.albums.iter().any(|a| a.id == track.album.id)
+                && matches_any(&terms, SearchField::Album, &track.album.title)
+            {
+                results.albums.push(track.album.clone());
+            }
+            for artist in &track.artists {
+                if !results.artists.iter().any(|a| a.id == artist.id)
+                    && matches_any(&terms, SearchField::Artist, &artist.name)
+                {
+                    results.artists.push(artist.clone());
+                }
+            }
+            results.tracks.push(track);
+        }
+        Ok(results)
+    }
+
+    /// Build the search index from the database the first time it is needed. Tracks
+    /// that change while the index is built are applied to it afterwards, so changes
+    /// made between reading the tracks and storing the index aren't lost.
+    async fn ensure_search_index(&self) -> Result<()> {
+        // one build at a time, later callers wait for it and use its index
+        let _building = self.search_build.lock().await;
+        {
+            let mut state = self
+                .search_index
+                .write()
+                .map_err(|_| anyhow::anyhow!("Search index lock poisoned"))?;
+            if matches!(*state, IndexState::Built(_)) {
+                return Ok(());
+            }
+            *state = IndexState::Building(Vec::new());
+        }
+        let tracks = self.all_tracks().await;
+        let mut state = self
+            .search_index
+            .write()
+            .map_err(|_| anyhow::anyhow!("Search index lock poisoned"))?;
+        // the library may have been restored meanwhile, the next search builds it again
+        let IndexState::Building(changes) = std::mem::take(&mut *state) else {
+            return Ok(());
+        };
+        let mut index = SearchIndex::new(&tracks?);
+        for change in changes {
+            change.apply(&mut index);
+        }
+        *state = IndexState::Built(index);
+        Ok(())
+    }
+
+    fn change_index(&self, changes: impl IntoIterator<Item = IndexChange>) {
+        if let Ok(mut state) = self.search_index.write() {
+            match &mut *state {
+                IndexState::Unbuilt => {}
+                IndexState::Building(pending) => pending.extend(changes),
+                IndexState::Built(index) => {
+                    for change in changes {
+                        change.apply(index);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keep the search index in sync with changed tracks. Does nothing until it is built.
+    pub(super) fn index_tracks(&self, tracks: &[Track]) {
+        self.change_index(tracks.iter().cloned().map(IndexChange::Insert));
+    }
+
+    pub(super) fn unindex_track(&self, track_id: &str) {
+        self.change_index([IndexChange::Remove(track_id.to_string())]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{AudioProperties, TrackMetadata, TrackSource};
+
+    fn track(id: &str, title: &str, artist: &str, album: &str) -> Track {
+        let artist = Artist::new(artist.to_string());
+        Track {
+            id: id.to_string(),
+            title: title.to_string(),
+            artists: vec![artist.clone()],
+            album: Album::new(album.to_string(), vec![artist], Some(1997), None),
+            duration: 200.0,
+            path: None,
+            source: TrackSource::Local,
+            source_id: None,
+            track_number: None,
+            file_size: None,
+            content_hash: None,
+            metadata: TrackMetadata::default(),
+            audio: AudioProperties::default(),
+            cue: None,
+        }
+    }
+
+    fn ids(results: Vec<(String, f32)>) -> Vec<String> {
+        results.into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn tokenize_folds_case_and_diacritics() {
+        assert_eq!(
+            tokenize("Beyoncé - Halo (Live, 2009)"),
+            ["beyonce", "halo", "live", "2009"]
+        );
+        assert_eq!(tokenize("  ...  "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn parse_query_fields_and_quotes() {
+        let terms = parse_query(r#"artist:"the beatles" help unknown:x"#);
+        let parsed: Vec<(Option<SearchField>, &str)> =
+            terms.iter().map(|t| (t.field, t.token.as_str())).collect();
+        assert_eq!(
+            parsed,
+            [
+                (Some(SearchField::Artist), "the"),
+                (Some(SearchField::Artist), "beatles"),
+                (None, "help"),
+                (None, "unknown"),
+                (None, "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranking() {
+        let mut index = SearchIndex::new(&[
+            track("a", "Karma Police", "Radiohead", "OK Computer"),
+            track("b", "Paranoid Android", "Radiohead", "OK Computer"),
+            track("c", "Police Academy", "Someone", "Karmageddon"),
+            track("d", "Lucky", "Radiohead", "Karma"),
+        ]);
+        // a title match beats an album match, which beats a prefix of an album
+        assert_eq!(ids(index.search("karma")), ["a", "d", "c"]);
+        // every term has to match
+        assert_eq!(ids(index.search("karma police")), ["a", "c"]);
+        assert_eq!(ids(index.search("album:karma")), ["d", "c"]);
+        assert_eq!(ids(index.search("title:computer")), Vec::<String>::new());
+        assert_eq!(ids(index.search("year:1997 paranoid")), ["b"]);
+
+        index.remove("a");
+        assert_eq!(ids(index.search("karma police")), ["c"]);
+        // inserting a track again replaces it
+        index.insert(&track("d", "Lucky", "Radiohead", "OK Computer"));
+        assert_eq!(ids(index.search("karma")), ["c"]);
+    }
+
+    #[test]
+    fn removed_tracks_are_compacted() {
+        let mut index = SearchIndex::new(&[
+            track("a", "Karma Police", "Radiohead", "OK Computer"),
+            track("b", "Lucky", "Radiohead", "OK Computer"),
+        ]);
+        for _ in 0..100 {
+            index.insert(&track("a", "Karma Police", "Radiohead", "OK Computer"));
+        }
+        assert!(index.docs.len() <= 4);
+        assert!(index.tokens["radiohead"].len() <= 8);
+        assert_eq!(ids(index.search("radiohead")), ["a", "b"]);
+
+        index.remove("a");
+        index.remove("b");
+        assert!(index.docs.is_empty() && index.tokens.is_empty());
+    }
+}
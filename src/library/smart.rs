@@ -0,0 +1,505 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use turso::Value;
+
+use crate::library::{Library, TRACK_FROM, Track, TrackSort};
+
+/// What a smart playlist rule looks at
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleField {
+    #[default]
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Composer,
+    Codec,
+    Year,
+    PlayCount,
+    SkipCount,
+    /// Stars, 0 for unrated tracks
+    Rating,
+    Loved,
+    /// Length in seconds
+    Duration,
+    /// When the track was added, compared in days
+    Added,
+    /// When the track was last played, compared in days
+    LastPlayed,
+}
+
+/// How a rule compares its field to its value
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleOperator {
+    #[default]
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    StartsWith,
+    GreaterThan,
+    LessThan,
+    InTheLast,
+    NotInTheLast,
+}
+
+/// A condition on tracks, e.g. "genre is Jazz". The value is kept as typed and parsed
+/// when the rules are compiled.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Rule {
+    pub field: RuleField,
+    pub operator: RuleOperator,
+    pub value: String,
+}
+
+/// The rules of a smart playlist, whose tracks are found by running them against the
+/// library instead of being stored
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SmartRules {
+    /// Whether tracks have to match every rule, or any of them
+    pub match_all: bool,
+    pub rules: Vec<Rule>,
+    pub sort: TrackSort,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+/// The column or linked table a field is read from
+enum Source {
+    Text(&'static str),
+    /// A text column in a table linked to the track, matching if any of its rows match
+    Linked {
+        from: &'static str,
+        column: &'static str,
+    },
+    Number(&'static str),
+    /// A column holding 0 or 1, compared with "yes" or "no"
+    Flag(&'static str),
+    Days(&'static str),
+}
+
+const TEXT_OPERATORS: &[RuleOperator] = &[
+    RuleOperator::Is,
+    RuleOperator::IsNot,
+    RuleOperator::Contains,
+    RuleOperator::DoesNotContain,
+    RuleOperator::StartsWith,
+];
+
+const NUMBER_OPERATORS: &[RuleOperator] = &[
+    RuleOperator::Is,
+    RuleOperator::IsNot,
+    RuleOperator::GreaterThan,
+    RuleOperator::LessThan,
+];
+
+const FLAG_OPERATORS: &[RuleOperator] = &[RuleOperator::Is, RuleOperator::IsNot];
+
+const DAYS_OPERATORS: &[RuleOperator] = &[RuleOperator::InTheLast, RuleOperator::NotInTheLast];
+
+const DAY_MILLIS: f64 = 86_400_000.0;
+
+impl RuleField {
+    pub const ALL: [RuleField; 14] = [
+        RuleField::Title,
+        RuleField::Artist,
+        RuleField::Album,
+        RuleField::Genre,
+        RuleField::Composer,
+        RuleField::Codec,
+        RuleField::Year,
+        RuleField::PlayCount,
+        RuleField::SkipCount,
+        RuleField::Rating,
+        RuleField::Loved,
+        RuleField::Duration,
+        RuleField::Added,
+        RuleField::LastPlayed,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RuleField::Title => "Title",
+            RuleField::Artist => "Artist",
+            RuleField::Album => "Album",
+            RuleField::Genre => "Genre",
+            RuleField::Composer => "Composer",
+            RuleField::Codec => "Codec",
+            RuleField::Year => "Year",
+            RuleField::PlayCount => "Play count",
+            RuleField::SkipCount => "Skip count",
+            RuleField::Rating => "Rating",
+            RuleField::Loved => "Loved",
+            RuleField::Duration => "Duration (seconds)",
+            RuleField::Added => "Date added",
+            RuleField::LastPlayed => "Last played",
+        }
+    }
+
+    /// Operators that can be used with this field
+    pub fn operators(&self) -> &'static [RuleOperator] {
+        match self.source() {
+            Source::Text(_) | Source::Linked { .. } => TEXT_OPERATORS,
+            Source::Number(_) => NUMBER_OPERATORS,
+            Source::Flag(_) => FLAG_OPERATORS,
+            Source::Days(_) => DAYS_OPERATORS,
+        }
+    }
+
+    fn source(&self) -> Source {
+        match self {
+            RuleField::Title => Source::Text("t.title"),
+            RuleField::Artist => Source::Linked {
+                from: "track_artists ra INNER JOIN artists rar ON rar.id = ra.artist_id
+                       WHERE ra.track_id = t.id",
+                column: "rar.name",
+            },
+            RuleField::Album => Source::Text("al.title"),
+            RuleField::Genre => Source::Linked {
+                from: "track_genres rg WHERE rg.track_id = t.id",
+                column: "rg.genre",
+            },
+            RuleField::Composer => Source::Text("t.composer"),
+            RuleField::Codec => Source::Text("t.codec"),
+            RuleField::Year => Source::Number("al.release_year"),
+            RuleField::PlayCount => Source::Number("t.play_count"),
+            RuleField::SkipCount => Source::Number("t.skip_count"),
+            RuleField::Rating => Source::Number("t.rating"),
+            RuleField::Loved => Source::Flag("t.loved"),
+            RuleField::Duration => Source::Number("t.duration"),
+            RuleField::Added => Source::Days("t.added_at"),
+            RuleField::LastPlayed => Source::Days("t.last_played"),
+        }
+    }
+}
+
+impl RuleOperator {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RuleOperator::Is => "is",
+            RuleOperator::IsNot => "is not",
+            RuleOperator::Contains => "contains",
+            RuleOperator::DoesNotContain => "does not contain",
+            RuleOperator::StartsWith => "starts with",
+            RuleOperator::GreaterThan => "is greater than",
+            RuleOperator::LessThan => "is less than",
+            RuleOperator::InTheLast => "in the last (days)",
+            RuleOperator::NotInTheLast => "not in the last (days)",
+        }
+    }
+
+    /// Whether the operator matches tracks where the positive form doesn't
+    fn negated(&self) -> bool {
+        matches!(
+            self,
+            RuleOperator::IsNot | RuleOperator::DoesNotContain | RuleOperator::NotInTheLast
+        )
+    }
+}
+
+impl Rule {
+    /// Compile the rule to an SQL condition on `t` (tracks) and `al` (albums), pushing
+    /// its parameters onto `params`
+    fn to_sql(&self, params: &mut Vec<Value>) -> Result<String> {
+        if !self.field.operators().contains(&self.operator) {
+            return Err(anyhow::anyhow!(
+                "\"{}\" can't be used with {}",
+                self.operator.label(),
+                self.field.label()
+            ));
+        }
+        let value = self.value.trim();
+        match self.field.source() {
+            Source::Text(column) => {
+                let condition = text_condition(column, self.operator, value, params);
+                Ok(if self.operator.negated() {
+                    format!("NOT ({})", condition)
+                } else {
+                    condition
+                })
+            }
+            Source::Linked { from, column } => {
+                let condition = text_condition(column, self.operator, value, params);
+                let exists = format!("EXISTS (SELECT 1 FROM {} AND {})", from, condition);
+                Ok(if self.operator.negated() {
+                    format!("NOT {}", exists)
+                } else {
+                    exists
+                })
+            }
+            Source::Number(column) => {
+                params.push(Value::Real(self.number(value)?));
+                Ok(match self.operator {
+                    RuleOperator::IsNot => format!("({column} IS NULL OR {column} != ?)"),
+                    RuleOperator::GreaterThan => format!("{column} > ?"),
+                    RuleOperator::LessThan => format!("{column} < ?"),
+                    _ => format!("{column} = ?"),
+                })
+            }
+            Source::Flag(column) => {
+                let value = match value.to_lowercase().as_str() {
+                    "yes" | "true" | "1" => 1,
+                    "no" | "false" | "0" => 0,
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "{} needs \"yes\" or \"no\", not \"{}\"",
+                            self.field.label(),
+                            value
+                        ));
+                    }
+                };
+                params.push(Value::Integer(value));
+                Ok(if self.operator.negated() {
+                    format!("{column} != ?")
+                } else {
+                    format!("{column} = ?")
+                })
+            }
+            Source::Days(column) => {
+                let since = Utc::now().timestamp_millis() as f64 - self.number(value)? * DAY_MILLIS;
+                params.push(Value::Integer(since as i64));
+                Ok(if self.operator.negated() {
+                    format!("({column} IS NULL OR {column} < ?)")
+                } else {
+                    format!("{column} >= ?")
+                })
+            }
+        }
+    }
+
+    fn number(&self, value: &str) -> Result<f64> {
+        value.parse().map_err(|_| {
+            anyhow::anyhow!("{} needs a number, not \"{}\"", self.field.label(), value)
+        })
+    }
+}
+
+/// Case insensitive comparison of a text column, always in its positive form
+/// (`is`, `contains` or `starts with`). Missing values compare as empty text.
+fn text_condition(
+    column: &str,
+    operator: RuleOperator,
+    value: &str,
+    params: &mut Vec<Value>,
+) -> String {
+    params.push(Value::Text(value.to_string()));
+    let column = format!("lower(COALESCE({}, ''))", column);
+    match operator {
+        RuleOperator::Contains | RuleOperator::DoesNotContain => {
+            format!("instr({}, lower(?)) > 0", column)
+        }
+        RuleOperator::StartsWith => format!("instr({}, lower(?)) = 1", column),
+        _ => format!("{} = lower(?)", column),
+    }
+}
+
+impl Default for SmartRules {
+    fn default() -> Self {
+        Self {
+            match_all: true,
+            rules: Vec::new(),
+            sort: TrackSort::default(),
+            descending: false,
+            limit: None,
+        }
+    }
+}
+
+impl SmartRules {
+    /// Compile the rules to a clause for `Library::load_tracks` (WHERE, ORDER BY and
+    /// LIMIT) and its parameters. Values are always bound, never written into the SQL.
+    pub fn to_sql(&self) -> Result<(String, Vec<Value>)> {
+        let mut params = Vec::new();
+        let conditions = self
+            .rules
+            .iter()
+            .map(|rule| rule.to_sql(&mut params).map(|sql| format!("({})", sql)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut clause = String::new();
+        if !conditions.is_empty() {
+            let join = if self.match_all { " AND " } else { " OR " };
+            clause.push_str(&format!("WHERE {} ", conditions.join(join)));
+        }
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        clause.push_str(&format!(
+            "ORDER BY {} {}, t.id",
+            self.sort.expression(),
+            direction
+        ));
+        if let Some(limit) = self.limit {
+            clause.push_str(" LIMIT ?");
+            params.push(Value::Integer(limit as i64));
+        }
+        Ok((clause, params))
+    }
+}
+
+impl Library {
+    /// Tracks matching a smart playlist's rules, in its order
+    pub async fn smart_playlist_tracks(&self, rules: &SmartRules) -> Result<Vec<Track>> {
+        let (clause, params) = rules.to_sql()?;
+        self.load_tracks(&clause, params).await
+    }
+
+    /// Number of tracks matching a smart playlist's rules
+    pub async fn smart_playlist_count(&self, rules: &SmartRules) -> Result<usize> {
+        let (clause, params) = rules.to_sql()?;
+        let rows = self
+            .query(
+                &format!("SELECT COUNT(*) FROM (SELECT t.id {TRACK_FROM} {clause})"),
+                params,
+            )
+            .await?;
+        match rows.first() {
+            Some(row) => Ok(Self::get_i64(&row[0])? as usize),
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(field: RuleField, operator: RuleOperator, value: &str) -> Rule {
+        Rule {
+            field,
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    fn compile(rule: &Rule) -> (String, Vec<Value>) {
+        let mut params = Vec::new();
+        let sql = rule.to_sql(&mut params).expect("Failed to compile rule");
+        (sql, params)
+    }
+
+    #[test]
+    fn every_source_compiles() {
+        assert_eq!(
+            compile(&rule(RuleField::Title, RuleOperator::Is, " Song ")),
+            (
+                "lower(COALESCE(t.title, '')) = lower(?)".to_string(),
+                vec![Value::Text("Song".to_string())]
+            )
+        );
+        let (sql, params) = compile(&rule(RuleField::Genre, RuleOperator::StartsWith, "jazz"));
+        assert!(sql.starts_with("EXISTS (SELECT 1 FROM track_genres rg"));
+        assert!(sql.ends_with("AND instr(lower(COALESCE(rg.genre, '')), lower(?)) = 1)"));
+        assert_eq!(params, [Value::Text("jazz".to_string())]);
+        assert_eq!(
+            compile(&rule(RuleField::PlayCount, RuleOperator::GreaterThan, "5")),
+            ("t.play_count > ?".to_string(), vec![Value::Real(5.0)])
+        );
+        assert_eq!(
+            compile(&rule(RuleField::Loved, RuleOperator::Is, "Yes")),
+            ("t.loved = ?".to_string(), vec![Value::Integer(1)])
+        );
+        let (sql, params) = compile(&rule(RuleField::Added, RuleOperator::InTheLast, "7"));
+        assert_eq!(sql, "t.added_at >= ?");
+        let week_ago = Utc::now().timestamp_millis() - 7 * DAY_MILLIS as i64;
+        assert!(matches!(params[..], [Value::Integer(since)] if (since - week_ago).abs() < 60_000));
+    }
+
+    #[test]
+    fn negated_operators_match_missing_values() {
+        // text compares missing values as empty, so NOT keeps tracks without a composer
+        assert_eq!(
+            compile(&rule(RuleField::Composer, RuleOperator::IsNot, "Bach")).0,
+            "NOT (lower(COALESCE(t.composer, '')) = lower(?))"
+        );
+        let (sql, _) = compile(&rule(RuleField::Artist, RuleOperator::DoesNotContain, "x"));
+        assert!(sql.starts_with("NOT EXISTS (SELECT 1 FROM track_artists ra"));
+        assert!(sql.ends_with("AND instr(lower(COALESCE(rar.name, '')), lower(?)) > 0)"));
+        assert_eq!(
+            compile(&rule(RuleField::Year, RuleOperator::IsNot, "1999")).0,
+            "(al.release_year IS NULL OR al.release_year != ?)"
+        );
+        assert_eq!(
+            compile(&rule(
+                RuleField::LastPlayed,
+                RuleOperator::NotInTheLast,
+                "30"
+            ))
+            .0,
+            "(t.last_played IS NULL OR t.last_played < ?)"
+        );
+        assert_eq!(
+            compile(&rule(RuleField::Loved, RuleOperator::IsNot, "no")),
+            ("t.loved != ?".to_string(), vec![Value::Integer(0)])
+        );
+    }
+
+    #[test]
+    fn rules_are_joined_and_limited() {
+        let rules = SmartRules {
+            match_all: false,
+            rules: vec![
+                rule(
+                    RuleField::Title,
+                    RuleOperator::Contains,
+                    "'; DROP TABLE tracks; --",
+                ),
+                rule(RuleField::Rating, RuleOperator::LessThan, "3"),
+            ],
+            sort: TrackSort::PlayCount,
+            descending: true,
+            limit: Some(25),
+        };
+        let (clause, params) = rules.to_sql().expect("Failed to compile rules");
+        assert_eq!(
+            clause,
+            "WHERE (instr(lower(COALESCE(t.title, '')), lower(?)) > 0) OR (t.rating < ?) \
+             ORDER BY t.play_count DESC, t.id LIMIT ?"
+        );
+        assert_eq!(
+            params,
+            [
+                Value::Text("'; DROP TABLE tracks; --".to_string()),
+                Value::Real(3.0),
+                Value::Integer(25),
+            ]
+        );
+
+        let all = SmartRules {
+            rules: rules.rules.clone(),
+            ..SmartRules::default()
+        };
+        let (clause, params) = all.to_sql().expect("Failed to compile rules");
+        assert!(clause.contains(") AND ("));
+        assert!(!clause.contains("LIMIT"));
+        assert_eq!(params.len(), 2);
+        let (clause, params) = SmartRules::default()
+            .to_sql()
+            .expect("Failed to compile rules");
+        assert_eq!(clause, "ORDER BY t.sort_key ASC, t.id");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn mismatched_rules_are_rejected() {
+        let mut params = Vec::new();
+        for rule in [
+            rule(RuleField::Title, RuleOperator::GreaterThan, "1"),
+            rule(RuleField::PlayCount, RuleOperator::Contains, "1"),
+            rule(RuleField::Loved, RuleOperator::InTheLast, "1"),
+            rule(RuleField::Added, RuleOperator::Is, "1"),
+            rule(RuleField::PlayCount, RuleOperator::Is, "many"),
+            rule(RuleField::Loved, RuleOperator::Is, "maybe"),
+        ] {
+            assert!(rule.to_sql(&mut params).is_err(), "{:?} compiled", rule);
+        }
+        let rules = SmartRules {
+            rules: vec![
+                rule(RuleField::Title, RuleOperator::Is, "a"),
+                rule(RuleField::Year, RuleOperator::StartsWith, "19"),
+            ],
+            ..SmartRules::default()
+        };
+        assert!(rules.to_sql().is_err());
+    }
+}
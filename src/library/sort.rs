@@ -0,0 +1,127 @@
+use icu_collator::{Collator, CollatorBorrowed, CollatorPreferences, options::CollatorOptions};
+use icu_locale_core::Locale;
+use once_cell::sync::OnceCell;
+
+/// Leading articles ignored when sorting, so "The Beatles" sorts under B
+const ARTICLES: &[&str] = &[
+    "the ", "a ", "an ", "le ", "la ", "les ", "l'", "el ", "los ", "las ",
+];
+
+static COLLATOR: OnceCell<CollatorBorrowed<'static>> = OnceCell::new();
+
+/// Locale of the system, e.g. `sv-SE` from `sv_SE.UTF-8`
+fn system_locale() -> Option<Locale> {
+    let name = sys_locale::get_locale()?;
+    let name = name.split(['.', '@']).next()?.replace('_', "-");
+    Locale::try_from_str(&name).ok()
+}
+
+/// Collator for the system's language, falling back to the root collation, which
+/// orders most scripts sensibly but knows no language's special letters
+fn collator() -> &'static CollatorBorrowed<'static> {
+    COLLATOR.get_or_init(|| {
+        let preferences = system_locale()
+            .map(|locale| CollatorPreferences::from(&locale))
+            .unwrap_or_default();
+        Collator::try_new(preferences, CollatorOptions::default())
+            .or_else(|_| Collator::try_new(Default::default(), CollatorOptions::default()))
+            .expect("The root collation is built in")
+    })
+}
+
+/// The name without a leading article or punctuation, unless nothing would be left
+fn strip_name(name: &str) -> &str {
+    let mut key = name.trim();
+    if let Some(article) = ARTICLES.iter().find(|article| {
+        key.get(..article.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(article))
+    }) && key.len() > article.len()
+    {
+        key = &key[article.len()..];
+    }
+    let stripped = key.trim_start_matches(|c: char| !c.is_alphanumeric());
+    if stripped.is_empty() { key } else { stripped }
+}
+
+/// Collation key of `text`, hex encoded so comparing keys as strings (as SQLite
+/// does) gives the collator's order
+fn collation_key(collator: &CollatorBorrowed, text: &str) -> String {
+    let mut key: Vec<u8> = Vec::new();
+    let _ = collator.write_sort_key_to(text, &mut key);
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Key that artists, albums and tracks are ordered by.
+///
+/// A sort name from the file's tags (e.g. "Beatles, The" or a romanised name) is used
+/// as given, otherwise leading articles and punctuation are dropped from the name.
+/// Either way the key is a collation key for the system's language, so accents, case
+/// and other scripts sort the way a reader of that language expects. Keys are stored,
+/// so ones written under another language keep that language's order.
+pub fn sort_key(name: &str, sort_name: Option<&str>) -> String {
+    let text = match sort_name.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(sort_name) => sort_name,
+        None => strip_name(name),
+    };
+    collation_key(collator(), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_key(text: &str) -> String {
+        let collator = Collator::try_new(Default::default(), CollatorOptions::default())
+            .expect("The root collation is built in");
+        collation_key(&collator, strip_name(text))
+    }
+
+    #[test]
+    fn articles_and_punctuation_are_skipped() {
+        assert_eq!(strip_name("The Beatles"), "Beatles");
+        assert_eq!(strip_name("  the  Beatles"), "Beatles");
+        assert_eq!(strip_name("L'Impératrice"), "Impératrice");
+        assert_eq!(
+            strip_name("...And You Will Know Us"),
+            "And You Will Know Us"
+        );
+        // names that are only an article, or don't start with one, are kept
+        assert_eq!(strip_name("The"), "The");
+        assert_eq!(strip_name("Theatre"), "Theatre");
+        assert_eq!(strip_name("Anathema"), "Anathema");
+        assert_eq!(strip_name("!!!"), "!!!");
+    }
+
+    #[test]
+    fn sort_names_are_used_as_given() {
+        assert_eq!(
+            sort_key("The Beatles", Some("Beatles, The")),
+            sort_key("Beatles, The", None)
+        );
+        assert_eq!(sort_key("Björk", Some("  ")), sort_key("Björk", None));
+        assert_eq!(sort_key("The Beatles", None), sort_key("Beatles", None));
+    }
+
+    #[test]
+    fn keys_follow_collation_order() {
+        let sorted = [
+            "abba",
+            "Émile",
+            "Eno",
+            "zappa",
+            "Αλκίνοος",
+            "Ωμέγα",
+            "Аквариум",
+            "Ёлка",
+            "Кино",
+            "中島みゆき",
+        ];
+        let keys: Vec<String> = sorted.iter().map(|name| root_key(name)).collect();
+        let mut ordered = keys.clone();
+        ordered.sort();
+        assert_eq!(keys, ordered);
+        // accents and case only break ties
+        assert!(root_key("emile") < root_key("Émile"));
+        assert!(root_key("Émile") < root_key("Emilia"));
+    }
+}
@@ -1,12 +1,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 pub mod components;
 pub mod controls;
+pub mod fingerprint;
 pub mod library;
 pub mod lyrics;
 pub mod player;
 pub mod preferences;
 pub mod providers;
 pub mod resources;
+#[cfg(test)]
+mod test_audio;
 pub mod views;
 
 use std::{path::PathBuf, time::Duration};
@@ -22,7 +25,7 @@ use tokio::{
 
 use crate::{
     components::sidebar::NavigationState,
-    library::LIBRARY,
+    library::{LIBRARY, art::ArtSize},
     player::{PLAYER, Player, PlayerEvent},
     preferences::{PREFERENCES, read_preferences},
     resources::Resources,
@@ -32,8 +35,16 @@ pub struct App {
     player: Entity<components::player::Player>,
     sidebar: Entity<components::sidebar::Sidebar>,
     home_view: Entity<views::HomeView>,
+    albums_view: Entity<views::AlbumsView>,
+    artists_view: Entity<views::ArtistsView>,
     search_view: Entity<views::SearchView>,
     lyrics_view: Entity<views::LyricsView>,
+    health_view: Entity<views::HealthView>,
+    history_view: Entity<views::HistoryView>,
+    import_view: Entity<views::ImportView>,
+    duplicates_view: Entity<views::DuplicatesView>,
+    tag_editor_view: Entity<views::TagEditorView>,
+    playlist_view: Entity<views::PlaylistView>,
 }
 
 impl App {
@@ -42,15 +53,35 @@ impl App {
 
         let sidebar = cx.new(|cx| components::sidebar::Sidebar::new(cx));
         let home_view = cx.new(|cx| views::HomeView::new(window, cx));
+        let albums_view = cx.new(|cx| views::AlbumsView::new(window, cx));
+        let navigation_state = sidebar.read(cx).navigation_state.clone();
+        let albums_for_artists = albums_view.clone();
+        let artists_view = cx.new(|cx| {
+            views::ArtistsView::new(window, cx, albums_for_artists, navigation_state.clone())
+        });
         let player_for_search = player.clone();
         let search_view = cx.new(|cx| views::SearchView::new(window, cx, player_for_search));
         let lyrics_view = cx.new(|cx| views::LyricsView::new(window, cx));
+        let health_view = cx.new(|cx| views::HealthView::new(window, cx));
+        let history_view = cx.new(|cx| views::HistoryView::new(window, cx));
+        let import_view = cx.new(|cx| views::ImportView::new(window, cx));
+        let duplicates_view = cx.new(|cx| views::DuplicatesView::new(window, cx));
+        let tag_editor_view = cx.new(|cx| views::TagEditorView::new(window, cx));
+        let playlist_view = cx.new(|cx| views::PlaylistView::new(window, cx, navigation_state));
         Self {
             player,
             sidebar,
             home_view,
+            albums_view,
+            artists_view,
             search_view,
             lyrics_view,
+            health_view,
+            history_view,
+            import_view,
+            duplicates_view,
+            tag_editor_view,
+            playlist_view,
         }
     }
 }
@@ -60,8 +91,16 @@ impl Render for App {
         let navitem = sidebar.navigation_state.read(cx);
         let render = match navitem {
             NavigationState::Home => self.home_view.clone().into_any_element(),
+            NavigationState::Albums => self.albums_view.clone().into_any_element(),
+            NavigationState::Artists => self.artists_view.clone().into_any_element(),
             NavigationState::Search => self.search_view.clone().into_any_element(),
             NavigationState::Lyrics => self.lyrics_view.clone().into_any_element(),
+            NavigationState::Health => self.health_view.clone().into_any_element(),
+            NavigationState::History => self.history_view.clone().into_any_element(),
+            NavigationState::Import => self.import_view.clone().into_any_element(),
+            NavigationState::Duplicates => self.duplicates_view.clone().into_any_element(),
+            NavigationState::TagEditor => self.tag_editor_view.clone().into_any_element(),
+            NavigationState::Playlist(_) => self.playlist_view.clone().into_any_element(),
         };
         // This is a weird bug as "DM Sans" works perfectly fine on Linux, but
         // Windows only recognises the font as "DM Sans 14pt" for some reason.
@@ -122,6 +161,7 @@ async fn main() {
         .await
         .expect("Failed to initialize library");
     println!("Library initialized successfully.");
+    task::spawn(library::backup::run_automatic_backups());
     task::spawn(async move {
         let preferences = PREFERENCES
             .get()
@@ -175,6 +215,7 @@ async fn main() {
                 }
                 PlayerEvent::End => {}
                 PlayerEvent::TrackLoaded(track) => {
+                    let album_art = track.album.thumbnail(ArtSize::Large).await;
                     let mut controls = CONTROLS
                         .get()
                         .expect("Media controls not initialized")
@@ -183,10 +224,10 @@ async fn main() {
                     #[cfg(target_os = "windows")]
                     {
                         // On Windows, set the album art if available
-                        if let Some(ref album_art) = track.album.album_art {
+                        if let Some(ref album_art) = album_art {
                             use souvlaki::platform::windows::WindowsCover;
                             controls
-                                .set_cover(Some(WindowsCover::Bytes(album_art.clone())))
+                                .set_cover(Some(WindowsCover::Bytes(album_art.to_vec())))
                                 .unwrap_or_else(|e| {
                                     eprintln!("Failed to set album art: {:?}", e);
                                 });
@@ -195,7 +236,7 @@ async fn main() {
                     #[cfg(target_os = "linux")]
                     {
                         // On Linux, also set the album art if available
-                        if let Some(ref album_art) = track.album.album_art {
+                        if let Some(ref album_art) = album_art {
                             use souvlaki::platform::mpris::MprisCover;
                             controls
                                 .set_cover(Some(
@@ -270,6 +311,7 @@ async fn main() {
     app.run(move |cx| {
         // This must be called before using any GPUI Component features.
         gpui_component::init(cx);
+        components::player::init(cx);
         let theme_name = SharedString::from("Tokyo Night"); // TODO: theme preferences
         if let Err(err) = ThemeRegistry::watch_dir(PathBuf::from("./themes"), cx, move |cx| {
             if let Some(theme) = ThemeRegistry::global(cx).themes().get(&theme_name).cloned() {
@@ -11,10 +11,27 @@ use tokio::{
     task, time,
 };
 
-use crate::{library::Track, preferences::PREFERENCES};
+use crate::{
+    library::{LIBRARY, Track},
+    preferences::PREFERENCES,
+};
 
 pub static PLAYER: OnceCell<Player> = OnceCell::new();
 
+/// Share of a track that has to be reached for it to count as played
+const PLAYED_FRACTION: f32 = 0.5;
+
+/// Seconds listened after which long tracks count as played, before `PLAYED_FRACTION`
+const PLAYED_SECONDS: f32 = 240.0;
+
+/// Largest step in position between two progress updates that counts as listening,
+/// anything further is a seek
+const MAX_LISTEN_STEP: f32 = 2.0;
+
+/// Seconds before the end of a track at which the next one is loaded, if it continues
+/// the same recording, so there is no gap between them
+const PRELOAD_SECONDS: f32 = 2.0;
+
 #[derive(Debug, Clone)]
 pub struct Player {
     pub in_cmd: UnboundedSender<PlayerCommand>,
@@ -70,6 +87,11 @@ impl Player {
             let mut current_track: Option<Track> = None;
             let mut queue: Vec<Track> = Vec::new();
             let mut repeat_mode = Repeat::Off;
+            // whether the current track has been recorded as played yet
+            let mut play_counted = false;
+            let mut listened = Listened::default();
+            // the track appended to the sink after the current one, and its duration
+            let mut preloaded: Option<(Track, f32)> = None;
             loop {
                 // drain all pending commands
                 let mut commands = Vec::new();
@@ -112,8 +134,15 @@ impl Player {
                             }
                         }
                         PlayerCommand::ClearQueue => {
+                            if let Some(current) = &current_track
+                                && !play_counted
+                                && current_duration > 0.0
+                            {
+                                record_listen(current, false);
+                            }
                             queue.clear();
                             current_track = None;
+                            preloaded = None;
                             sink.clear();
                             current_duration = 0.0;
                         }
@@ -123,13 +152,22 @@ impl Player {
                                 continue;
                             }
                             if let Some(current) = &current_track {
+                                if !play_counted && current_duration > 0.0 {
+                                    record_listen(current, false);
+                                }
                                 sink.clear();
                                 current_duration = 0.0;
+                                // the preloaded track hasn't started yet, so it is still next
+                                if let Some((track, _)) = preloaded.take() {
+                                    queue.insert(0, track);
+                                }
                                 if repeat_mode == Repeat::All {
                                     queue.push(current.clone());
                                 }
                             }
                             current_track = Some(queue.remove(0));
+                            play_counted = false;
+                            listened = Listened::default();
                             if let Some(ref track) = current_track {
                                 in_evt_clone
                                     .send(PlayerEvent::TrackLoaded(track.clone()))
@@ -171,14 +209,17 @@ impl Player {
                                 Duration::from_secs_f32(pos * current_duration),
                                 current_duration
                             );
-                            sink.try_seek(Duration::from_secs_f32(pos * current_duration))
-                                .unwrap_or_else(|e| {
-                                    println!("Failed to seek to position: {:?}", e);
-                                });
+                            match sink.try_seek(Duration::from_secs_f32(pos * current_duration)) {
+                                Ok(()) => listened.seek(pos * current_duration),
+                                Err(e) => println!("Failed to seek to position: {:?}", e),
+                            }
                         }
                         PlayerCommand::Stop => {
                             sink.clear();
                             current_duration = 0.0;
+                            if let Some((track, _)) = preloaded.take() {
+                                queue.insert(0, track);
+                            }
                         }
                         PlayerCommand::Pause => {
                             if sink.is_paused() {
@@ -230,7 +271,17 @@ impl Player {
                     }
                 }
                 if sink.empty() && current_duration > 0.0 {
+                    // ran out before the last progress update reached the threshold, e.g.
+                    // short tracks, or was skipped by seeking to the end
+                    if let Some(ref track) = current_track
+                        && !play_counted
+                    {
+                        record_listen(track, counts_as_played(listened.seconds, current_duration));
+                    }
+                    play_counted = false;
+                    listened = Listened::default();
                     current_duration = 0.0;
+                    preloaded = None;
                     in_evt_clone.send(PlayerEvent::End).unwrap_or_else(|_| {
                         println!("Failed to send end event");
                         0
@@ -244,8 +295,8 @@ impl Player {
                             queue.push(track.clone());
                         }
                     }
-                    if let Some(next_track) = queue.first().cloned() {
-                        current_track = Some(next_track);
+                    if !queue.is_empty() {
+                        current_track = Some(queue.remove(0));
                         sink.clear();
                         current_duration = 0.0;
                         if let Some(ref track) = current_track {
@@ -280,18 +331,77 @@ impl Player {
                         current_track = None;
                     }
                 } else if !sink.empty() && !sink.is_paused() {
+                    // the preloaded track is playing once the one before it has run out
+                    if let Some((next, duration)) = preloaded.take_if(|_| sink.len() <= 1) {
+                        if let Some(ref track) = current_track
+                            && !play_counted
+                        {
+                            record_listen(
+                                track,
+                                counts_as_played(listened.seconds, current_duration),
+                            );
+                        }
+                        if repeat_mode == Repeat::All
+                            && let Some(ref track) = current_track
+                        {
+                            queue.push(track.clone());
+                        }
+                        in_evt_clone.send(PlayerEvent::End).unwrap_or_else(|_| {
+                            println!("Failed to send end event");
+                            0
+                        });
+                        in_evt_clone
+                            .send(PlayerEvent::TrackLoaded(next.clone()))
+                            .unwrap_or_else(|_| {
+                                println!("Failed to send track loaded event");
+                                0
+                            });
+                        current_track = Some(next);
+                        current_duration = duration;
+                        play_counted = false;
+                        listened = Listened::default();
+                    }
                     if Utc::now().timestamp_millis() - last_progress_updated < 100 {
                         time::sleep(std::time::Duration::from_millis(100)).await;
                         continue; // Skip if the last update was too recent
                     }
+                    let position = sink.get_pos().as_secs_f32();
+                    listened.advance(position);
+                    if !play_counted && counts_as_played(listened.seconds, current_duration) {
+                        if let Some(ref track) = current_track {
+                            record_listen(track, true);
+                        }
+                        play_counted = true;
+                    }
                     // Emit progress event based on current position
                     in_evt_clone
-                        .send(PlayerEvent::Progress(
-                            sink.get_pos().as_secs_f32(),
-                            current_duration,
-                        ))
+                        .send(PlayerEvent::Progress(position, current_duration))
                         .unwrap();
                     last_progress_updated = Utc::now().timestamp_millis();
+                    // e.g. consecutive tracks of a CUE sheet, which would otherwise be
+                    // split by the time it takes to open the file again
+                    if preloaded.is_none()
+                        && repeat_mode != Repeat::One
+                        && current_duration - position < PRELOAD_SECONDS
+                        && let Some(ref current) = current_track
+                        && queue.first().is_some_and(|next| next.follows(current))
+                    {
+                        let next = queue.remove(0);
+                        match next.load().await {
+                            Ok(source) => {
+                                let duration = source
+                                    .total_duration()
+                                    .map(|d| d.as_secs_f32())
+                                    .unwrap_or(0.0);
+                                sink.append(source);
+                                preloaded = Some((next, duration));
+                            }
+                            Err(e) => {
+                                println!("Failed to preload track source: {:?}", e);
+                                queue.insert(0, next);
+                            }
+                        }
+                    }
                 }
                 time::sleep(std::time::Duration::from_millis(200)).await;
             }
@@ -306,6 +416,20 @@ impl Player {
         println!("Track added to queue.");
     }
 
+    /// Add `tracks` to the end of the queue, in order
+    pub fn enqueue(&self, tracks: Vec<Track>) {
+        for track in tracks {
+            self.add_track(track);
+        }
+    }
+
+    /// Replace the queue with `tracks` and start playing the first one
+    pub fn play_tracks(&self, tracks: Vec<Track>) {
+        self.clear_queue();
+        self.enqueue(tracks);
+        self.play();
+    }
+
     pub fn play(&self) {
         self.in_cmd
             .send(PlayerCommand::Play)
@@ -359,3 +483,99 @@ impl Player {
         println!("Repeat mode set.");
     }
 }
+
+/// Seconds of the current track actually heard, counted from the progress updates so
+/// seeking ahead doesn't count as listening
+#[derive(Debug, Default)]
+struct Listened {
+    seconds: f32,
+    /// Position at the last update
+    position: f32,
+}
+
+impl Listened {
+    /// Count the time since the last update, unless playback jumped to `position`
+    fn advance(&mut self, position: f32) {
+        let step = position - self.position;
+        if step > 0.0 && step <= MAX_LISTEN_STEP {
+            self.seconds += step;
+        }
+        self.position = position;
+    }
+
+    /// Playback continues from `position`, without having heard what's in between
+    fn seek(&mut self, position: f32) {
+        self.position = position;
+    }
+}
+
+/// Whether `listened` seconds of a track `duration` seconds long is enough to count
+/// as played
+fn counts_as_played(listened: f32, duration: f32) -> bool {
+    duration > 0.0 && (listened >= duration * PLAYED_FRACTION || listened >= PLAYED_SECONDS)
+}
+
+/// Record a play, or a skip if `played` is false, in the listening history
+fn record_listen(track: &Track, played: bool) {
+    let track_id = track.id.clone();
+    task::spawn(async move {
+        let Some(library) = LIBRARY.get() else {
+            return;
+        };
+        let result = if played {
+            library.record_play(&track_id).await
+        } else {
+            library.record_skip(&track_id).await
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to record listening history: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_or_four_minutes_count_as_played() {
+        assert!(!counts_as_played(89.0, 180.0));
+        assert!(counts_as_played(90.0, 180.0));
+        // long tracks count after four minutes
+        assert!(!counts_as_played(239.0, 1200.0));
+        assert!(counts_as_played(240.0, 1200.0));
+        // tracks of unknown length never do
+        assert!(!counts_as_played(500.0, 0.0));
+    }
+
+    /// Position updates from `start` to `end`, as the player sends them while playing
+    fn play(listened: &mut Listened, start: f32, end: f32) {
+        let mut position = start;
+        while position < end {
+            position = (position + 0.2).min(end);
+            listened.advance(position);
+        }
+    }
+
+    #[test]
+    fn seeking_ahead_is_not_listening() {
+        let mut listened = Listened::default();
+        play(&mut listened, 0.0, 10.0);
+        assert!((listened.seconds - 10.0).abs() < 0.01);
+
+        // seeking straight to the end of a track hears none of it
+        listened.seek(178.0);
+        play(&mut listened, 178.0, 180.0);
+        assert!((listened.seconds - 12.0).abs() < 0.01);
+        assert!(!counts_as_played(listened.seconds, 180.0));
+
+        // a jump between updates, e.g. a seek the player didn't report, doesn't count either
+        listened.advance(100.0);
+        assert!((listened.seconds - 12.0).abs() < 0.01);
+
+        // seeking back and listening again counts the replayed part
+        listened.seek(0.0);
+        play(&mut listened, 0.0, 80.0);
+        assert!(counts_as_played(listened.seconds, 180.0));
+    }
+}
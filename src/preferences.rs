@@ -5,10 +5,61 @@ use tokio::{fs, sync::RwLock};
 
 pub static PREFERENCES: OnceCell<RwLock<Preferences>> = OnceCell::new();
 
+const DEFAULT_YOUTUBE_FILENAME_TEMPLATE: &str = "{artist}/{album}/{track} - {title}.m4a";
+
+fn default_youtube_filename_template() -> String {
+    DEFAULT_YOUTUBE_FILENAME_TEMPLATE.to_string()
+}
+
+fn default_backups_kept() -> usize {
+    5
+}
+
+pub fn default_cover_art_patterns() -> Vec<String> {
+    [
+        "cover",
+        "front",
+        "folder",
+        "album",
+        "albumart*",
+        "*front*",
+        "*cover*",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Preferences {
     pub use_system_audio_controls: bool,
     pub volume: f32,
+    /// Directories imported into the library, searched when relocating moved files
+    #[serde(default)]
+    pub library_roots: Vec<String>,
+    /// Music folder downloaded YouTube tracks are also saved to, if set
+    #[serde(default)]
+    pub youtube_export_folder: Option<String>,
+    /// Where in `youtube_export_folder` tracks are saved. `{artist}`, `{album}`,
+    /// `{track}`, `{title}` and `{year}` are replaced with the track's tags.
+    #[serde(default = "default_youtube_filename_template")]
+    pub youtube_filename_template: String,
+    /// File names (without extension) of cover images next to local tracks, in order of
+    /// preference. Matched case-insensitively, `*` matches any text.
+    #[serde(default = "default_cover_art_patterns")]
+    pub cover_art_patterns: Vec<String>,
+    /// Read star ratings from files' tags when importing, and write them back when they
+    /// change (POPM for MP3, a RATING item in other formats)
+    #[serde(default)]
+    pub rating_tags: bool,
+    /// Hours between automatic backups of the library, 0 turns them off
+    #[serde(default)]
+    pub backup_interval_hours: u32,
+    /// Number of automatic backups kept, older ones are deleted
+    #[serde(default = "default_backups_kept")]
+    pub backups_kept: usize,
+    /// AcoustID application key, needed to identify tracks online by their fingerprint
+    #[serde(default)]
+    pub acoustid_api_key: Option<String>,
 }
 
 impl Default for Preferences {
@@ -16,6 +67,14 @@ impl Default for Preferences {
         Preferences {
             use_system_audio_controls: true,
             volume: 0.5,
+            library_roots: Vec::new(),
+            youtube_export_folder: None,
+            youtube_filename_template: default_youtube_filename_template(),
+            cover_art_patterns: default_cover_art_patterns(),
+            rating_tags: false,
+            backup_interval_hours: 0,
+            backups_kept: default_backups_kept(),
+            acoustid_api_key: None,
         }
     }
 }
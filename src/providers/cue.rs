@@ -0,0 +1,411 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use rodio::{ChannelCount, SampleRate, Source, source::SeekError};
+use ulid::Ulid;
+
+use crate::{
+    library::{Artist, CueRange, Track},
+    providers::local,
+};
+
+/// CUE times count frames of 1/75 s, as on a CD
+const FRAMES_PER_SECOND: f64 = 75.0;
+
+/// Album described by a CUE sheet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueTrack {
+    pub number: i32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub songwriter: Option<String>,
+    pub isrc: Option<String>,
+    /// Audio file the track is in, as written in the sheet
+    pub file: String,
+    /// Seconds into the file the track starts at (its `INDEX 01`)
+    pub start: f64,
+}
+
+/// CUE sheets are often written by Windows rippers in the system code page rather
+/// than UTF-8, Latin-1 is the best guess for those
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// The value after a command, without the quotes around it. Values that aren't quoted
+/// end at the first space, e.g. the file type after `FILE "name.flac"`.
+fn value(arguments: &str) -> String {
+    let arguments = arguments.trim();
+    match arguments.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => arguments
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// Parse a `mm:ss:ff` time into seconds
+fn parse_time(time: &str) -> Option<f64> {
+    let mut parts = time.trim().split(':').map(|part| part.parse::<u32>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(minutes as f64 * 60.0 + seconds as f64 + frames as f64 / FRAMES_PER_SECOND)
+}
+
+pub fn parse(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    let mut file = String::new();
+    let mut track: Option<CueTrack> = None;
+    for line in text.lines().map(str::trim) {
+        let (command, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = arguments.trim();
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => file = value(arguments),
+            "TRACK" => {
+                sheet.tracks.extend(track.take().filter(|t| t.start >= 0.0));
+                // only audio tracks can be played, e.g. not the data track of an enhanced CD
+                let (number, kind) = arguments
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((arguments, ""));
+                if kind.trim().eq_ignore_ascii_case("AUDIO") {
+                    track = Some(CueTrack {
+                        number: number.parse().unwrap_or(sheet.tracks.len() as i32 + 1),
+                        file: file.clone(),
+                        // set by `INDEX 01`, tracks without one are dropped
+                        start: -1.0,
+                        ..Default::default()
+                    });
+                }
+            }
+            "INDEX" => {
+                let (number, time) = arguments
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((arguments, ""));
+                if let Some(track) = &mut track
+                    && number.parse() == Ok(1)
+                    && let Some(start) = parse_time(time)
+                {
+                    track.start = start;
+                }
+            }
+            "TITLE" => match &mut track {
+                Some(track) => track.title = Some(value(arguments)),
+                None => sheet.title = Some(value(arguments)),
+            },
+            "PERFORMER" => match &mut track {
+                Some(track) => track.performer = Some(value(arguments)),
+                None => sheet.performer = Some(value(arguments)),
+            },
+            "SONGWRITER" => {
+                if let Some(track) = &mut track {
+                    track.songwriter = Some(value(arguments));
+                }
+            }
+            "ISRC" => {
+                if let Some(track) = &mut track {
+                    track.isrc = Some(value(arguments));
+                }
+            }
+            "REM" => {
+                let (field, rest) = arguments
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((arguments, ""));
+                match field.to_ascii_uppercase().as_str() {
+                    "DATE" => sheet.date = Some(value(rest)),
+                    "GENRE" => sheet.genre = Some(value(rest)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    sheet.tracks.extend(track.filter(|t| t.start >= 0.0));
+    sheet.tracks.retain(|t| !t.file.is_empty());
+    sheet
+}
+
+/// Path of an audio file named in a sheet. Rippers often convert the audio after
+/// writing the sheet, so a file with the same name but another extension is also
+/// accepted, e.g. `album.flac` for `album.wav`.
+fn audio_path(cue_path: &Path, file: &str) -> Option<PathBuf> {
+    let directory = cue_path.parent()?;
+    let path = directory.join(file.replace('\\', std::path::MAIN_SEPARATOR_STR));
+    if path.is_file() {
+        return Some(path);
+    }
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let mut candidates: Vec<PathBuf> = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|candidate| {
+            candidate.is_file()
+                && !candidate
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+                && candidate
+                    .file_stem()
+                    .is_some_and(|s| s.to_string_lossy().to_lowercase() == stem)
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().next()
+}
+
+/// Read a CUE sheet and the files it refers to, giving one track per CUE track. The
+/// tracks share their file's audio properties, art and hash, and take their titles
+/// and artists from the sheet, falling back to the file's own tags.
+pub fn resolve_tracks(cue_path: &Path) -> Result<Vec<Track>> {
+    let sheet = parse(&decode(&std::fs::read(cue_path)?));
+    if sheet.tracks.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} has no audio tracks",
+            cue_path.display()
+        ));
+    }
+    let mut tracks = Vec::new();
+    let mut files: Vec<&str> = sheet.tracks.iter().map(|t| t.file.as_str()).collect();
+    files.dedup();
+    for file in files {
+        let path = audio_path(cue_path, file).ok_or(anyhow::anyhow!(
+            "{} refers to {}, which does not exist",
+            cue_path.display(),
+            file
+        ))?;
+        let whole = local::resolve_track(&path.to_string_lossy())?;
+        let mut in_file: Vec<&CueTrack> = sheet.tracks.iter().filter(|t| t.file == file).collect();
+        in_file.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        let mut album = whole.album.clone();
+        if let Some(title) = &sheet.title {
+            album.title = title.clone();
+        }
+        if let Some(performer) = &sheet.performer {
+            album.artists = vec![Artist::new(performer.clone())];
+        }
+        if let Some(date) = &sheet.date {
+            album.release_date = Some(date.clone());
+            album.release_year = date.get(0..4).and_then(|year| year.parse().ok());
+        }
+
+        for (i, cue) in in_file.iter().enumerate() {
+            // a track runs until the next one in the file starts, so consecutive tracks
+            // play without a gap
+            let end = in_file
+                .get(i + 1)
+                .map(|next| next.start)
+                .unwrap_or(whole.duration);
+            let mut track = whole.clone();
+            track.id = Ulid::new().to_string();
+            track.title = cue
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {}", cue.number));
+            if let Some(performer) = cue.performer.as_ref().or(sheet.performer.as_ref()) {
+                track.artists = vec![Artist::new(performer.clone())];
+            }
+            track.album = album.clone();
+            track.duration = (end - cue.start).max(0.0);
+            track.track_number = Some(cue.number);
+            track.metadata.track_total = Some(in_file.len() as i32);
+            track.metadata.sort_title = None;
+            if let Some(genre) = &sheet.genre {
+                track.metadata.genres = vec![genre.clone()];
+            }
+            if cue.songwriter.is_some() {
+                track.metadata.composer = cue.songwriter.clone();
+            }
+            track.metadata.isrc = cue.isrc.clone();
+            track.cue = Some(CueRange {
+                start: cue.start,
+                end,
+            });
+            tracks.push(track);
+        }
+    }
+    Ok(tracks)
+}
+
+/// Plays the part of a file a CUE track covers. Positions, seeks and the total
+/// duration are relative to the track's start.
+pub struct CueSource<S> {
+    inner: S,
+    start: Duration,
+    length: Duration,
+    /// Samples left before the end of the track
+    remaining: u64,
+}
+
+fn samples_in<S: Source>(duration: Duration, source: &S) -> u64 {
+    let rate = u32::from(source.sample_rate()) as f64 * u16::from(source.channels()) as f64;
+    let samples = (duration.as_secs_f64() * rate).round() as u64;
+    // end on a whole frame so the next track starts on the first channel
+    samples - samples % u16::from(source.channels()).max(1) as u64
+}
+
+impl<S: Source> CueSource<S> {
+    pub fn new(mut inner: S, range: CueRange) -> Result<Self> {
+        let start = Duration::from_secs_f64(range.start.max(0.0));
+        if !start.is_zero() {
+            inner
+                .try_seek(start)
+                .map_err(|e| anyhow::anyhow!("Failed to seek to the track: {:?}", e))?;
+        }
+        let length = Duration::from_secs_f64((range.end - range.start).max(0.0));
+        let remaining = samples_in(length, &inner);
+        Ok(Self {
+            inner,
+            start,
+            length,
+            remaining,
+        })
+    }
+}
+
+impl<S: Source> Iterator for CueSource<S> {
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.inner.next()
+    }
+}
+
+impl<S: Source> Source for CueSource<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        Some(
+            self.inner
+                .current_span_len()
+                .map_or(remaining, |len| len.min(remaining)),
+        )
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.length)
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        let position = position.min(self.length);
+        self.inner.try_seek(self.start + position)?;
+        self.remaining = samples_in(self.length - position, &self.inner);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use rodio::Decoder;
+
+    use super::*;
+    use crate::test_audio::cue_album;
+
+    #[test]
+    fn sheets_are_parsed() {
+        let sheet = parse(
+            "REM GENRE Jazz\nTITLE \"Album\"\nFILE data.bin BINARY\n\
+             TRACK 01 MODE1/2352\n  INDEX 01 00:00:00\n\
+             FILE \"Disc One.flac\" WAVE\n\
+             TRACK 02 AUDIO\n  SONGWRITER \"Writer\"\n  ISRC GBAYE0000001\n\
+             INDEX 01 01:02:15\n\
+             TRACK 03 AUDIO\n  INDEX 00 02:00:00\n",
+        );
+        assert_eq!(sheet.title.as_deref(), Some("Album"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        // the data track and the track without an `INDEX 01` are left out
+        assert_eq!(
+            sheet.tracks,
+            [CueTrack {
+                number: 2,
+                songwriter: Some("Writer".to_string()),
+                isrc: Some("GBAYE0000001".to_string()),
+                file: "Disc One.flac".to_string(),
+                start: 62.2,
+                ..Default::default()
+            }]
+        );
+        assert_eq!(decode(b"TITLE \"Caf\xe9\""), "TITLE \"Café\"");
+    }
+
+    #[test]
+    fn tracks_resolved_from_sheet() {
+        let dir = cue_album();
+        let tracks = resolve_tracks(&dir.join("album.cue")).expect("Failed to read sheet");
+        let titles: Vec<&str> = tracks.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["First", "Second", "Third"]);
+        assert_eq!(tracks[1].artists_string(), "Guest");
+        assert_eq!(tracks[2].artists_string(), "Artist 0");
+        assert_eq!(tracks[0].album.title, "Album 0");
+        assert_eq!(tracks[0].album.release_year, Some(2001));
+        // each track ends where the next one starts, the last one at the end of the file
+        let ranges: Vec<CueRange> = tracks.iter().filter_map(|t| t.cue).collect();
+        for (range, (start, end)) in ranges.iter().zip([(0.0, 10.0), (10.0, 20.4), (20.4, 30.0)]) {
+            assert!((range.start - start).abs() < 0.001 && (range.end - end).abs() < 0.001);
+        }
+        assert!((tracks[2].duration - 9.6).abs() < 0.001);
+
+        // a sheet for audio that has since been converted still finds it
+        std::fs::rename(dir.join("album.wav"), dir.join("album.flac"))
+            .expect("Failed to rename file");
+        assert!(audio_path(&dir.join("album.cue"), "album.wav").is_some());
+        std::fs::remove_file(dir.join("album.flac")).expect("Failed to remove file");
+        assert!(resolve_tracks(&dir.join("album.cue")).is_err());
+    }
+
+    #[test]
+    fn source_plays_its_range() {
+        let dir = cue_album();
+        let open = || {
+            Decoder::try_from(File::open(dir.join("album.wav")).expect("Failed to open file"))
+                .expect("Failed to decode file")
+        };
+        let range = CueRange {
+            start: 10.0,
+            end: 20.4,
+        };
+        let source = CueSource::new(open(), range).expect("Failed to seek");
+        let length = source.total_duration().expect("Missing duration");
+        assert!((length.as_secs_f64() - 10.4).abs() < 0.001);
+        let samples: Vec<f32> = source.collect();
+        assert_eq!(samples.len(), 104 * 800);
+        let whole: Vec<f32> = open().skip(10 * 8000).take(10).collect();
+        assert_eq!(samples[..10], whole[..]);
+
+        // seeks are relative to the start of the track
+        let mut source = CueSource::new(open(), range).expect("Failed to seek");
+        source
+            .try_seek(Duration::from_secs(5))
+            .expect("Failed to seek");
+        assert_eq!(source.count(), 54 * 800);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use lofty::{file::FileType, probe::Probe};
+
+/// An audio format Vibrance knows about, whether or not it can play it
+#[derive(Debug, PartialEq)]
+pub struct Format {
+    pub name: &'static str,
+    /// Extensions files in this format usually have, lowercase and without the dot
+    pub extensions: &'static [&'static str],
+    /// How the tag reader identifies the format, `None` for formats it can't read
+    pub file_type: Option<FileType>,
+    /// Whether the decoder can play the format
+    pub playable: bool,
+}
+
+pub const FORMATS: &[Format] = &[
+    Format {
+        name: "MP3",
+        extensions: &["mp3"],
+        file_type: Some(FileType::Mpeg),
+        playable: true,
+    },
+    Format {
+        name: "FLAC",
+        extensions: &["flac"],
+        file_type: Some(FileType::Flac),
+        playable: true,
+    },
+    Format {
+        name: "WAV",
+        extensions: &["wav", "wave"],
+        file_type: Some(FileType::Wav),
+        playable: true,
+    },
+    Format {
+        name: "AIFF",
+        extensions: &["aiff", "aif", "aifc"],
+        file_type: Some(FileType::Aiff),
+        playable: true,
+    },
+    Format {
+        name: "Vorbis",
+        extensions: &["ogg", "oga"],
+        file_type: Some(FileType::Vorbis),
+        playable: true,
+    },
+    // AAC or ALAC, both of which can be played
+    Format {
+        name: "MP4",
+        extensions: &["m4a", "m4b", "mp4"],
+        file_type: Some(FileType::Mp4),
+        playable: true,
+    },
+    Format {
+        name: "AAC",
+        extensions: &["aac"],
+        file_type: Some(FileType::Aac),
+        playable: true,
+    },
+    Format {
+        name: "Opus",
+        extensions: &["opus"],
+        file_type: Some(FileType::Opus),
+        playable: false,
+    },
+    Format {
+        name: "Speex",
+        extensions: &["spx"],
+        file_type: Some(FileType::Speex),
+        playable: false,
+    },
+    Format {
+        name: "WavPack",
+        extensions: &["wv"],
+        file_type: Some(FileType::WavPack),
+        playable: false,
+    },
+    Format {
+        name: "APE",
+        extensions: &["ape"],
+        file_type: Some(FileType::Ape),
+        playable: false,
+    },
+    Format {
+        name: "Musepack",
+        extensions: &["mpc", "mp+", "mpp"],
+        file_type: Some(FileType::Mpc),
+        playable: false,
+    },
+    Format {
+        name: "WMA",
+        extensions: &["wma"],
+        file_type: None,
+        playable: false,
+    },
+    Format {
+        name: "Matroska audio",
+        extensions: &["mka"],
+        file_type: None,
+        playable: false,
+    },
+    Format {
+        name: "DSD",
+        extensions: &["dsf", "dff"],
+        file_type: None,
+        playable: false,
+    },
+    Format {
+        name: "CAF",
+        extensions: &["caf"],
+        file_type: None,
+        playable: false,
+    },
+];
+
+pub fn by_file_type(file_type: FileType) -> Option<&'static Format> {
+    FORMATS.iter().find(|f| f.file_type == Some(file_type))
+}
+
+/// The format a file's extension claims it is in
+pub fn by_extension(path: &Path) -> Option<&'static Format> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    FORMATS
+        .iter()
+        .find(|f| f.extensions.contains(&extension.as_str()))
+}
+
+/// The format a file is actually in, going by its contents. The extension is only
+/// trusted for formats that can't be recognised that way, or files too damaged to be,
+/// e.g. an `.ogg` file holding Opus is detected as Opus and an MP3 named `.dat` as MP3.
+pub fn detect(path: &Path) -> Option<&'static Format> {
+    let by_content = File::open(path).ok().and_then(|file| {
+        Probe::new(BufReader::new(file))
+            .guess_file_type()
+            .ok()?
+            .file_type()
+    });
+    match by_content {
+        Some(file_type) => by_file_type(file_type),
+        None => by_extension(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_audio::{temp_dir, tune, write_wav};
+
+    #[test]
+    fn formats_detected_by_content() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("notes.txt"), "not audio").expect("Failed to write file");
+        std::fs::write(dir.join("broken.wv"), "not audio").expect("Failed to write file");
+        write_wav(&dir.join("renamed.dat"), &tune(1, 1, 8000), 1, 8000);
+        // the first page of an Ogg stream holding Opus rather than Vorbis
+        let mut ogg = b"OggS".to_vec();
+        ogg.resize(26, 0);
+        ogg.extend([1, 19]);
+        ogg.extend(b"OpusHead");
+        ogg.resize(64, 0);
+        std::fs::write(dir.join("song.ogg"), ogg).expect("Failed to write file");
+
+        let detected = |name: &str| detect(&dir.join(name)).map(|f| f.name);
+        assert_eq!(detected("renamed.dat"), Some("WAV"));
+        assert_eq!(detected("song.ogg"), Some("Opus"));
+        // formats that can't be recognised go by their extension
+        assert_eq!(detected("broken.wv"), Some("WavPack"));
+        assert_eq!(detected("notes.txt"), None);
+        assert!(by_extension(Path::new("a.FLAC")).is_some_and(|f| f.playable));
+        assert!(!by_extension(Path::new("a.opus")).is_some_and(|f| f.playable));
+    }
+}
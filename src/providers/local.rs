@@ -1,13 +1,28 @@
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
 use lofty::{
-    file::{AudioFile, TaggedFileExt},
+    config::WriteOptions,
+    file::{AudioFile, FileType, TaggedFile, TaggedFileExt},
+    picture::{Picture, PictureType},
     probe::Probe,
-    tag::ItemKey,
+    tag::{ItemKey, ItemValue, Tag, TagItem, TagType},
 };
+use rodio::{Decoder, Source};
 use ulid::Ulid;
 
-use crate::library::{Album, Artist, Track, TrackSource};
+use crate::{
+    fingerprint::{self, Fingerprint},
+    library::{
+        Album, Artist, AudioProperties, CueRange, Track, TrackMetadata, TrackSource, edit::TagEdit,
+        rating::MAX_RATING,
+    },
+    preferences::{PREFERENCES, default_cover_art_patterns},
+    providers::{cue::CueSource, formats},
+};
 
 pub fn resolve_track(path: &str) -> anyhow::Result<Track> {
     if path.is_empty() {
@@ -17,11 +32,14 @@ pub fn resolve_track(path: &str) -> anyhow::Result<Track> {
     if !path.exists() {
         return Err(anyhow::anyhow!("File does not exist: {}", path.display()));
     }
-    let tag = Probe::open(&path)?.read()?;
-    let properties = tag.properties();
-    let tag = match tag.primary_tag() {
+    let file_size = path.metadata()?.len();
+    let content_hash = content_hash(&path)?;
+    let tagged_file = Probe::open(&path)?.read()?;
+    let properties = tagged_file.properties();
+    let audio = audio_properties(&tagged_file);
+    let tag = match tagged_file.primary_tag() {
         Some(primary_tag) => Some(primary_tag),
-        None => tag.first_tag(),
+        None => tagged_file.first_tag(),
     };
     let id = Ulid::new().to_string();
     let mut artists = tag.map_or_else(Vec::new, |t| {
@@ -35,37 +53,446 @@ pub fn resolve_track(path: &str) -> anyhow::Result<Track> {
             artists.push(artist);
         }
     }
+    // an embedded front cover wins over a cover image in the folder, which wins over
+    // other embedded pictures (often a back cover or a photo of the artist)
     let album_art = tag
-        .and_then(|t| t.pictures().get(0))
-        .map(|p| p.data().to_vec());
+        .and_then(|t| t.get_picture_type(PictureType::CoverFront))
+        .map(|p| p.data().to_vec())
+        .or_else(|| sidecar_art(&path))
+        .or_else(|| {
+            tag.and_then(|t| t.pictures().first())
+                .map(|p| p.data().to_vec())
+        });
     let album = tag
         .map(|t| t.get_string(&ItemKey::AlbumTitle).map(String::from))
         .flatten()
         .unwrap_or("Unknown Album".to_string());
-    let release_year = tag.and_then(|t| {
-        t.get_string(&ItemKey::Year)
-            .and_then(|date_str| date_str.get(0..4))
-            .and_then(|year_str| year_str.parse::<i32>().ok())
+    let release_date = tag.and_then(|t| {
+        t.get_string(&ItemKey::RecordingDate)
+            .or_else(|| t.get_string(&ItemKey::Year))
+            .map(|date| date.trim().to_string())
+    });
+    let release_year = release_date
+        .as_deref()
+        .and_then(|date_str| date_str.get(0..4))
+        .and_then(|year_str| year_str.parse::<i32>().ok());
+    let artist_sort = tag.and_then(|t| t.get_string(&ItemKey::TrackArtistSortOrder));
+    let artists: Vec<Artist> = artists
+        .iter()
+        .map(|a| {
+            let mut artist = Artist::new(a.to_string());
+            // the sort tag covers the whole artist field, so only use it when there is one artist
+            if artists.len() == 1 {
+                artist.sort_name = artist_sort.map(String::from);
+            }
+            artist
+        })
+        .collect();
+    // fall back to the first track artist when there is no album artist tag
+    let album_artists = match tag.and_then(|t| t.get_string(&ItemKey::AlbumArtist)) {
+        Some(album_artist) => {
+            let mut artist = Artist::new(album_artist.to_string());
+            artist.sort_name = tag
+                .and_then(|t| t.get_string(&ItemKey::AlbumArtistSortOrder))
+                .map(String::from);
+            vec![artist]
+        }
+        None => artists.first().into_iter().cloned().collect(),
+    };
+    let mut album = Album::new(album, album_artists, release_year, album_art);
+    album.sort_title = tag
+        .and_then(|t| t.get_string(&ItemKey::AlbumTitleSortOrder))
+        .map(String::from);
+    album.release_date = release_date;
+    album.label = tag.and_then(|t| {
+        t.get_string(&ItemKey::Label)
+            .or_else(|| t.get_string(&ItemKey::Publisher))
+            .map(String::from)
     });
-    let artists: Vec<Artist> = artists.iter().map(|a| Artist::new(a.to_string())).collect();
     Ok(Track {
         id,
         title: tag
             .and_then(|t| t.get_string(&ItemKey::TrackTitle).map(String::from))
             .unwrap_or_else(|| path.file_stem().unwrap().to_string_lossy().to_string()),
-        album: Album::new(
-            album,
-            artists.first().into_iter().cloned().collect(),
-            release_year,
-            album_art,
-        ),
+        album,
         artists,
         duration: properties.duration().as_secs_f64(),
         path: Some(path.to_string_lossy().to_string()),
         source: TrackSource::Local,
         source_id: None,
-        track_number: tag
-            .and_then(|t| t.get_string(&ItemKey::TrackNumber).map(|i| i.parse().ok()))
-            .flatten(),
+        track_number: tag.and_then(|t| number_item(t, &ItemKey::TrackNumber)),
+        file_size: Some(file_size),
+        content_hash: Some(content_hash),
+        metadata: tag.map(track_metadata).unwrap_or_default(),
+        audio,
+        cue: None,
+    })
+}
+
+/// Extensions of the images that are picked up as folder cover art
+const COVER_ART_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+
+/// Read the cover image next to a track (e.g. `cover.jpg` or `folder.png`), trying the
+/// name patterns from the preferences in order
+fn sidecar_art(track_path: &Path) -> Option<Vec<u8>> {
+    let images: Vec<(String, PathBuf)> = std::fs::read_dir(track_path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .is_some_and(|ext| COVER_ART_EXTENSIONS.contains(&ext.as_str()))
+        })
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+            Some((stem, path))
+        })
+        .collect();
+    if images.is_empty() {
+        return None;
+    }
+
+    // resolve_track runs on blocking threads, so don't wait for the preferences if
+    // they are being written
+    let patterns = PREFERENCES
+        .get()
+        .and_then(|preferences| preferences.try_read().ok())
+        .map(|preferences| preferences.cover_art_patterns.clone())
+        .unwrap_or_else(default_cover_art_patterns);
+    patterns.iter().find_map(|pattern| {
+        let pattern = pattern.to_lowercase();
+        let mut matches: Vec<&PathBuf> = images
+            .iter()
+            .filter(|(stem, _)| matches_pattern(stem, &pattern))
+            .map(|(_, path)| path)
+            .collect();
+        // read_dir has no defined order, keep the choice stable between imports
+        matches.sort();
+        matches
+            .into_iter()
+            .find_map(|path| std::fs::read(path).ok())
+    })
+}
+
+/// Whether `name` matches `pattern`, where `*` in the pattern matches any text
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no wildcard, the whole name has to match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn track_metadata(tag: &Tag) -> TrackMetadata {
+    let text = |key: &ItemKey| tag.get_string(key).map(|s| s.trim().to_string());
+    // several genres are sometimes packed into one value, e.g. "Rock; Pop"
+    let mut genres: Vec<String> = tag
+        .get_strings(&ItemKey::Genre)
+        .flat_map(|genre| genre.split(';'))
+        .map(|genre| genre.trim().to_string())
+        .filter(|genre| !genre.is_empty())
+        .collect();
+    genres.dedup();
+    TrackMetadata {
+        genres,
+        disc_number: number_item(tag, &ItemKey::DiscNumber),
+        disc_total: number_item(tag, &ItemKey::DiscTotal),
+        track_total: number_item(tag, &ItemKey::TrackTotal),
+        composer: text(&ItemKey::Composer),
+        isrc: text(&ItemKey::Isrc),
+        comment: text(&ItemKey::Comment),
+        sort_title: text(&ItemKey::TrackTitleSortOrder),
+        rating: rating_tags_enabled()
+            .then(|| read_rating(tag))
+            .flatten()
+            .unwrap_or(0),
+        loved: false,
+    }
+}
+
+/// Parse a numeric tag item, accepting the "3/12" form some formats use
+fn number_item(tag: &Tag, key: &ItemKey) -> Option<i32> {
+    tag.get_string(key)?.split('/').next()?.trim().parse().ok()
+}
+
+/// Read the audio properties of the file at `path` without resolving its tags
+pub fn read_audio_properties(path: &Path) -> anyhow::Result<AudioProperties> {
+    Ok(audio_properties(&Probe::open(path)?.read()?))
+}
+
+fn audio_properties(tagged_file: &TaggedFile) -> AudioProperties {
+    let properties = tagged_file.properties();
+    AudioProperties {
+        bitrate: properties.audio_bitrate(),
+        sample_rate: properties.sample_rate(),
+        channels: properties.channels(),
+        codec: Some(codec_name(tagged_file.file_type())),
+    }
+}
+
+fn codec_name(file_type: FileType) -> String {
+    match formats::by_file_type(file_type) {
+        Some(format) => format.name.to_string(),
+        None => format!("{:?}", file_type),
+    }
+}
+
+/// Write the fields set in `edit` to the file's tags. The file's primary tag format
+/// is used (ID3v2 for MP3, Vorbis comments for FLAC and Ogg, MP4 atoms for M4A),
+/// and created if the file has no tag yet.
+pub fn write_tags(path: &Path, edit: &TagEdit) -> anyhow::Result<()> {
+    let mut tagged_file = Probe::open(path)?.read()?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or(anyhow::anyhow!("File does not support tags"))?;
+
+    let set_text = |tag: &mut Tag, key: ItemKey, value: Option<String>| match value {
+        Some(value) if !value.is_empty() => {
+            tag.insert_text(key, value);
+        }
+        _ => tag.remove_key(&key),
+    };
+    if let Some(title) = &edit.title {
+        set_text(tag, ItemKey::TrackTitle, Some(title.clone()));
+    }
+    if let Some(artists) = &edit.artists {
+        set_text(tag, ItemKey::TrackArtist, Some(artists.join("; ")));
+        tag.remove_key(&ItemKey::TrackArtists);
+        for artist in artists {
+            tag.push(TagItem::new(
+                ItemKey::TrackArtists,
+                ItemValue::Text(artist.clone()),
+            ));
+        }
+    }
+    if let Some(album) = &edit.album {
+        set_text(tag, ItemKey::AlbumTitle, Some(album.clone()));
+    }
+    if let Some(album_artist) = &edit.album_artist {
+        set_text(tag, ItemKey::AlbumArtist, Some(album_artist.clone()));
+    }
+    if let Some(year) = edit.year {
+        // the full date would otherwise take precedence over the year when reading
+        set_text(tag, ItemKey::RecordingDate, year.map(|y| y.to_string()));
+        set_text(tag, ItemKey::Year, year.map(|y| y.to_string()));
+    }
+    if let Some(track_number) = edit.track_number {
+        set_text(
+            tag,
+            ItemKey::TrackNumber,
+            track_number.map(|n| n.to_string()),
+        );
+    }
+    if let Some(disc_number) = edit.disc_number {
+        set_text(tag, ItemKey::DiscNumber, disc_number.map(|n| n.to_string()));
+    }
+    if let Some(genres) = &edit.genres {
+        tag.remove_key(&ItemKey::Genre);
+        for genre in genres {
+            tag.push(TagItem::new(ItemKey::Genre, ItemValue::Text(genre.clone())));
+        }
+    }
+    if let Some(composer) = &edit.composer {
+        set_text(tag, ItemKey::Composer, composer.clone());
+    }
+    if let Some(cover) = &edit.cover {
+        let mut picture = Picture::from_reader(&mut Cursor::new(cover))?;
+        picture.set_pic_type(PictureType::CoverFront);
+        tag.remove_picture_type(PictureType::CoverFront);
+        tag.push_picture(picture);
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+/// Email written to POPM frames. Players only show ratings from frames they recognise,
+/// and this is the one most of them read.
+const POPM_EMAIL: &str = "Windows Media Player 9 Series";
+
+/// Whether ratings are read from and written to tags, see `Preferences::rating_tags`
+pub fn rating_tags_enabled() -> bool {
+    PREFERENCES
+        .get()
+        .and_then(|preferences| preferences.try_read().ok())
+        .is_some_and(|preferences| preferences.rating_tags)
+}
+
+/// Item holding the rating in tag formats without a dedicated frame for it
+fn rating_key(tag_type: TagType) -> ItemKey {
+    match tag_type {
+        TagType::Mp4Ilst => ItemKey::Unknown("----:com.apple.iTunes:RATING".to_string()),
+        _ => ItemKey::Unknown("RATING".to_string()),
+    }
+}
+
+/// Read a star rating from a tag: the POPM frame in ID3v2, a RATING item elsewhere.
+/// RATING is either 0–5 stars or 0–100, depending on the program that wrote it.
+fn read_rating(tag: &Tag) -> Option<u8> {
+    if let Some(ItemValue::Binary(popm)) = tag.get(&ItemKey::Popularimeter).map(TagItem::value) {
+        // the email is null terminated and followed by the rating byte
+        let start = popm.iter().position(|&b| b == 0)? + 1;
+        return popm.get(start).map(|&byte| popm_to_stars(byte));
+    }
+    let value: f32 = tag
+        .get_string(&rating_key(tag.tag_type()))?
+        .trim()
+        .parse()
+        .ok()?;
+    let stars = if value <= MAX_RATING as f32 {
+        value
+    } else {
+        value / 20.0
+    };
+    Some(stars.round().clamp(0.0, MAX_RATING as f32) as u8)
+}
+
+/// Stars for a POPM rating byte, using the ranges Windows Media Player reads them with
+fn popm_to_stars(byte: u8) -> u8 {
+    match byte {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+/// POPM rating byte for a number of stars
+fn stars_to_popm(stars: u8) -> u8 {
+    [0, 1, 64, 128, 196, 255][stars.min(MAX_RATING) as usize]
+}
+
+/// Write a star rating to the file's primary tag, removing it for 0 (unrated)
+pub fn write_rating(path: &Path, rating: u8) -> anyhow::Result<()> {
+    let mut tagged_file = Probe::open(path)?.read()?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or(anyhow::anyhow!("File does not support tags"))?;
+
+    if tag.tag_type() == TagType::Id3v2 {
+        tag.remove_key(&ItemKey::Popularimeter);
+        if rating > 0 {
+            let mut popm = POPM_EMAIL.as_bytes().to_vec();
+            popm.push(0);
+            popm.push(stars_to_popm(rating));
+            tag.push(TagItem::new(
+                ItemKey::Popularimeter,
+                ItemValue::Binary(popm),
+            ));
+        }
+    } else {
+        let key = rating_key(tag.tag_type());
+        if rating > 0 {
+            tag.insert_text(key, (rating.min(MAX_RATING) as u32 * 20).to_string());
+        } else {
+            tag.remove_key(&key);
+        }
+    }
+
+    tagged_file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Bytes read from each of the start, middle and end of a file by `content_hash`
+const HASH_SAMPLE_SIZE: u64 = 256 * 1024;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Hash the size of a file and samples of its start, middle and end (64-bit FNV-1a,
+/// hex encoded). Reading whole lossless albums on every import would be slow, and tags
+/// live at the start or the end, so edits still change the hash. This is not
+/// cryptographic, it only needs to tell files apart.
+pub fn content_hash(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hash = fnv1a(FNV_OFFSET, &size.to_le_bytes());
+    if size <= 3 * HASH_SAMPLE_SIZE {
+        let mut contents = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut contents)?;
+        hash = fnv1a(hash, &contents);
+    } else {
+        let mut buffer = vec![0u8; HASH_SAMPLE_SIZE as usize];
+        for offset in [0, (size - HASH_SAMPLE_SIZE) / 2, size - HASH_SAMPLE_SIZE] {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+            hash = fnv1a(hash, &buffer);
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// Hash the size and every byte of a file, which `content_hash` only does for small
+/// files. Reading the whole file is slow, so this only confirms that files whose
+/// content hashes match are identical.
+pub fn full_content_hash(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hash = fnv1a(FNV_OFFSET, &size.to_le_bytes());
+    let mut buffer = vec![0u8; HASH_SAMPLE_SIZE as usize];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buffer[..read]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// Decode a file, or the part of it a CUE track covers
+fn decode(path: &Path, cue: Option<CueRange>) -> anyhow::Result<Box<dyn Source + Send>> {
+    let decoder = Decoder::try_from(File::open(path)?)?;
+    Ok(match cue {
+        Some(range) => Box::new(CueSource::new(decoder, range)?),
+        None => Box::new(decoder),
     })
 }
+
+/// Hash all the decoded samples of a file, with the same function as `content_hash`.
+/// Unlike the content hash it stays the same when only the tags differ.
+pub fn audio_hash(path: &Path, cue: Option<CueRange>) -> anyhow::Result<String> {
+    let decoder = decode(path, cue)?;
+    let hash = decoder.fold(FNV_OFFSET, |hash, sample| {
+        fnv1a(hash, &sample.to_bits().to_le_bytes())
+    });
+    Ok(format!("{:016x}", hash))
+}
+
+/// Acoustic fingerprint of the start of a file, see `Fingerprint`
+pub fn fingerprint(path: &Path, cue: Option<CueRange>) -> anyhow::Result<Fingerprint> {
+    let decoder = decode(path, cue)?;
+    let channels = u16::from(decoder.channels());
+    let sample_rate = u32::from(decoder.sample_rate());
+    let limit = fingerprint::MAX_SECONDS * sample_rate as usize * channels as usize;
+    let samples: Vec<f32> = decoder.take(limit).collect();
+    Ok(Fingerprint::from_samples(&samples, channels, sample_rate))
+}
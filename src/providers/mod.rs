@@ -1,2 +1,4 @@
+pub mod cue;
+pub mod formats;
 pub mod local;
 pub mod youtube;
@@ -1,4 +1,8 @@
-use std::{io::SeekFrom, sync::Arc};
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Result;
 use futures::{
@@ -18,8 +22,12 @@ use tokio::{
 use ulid::Ulid;
 
 use crate::{
-    library::{Album, Artist, LIBRARY, Track, TrackSource},
+    library::{
+        Album, Artist, AudioProperties, LIBRARY, Track, TrackMetadata, TrackSource, edit::TagEdit,
+    },
     lyrics::{self},
+    preferences::PREFERENCES,
+    providers::local,
 };
 
 pub static YT_CLIENT: OnceCell<RustyPipe> = OnceCell::new();
@@ -202,6 +210,12 @@ pub async fn query_track(id: &str) -> Result<Track> {
         source_id: Some(track.track.id),
         source: TrackSource::YouTube,
         track_number: None,
+        file_size: None,
+        content_hash: None,
+        metadata: TrackMetadata::default(),
+        // only known once the audio has been downloaded
+        audio: AudioProperties::default(),
+        cue: None,
     };
     Ok(track)
 }
@@ -244,9 +258,213 @@ pub async fn download_track_and_save(track: &Track, path: &str) -> Result<()> {
         return Err(anyhow::anyhow!("Track does not have a YouTube ID"));
     };
     download_track(video_id, &path).await?;
+    // tag the file so it is usable outside Vibrance too
+    let cover = track.album.art().await.map(|art| art.as_ref().clone());
+    if let Err(e) = local::write_tags(Path::new(path), &embedded_tags(track, cover)) {
+        eprintln!("Failed to write tags to {}: {}", path, e);
+    }
+    if let Err(e) = export_track(track, Path::new(path)).await {
+        eprintln!("Failed to save track to the music folder: {}", e);
+    }
+    let mut track = track.clone();
+    match local::read_audio_properties(Path::new(path)) {
+        Ok(audio) => track.audio = audio,
+        Err(e) => eprintln!("Failed to read audio properties of {}: {}", path, e),
+    }
     let library = LIBRARY
         .get()
         .ok_or(anyhow::anyhow!("Library not initialized"))?;
     library.add_track(&track).await?;
     Ok(())
 }
+
+fn embedded_tags(track: &Track, cover: Option<Vec<u8>>) -> TagEdit {
+    TagEdit {
+        title: Some(track.title.clone()),
+        artists: Some(track.artists.iter().map(|a| a.name.clone()).collect()),
+        album: Some(track.album.title.clone()),
+        album_artist: track.album.artists.first().map(|a| a.name.clone()),
+        year: track.album.release_year.map(Some),
+        cover,
+        ..Default::default()
+    }
+}
+
+/// Copy a downloaded track into the music folder chosen in the preferences, if any
+async fn export_track(track: &Track, path: &Path) -> Result<()> {
+    let (folder, template) = {
+        let preferences = PREFERENCES
+            .get()
+            .ok_or(anyhow::anyhow!("Preferences not initialized"))?
+            .read()
+            .await;
+        match &preferences.youtube_export_folder {
+            Some(folder) => (
+                PathBuf::from(folder),
+                preferences.youtube_filename_template.clone(),
+            ),
+            None => return Ok(()),
+        }
+    };
+    let destination = folder.join(export_path(&template, track));
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::copy(path, &destination).await?;
+    Ok(())
+}
+
+/// Fill in a filename template such as `{artist}/{album}/{track} - {title}.m4a`.
+/// Tag values can't add directories or leave the folder, only the template's `/` can.
+fn export_path(template: &str, track: &Track) -> PathBuf {
+    let sanitize = |value: &str| {
+        value
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect::<String>()
+    };
+    let artist = track
+        .album
+        .artists
+        .first()
+        .or(track.artists.first())
+        .map(|a| a.name.as_str())
+        .unwrap_or("Unknown Artist");
+    let values = [
+        ("{artist}", sanitize(artist)),
+        ("{album}", sanitize(&track.album.title)),
+        ("{title}", sanitize(&track.title)),
+        (
+            "{track}",
+            track
+                .track_number
+                .map(|n| format!("{:02}", n))
+                .unwrap_or_default(),
+        ),
+        (
+            "{year}",
+            track
+                .album
+                .release_year
+                .map(|y| y.to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+    template
+        .split('/')
+        .map(|component| {
+            // in one pass, so values that look like placeholders are kept as they are
+            let mut filled = String::new();
+            let mut rest = component;
+            while let Some(start) = rest.find('{') {
+                filled.push_str(&rest[..start]);
+                rest = &rest[start..];
+                match values
+                    .iter()
+                    .find(|(placeholder, _)| rest.starts_with(placeholder))
+                {
+                    Some((placeholder, value)) => {
+                        filled.push_str(value);
+                        rest = &rest[placeholder.len()..];
+                    }
+                    None => {
+                        filled.push('{');
+                        rest = &rest[1..];
+                    }
+                }
+            }
+            filled.push_str(rest);
+            // drop separators left over from empty values, e.g. " - Title.m4a"
+            let component = filled
+                .trim()
+                .trim_start_matches(|c: char| c == '-' || c.is_whitespace())
+                .trim_end_matches('.')
+                .to_string();
+            match component.as_str() {
+                "" | "." | ".." => "_".to_string(),
+                _ => component,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(artist: &str, album: &str, title: &str, number: Option<i32>) -> Track {
+        let artist = Artist::new(artist.to_string());
+        Track {
+            id: Ulid::new().to_string(),
+            title: title.to_string(),
+            artists: vec![artist.clone()],
+            album: Album::new(album.to_string(), vec![artist], None, None),
+            duration: 200.0,
+            path: None,
+            source: TrackSource::YouTube,
+            source_id: None,
+            track_number: number,
+            file_size: None,
+            content_hash: None,
+            metadata: TrackMetadata::default(),
+            audio: AudioProperties::default(),
+            cue: None,
+        }
+    }
+
+    fn path(components: &[&str]) -> PathBuf {
+        components.iter().collect()
+    }
+
+    const TEMPLATE: &str = "{artist}/{album}/{track} - {title}.m4a";
+
+    #[test]
+    fn export_path_fills_template() {
+        assert_eq!(
+            export_path(
+                TEMPLATE,
+                &track("Radiohead", "OK Computer", "Lucky", Some(11))
+            ),
+            path(&["Radiohead", "OK Computer", "11 - Lucky.m4a"])
+        );
+        // values are only filled in once, even when they look like placeholders
+        assert_eq!(
+            export_path(TEMPLATE, &track("{title}", "{album}", "Song", Some(1))),
+            path(&["{title}", "{album}", "01 - Song.m4a"])
+        );
+    }
+
+    #[test]
+    fn export_path_stays_in_folder() {
+        // separators in values don't add directories
+        assert_eq!(
+            export_path(TEMPLATE, &track("AC/DC", "Back\\In Black", "a/../b", None)),
+            path(&["AC_DC", "Back_In Black", "a_.._b.m4a"])
+        );
+        // values can't climb out of the folder
+        assert_eq!(
+            export_path(TEMPLATE, &track("..", ".", "..", Some(1))),
+            path(&["_", "_", "01 - ...m4a"])
+        );
+        assert_eq!(
+            export_path("{title}", &track("Artist", "Album", "..", None)),
+            path(&["_"])
+        );
+    }
+
+    #[test]
+    fn export_path_drops_empty_values() {
+        assert_eq!(
+            export_path(TEMPLATE, &track("Artist", "", "Song", None)),
+            path(&["Artist", "_", "Song.m4a"])
+        );
+        assert_eq!(
+            export_path("{year}/{title}", &track("Artist", "Album", "", None)),
+            path(&["_", "_"])
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Audio files and folders for tests
+
+use std::{
+    f32::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use ulid::Ulid;
+
+/// A directory of its own in the temp directory
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vibrance-test-{}", Ulid::new()));
+    std::fs::create_dir_all(&dir).expect("Failed to create directory");
+    dir
+}
+
+/// A directory holding `album.cue`, a sheet of three tracks starting at 0, 10 and
+/// 20.4 s, and the 30 s of 8 kHz mono audio it refers to
+pub fn cue_album() -> PathBuf {
+    let dir = temp_dir();
+    std::fs::write(
+        dir.join("album.cue"),
+        "REM DATE 2001\r\nPERFORMER \"Artist 0\"\r\nTITLE \"Album 0\"\r\n\
+         FILE \"album.wav\" WAVE\r\n\
+         TRACK 01 AUDIO\r\n  TITLE \"First\"\r\n  INDEX 01 00:00:00\r\n\
+         TRACK 02 AUDIO\r\n  TITLE \"Second\"\r\n  PERFORMER \"Guest\"\r\n\
+         INDEX 00 00:09:00\r\n  INDEX 01 00:10:00\r\n\
+         TRACK 03 AUDIO\r\n  TITLE \"Third\"\r\n  INDEX 01 00:20:30\r\n",
+    )
+    .expect("Failed to write file");
+    write_wav(&dir.join("album.wav"), &tune(1, 30, 8000), 1, 8000);
+    dir
+}
+
+/// Half-second notes picked by `seed`, with overtones so each note has a chroma
+pub fn tune(seed: u64, seconds: usize, sample_rate: u32) -> Vec<f32> {
+    let mut state = seed;
+    let mut samples = Vec::new();
+    for _ in 0..seconds * 2 {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let frequency = 220.0 * 2f32.powf(((state >> 33) % 24) as f32 / 12.0);
+        for i in 0..sample_rate as usize / 2 {
+            let t = i as f32 / sample_rate as f32;
+            let sample: f32 = (1..=3)
+                .map(|h| (2.0 * PI * frequency * h as f32 * t).sin() / h as f32)
+                .sum();
+            samples.push(sample * 0.3);
+        }
+    }
+    samples
+}
+
+/// Write mono samples as a 16-bit WAV file, with the same samples on every channel
+pub fn write_wav(path: &Path, samples: &[f32], channels: u16, sample_rate: u32) {
+    let data: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            std::iter::repeat_n(value.to_le_bytes(), channels as usize).flatten()
+        })
+        .collect();
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + data.len() as u32).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(channels.to_le_bytes());
+    wav.extend(sample_rate.to_le_bytes());
+    wav.extend((sample_rate * channels as u32 * 2).to_le_bytes());
+    wav.extend((channels * 2).to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend((data.len() as u32).to_le_bytes());
+    wav.extend(data);
+    std::fs::write(path, wav).expect("Failed to write file");
+}
@@ -0,0 +1,522 @@
+use std::sync::Arc;
+
+use gpui::{
+    AppContext, ClickEvent, Div, ElementId, Entity, InteractiveElement, IntoElement, ParentElement,
+    Render, Stateful, StatefulInteractiveElement, Styled, Subscription, Window, img, px,
+    uniform_list,
+};
+use gpui_component::{
+    Icon as GpuiIcon, StyledExt,
+    button::{Button, ButtonVariants},
+    input::{Input, InputEvent, InputState},
+};
+use rand::seq::SliceRandom;
+use tokio::task;
+
+use crate::{
+    components::{
+        album_art_source,
+        icon::Icon,
+        track_list::{TrackList, TrackListDelegate},
+    },
+    library::{
+        Album, LIBRARY, LibraryEvent, Track, art::ArtSize, search::normalize, sort::sort_key,
+    },
+    player::PLAYER,
+};
+
+/// Width of an album tile in the grid, cover included
+const TILE_WIDTH: f32 = 176.0;
+
+/// Approximate width taken up by the sidebar and padding, used to fit the grid columns
+const GRID_MARGIN: f32 = 300.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum AlbumSort {
+    #[default]
+    Title,
+    Artist,
+    Year,
+}
+
+impl AlbumSort {
+    const ALL: [AlbumSort; 3] = [AlbumSort::Title, AlbumSort::Artist, AlbumSort::Year];
+
+    fn label(&self) -> &'static str {
+        match self {
+            AlbumSort::Title => "Title",
+            AlbumSort::Artist => "Artist",
+            AlbumSort::Year => "Year",
+        }
+    }
+}
+
+/// Names of an album's artists, for display
+fn artists_string(album: &Album) -> String {
+    album
+        .artists
+        .iter()
+        .map(|a| a.name.clone())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A clickable album cover with its title and artists, for album grids
+pub fn album_tile(id: impl Into<ElementId>, album: &Album) -> Stateful<Div> {
+    gpui::div()
+        .id(id)
+        .v_flex()
+        .w(px(TILE_WIDTH))
+        .p_2()
+        .gap_1()
+        .rounded_md()
+        .cursor_pointer()
+        .hover(|style| style.bg(gpui::rgba(0xffffff10)))
+        .child(
+            img(album_art_source(
+                None,
+                Some(album.id.clone()),
+                ArtSize::Medium,
+            ))
+            .size(px(TILE_WIDTH - 16.0))
+            .rounded_md(),
+        )
+        .child(
+            gpui::div()
+                .text_sm()
+                .font_semibold()
+                .text_ellipsis()
+                .child(album.title.clone()),
+        )
+        .child(
+            gpui::div()
+                .text_xs()
+                .text_ellipsis()
+                .child(artists_string(album)),
+        )
+}
+
+/// The album page that is open, and its tracks in disc and track order
+struct AlbumPage {
+    album: Album,
+    tracks: Vec<Track>,
+}
+
+pub struct AlbumsView {
+    /// Every album in the library, ordered by title
+    albums: Vec<Album>,
+    /// The albums shown in the grid, after filtering and sorting
+    visible: Vec<Album>,
+    filter_input: Entity<InputState>,
+    filter: String,
+    sort: AlbumSort,
+    descending: bool,
+    /// Number of tiles per grid row, fitted to the window on every render
+    columns: usize,
+    page: Option<AlbumPage>,
+    track_list: Entity<TrackList<Track>>,
+    _s: Vec<Subscription>,
+}
+
+impl AlbumsView {
+    pub fn new(window: &mut gpui::Window, cx: &mut gpui::Context<Self>) -> Self {
+        let track_list = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+        let filter_input = cx
+            .new(|cx| InputState::new(window, cx).placeholder("Filter albums by title or artist"));
+
+        let _s = vec![
+            cx.subscribe_in(&filter_input, window, move |this, state, event, _, cx| {
+                if let InputEvent::Change = event {
+                    this.filter = normalize(state.read(cx).value().trim());
+                    this.apply_filter();
+                    cx.notify();
+                }
+            }),
+        ];
+
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                match event {
+                    LibraryEvent::TracksAdded(_)
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::Restored => {
+                        let Some(this) = this.upgrade() else {
+                            return;
+                        };
+                        app.update_entity(&this, |view, cx| view.reload(cx)).ok();
+                    }
+                    LibraryEvent::PlaylistsChanged | LibraryEvent::PlaysRecorded(_) => {}
+                }
+            }
+        })
+        .detach();
+
+        let mut view = Self {
+            albums: Vec::new(),
+            visible: Vec::new(),
+            filter_input,
+            filter: String::new(),
+            sort: AlbumSort::default(),
+            descending: false,
+            columns: 1,
+            page: None,
+            track_list,
+            _s,
+        };
+        view.reload(cx);
+        view
+    }
+
+    /// Reload the albums, and the tracks of the open album page
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        let page_album = self.page.as_ref().map(|page| page.album.id.clone());
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let albums = library.all_albums().await?;
+                let tracks = match page_album {
+                    Some(id) => Some(library.get_tracks_by_album(&id).await?),
+                    None => None,
+                };
+                Ok((albums, tracks))
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let (albums, tracks) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Failed to load albums: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.albums = albums;
+                    view.apply_filter();
+                    let page_album = view.page.as_ref().map(|page| page.album.id.clone());
+                    if let (Some(page_album), Some(tracks)) = (page_album, tracks) {
+                        // the album may have been renamed or merged away in the meantime
+                        match view.albums.iter().find(|a| a.id == page_album).cloned() {
+                            Some(album) => view.show_page(album, tracks, cx),
+                            None => view.page = None,
+                        }
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// Recompute the albums shown in the grid from the filter and sort order
+    fn apply_filter(&mut self) {
+        let filter = &self.filter;
+        let mut visible: Vec<Album> = self
+            .albums
+            .iter()
+            .filter(|album| {
+                filter.is_empty()
+                    || normalize(&album.title).contains(filter)
+                    || album
+                        .artists
+                        .iter()
+                        .any(|artist| normalize(&artist.name).contains(filter))
+            })
+            .cloned()
+            .collect();
+        // albums come ordered by title, the sort is stable so ties stay in that order
+        match self.sort {
+            AlbumSort::Title => {}
+            AlbumSort::Artist => visible.sort_by_cached_key(|album| {
+                album
+                    .artists
+                    .first()
+                    .map(|artist| sort_key(&artist.name, artist.sort_name.as_deref()))
+            }),
+            AlbumSort::Year => visible.sort_by_key(|album| album.release_year),
+        }
+        if self.descending {
+            visible.reverse();
+        }
+        self.visible = visible;
+    }
+
+    /// Sort by `sort`, or flip the direction if the grid is already sorted by it
+    fn set_sort(&mut self, sort: AlbumSort, cx: &mut gpui::Context<Self>) {
+        if self.sort == sort {
+            self.descending = !self.descending;
+        } else {
+            self.sort = sort;
+            self.descending = false;
+        }
+        self.apply_filter();
+        cx.notify();
+    }
+
+    /// Show the page of `album`, replacing the grid until it is closed
+    pub fn open_album(&mut self, album: Album, cx: &mut gpui::Context<Self>) {
+        cx.spawn(async move |this, app| {
+            let album_id = album.id.clone();
+            let tracks = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.get_tracks_by_album(&album_id).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten()
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load album tracks: {}", e);
+                vec![]
+            });
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.show_page(album, tracks, cx);
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn show_page(&mut self, album: Album, tracks: Vec<Track>, cx: &mut gpui::Context<Self>) {
+        // playing a track plays the rest of the album after it
+        let album_tracks = tracks.clone();
+        let on_play = Arc::new(move |track: Track| {
+            let Some(player) = PLAYER.get() else {
+                eprintln!("Player not initialized");
+                return;
+            };
+            let start = album_tracks
+                .iter()
+                .position(|t| t.id == track.id)
+                .unwrap_or(0);
+            player.play_tracks(album_tracks[start..].to_vec());
+        });
+        let delegate = TrackListDelegate::new(tracks.clone()).with_on_play(on_play);
+        self.track_list
+            .update(cx, |list, cx| list.update_delegate(cx, delegate));
+        self.page = Some(AlbumPage { album, tracks });
+    }
+
+    fn close_album(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        self.page = None;
+        cx.notify();
+    }
+
+    fn play_album(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        _cx: &mut gpui::Context<Self>,
+    ) {
+        if let (Some(page), Some(player)) = (&self.page, PLAYER.get()) {
+            player.play_tracks(page.tracks.clone());
+        }
+    }
+
+    fn shuffle_album(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        _cx: &mut gpui::Context<Self>,
+    ) {
+        if let (Some(page), Some(player)) = (&self.page, PLAYER.get()) {
+            let mut tracks = page.tracks.clone();
+            tracks.shuffle(&mut rand::rng());
+            player.play_tracks(tracks);
+        }
+    }
+
+    fn queue_album(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        _cx: &mut gpui::Context<Self>,
+    ) {
+        if let (Some(page), Some(player)) = (&self.page, PLAYER.get()) {
+            player.enqueue(page.tracks.clone());
+        }
+    }
+
+    fn render_tile(
+        &self,
+        index: usize,
+        album: &Album,
+        cx: &mut gpui::Context<Self>,
+    ) -> impl IntoElement {
+        let open = album.clone();
+        album_tile(("album", index), album)
+            .on_click(cx.listener(move |view, _, _, cx| view.open_album(open.clone(), cx)))
+    }
+
+    fn render_grid(
+        &mut self,
+        window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) -> impl IntoElement {
+        let width = f32::from(window.viewport_size().width) - GRID_MARGIN;
+        self.columns = ((width / TILE_WIDTH).floor() as usize).max(1);
+        let rows = self.visible.len().div_ceil(self.columns);
+
+        let sort_buttons = AlbumSort::ALL.iter().enumerate().map(|(i, &sort)| {
+            let active = self.sort == sort;
+            let label = match (active, self.descending) {
+                (true, false) => format!("{} ↑", sort.label()),
+                (true, true) => format!("{} ↓", sort.label()),
+                (false, _) => sort.label().to_string(),
+            };
+            let button = Button::new(("album_sort", i)).label(label);
+            let button = if active {
+                button.primary()
+            } else {
+                button.ghost()
+            };
+            button.on_click(cx.listener(move |view, _, _, cx| view.set_sort(sort, cx)))
+        });
+
+        gpui::div()
+            .size_full()
+            .v_flex()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Albums"),
+            )
+            .child(Input::new(&self.filter_input).prefix(GpuiIcon::new(Icon::Search)))
+            .child(gpui::div().h_flex().gap_2().children(sort_buttons))
+            .child(
+                // only the rows in view are rendered, so large libraries stay fast
+                uniform_list(
+                    "albums_grid",
+                    rows,
+                    cx.processor(|view, range: std::ops::Range<usize>, _window, cx| {
+                        range
+                            .map(|row| {
+                                let start = row * view.columns;
+                                let end = (start + view.columns).min(view.visible.len());
+                                gpui::div().h_flex().children(
+                                    (start..end)
+                                        .map(|i| view.render_tile(i, &view.visible[i], cx))
+                                        .collect::<Vec<_>>(),
+                                )
+                            })
+                            .collect()
+                    }),
+                )
+                .flex_1(),
+            )
+    }
+
+    fn render_page(&self, page: &AlbumPage, cx: &mut gpui::Context<Self>) -> impl IntoElement {
+        let album = &page.album;
+        let mut details = vec![format!("{} track(s)", page.tracks.len())];
+        if let Some(year) = album.release_year {
+            details.insert(0, year.to_string());
+        }
+        if let Some(label) = &album.label {
+            details.push(label.clone());
+        }
+
+        gpui::div()
+            .size_full()
+            .v_flex()
+            .gap_4()
+            .child(
+                gpui::div().child(
+                    Button::new("album_back")
+                        .ghost()
+                        .label("← Albums")
+                        .on_click(cx.listener(Self::close_album)),
+                ),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_4()
+                    .child(
+                        img(album_art_source(
+                            None,
+                            Some(album.id.clone()),
+                            ArtSize::Medium,
+                        ))
+                        .size(px(160.0))
+                        .rounded_md(),
+                    )
+                    .child(
+                        gpui::div()
+                            .v_flex()
+                            .gap_2()
+                            .min_w_0()
+                            .child(
+                                gpui::div()
+                                    .text_xl()
+                                    .font_weight(gpui::FontWeight::BOLD)
+                                    .child(album.title.clone()),
+                            )
+                            .child(gpui::div().child(artists_string(album)))
+                            .child(gpui::div().text_sm().child(details.join(" · ")))
+                            .child(
+                                gpui::div()
+                                    .h_flex()
+                                    .gap_2()
+                                    .child(
+                                        Button::new("album_play")
+                                            .primary()
+                                            .icon(Icon::Play)
+                                            .label("Play album")
+                                            .on_click(cx.listener(Self::play_album)),
+                                    )
+                                    .child(
+                                        Button::new("album_shuffle")
+                                            .label("Shuffle album")
+                                            .on_click(cx.listener(Self::shuffle_album)),
+                                    )
+                                    .child(
+                                        Button::new("album_queue")
+                                            .label("Add to queue")
+                                            .on_click(cx.listener(Self::queue_album)),
+                                    ),
+                            ),
+                    ),
+            )
+            .child(self.track_list.clone())
+    }
+}
+
+impl Render for AlbumsView {
+    fn render(
+        &mut self,
+        window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        let content = match &self.page {
+            Some(page) => self.render_page(page, cx).into_any_element(),
+            None => self.render_grid(window, cx).into_any_element(),
+        };
+
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .child(content)
+    }
+}
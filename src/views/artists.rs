@@ -0,0 +1,396 @@
+use std::sync::Arc;
+
+use gpui::{
+    AppContext, ClickEvent, Entity, InteractiveElement, IntoElement, ParentElement, Render,
+    StatefulInteractiveElement, Styled, Subscription, Window, uniform_list,
+};
+use gpui_component::{
+    Icon as GpuiIcon, StyledExt,
+    button::{Button, ButtonVariants},
+    input::{Input, InputEvent, InputState},
+};
+use tokio::task;
+
+use crate::{
+    components::{
+        icon::Icon,
+        sidebar::NavigationState,
+        track_list::{TrackList, TrackListDelegate},
+    },
+    library::{Album, ArtistSummary, LIBRARY, LibraryEvent, Track, search::normalize},
+    player::PLAYER,
+    views::{AlbumsView, albums::album_tile},
+};
+
+/// Number of most played tracks shown on an artist page
+const TOP_TRACKS: usize = 10;
+
+/// The artist page that is open
+struct ArtistPage {
+    summary: ArtistSummary,
+    albums: Vec<Album>,
+}
+
+pub struct ArtistsView {
+    /// Every artist in the library, ordered by sort name
+    artists: Vec<ArtistSummary>,
+    /// The artists shown in the list, after filtering
+    visible: Vec<ArtistSummary>,
+    filter_input: Entity<InputState>,
+    filter: String,
+    page: Option<ArtistPage>,
+    top_tracks: Entity<TrackList<Track>>,
+    /// Albums open in the Albums view, which is switched to
+    albums_view: Entity<AlbumsView>,
+    navigation_state: Entity<NavigationState>,
+    _s: Vec<Subscription>,
+}
+
+impl ArtistsView {
+    pub fn new(
+        window: &mut gpui::Window,
+        cx: &mut gpui::Context<Self>,
+        albums_view: Entity<AlbumsView>,
+        navigation_state: Entity<NavigationState>,
+    ) -> Self {
+        let top_tracks = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+        let filter_input = cx.new(|cx| InputState::new(window, cx).placeholder("Filter artists"));
+
+        let _s = vec![
+            cx.subscribe_in(&filter_input, window, move |this, state, event, _, cx| {
+                if let InputEvent::Change = event {
+                    this.filter = normalize(state.read(cx).value().trim());
+                    this.apply_filter();
+                    cx.notify();
+                }
+            }),
+        ];
+
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                match event {
+                    LibraryEvent::TracksAdded(_)
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::Restored => {
+                        let Some(this) = this.upgrade() else {
+                            return;
+                        };
+                        app.update_entity(&this, |view, cx| view.reload(cx)).ok();
+                    }
+                    LibraryEvent::PlaylistsChanged | LibraryEvent::PlaysRecorded(_) => {}
+                }
+            }
+        })
+        .detach();
+
+        let mut view = Self {
+            artists: Vec::new(),
+            visible: Vec::new(),
+            filter_input,
+            filter: String::new(),
+            page: None,
+            top_tracks,
+            albums_view,
+            navigation_state,
+            _s,
+        };
+        view.reload(cx);
+        view
+    }
+
+    /// Reload the artists, and the open artist page
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        let page_artist = self
+            .page
+            .as_ref()
+            .map(|page| page.summary.artist.id.clone());
+        cx.spawn(async move |this, app| {
+            let artists = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.all_artist_summaries().await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let artists = match artists {
+                Ok(artists) => artists,
+                Err(e) => {
+                    eprintln!("Failed to load artists: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.artists = artists;
+                    view.apply_filter();
+                    if let Some(id) = page_artist {
+                        match view.artists.iter().find(|a| a.artist.id == id).cloned() {
+                            Some(summary) => view.open_artist(summary, cx),
+                            None => view.page = None,
+                        }
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// Recompute the artists shown in the list from the filter
+    fn apply_filter(&mut self) {
+        self.visible = self
+            .artists
+            .iter()
+            .filter(|summary| {
+                self.filter.is_empty() || normalize(&summary.artist.name).contains(&self.filter)
+            })
+            .cloned()
+            .collect();
+    }
+
+    fn open_artist(&mut self, summary: ArtistSummary, cx: &mut gpui::Context<Self>) {
+        cx.spawn(async move |this, app| {
+            let artist_id = summary.artist.id.clone();
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let albums = library.get_albums_by_artist(&artist_id).await?;
+                let top_tracks = library
+                    .get_top_tracks_by_artist(&artist_id, TOP_TRACKS)
+                    .await?;
+                Ok((albums, top_tracks))
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let (albums, top_tracks) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Failed to load artist: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    // playing a top track plays the ones below it too
+                    let queue = top_tracks.clone();
+                    let on_play = Arc::new(move |track: Track| {
+                        let Some(player) = PLAYER.get() else {
+                            eprintln!("Player not initialized");
+                            return;
+                        };
+                        let start = queue.iter().position(|t| t.id == track.id).unwrap_or(0);
+                        player.play_tracks(queue[start..].to_vec());
+                    });
+                    let delegate = TrackListDelegate::new(top_tracks).with_on_play(on_play);
+                    view.top_tracks
+                        .update(cx, |list, cx| list.update_delegate(cx, delegate));
+                    view.page = Some(ArtistPage { summary, albums });
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn close_artist(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        self.page = None;
+        cx.notify();
+    }
+
+    /// Play every track by the artist, album by album
+    fn play_all(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let Some(page) = &self.page else {
+            return;
+        };
+        let artist_id = page.summary.artist.id.clone();
+        cx.spawn(async move |_, _| {
+            let tracks = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let mut tracks = Vec::new();
+                for album in library.get_albums_by_artist(&artist_id).await? {
+                    tracks.extend(
+                        library
+                            .get_tracks_by_album(&album.id)
+                            .await?
+                            .into_iter()
+                            .filter(|t| t.artists.iter().any(|a| a.id == artist_id)),
+                    );
+                }
+                Ok(tracks)
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+            match (tracks, PLAYER.get()) {
+                (Ok(tracks), Some(player)) => player.play_tracks(tracks),
+                (Err(e), _) => eprintln!("Failed to load artist tracks: {}", e),
+                (_, None) => eprintln!("Player not initialized"),
+            }
+        })
+        .detach();
+    }
+
+    /// Open `album` in the Albums view
+    fn show_album(&mut self, album: Album, cx: &mut gpui::Context<Self>) {
+        self.albums_view
+            .update(cx, |view, cx| view.open_album(album, cx));
+        self.navigation_state.update(cx, |state, _| {
+            *state = NavigationState::Albums;
+        });
+    }
+
+    fn render_list(&self, cx: &mut gpui::Context<Self>) -> impl IntoElement {
+        gpui::div()
+            .size_full()
+            .v_flex()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Artists"),
+            )
+            .child(Input::new(&self.filter_input).prefix(GpuiIcon::new(Icon::Search)))
+            .child(
+                uniform_list(
+                    "artists_list",
+                    self.visible.len(),
+                    cx.processor(|view, range: std::ops::Range<usize>, _window, cx| {
+                        range
+                            .map(|i| {
+                                let summary = view.visible[i].clone();
+                                let counts = format!(
+                                    "{} album(s) · {} track(s)",
+                                    summary.album_count, summary.track_count
+                                );
+                                gpui::div()
+                                    .id(("artist", i))
+                                    .h_flex()
+                                    .w_full()
+                                    .px_2()
+                                    .py_2()
+                                    .gap_4()
+                                    .rounded_md()
+                                    .cursor_pointer()
+                                    .hover(|style| style.bg(gpui::rgba(0xffffff10)))
+                                    .child(
+                                        gpui::div()
+                                            .flex_1()
+                                            .min_w_0()
+                                            .text_ellipsis()
+                                            .font_semibold()
+                                            .child(summary.artist.name.clone()),
+                                    )
+                                    .child(gpui::div().text_sm().child(counts))
+                                    .on_click(cx.listener(move |view, _, _, cx| {
+                                        view.open_artist(summary.clone(), cx)
+                                    }))
+                            })
+                            .collect()
+                    }),
+                )
+                .flex_1(),
+            )
+    }
+
+    fn render_page(&self, page: &ArtistPage, cx: &mut gpui::Context<Self>) -> impl IntoElement {
+        let summary = &page.summary;
+        gpui::div()
+            .size_full()
+            .v_flex()
+            .gap_4()
+            .child(
+                gpui::div().child(
+                    Button::new("artist_back")
+                        .ghost()
+                        .label("← Artists")
+                        .on_click(cx.listener(Self::close_artist)),
+                ),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_4()
+                    .child(
+                        gpui::div()
+                            .v_flex()
+                            .flex_1()
+                            .min_w_0()
+                            .child(
+                                gpui::div()
+                                    .text_xl()
+                                    .font_weight(gpui::FontWeight::BOLD)
+                                    .child(summary.artist.name.clone()),
+                            )
+                            .child(gpui::div().text_sm().child(format!(
+                                "{} album(s) · {} track(s)",
+                                summary.album_count, summary.track_count
+                            ))),
+                    )
+                    .child(
+                        Button::new("artist_play_all")
+                            .primary()
+                            .icon(Icon::Play)
+                            .label("Play all")
+                            .on_click(cx.listener(Self::play_all)),
+                    ),
+            )
+            .child(gpui::div().font_semibold().child("Albums"))
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .flex_wrap()
+                    .children(page.albums.iter().enumerate().map(|(i, album)| {
+                        let open = album.clone();
+                        album_tile(("artist_album", i), album).on_click(
+                            cx.listener(move |view, _, _, cx| view.show_album(open.clone(), cx)),
+                        )
+                    })),
+            )
+            .child(gpui::div().font_semibold().child("Top tracks"))
+            .child(self.top_tracks.clone())
+    }
+}
+
+impl Render for ArtistsView {
+    fn render(
+        &mut self,
+        _window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        let content = match &self.page {
+            Some(page) => self.render_page(page, cx).into_any_element(),
+            None => self.render_list(cx).into_any_element(),
+        };
+
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .child(content)
+    }
+}
@@ -0,0 +1,223 @@
+use gpui::prelude::FluentBuilder;
+use gpui::{
+    ClickEvent, InteractiveElement, IntoElement, ParentElement, Render, StatefulInteractiveElement,
+    Styled, Window,
+};
+use gpui_component::{StyledExt, button::Button};
+use tokio::task;
+
+use crate::library::{
+    LIBRARY, Track, TrackSource,
+    duplicates::{DuplicateGroup, DuplicateKind},
+};
+
+pub struct DuplicatesView {
+    groups: Vec<DuplicateGroup>,
+    status: Option<String>,
+    busy: bool,
+}
+
+impl DuplicatesView {
+    pub fn new(_window: &mut gpui::Window, _cx: &mut gpui::Context<Self>) -> Self {
+        Self {
+            groups: Vec::new(),
+            status: None,
+            busy: false,
+        }
+    }
+
+    fn scan(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status =
+            Some("Looking for duplicates, fingerprinting new files takes a while...".to_string());
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.find_duplicates().await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(groups) => {
+                            view.status = Some(match groups.len() {
+                                0 => "No duplicates found".to_string(),
+                                count => format!("Found {} group(s) of duplicates", count),
+                            });
+                            view.groups = groups;
+                        }
+                        Err(e) => view.status = Some(format!("Failed to find duplicates: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// Keep one track of a group and merge the others into it
+    fn keep(&mut self, group: usize, keep: usize, cx: &mut gpui::Context<Self>) {
+        let Some(tracks) = self.groups.get(group).map(|g| g.tracks.clone()) else {
+            return;
+        };
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        cx.notify();
+        let keep_id = tracks[keep].id.clone();
+        let duplicates: Vec<String> = tracks.iter().map(|t| t.id.clone()).collect();
+        cx.spawn(async move |this, app| {
+            let merge_id = keep_id.clone();
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.merge_duplicates(&merge_id, &duplicates).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(track) => {
+                            view.status = Some(format!("Kept {}", track.title));
+                            // the group may have moved if the view was rescanned meanwhile
+                            view.groups
+                                .retain(|g| !g.tracks.iter().any(|t| t.id == keep_id));
+                        }
+                        Err(e) => view.status = Some(format!("Failed to merge: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Where the track comes from, so copies with the same tags can be told apart
+fn location(track: &Track) -> String {
+    match (&track.source, &track.path, &track.source_id) {
+        (TrackSource::Local, Some(path), _) => path.clone(),
+        (TrackSource::YouTube, _, Some(video)) => format!("YouTube: {}", video),
+        (source, _, _) => source.as_str().to_string(),
+    }
+}
+
+fn track_line(track: &Track) -> String {
+    let artists = track
+        .artists
+        .iter()
+        .map(|a| a.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "{} - {} ({}, {})",
+        artists,
+        track.title,
+        track.album.title,
+        format_duration(track.duration)
+    )
+}
+
+impl Render for DuplicatesView {
+    fn render(
+        &mut self,
+        _window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        let groups = self.groups.iter().enumerate().map(|(index, group)| {
+            gpui::div()
+                .id(("duplicates_group", index))
+                .v_flex()
+                .gap_1()
+                .child(
+                    gpui::div()
+                        .font_weight(gpui::FontWeight::BOLD)
+                        .child(group.kind.label()),
+                )
+                .children(group.tracks.iter().enumerate().map(|(position, track)| {
+                    gpui::div()
+                        .h_flex()
+                        .gap_2()
+                        .child(
+                            Button::new(("duplicates_keep", position))
+                                .label("Keep this")
+                                .on_click(cx.listener(move |view, _, _, cx| {
+                                    view.keep(index, position, cx)
+                                })),
+                        )
+                        .child(
+                            gpui::div()
+                                .v_flex()
+                                .child(gpui::div().text_sm().child(track_line(track)))
+                                .child(gpui::div().text_xs().child(location(track))),
+                        )
+                }))
+                .when(group.kind == DuplicateKind::SimilarTags, |div| {
+                    div.child(
+                        gpui::div()
+                            .text_xs()
+                            .child("Check these are the same recording before merging"),
+                    )
+                })
+        });
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Duplicates"),
+            )
+            .child(gpui::div().text_sm().child(
+                "Keeping a copy removes the others, moving their plays and playlist entries to it",
+            ))
+            .child(
+                gpui::div().h_flex().gap_2().child(
+                    Button::new("duplicates_scan")
+                        .label("Find duplicates")
+                        .on_click(cx.listener(Self::scan)),
+                ),
+            )
+            .when_some(self.status.clone(), |div, status| {
+                div.child(gpui::div().text_sm().child(status))
+            })
+            .child(
+                gpui::div()
+                    .id("duplicates_groups")
+                    .flex_1()
+                    .min_h_0()
+                    .overflow_y_scroll()
+                    .v_flex()
+                    .gap_4()
+                    .children(groups),
+            )
+    }
+}
@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+
+use gpui::prelude::FluentBuilder;
+use gpui::{AppContext, ClickEvent, Entity, IntoElement, ParentElement, Render, Styled, Window};
+use gpui_component::{
+    StyledExt,
+    button::Button,
+    input::{Input, InputState},
+};
+use tokio::task;
+
+use crate::{
+    components::track_list::{TrackList, TrackListDelegate},
+    library::{LIBRARY, Track},
+    preferences::PREFERENCES,
+};
+
+pub struct HealthView {
+    track_list: Entity<TrackList<Track>>,
+    from_input: Entity<InputState>,
+    to_input: Entity<InputState>,
+    status: Option<String>,
+    busy: bool,
+}
+
+impl HealthView {
+    pub fn new(window: &mut gpui::Window, cx: &mut gpui::Context<Self>) -> Self {
+        let track_list = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+        let from_input =
+            cx.new(|cx| InputState::new(window, cx).placeholder("Old folder, e.g. /mnt/old/Music"));
+        let to_input =
+            cx.new(|cx| InputState::new(window, cx).placeholder("New folder, e.g. /home/me/Music"));
+        Self {
+            track_list,
+            from_input,
+            to_input,
+            status: None,
+            busy: false,
+        }
+    }
+
+    fn show_missing(&mut self, missing: Vec<Track>, cx: &mut gpui::Context<Self>) {
+        self.track_list.update(cx, |list, cx| {
+            list.update_delegate(cx, TrackListDelegate::new(missing))
+        });
+    }
+
+    fn scan(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status = Some("Looking for missing files...".to_string());
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.find_missing_tracks().await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(missing) => {
+                            view.status = Some(format!("{} track(s) are missing", missing.len()));
+                            view.show_missing(missing, cx);
+                        }
+                        Err(e) => view.status = Some(format!("Scan failed: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn relocate(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status = Some("Searching library folders for moved files...".to_string());
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let roots: Vec<PathBuf> = PREFERENCES
+                    .get()
+                    .ok_or(anyhow::anyhow!("Preferences not initialized"))?
+                    .read()
+                    .await
+                    .library_roots
+                    .iter()
+                    .map(PathBuf::from)
+                    .collect();
+                library.relocate_missing_tracks(&roots).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(report) => {
+                            view.status = Some(format!(
+                                "Relocated {} track(s), {} still missing",
+                                report.relocated.len(),
+                                report.missing.len()
+                            ));
+                            view.show_missing(report.missing, cx);
+                        }
+                        Err(e) => view.status = Some(format!("Relocation failed: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn relink(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        let from = self.from_input.read(cx).value().trim().to_string();
+        let to = self.to_input.read(cx).value().trim().to_string();
+        if self.busy || from.is_empty() || to.is_empty() {
+            return;
+        }
+        self.busy = true;
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let report = library
+                    .relink_prefix(Path::new(&from), Path::new(&to))
+                    .await?;
+                let missing = library.find_missing_tracks().await?;
+                Ok((report, missing))
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok((report, missing)) => {
+                            view.status = Some(format!(
+                                "Relinked {} track(s), {} not found in the new folder, {} still missing",
+                                report.relocated.len(),
+                                report.missing.len(),
+                                missing.len()
+                            ));
+                            view.show_missing(missing, cx);
+                        }
+                        Err(e) => view.status = Some(format!("Relink failed: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+impl Render for HealthView {
+    fn render(
+        &mut self,
+        _window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Library health"),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("health_scan")
+                            .label("Find missing files")
+                            .on_click(cx.listener(Self::scan)),
+                    )
+                    .child(
+                        Button::new("health_relocate")
+                            .label("Relocate automatically")
+                            .on_click(cx.listener(Self::relocate)),
+                    ),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(gpui::div().flex_1().child(Input::new(&self.from_input)))
+                    .child(gpui::div().flex_1().child(Input::new(&self.to_input)))
+                    .child(
+                        Button::new("health_relink")
+                            .label("Relink folder")
+                            .on_click(cx.listener(Self::relink)),
+                    ),
+            )
+            .when_some(self.status.clone(), |div, status| {
+                div.child(gpui::div().text_sm().child(status))
+            })
+            .child(self.track_list.clone())
+    }
+}
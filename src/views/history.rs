@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use chrono::{Datelike, Local, TimeZone};
+use gpui::{AppContext, Entity, IntoElement, ParentElement, Render, Styled};
+use gpui_component::StyledExt;
+use tokio::task;
+
+use crate::{
+    components::track_list::{TrackList, TrackListDelegate},
+    library::{
+        LIBRARY, LibraryEvent,
+        history::{ArtistPlays, Play, TrackPlays},
+    },
+    player::PLAYER,
+};
+
+/// Number of plays listed under "Recently played"
+const RECENT_PLAYS: usize = 50;
+
+/// Number of tracks listed under "Most played this month"
+const MOST_PLAYED: usize = 25;
+
+/// Number of artists listed under "Top artists this month"
+const TOP_ARTISTS: usize = 10;
+
+pub struct HistoryView {
+    recent: Entity<TrackList<Play>>,
+    most_played: Entity<TrackList<TrackPlays>>,
+    top_artists: Vec<ArtistPlays>,
+}
+
+impl HistoryView {
+    pub fn new(window: &mut gpui::Window, cx: &mut gpui::Context<Self>) -> Self {
+        let recent = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+        let most_played = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                match event {
+                    LibraryEvent::PlaysRecorded(_)
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::Restored => {
+                        let Some(this) = this.upgrade() else {
+                            return;
+                        };
+                        app.update_entity(&this, |view, cx| view.reload(cx)).ok();
+                    }
+                    LibraryEvent::TracksAdded(_) | LibraryEvent::PlaylistsChanged => {}
+                }
+            }
+        })
+        .detach();
+
+        let mut view = Self {
+            recent,
+            most_played,
+            top_artists: Vec::new(),
+        };
+        view.reload(cx);
+        view
+    }
+
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        cx.spawn(async move |this, app| {
+            let history = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let since = start_of_month();
+                let recent = library.recently_played(RECENT_PLAYS).await?;
+                let most_played = library.most_played_since(since, MOST_PLAYED).await?;
+                let top_artists = library.artist_play_totals(since, TOP_ARTISTS).await?;
+                Ok((recent, most_played, top_artists))
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let (recent, most_played, top_artists) = match history {
+                Ok(history) => history,
+                Err(e) => {
+                    eprintln!("Failed to load listening history: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    let on_play_recent = Arc::new(|play: Play| match PLAYER.get() {
+                        Some(player) => player.play_tracks(vec![play.track]),
+                        None => eprintln!("Player not initialized"),
+                    });
+                    view.recent.update(cx, |list, cx| {
+                        list.update_delegate(
+                            cx,
+                            TrackListDelegate::new(recent).with_on_play(on_play_recent),
+                        )
+                    });
+
+                    // playing a track plays the ones below it too
+                    let queue = most_played.clone();
+                    let on_play_most = Arc::new(move |item: TrackPlays| {
+                        let Some(player) = PLAYER.get() else {
+                            eprintln!("Player not initialized");
+                            return;
+                        };
+                        let start = queue
+                            .iter()
+                            .position(|t| t.track.id == item.track.id)
+                            .unwrap_or(0);
+                        player
+                            .play_tracks(queue[start..].iter().map(|t| t.track.clone()).collect());
+                    });
+                    view.most_played.update(cx, |list, cx| {
+                        list.update_delegate(
+                            cx,
+                            TrackListDelegate::new(most_played).with_on_play(on_play_most),
+                        )
+                    });
+
+                    view.top_artists = top_artists;
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+/// Unix time in milliseconds of the start of the current month, in local time
+fn start_of_month() -> i64 {
+    let today = Local::now().date_naive();
+    today
+        .with_day(1)
+        .and_then(|first| first.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+        .map(|start| start.timestamp_millis())
+        .unwrap_or(0)
+}
+
+/// A number of seconds as hours and minutes, e.g. "3 h 25 min"
+fn format_listened(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as u64;
+    if minutes >= 60 {
+        format!("{} h {} min", minutes / 60, minutes % 60)
+    } else {
+        format!("{} min", minutes)
+    }
+}
+
+impl Render for HistoryView {
+    fn render(
+        &mut self,
+        _window: &mut gpui::Window,
+        _cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("History"),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .flex_1()
+                    .min_h_0()
+                    .gap_4()
+                    .child(
+                        gpui::div()
+                            .v_flex()
+                            .flex_1()
+                            .h_full()
+                            .gap_2()
+                            .child(gpui::div().font_semibold().child("Recently played"))
+                            .child(gpui::div().flex_1().min_h_0().child(self.recent.clone())),
+                    )
+                    .child(
+                        gpui::div()
+                            .v_flex()
+                            .flex_1()
+                            .h_full()
+                            .gap_2()
+                            .child(gpui::div().font_semibold().child("Most played this month"))
+                            .child(
+                                gpui::div()
+                                    .flex_1()
+                                    .min_h_0()
+                                    .child(self.most_played.clone()),
+                            )
+                            .child(gpui::div().font_semibold().child("Top artists this month"))
+                            .children(self.top_artists.iter().map(|totals| {
+                                gpui::div()
+                                    .h_flex()
+                                    .justify_between()
+                                    .gap_4()
+                                    .child(
+                                        gpui::div()
+                                            .text_ellipsis()
+                                            .child(totals.artist.name.clone()),
+                                    )
+                                    .child(gpui::div().text_sm().child(format!(
+                                        "{} play(s) · {}",
+                                        totals.plays,
+                                        format_listened(totals.duration)
+                                    )))
+                            })),
+                    ),
+            )
+    }
+}
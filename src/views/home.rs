@@ -1,17 +1,41 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use gpui::{AppContext, Entity, IntoElement, ParentElement, Render, Styled};
-use gpui_component::StyledExt;
+use futures::FutureExt;
+use gpui::{
+    AppContext, Entity, IntoElement, ParentElement, Render, Styled, Subscription,
+    prelude::FluentBuilder,
+};
+use gpui_component::{
+    Icon as GpuiIcon, StyledExt,
+    button::{Button, ButtonVariants},
+    input::{Input, InputEvent, InputState},
+};
 use tokio::task;
 
 use crate::{
-    components::track_list::{TrackList, TrackListDelegate},
-    library::{LIBRARY, LibraryEvent, Track},
+    components::{
+        icon::Icon,
+        track_list::{LoadPageCallback, OnPlayCallback, TrackList, TrackListDelegate},
+    },
+    library::{
+        LIBRARY, LibraryEvent, Track, TrackCursor, TrackFilter, TrackSort,
+        rating::{MAX_RATING, stars},
+    },
     player::PLAYER,
 };
 
+/// Number of tracks fetched at a time when browsing the whole library
+const PAGE_SIZE: usize = 200;
+
 pub struct HomeView {
     track_list: Entity<TrackList<Track>>,
+    search_input: Entity<InputState>,
+    on_play: OnPlayCallback<Track>,
+    query: String,
+    sort: TrackSort,
+    descending: bool,
+    filter: TrackFilter,
+    _s: Vec<Subscription>,
 }
 
 impl HomeView {
@@ -33,69 +57,192 @@ impl HomeView {
             TrackListDelegate::new(vec![]).with_on_play(on_play_callback.clone());
 
         let track_list = cx.new(|cx| TrackList::new(window, cx, initial_delegate));
+        let search_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .placeholder("Search library... (e.g. artist:radiohead year:1997)")
+        });
 
-        let track_list_clone = track_list.clone();
-        let on_play_for_load = on_play_callback.clone();
-        cx.spawn(async move |_, app| {
-            let tracks = task::spawn(async move {
-                let library = LIBRARY
-                    .get()
-                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
-                Ok(library.all_tracks().await?)
-            })
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
-            .flatten()
-            .unwrap_or_default();
-
-            let new_delegate = TrackListDelegate::new(tracks).with_on_play(on_play_for_load);
-            app.update_entity(&track_list_clone, |e, cx| {
-                e.update_delegate(cx, new_delegate)
-            })
-            .ok();
-        })
-        .detach();
+        let _s = vec![
+            cx.subscribe_in(&search_input, window, move |this, state, event, _, cx| {
+                if let InputEvent::PressEnter { .. } = event {
+                    this.query = state.read(cx).value().trim().to_string();
+                    this.reload(cx);
+                    cx.notify();
+                }
+            }),
+        ];
 
-        let track_list_for_events = track_list.clone();
-        let on_play_for_events = on_play_callback.clone();
-        cx.spawn(async move |_, app| {
+        cx.spawn(async move |this, app| {
             let library = LIBRARY.get().expect("Library not initialized");
             let mut recv = library.subscribe();
-            while let Ok(event) = recv.recv().await {
+            while let Some(event) = recv.recv().await {
                 match event {
-                    LibraryEvent::TracksAdded(_) => {
-                        let tracks = task::spawn(async move {
-                            let library = LIBRARY
-                                .get()
-                                .ok_or(anyhow::anyhow!("Library not initialized"))?;
-                            Ok(library.all_tracks().await?)
-                        })
-                            .await
-                            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
-                            .flatten()
-                            .unwrap_or_default();
-                        
-                        let new_delegate = TrackListDelegate::new(tracks).with_on_play(on_play_for_events.clone());
-                        app.update_entity(&track_list_for_events, |e, cx| {
-                            e.update_delegate(cx, new_delegate)
-                        })
-                        .ok();
+                    LibraryEvent::TracksAdded(_)
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::Restored => {
+                        let Some(this) = this.upgrade() else {
+                            return;
+                        };
+                        app.update_entity(&this, |view, cx| view.reload(cx)).ok();
                     }
+                    LibraryEvent::PlaylistsChanged | LibraryEvent::PlaysRecorded(_) => {}
                 }
             }
         })
         .detach();
 
-        Self { track_list }
+        let mut view = Self {
+            track_list,
+            search_input,
+            on_play: on_play_callback,
+            query: String::new(),
+            sort: TrackSort::default(),
+            descending: false,
+            filter: TrackFilter::default(),
+            _s,
+        };
+        view.reload(cx);
+        view
+    }
+
+    /// Reload the track list, filtered by the current search query if there is one.
+    /// Without a query the library is loaded a page at a time in the current sort order.
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        let query = self.query.clone();
+        let sort = self.sort;
+        let descending = self.descending;
+        let filter = self.filter;
+        let track_list = self.track_list.clone();
+        let on_play = self.on_play.clone();
+        cx.spawn(async move |_, app| {
+            let new_delegate = if query.is_empty() {
+                let load_page = page_loader(sort, descending, filter);
+                let (tracks, has_more) = load_page().await.unwrap_or_else(|e| {
+                    eprintln!("Failed to load tracks: {}", e);
+                    (vec![], false)
+                });
+                TrackListDelegate::new(tracks).with_load_page(load_page, has_more)
+            } else {
+                let tracks = task::spawn(async move {
+                    let library = LIBRARY
+                        .get()
+                        .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                    Ok(library.search(&query).await?.tracks)
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+                .flatten()
+                .unwrap_or_default();
+                TrackListDelegate::new(tracks)
+            };
+
+            let new_delegate = new_delegate.with_on_play(on_play);
+            app.update_entity(&track_list, |e, cx| e.update_delegate(cx, new_delegate))
+                .ok();
+        })
+        .detach();
+    }
+
+    /// Sort by `sort`, or flip the direction if the library is already sorted by it
+    fn set_sort(&mut self, sort: TrackSort, cx: &mut gpui::Context<Self>) {
+        if self.sort == sort {
+            self.descending = !self.descending;
+        } else {
+            self.sort = sort;
+            self.descending = false;
+        }
+        self.reload(cx);
+        cx.notify();
+    }
+
+    /// Show only tracks rated at least one star more, going back to every track after
+    /// the highest rating
+    fn cycle_min_rating(&mut self, cx: &mut gpui::Context<Self>) {
+        self.filter.min_rating = (self.filter.min_rating + 1) % (MAX_RATING + 1);
+        self.reload(cx);
+        cx.notify();
+    }
+
+    fn toggle_loved_only(&mut self, cx: &mut gpui::Context<Self>) {
+        self.filter.loved_only = !self.filter.loved_only;
+        self.reload(cx);
+        cx.notify();
     }
 }
 
+/// Returns a callback that fetches consecutive pages of the library, starting from the first
+fn page_loader(sort: TrackSort, descending: bool, filter: TrackFilter) -> LoadPageCallback<Track> {
+    // `None` once the last page has been loaded
+    let cursor: Arc<Mutex<Option<Option<TrackCursor>>>> = Arc::new(Mutex::new(Some(None)));
+    Arc::new(move || {
+        let cursor = cursor.clone();
+        async move {
+            let Some(after) = cursor.lock().unwrap().clone() else {
+                return Ok((vec![], false));
+            };
+            let page = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library
+                    .tracks_page(sort, descending, filter, after.as_ref(), PAGE_SIZE)
+                    .await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten()?;
+            let has_more = page.next.is_some();
+            *cursor.lock().unwrap() = page.next.map(Some);
+            Ok((page.tracks, has_more))
+        }
+        .boxed()
+    })
+}
+
 impl Render for HomeView {
     fn render(
         &mut self,
         _window: &mut gpui::Window,
-        _cx: &mut gpui::Context<'_, Self>,
+        cx: &mut gpui::Context<'_, Self>,
     ) -> impl IntoElement {
+        // sorting only applies when browsing, search results are ordered by relevance
+        let sort_buttons = self.query.is_empty().then(|| {
+            gpui::div()
+                .h_flex()
+                .gap_2()
+                .children(TrackSort::ALL.iter().enumerate().map(|(i, &sort)| {
+                    let active = self.sort == sort;
+                    let label = match (active, self.descending) {
+                        (true, false) => format!("{} ↑", sort.label()),
+                        (true, true) => format!("{} ↓", sort.label()),
+                        (false, _) => sort.label().to_string(),
+                    };
+                    let button = Button::new(("sort", i)).label(label);
+                    let button = if active {
+                        button.primary()
+                    } else {
+                        button.ghost()
+                    };
+                    button.on_click(cx.listener(move |view, _, _, cx| view.set_sort(sort, cx)))
+                }))
+                .child(
+                    Button::new("filter_rating")
+                        .ghost()
+                        .label(match self.filter.min_rating {
+                            0 => "Any rating".to_string(),
+                            min => format!("{} or more", stars(min)),
+                        })
+                        .on_click(cx.listener(|view, _, _, cx| view.cycle_min_rating(cx))),
+                )
+                .child(
+                    Button::new("filter_loved")
+                        .label("♥ Loved")
+                        .when(self.filter.loved_only, |button| button.primary())
+                        .when(!self.filter.loved_only, |button| button.ghost())
+                        .on_click(cx.listener(|view, _, _, cx| view.toggle_loved_only(cx))),
+                )
+        });
+
         gpui::div()
             .w_full()
             .h_full()
@@ -109,6 +256,8 @@ impl Render for HomeView {
                     .font_weight(gpui::FontWeight::BOLD)
                     .child("Library"),
             )
+            .child(Input::new(&self.search_input).prefix(GpuiIcon::new(Icon::Search)))
+            .children(sort_buttons)
             .child(self.track_list.clone())
     }
 }
@@ -0,0 +1,352 @@
+use std::path::PathBuf;
+
+use gpui::prelude::FluentBuilder;
+use gpui::{
+    ClickEvent, Global, InteractiveElement, IntoElement, ParentElement, PathPromptOptions, Render,
+    SharedString, StatefulInteractiveElement, Styled, Window,
+};
+use gpui_component::{StyledExt, button::Button};
+use tokio::task;
+
+use crate::library::{
+    LIBRARY,
+    import::{ImportReport, ImportSource},
+};
+
+/// Number of unmatched tracks listed under a report, the rest are only counted
+const UNMATCHED_SHOWN: usize = 50;
+
+/// Outcome of the last time files or a folder were added from the sidebar
+#[derive(Clone, Debug, Default)]
+pub struct FileImportReport {
+    pub added: usize,
+    /// Audio files in formats that can't be played, with the format's name
+    pub unsupported: Vec<(PathBuf, &'static str)>,
+    /// Files that couldn't be added, with why
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl Global for FileImportReport {}
+
+impl FileImportReport {
+    /// Whether every file found was added
+    pub fn is_complete(&self) -> bool {
+        self.unsupported.is_empty() && self.failed.is_empty()
+    }
+}
+
+pub struct ImportView {
+    /// Library file of the other player and which player it is from
+    file: Option<(PathBuf, ImportSource)>,
+    report: Option<ImportReport>,
+    status: Option<String>,
+    busy: bool,
+}
+
+impl ImportView {
+    pub fn new(_window: &mut gpui::Window, _cx: &mut gpui::Context<Self>) -> Self {
+        Self {
+            file: None,
+            report: None,
+            status: None,
+            busy: false,
+        }
+    }
+
+    fn choose_file(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let path_future = cx.prompt_for_paths(PathPromptOptions {
+            directories: false,
+            files: true,
+            multiple: false,
+            prompt: Some(SharedString::new(
+                "Select a rhythmdb.xml, iTunes Library.xml or beets library.db",
+            )),
+        });
+        cx.spawn(async move |this, app| {
+            let Some(path) = path_future
+                .await
+                .ok()
+                .and_then(|r| r.ok())
+                .flatten()
+                .and_then(|paths| paths.into_iter().next())
+            else {
+                return;
+            };
+            let detect_path = path.clone();
+            let source = task::spawn_blocking(move || ImportSource::detect(&detect_path))
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+                .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.report = None;
+                    match source {
+                        Ok(Some(source)) => {
+                            view.status = None;
+                            view.file = Some((path, source));
+                        }
+                        Ok(None) => {
+                            view.status = Some(format!(
+                                "{} is not a library file Vibrance can import",
+                                path.display()
+                            ));
+                            view.file = None;
+                        }
+                        Err(e) => {
+                            view.status = Some(format!("Failed to read {}: {}", path.display(), e));
+                            view.file = None;
+                        }
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn preview(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        self.run(true, cx);
+    }
+
+    fn import(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        self.run(false, cx);
+    }
+
+    fn run(&mut self, dry_run: bool, cx: &mut gpui::Context<Self>) {
+        let Some((path, source)) = self.file.clone() else {
+            return;
+        };
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status = Some(format!("Reading the {} library...", source.name()));
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.import_player_library(source, &path, dry_run).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(report) => {
+                            view.status = None;
+                            view.report = Some(report);
+                        }
+                        Err(e) => view.status = Some(format!("Import failed: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+/// The lines summarizing a report
+fn report_lines(report: &ImportReport) -> Vec<String> {
+    let (heading, added, playlists) = if report.dry_run {
+        ("Importing would", "add", "create")
+    } else {
+        ("Imported", "added", "created")
+    };
+    let mut lines = vec![
+        format!(
+            "{}: {} track(s) matched by path, {} by artist and title",
+            heading, report.matched_by_path, report.matched_by_tags
+        ),
+        format!("{} {} file(s) to the library", added, report.added),
+        format!(
+            "{} track(s) with ratings or play statistics",
+            report.updated
+        ),
+    ];
+    for (name, tracks) in &report.playlists {
+        lines.push(format!(
+            "{} playlist {} ({} tracks)",
+            playlists, name, tracks
+        ));
+    }
+    if !report.skipped_playlists.is_empty() {
+        lines.push(format!(
+            "Skipped playlists that exist or have no tracks here: {}",
+            report.skipped_playlists.join(", ")
+        ));
+    }
+    if !report.unsupported.is_empty() {
+        lines.push(format!(
+            "{} file(s) in formats Vibrance can't play:",
+            report.unsupported.len()
+        ));
+        lines.extend(
+            report
+                .unsupported
+                .iter()
+                .take(UNMATCHED_SHOWN)
+                .map(|(path, format)| format!("{} ({})", path.display(), format)),
+        );
+        if report.unsupported.len() > UNMATCHED_SHOWN {
+            lines.push(format!(
+                "and {} more",
+                report.unsupported.len() - UNMATCHED_SHOWN
+            ));
+        }
+    }
+    if !report.unmatched.is_empty() {
+        lines.push(format!("{} track(s) not found:", report.unmatched.len()));
+        lines.extend(report.unmatched.iter().take(UNMATCHED_SHOWN).cloned());
+        if report.unmatched.len() > UNMATCHED_SHOWN {
+            lines.push(format!(
+                "and {} more",
+                report.unmatched.len() - UNMATCHED_SHOWN
+            ));
+        }
+    }
+    lines
+}
+
+fn file_report_lines(report: &FileImportReport) -> Vec<String> {
+    let mut lines = vec![format!("Added {} track(s) to the library", report.added)];
+    let mut list = |heading: String, entries: Vec<String>| {
+        if entries.is_empty() {
+            return;
+        }
+        lines.push(heading);
+        let total = entries.len();
+        lines.extend(entries.into_iter().take(UNMATCHED_SHOWN));
+        if total > UNMATCHED_SHOWN {
+            lines.push(format!("and {} more", total - UNMATCHED_SHOWN));
+        }
+    };
+    list(
+        format!(
+            "{} audio file(s) in formats Vibrance can't play:",
+            report.unsupported.len()
+        ),
+        report
+            .unsupported
+            .iter()
+            .map(|(path, format)| format!("{} ({})", path.display(), format))
+            .collect(),
+    );
+    list(
+        format!("{} file(s) couldn't be added:", report.failed.len()),
+        report
+            .failed
+            .iter()
+            .map(|(path, error)| format!("{}: {}", path.display(), error))
+            .collect(),
+    );
+    lines
+}
+
+impl Render for ImportView {
+    fn render(
+        &mut self,
+        _window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        let file = self
+            .file
+            .as_ref()
+            .map(|(path, source)| format!("{} library: {}", source.name(), path.display()));
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Import from another player"),
+            )
+            .child(gpui::div().text_sm().child(
+                "Brings over ratings, play counts and playlists from Rhythmbox, iTunes or beets",
+            ))
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("import_choose")
+                            .label("Choose library file")
+                            .on_click(cx.listener(Self::choose_file)),
+                    )
+                    .when(self.file.is_some(), |div| {
+                        div.child(
+                            Button::new("import_preview")
+                                .label("Preview")
+                                .on_click(cx.listener(Self::preview)),
+                        )
+                        .child(
+                            Button::new("import_run")
+                                .label("Import")
+                                .on_click(cx.listener(Self::import)),
+                        )
+                    }),
+            )
+            .when_some(file, |div, file| {
+                div.child(gpui::div().text_sm().child(file))
+            })
+            .when_some(self.status.clone(), |div, status| {
+                div.child(gpui::div().text_sm().child(status))
+            })
+            .when_some(self.report.as_ref(), |div, report| {
+                div.child(
+                    gpui::div()
+                        .id("import_report")
+                        .flex_1()
+                        .min_h_0()
+                        .overflow_y_scroll()
+                        .v_flex()
+                        .gap_1()
+                        .children(
+                            report_lines(report)
+                                .into_iter()
+                                .map(|line| gpui::div().text_sm().child(line)),
+                        ),
+                )
+            })
+            .when_some(cx.try_global::<FileImportReport>(), |div, report| {
+                div.child(
+                    gpui::div()
+                        .text_lg()
+                        .font_weight(gpui::FontWeight::BOLD)
+                        .child("Last added files"),
+                )
+                .child(
+                    gpui::div()
+                        .id("import_file_report")
+                        .flex_1()
+                        .min_h_0()
+                        .overflow_y_scroll()
+                        .v_flex()
+                        .gap_1()
+                        .children(
+                            file_report_lines(report)
+                                .into_iter()
+                                .map(|line| gpui::div().text_sm().child(line)),
+                        ),
+                )
+            })
+    }
+}
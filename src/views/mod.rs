@@ -1,6 +1,22 @@
+pub mod albums;
+pub mod artists;
+pub mod duplicates;
+pub mod health;
+pub mod history;
 pub mod home;
+pub mod import;
 pub mod lyrics;
+pub mod playlist;
 pub mod search;
+pub mod tag_editor;
+pub use albums::AlbumsView;
+pub use artists::ArtistsView;
+pub use duplicates::DuplicatesView;
+pub use health::HealthView;
+pub use history::HistoryView;
 pub use home::HomeView;
+pub use import::ImportView;
 pub use lyrics::LyricsView;
+pub use playlist::PlaylistView;
 pub use search::SearchView;
+pub use tag_editor::TagEditorView;
@@ -0,0 +1,658 @@
+use std::sync::Arc;
+
+use gpui::{
+    AppContext, ClickEvent, ElementId, Entity, IntoElement, ParentElement, Render, SharedString,
+    Styled, Subscription, Window, prelude::FluentBuilder,
+};
+use gpui_component::{
+    StyledExt,
+    button::{Button, ButtonVariants},
+    input::{Input, InputState},
+    popover::Popover,
+};
+use rand::seq::SliceRandom;
+use tokio::task;
+
+use crate::{
+    components::{
+        icon::Icon,
+        sidebar::NavigationState,
+        track_list::{RowAction, TrackList, TrackListDelegate},
+    },
+    library::{
+        LIBRARY, LibraryEvent, Playlist, PlaylistEntry, TrackSort,
+        smart::{Rule, RuleField, SmartRules},
+    },
+    player::PLAYER,
+};
+
+pub struct PlaylistView {
+    /// The open playlist, with its tracks in order
+    playlist: Option<Playlist>,
+    name_input: Entity<InputState>,
+    description_input: Entity<InputState>,
+    /// Set when a different playlist is opened, so the inputs are filled on the next render
+    fill_inputs: bool,
+    track_list: Entity<TrackList<PlaylistEntry>>,
+    navigation_state: Entity<NavigationState>,
+    /// Whether exported files use paths relative to the playlist file
+    export_relative: bool,
+    /// Rules of the open smart playlist as edited, saved with the playlist
+    rules: Option<SmartRules>,
+    /// Value input of each rule in `rules`
+    rule_inputs: Vec<Entity<InputState>>,
+    limit_input: Entity<InputState>,
+    status: Option<String>,
+    _s: Vec<Subscription>,
+}
+
+impl PlaylistView {
+    pub fn new(
+        window: &mut gpui::Window,
+        cx: &mut gpui::Context<Self>,
+        navigation_state: Entity<NavigationState>,
+    ) -> Self {
+        let track_list = cx.new(|cx| TrackList::new(window, cx, TrackListDelegate::new(vec![])));
+        let name_input = cx.new(|cx| InputState::new(window, cx).placeholder("Name"));
+        let description_input = cx.new(|cx| InputState::new(window, cx).placeholder("Description"));
+        let limit_input = cx.new(|cx| InputState::new(window, cx).placeholder("No limit"));
+
+        let _s = vec![cx.observe(&navigation_state, |view, _, cx| view.reload(cx))];
+
+        cx.spawn(async move |this, app| {
+            let library = LIBRARY.get().expect("Library not initialized");
+            let mut recv = library.subscribe();
+            while let Some(event) = recv.recv().await {
+                let Some(this) = this.upgrade() else {
+                    return;
+                };
+                app.update_entity(&this, |view, cx| match event {
+                    LibraryEvent::PlaylistsChanged
+                    | LibraryEvent::TracksUpdated(_)
+                    | LibraryEvent::Restored => view.reload(cx),
+                    // new tracks and plays can change what matches a smart playlist
+                    LibraryEvent::TracksAdded(_) | LibraryEvent::PlaysRecorded(_) => {
+                        if view.playlist.as_ref().is_some_and(|p| p.rules.is_some()) {
+                            view.reload(cx)
+                        }
+                    }
+                })
+                .ok();
+            }
+        })
+        .detach();
+
+        Self {
+            playlist: None,
+            name_input,
+            description_input,
+            fill_inputs: false,
+            track_list,
+            navigation_state,
+            export_relative: true,
+            rules: None,
+            rule_inputs: Vec::new(),
+            limit_input,
+            status: None,
+            _s,
+        }
+    }
+
+    /// Load the playlist selected in the sidebar
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        let NavigationState::Playlist(id) = self.navigation_state.read(cx) else {
+            return;
+        };
+        let id = id.clone();
+        cx.spawn(async move |this, app| {
+            let playlist = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.find_playlist_by_id(&id).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            let playlist = match playlist {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    eprintln!("Failed to load playlist: {}", e);
+                    return;
+                }
+            };
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| view.show(playlist, cx))
+                    .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn show(&mut self, playlist: Option<Playlist>, cx: &mut gpui::Context<Self>) {
+        let opened = playlist.as_ref().map(|p| &p.id) != self.playlist.as_ref().map(|p| &p.id);
+        if opened {
+            self.fill_inputs = true;
+            self.status = None;
+            self.rules = playlist.as_ref().and_then(|p| p.rules.clone());
+        }
+
+        let delegate = match &playlist {
+            Some(playlist) => playlist_delegate(playlist),
+            None => TrackListDelegate::new(vec![]),
+        };
+        self.track_list
+            .update(cx, |list, cx| list.update_delegate(cx, delegate));
+        self.playlist = playlist;
+        cx.notify();
+    }
+
+    fn save(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        let Some(playlist) = &self.playlist else {
+            return;
+        };
+        let name = self.name_input.read(cx).value().trim().to_string();
+        if name.is_empty() {
+            self.status = Some("The playlist needs a name".to_string());
+            cx.notify();
+            return;
+        }
+        let description = self.description_input.read(cx).value().trim().to_string();
+        let rules = match self.read_rules(cx) {
+            Ok(rules) => rules,
+            Err(e) => {
+                self.status = Some(e.to_string());
+                cx.notify();
+                return;
+            }
+        };
+        let playlist = Playlist {
+            name,
+            description: (!description.is_empty()).then_some(description),
+            rules,
+            ..playlist.clone()
+        };
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.update_playlist(&playlist).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.status = Some(match result {
+                        Ok(()) => "Saved".to_string(),
+                        Err(e) => format!("Saving failed: {}", e),
+                    });
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// The edited rules with the values typed in, checked by compiling them
+    fn read_rules(&self, cx: &gpui::App) -> anyhow::Result<Option<SmartRules>> {
+        let Some(rules) = &self.rules else {
+            return Ok(None);
+        };
+        let mut rules = rules.clone();
+        for (rule, input) in rules.rules.iter_mut().zip(&self.rule_inputs) {
+            rule.value = input.read(cx).value().trim().to_string();
+        }
+        let limit = self.limit_input.read(cx).value().trim().to_string();
+        rules.limit = if limit.is_empty() {
+            None
+        } else {
+            Some(
+                limit
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("The limit needs to be a number"))?,
+            )
+        };
+        rules.to_sql()?;
+        Ok(Some(rules))
+    }
+
+    fn add_rule(&mut self, _event: &ClickEvent, window: &mut Window, cx: &mut gpui::Context<Self>) {
+        if let Some(rules) = &mut self.rules {
+            rules.rules.push(Rule::default());
+            self.rule_inputs
+                .push(cx.new(|cx| InputState::new(window, cx).placeholder("Value")));
+            cx.notify();
+        }
+    }
+
+    fn remove_rule(&mut self, index: usize, cx: &mut gpui::Context<Self>) {
+        if let Some(rules) = &mut self.rules
+            && index < rules.rules.len()
+        {
+            rules.rules.remove(index);
+            self.rule_inputs.remove(index);
+            cx.notify();
+        }
+    }
+
+    fn set_rule_field(&mut self, index: usize, field: RuleField, cx: &mut gpui::Context<Self>) {
+        if let Some(rule) = self.rules.as_mut().and_then(|r| r.rules.get_mut(index)) {
+            rule.field = field;
+            // keep the operator if the new field supports it
+            if !field.operators().contains(&rule.operator) {
+                rule.operator = field.operators()[0];
+            }
+            cx.notify();
+        }
+    }
+
+    fn toggle_match_all(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        if let Some(rules) = &mut self.rules {
+            rules.match_all = !rules.match_all;
+            cx.notify();
+        }
+    }
+
+    fn toggle_descending(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        if let Some(rules) = &mut self.rules {
+            rules.descending = !rules.descending;
+            cx.notify();
+        }
+    }
+
+    fn render_rules(&self, rules: &SmartRules, cx: &mut gpui::Context<Self>) -> impl IntoElement {
+        gpui::div()
+            .v_flex()
+            .gap_2()
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("rules_match_all")
+                            .label(if rules.match_all {
+                                "Match all rules"
+                            } else {
+                                "Match any rule"
+                            })
+                            .on_click(cx.listener(Self::toggle_match_all)),
+                    )
+                    .child(
+                        Button::new("rules_add")
+                            .label("Add rule")
+                            .on_click(cx.listener(Self::add_rule)),
+                    ),
+            )
+            .children(rules.rules.iter().zip(&self.rule_inputs).enumerate().map(
+                |(i, (rule, input))| {
+                    gpui::div()
+                        .h_flex()
+                        .gap_2()
+                        .child(picker(
+                            ("rule_field", i),
+                            rule.field.label(),
+                            RuleField::ALL.iter().map(|field| (*field, field.label())),
+                            cx,
+                            move |view, field, cx| view.set_rule_field(i, field, cx),
+                        ))
+                        .child(picker(
+                            ("rule_operator", i),
+                            rule.operator.label(),
+                            rule.field
+                                .operators()
+                                .iter()
+                                .map(|operator| (*operator, operator.label())),
+                            cx,
+                            move |view, operator, cx| {
+                                if let Some(rule) =
+                                    view.rules.as_mut().and_then(|r| r.rules.get_mut(i))
+                                {
+                                    rule.operator = operator;
+                                    cx.notify();
+                                }
+                            },
+                        ))
+                        .child(gpui::div().flex_1().child(Input::new(input)))
+                        .child(
+                            Button::new(("rule_remove", i)).ghost().label("✕").on_click(
+                                cx.listener(move |view, _, _, cx| view.remove_rule(i, cx)),
+                            ),
+                        )
+                },
+            ))
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(gpui::div().text_sm().child("Sort by"))
+                    .child(picker(
+                        "rules_sort",
+                        rules.sort.label(),
+                        TrackSort::ALL.iter().map(|sort| (*sort, sort.label())),
+                        cx,
+                        |view, sort, cx| {
+                            if let Some(rules) = &mut view.rules {
+                                rules.sort = sort;
+                                cx.notify();
+                            }
+                        },
+                    ))
+                    .child(
+                        Button::new("rules_descending")
+                            .ghost()
+                            .label(if rules.descending {
+                                "Descending"
+                            } else {
+                                "Ascending"
+                            })
+                            .on_click(cx.listener(Self::toggle_descending)),
+                    )
+                    .child(gpui::div().text_sm().child("Limit"))
+                    .child(gpui::div().w_32().child(Input::new(&self.limit_input))),
+            )
+    }
+
+    fn delete(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        let Some(playlist) = self.playlist.take() else {
+            return;
+        };
+        self.navigation_state.update(cx, |state, cx| {
+            *state = NavigationState::Home;
+            cx.notify();
+        });
+        task::spawn(async move {
+            let Some(library) = LIBRARY.get() else {
+                return;
+            };
+            if let Err(e) = library.delete_playlist(&playlist.id).await {
+                eprintln!("Failed to delete playlist: {}", e);
+            }
+        });
+    }
+
+    fn play(&mut self, _event: &ClickEvent, _window: &mut Window, _cx: &mut gpui::Context<Self>) {
+        if let (Some(playlist), Some(player)) = (&self.playlist, PLAYER.get()) {
+            player.play_tracks(playlist.tracks());
+        }
+    }
+
+    fn shuffle(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        _cx: &mut gpui::Context<Self>,
+    ) {
+        if let (Some(playlist), Some(player)) = (&self.playlist, PLAYER.get()) {
+            let mut tracks = playlist.tracks();
+            tracks.shuffle(&mut rand::rng());
+            player.play_tracks(tracks);
+        }
+    }
+
+    fn toggle_export_relative(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        self.export_relative = !self.export_relative;
+        cx.notify();
+    }
+
+    /// Save the playlist as an M3U, PLS or XSPF file, by the extension chosen
+    fn export(&mut self, _event: &ClickEvent, _window: &mut Window, cx: &mut gpui::Context<Self>) {
+        let Some(playlist) = self.playlist.clone() else {
+            return;
+        };
+        let directory = dirs::audio_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_default();
+        let path_future =
+            cx.prompt_for_new_path(&directory, Some(&format!("{}.m3u8", playlist.name)));
+        let relative = self.export_relative;
+        cx.spawn(async move |this, app| {
+            let Some(path) = path_future.await.ok().and_then(|r| r.ok()).flatten() else {
+                return;
+            };
+            let result = task::spawn(async move { playlist.export(&path, relative).await })
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+                .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.status = Some(match result {
+                        Ok(0) => "Exported".to_string(),
+                        Ok(skipped) => format!(
+                            "Exported, left out {} track(s) that are not local files",
+                            skipped
+                        ),
+                        Err(e) => format!("Export failed: {}", e),
+                    });
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+}
+
+/// A button showing `label` that opens a list of `options` to pick one from
+fn picker<T: Copy + 'static>(
+    id: impl Into<ElementId>,
+    label: &'static str,
+    options: impl Iterator<Item = (T, &'static str)>,
+    cx: &mut gpui::Context<PlaylistView>,
+    on_pick: impl Fn(&mut PlaylistView, T, &mut gpui::Context<PlaylistView>) + Clone + 'static,
+) -> Popover {
+    let id = id.into();
+    Popover::new(id.clone())
+        .trigger(Button::new(id).label(label))
+        .child(
+            gpui::div()
+                .v_flex()
+                .gap_1()
+                .children(options.map(|(value, label)| {
+                    let on_pick = on_pick.clone();
+                    Button::new(SharedString::new(label))
+                        .ghost()
+                        .label(label)
+                        .on_click(cx.listener(move |view, _, _, cx| on_pick(view, value, cx)))
+                })),
+        )
+}
+
+/// Track list for a playlist, playing from the clicked entry on and with buttons to
+/// reorder and remove entries. The tracks of smart playlists come from their rules, so
+/// they can only be played.
+fn playlist_delegate(playlist: &Playlist) -> TrackListDelegate<PlaylistEntry> {
+    let entries = playlist.entries.clone();
+    let on_play = Arc::new(move |entry: PlaylistEntry| {
+        let Some(player) = PLAYER.get() else {
+            eprintln!("Player not initialized");
+            return;
+        };
+        let start = entries.iter().position(|e| e.id == entry.id).unwrap_or(0);
+        player.play_tracks(entries[start..].iter().map(|e| e.track.clone()).collect());
+    });
+
+    let delegate = TrackListDelegate::new(playlist.entries.clone()).with_on_play(on_play);
+    if playlist.rules.is_some() {
+        return delegate;
+    }
+
+    let len = playlist.entries.len();
+    let move_entry = |offset: isize| {
+        let playlist_id = playlist.id.clone();
+        move |row: usize, entry: PlaylistEntry| {
+            let Some(position) = row.checked_add_signed(offset).filter(|p| *p < len) else {
+                return;
+            };
+            let playlist_id = playlist_id.clone();
+            task::spawn(async move {
+                let Some(library) = LIBRARY.get() else {
+                    return;
+                };
+                if let Err(e) = library
+                    .move_playlist_entry(&playlist_id, &entry.id, position)
+                    .await
+                {
+                    eprintln!("Failed to move playlist entry: {}", e);
+                }
+            });
+        }
+    };
+    let remove_from = playlist.id.clone();
+    let remove = move |_row: usize, entry: PlaylistEntry| {
+        let playlist_id = remove_from.clone();
+        task::spawn(async move {
+            let Some(library) = LIBRARY.get() else {
+                return;
+            };
+            if let Err(e) = library.remove_playlist_entry(&playlist_id, &entry.id).await {
+                eprintln!("Failed to remove playlist entry: {}", e);
+            }
+        });
+    };
+
+    delegate.with_row_actions(vec![
+        RowAction::new("↑", move_entry(-1)),
+        RowAction::new("↓", move_entry(1)),
+        RowAction::new("✕", remove),
+    ])
+}
+
+impl Render for PlaylistView {
+    fn render(
+        &mut self,
+        window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        if self.fill_inputs {
+            self.fill_inputs = false;
+            let (name, description) = self
+                .playlist
+                .as_ref()
+                .map(|p| (p.name.clone(), p.description.clone().unwrap_or_default()))
+                .unwrap_or_default();
+            self.name_input
+                .update(cx, |state, cx| state.set_value(name, window, cx));
+            self.description_input
+                .update(cx, |state, cx| state.set_value(description, window, cx));
+
+            let rules = self.rules.clone().unwrap_or_default();
+            self.rule_inputs = rules
+                .rules
+                .iter()
+                .map(|rule| {
+                    let value = rule.value.clone();
+                    cx.new(|cx| {
+                        let mut state = InputState::new(window, cx).placeholder("Value");
+                        state.set_value(value, window, cx);
+                        state
+                    })
+                })
+                .collect();
+            let limit = rules.limit.map(|l| l.to_string()).unwrap_or_default();
+            self.limit_input
+                .update(cx, |state, cx| state.set_value(limit, window, cx));
+        }
+
+        let Some(playlist) = &self.playlist else {
+            return gpui::div()
+                .w_full()
+                .h_full()
+                .v_flex()
+                .px_5()
+                .py_2()
+                .child("Playlist not found");
+        };
+
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child(playlist.name.clone()),
+            )
+            .child(
+                gpui::div()
+                    .text_sm()
+                    .child(format!("{} track(s)", playlist.entries.len())),
+            )
+            .child(Input::new(&self.name_input))
+            .child(Input::new(&self.description_input))
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("playlist_play")
+                            .primary()
+                            .icon(Icon::Play)
+                            .label("Play")
+                            .on_click(cx.listener(Self::play)),
+                    )
+                    .child(
+                        Button::new("playlist_shuffle")
+                            .label("Shuffle")
+                            .on_click(cx.listener(Self::shuffle)),
+                    )
+                    .child(
+                        Button::new("playlist_save")
+                            .label("Save")
+                            .on_click(cx.listener(Self::save)),
+                    )
+                    .child(
+                        Button::new("playlist_export")
+                            .label("Export...")
+                            .on_click(cx.listener(Self::export)),
+                    )
+                    .child(
+                        Button::new("playlist_export_relative")
+                            .ghost()
+                            .label(if self.export_relative {
+                                "Relative paths"
+                            } else {
+                                "Absolute paths"
+                            })
+                            .on_click(cx.listener(Self::toggle_export_relative)),
+                    )
+                    .child(
+                        Button::new("playlist_delete")
+                            .danger()
+                            .label("Delete playlist")
+                            .on_click(cx.listener(Self::delete)),
+                    ),
+            )
+            .when_some(self.rules.as_ref(), |div, rules| {
+                div.child(self.render_rules(rules, cx))
+            })
+            .when_some(self.status.clone(), |div, status| {
+                div.child(gpui::div().text_sm().child(status))
+            })
+            .child(self.track_list.clone())
+    }
+}
@@ -0,0 +1,525 @@
+use std::collections::HashSet;
+
+use gpui::prelude::FluentBuilder;
+use gpui::{
+    AppContext, ClickEvent, Entity, IntoElement, ParentElement, PathPromptOptions, Render,
+    SharedString, Styled, Subscription, Window,
+};
+use gpui_component::{
+    Icon as GpuiIcon, StyledExt,
+    button::Button,
+    input::{Input, InputEvent, InputState},
+};
+use tokio::task;
+
+use crate::{
+    components::{
+        icon::Icon,
+        track_list::{TrackList, TrackListDelegate},
+    },
+    fingerprint::{Identification, acoustid::AcoustId},
+    library::{LIBRARY, Track, edit::TagEdit},
+    preferences::PREFERENCES,
+};
+
+pub struct TagEditorView {
+    search_input: Entity<InputState>,
+    track_list: Entity<TrackList<Track>>,
+    title: Entity<InputState>,
+    artists: Entity<InputState>,
+    album: Entity<InputState>,
+    album_artist: Entity<InputState>,
+    year: Entity<InputState>,
+    track_number: Entity<InputState>,
+    disc_number: Entity<InputState>,
+    genres: Entity<InputState>,
+    composer: Entity<InputState>,
+    /// File name and contents of the chosen cover image
+    cover: Option<(String, Vec<u8>)>,
+    /// Best match for the selected track, filled into the fields on the next render
+    identified: Option<Identification>,
+    query: String,
+    status: Option<String>,
+    busy: bool,
+    _s: Vec<Subscription>,
+}
+
+/// Split a list field such as "Rock; Pop" into its values
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn parse_number(name: &str, value: &str) -> Result<i32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} must be a number", name))
+}
+
+impl TagEditorView {
+    pub fn new(window: &mut gpui::Window, cx: &mut gpui::Context<Self>) -> Self {
+        let search_input = cx.new(|cx| {
+            InputState::new(window, cx)
+                .placeholder("Find tracks to edit... (e.g. album:\"ok computer\")")
+        });
+        let track_list = cx.new(|cx| {
+            TrackList::new(
+                window,
+                cx,
+                TrackListDelegate::new(vec![]).with_checkboxes(HashSet::new()),
+            )
+        });
+        let mut input = |placeholder: &'static str| {
+            cx.new(|cx| InputState::new(window, cx).placeholder(placeholder))
+        };
+        let title = input("Title");
+        let artists = input("Artists, separated by ;");
+        let album = input("Album");
+        let album_artist = input("Album artist");
+        let year = input("Year");
+        let track_number = input("Track number");
+        let disc_number = input("Disc number");
+        let genres = input("Genres, separated by ;");
+        let composer = input("Composer");
+
+        // the buttons show how many tracks are checked
+        let list_state = track_list.read(cx).list_state.clone();
+        let _s = vec![
+            cx.observe(&list_state, |_, _, cx| cx.notify()),
+            cx.subscribe_in(&search_input, window, move |this, state, event, _, cx| {
+                if let InputEvent::PressEnter { .. } = event {
+                    this.query = state.read(cx).value().trim().to_string();
+                    this.reload(cx);
+                }
+            }),
+        ];
+
+        Self {
+            search_input,
+            track_list,
+            title,
+            artists,
+            album,
+            album_artist,
+            year,
+            track_number,
+            disc_number,
+            genres,
+            composer,
+            cover: None,
+            identified: None,
+            query: String::new(),
+            status: None,
+            busy: false,
+            _s,
+        }
+    }
+
+    /// Show the tracks matching the current query
+    fn reload(&mut self, cx: &mut gpui::Context<Self>) {
+        let query = self.query.clone();
+        let track_list = self.track_list.clone();
+        cx.spawn(async move |_, app| {
+            let tracks = if query.is_empty() {
+                vec![]
+            } else {
+                task::spawn(async move {
+                    let library = LIBRARY
+                        .get()
+                        .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                    Ok(library.search(&query).await?.tracks)
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+                .flatten()
+                .unwrap_or_default()
+            };
+            app.update_entity(&track_list, |list, cx| {
+                // keep tracks checked across searches and edits
+                let checked = list.delegate(cx).checked_ids();
+                list.update_delegate(cx, TrackListDelegate::new(tracks).with_checkboxes(checked))
+            })
+            .ok();
+        })
+        .detach();
+    }
+
+    /// Build an edit from the filled in fields, empty fields are left unchanged
+    fn read_edit(&self, cx: &gpui::App) -> Result<TagEdit, String> {
+        let text = |input: &Entity<InputState>| {
+            let value = input.read(cx).value().trim().to_string();
+            (!value.is_empty()).then_some(value)
+        };
+        let number = |name: &str, input: &Entity<InputState>| {
+            text(input)
+                .map(|value| parse_number(name, &value).map(Some))
+                .transpose()
+        };
+        Ok(TagEdit {
+            title: text(&self.title),
+            artists: text(&self.artists).map(|value| split_list(&value)),
+            album: text(&self.album),
+            album_artist: text(&self.album_artist),
+            year: number("Year", &self.year)?,
+            track_number: number("Track number", &self.track_number)?,
+            disc_number: number("Disc number", &self.disc_number)?,
+            genres: text(&self.genres).map(|value| split_list(&value)),
+            composer: text(&self.composer).map(Some),
+            cover: self.cover.as_ref().map(|(_, data)| data.clone()),
+        })
+    }
+
+    /// Apply the edit to the checked tracks, or the highlighted one if none are checked
+    fn apply_to_checked(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let delegate = self.track_list.read(cx).delegate(cx);
+        let mut tracks: Vec<Track> = delegate.checked_items().into_iter().cloned().collect();
+        if tracks.is_empty() {
+            tracks.extend(delegate.selected_item().cloned());
+        }
+        if tracks.is_empty() {
+            self.status = Some("Check the tracks to edit first".to_string());
+            cx.notify();
+            return;
+        }
+        self.apply(tracks, cx);
+    }
+
+    fn toggle_all(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let list_state = self.track_list.read(cx).list_state.clone();
+        list_state.update(cx, |state, cx| {
+            let delegate = state.delegate_mut();
+            let all_checked = delegate.checked_items().len() == delegate.items().len();
+            delegate.check_all(!all_checked);
+            cx.notify();
+        });
+        cx.notify();
+    }
+
+    fn apply_to_all(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let tracks = self.track_list.read(cx).delegate(cx).items().to_vec();
+        self.apply(tracks, cx);
+    }
+
+    fn apply(&mut self, tracks: Vec<Track>, cx: &mut gpui::Context<Self>) {
+        if self.busy || tracks.is_empty() {
+            return;
+        }
+        let edit = match self.read_edit(cx) {
+            Ok(edit) if edit.is_empty() => {
+                self.status = Some("Fill in the fields to change".to_string());
+                cx.notify();
+                return;
+            }
+            Ok(edit) => edit,
+            Err(e) => {
+                self.status = Some(e);
+                cx.notify();
+                return;
+            }
+        };
+        self.busy = true;
+        self.status = Some(format!("Writing tags to {} track(s)...", tracks.len()));
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                library.edit_tracks(&tracks, &edit).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    view.status = Some(match result {
+                        Ok(report) => {
+                            let mut status = format!("Updated {} track(s)", report.updated.len());
+                            if let Some((track, reason)) = report.skipped.first() {
+                                status.push_str(&format!(
+                                    ", skipped {} ({}: {})",
+                                    report.skipped.len(),
+                                    track.title,
+                                    reason
+                                ));
+                            }
+                            status
+                        }
+                        Err(e) => format!("Editing failed: {}", e),
+                    });
+                    view.reload(cx);
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    /// Fill in the fields with what the selected track sounds like, going by its
+    /// fingerprint
+    fn identify_selected(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let Some(track) = self
+            .track_list
+            .read(cx)
+            .delegate(cx)
+            .selected_item()
+            .cloned()
+        else {
+            self.status = Some("Select a track first".to_string());
+            cx.notify();
+            return;
+        };
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status = Some(format!("Identifying {}...", track.title));
+        cx.notify();
+        cx.spawn(async move |this, app| {
+            let result = task::spawn(async move {
+                let library = LIBRARY
+                    .get()
+                    .ok_or(anyhow::anyhow!("Library not initialized"))?;
+                let lookup = PREFERENCES
+                    .get()
+                    .ok_or(anyhow::anyhow!("Preferences not initialized"))?
+                    .read()
+                    .await
+                    .acoustid_api_key
+                    .clone()
+                    .map(|api_key| AcoustId { api_key });
+                library.identify_track(&track, &lookup).await
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to join task: {}", e))
+            .flatten();
+
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    view.busy = false;
+                    match result {
+                        Ok(found) => match found.into_iter().next() {
+                            Some(identification) => {
+                                view.status = Some(format!(
+                                    "Sounds like {} by {} ({}, {:.0}% sure), apply to save it",
+                                    identification.title,
+                                    identification.artists.join(", "),
+                                    identification.source,
+                                    identification.score * 100.0
+                                ));
+                                view.identified = Some(identification);
+                            }
+                            None => view.status = Some("No match found".to_string()),
+                        },
+                        Err(e) => view.status = Some(format!("Identifying failed: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn choose_cover(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        let path_future = cx.prompt_for_paths(PathPromptOptions {
+            directories: false,
+            files: true,
+            multiple: false,
+            prompt: Some(SharedString::new("Select cover image")),
+        });
+        cx.spawn(async move |this, app| {
+            let Some(path) = path_future
+                .await
+                .ok()
+                .and_then(|r| r.ok())
+                .flatten()
+                .and_then(|paths| paths.into_iter().next())
+            else {
+                return;
+            };
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let data = tokio::fs::read(&path).await;
+            if let Some(this) = this.upgrade() {
+                app.update_entity(&this, |view, cx| {
+                    match data {
+                        Ok(data) => view.cover = Some((name, data)),
+                        Err(e) => view.status = Some(format!("Failed to read image: {}", e)),
+                    }
+                    cx.notify();
+                })
+                .ok();
+            }
+        })
+        .detach();
+    }
+
+    fn clear_cover(
+        &mut self,
+        _event: &ClickEvent,
+        _window: &mut Window,
+        cx: &mut gpui::Context<Self>,
+    ) {
+        self.cover = None;
+        cx.notify();
+    }
+}
+
+fn field(label: &'static str, input: &Entity<InputState>) -> impl IntoElement {
+    gpui::div()
+        .v_flex()
+        .flex_1()
+        .gap_1()
+        .child(gpui::div().text_sm().child(label))
+        .child(Input::new(input))
+}
+
+impl Render for TagEditorView {
+    fn render(
+        &mut self,
+        window: &mut gpui::Window,
+        cx: &mut gpui::Context<'_, Self>,
+    ) -> impl IntoElement {
+        if let Some(identification) = self.identified.take() {
+            self.title.update(cx, |state, cx| {
+                state.set_value(identification.title, window, cx)
+            });
+            self.artists.update(cx, |state, cx| {
+                state.set_value(identification.artists.join("; "), window, cx)
+            });
+            if let Some(album) = identification.album {
+                self.album
+                    .update(cx, |state, cx| state.set_value(album, window, cx));
+            }
+        }
+        let delegate = self.track_list.read(cx).delegate(cx);
+        let track_count = delegate.items().len();
+        let checked_count = delegate.checked_items().len();
+        gpui::div()
+            .w_full()
+            .h_full()
+            .v_flex()
+            .px_5()
+            .py_2()
+            .gap_4()
+            .child(
+                gpui::div()
+                    .text_xl()
+                    .font_weight(gpui::FontWeight::BOLD)
+                    .child("Edit tags"),
+            )
+            .child(Input::new(&self.search_input).prefix(GpuiIcon::new(Icon::Search)))
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(field("Title", &self.title))
+                    .child(field("Artists", &self.artists))
+                    .child(field("Composer", &self.composer)),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(field("Album", &self.album))
+                    .child(field("Album artist", &self.album_artist))
+                    .child(field("Genres", &self.genres)),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(field("Year", &self.year))
+                    .child(field("Track", &self.track_number))
+                    .child(field("Disc", &self.disc_number)),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("tag_editor_cover")
+                            .label("Choose cover...")
+                            .on_click(cx.listener(Self::choose_cover)),
+                    )
+                    .when_some(self.cover.as_ref(), |div, (name, _)| {
+                        div.child(gpui::div().text_sm().child(name.clone())).child(
+                            Button::new("tag_editor_clear_cover")
+                                .label("Keep current cover")
+                                .on_click(cx.listener(Self::clear_cover)),
+                        )
+                    }),
+            )
+            .child(
+                gpui::div()
+                    .h_flex()
+                    .gap_2()
+                    .child(
+                        Button::new("tag_editor_check_all")
+                            .label(if track_count > 0 && checked_count == track_count {
+                                "Uncheck all"
+                            } else {
+                                "Check all"
+                            })
+                            .on_click(cx.listener(Self::toggle_all)),
+                    )
+                    .child(
+                        Button::new("tag_editor_apply_checked")
+                            .label(if checked_count > 0 {
+                                format!("Apply to {} checked track(s)", checked_count)
+                            } else {
+                                "Apply to selected track".to_string()
+                            })
+                            .on_click(cx.listener(Self::apply_to_checked)),
+                    )
+                    .child(
+                        Button::new("tag_editor_apply_all")
+                            .label(format!("Apply to all {} track(s)", track_count))
+                            .on_click(cx.listener(Self::apply_to_all)),
+                    )
+                    .child(
+                        Button::new("tag_editor_identify")
+                            .label("Identify selected track")
+                            .on_click(cx.listener(Self::identify_selected)),
+                    ),
+            )
+            .when_some(self.status.clone(), |div, status| {
+                div.child(gpui::div().text_sm().child(status))
+            })
+            .child(self.track_list.clone())
+    }
+}